@@ -0,0 +1,335 @@
+//! Cleaning up the contours found in a mask so each LED ends up as exactly one blob.
+
+#[cfg(feature = "opencv")]
+use emath::Vec2;
+use emath::{Pos2, Rect};
+#[cfg(feature = "opencv")]
+use opencv::{
+    core::{
+        self, compare, min_max_loc, no_array, Mat, Point, Scalar, Vector, CMP_EQ, CV_32F, CV_32S,
+        CV_8U,
+    },
+    imgproc::{
+        bounding_rect, connected_components, cvt_color, distance_transform, draw_contours,
+        find_contours, moments, threshold, watershed, CHAIN_APPROX_NONE, CHAIN_APPROX_SIMPLE,
+        CHAIN_APPROX_TC89_L1, COLOR_GRAY2BGR, DIST_L2, FILLED, LINE_8, RETR_EXTERNAL, RETR_LIST,
+        THRESH_BINARY,
+    },
+    prelude::*,
+};
+#[cfg(feature = "opencv")]
+use rayon::prelude::*;
+
+#[cfg(feature = "opencv")]
+use crate::{Approximation, Retrieval, Settings};
+
+/// Masks with more rows than this are cut into bands that are searched for contours in
+/// parallel, which 4K frames need to keep up on a single core's worth of detection.
+#[cfg(feature = "opencv")]
+const PARALLEL_ROWS: i32 = 1080;
+
+/// Finds the blobs in a mask, as rects centered on their centroid.
+#[cfg(feature = "opencv")]
+pub fn find(mask: &Mat, settings: &Settings) -> opencv::Result<Vec<Rect>> {
+    let threads = rayon::current_num_threads();
+
+    // Redrawing the pieces of blobs cut by the bands fills their holes, which only the outer
+    // contours can do without
+    let found = if mask.rows() > PARALLEL_ROWS
+        && threads > 1
+        && settings.retrieval == Retrieval::External
+    {
+        find_banded(mask, settings, threads)?
+    } else {
+        blobs(&contours(mask, settings, Point::default())?, settings)?
+    };
+
+    let found = merge_close(found, settings.merge_radius);
+
+    Ok(found.iter().map(Blob::rect).collect())
+}
+
+/// The contours in `mask`, moved by `offset`.
+#[cfg(feature = "opencv")]
+fn contours(
+    mask: &Mat,
+    settings: &Settings,
+    offset: Point,
+) -> opencv::Result<Vector<Vector<Point>>> {
+    let mode = match settings.retrieval {
+        Retrieval::External => RETR_EXTERNAL,
+        Retrieval::List => RETR_LIST,
+    };
+    let method = match settings.approximation {
+        Approximation::None => CHAIN_APPROX_NONE,
+        Approximation::Simple => CHAIN_APPROX_SIMPLE,
+        Approximation::TehChin => CHAIN_APPROX_TC89_L1,
+    };
+    let mut contours = Vector::<Vector<Point>>::new();
+    find_contours(mask, &mut contours, mode, method, offset)?;
+    Ok(contours)
+}
+
+/// Cuts `mask` into a band per thread of rayon's pool and finds the blobs in each at once. Blobs
+/// crossing from one band into the next come out of both in pieces, so those pieces are drawn
+/// back into a mask of their own, where they join up again, and found once more.
+#[cfg(feature = "opencv")]
+fn find_banded(mask: &Mat, settings: &Settings, threads: usize) -> opencv::Result<Vec<Blob>> {
+    let (rows, cols) = (mask.rows(), mask.cols());
+    let height = (rows + threads as i32 - 1) / threads as i32;
+
+    // A Mat can be sent but not shared, so each thread gets its own view of the mask
+    let mut bands = Vec::with_capacity(threads);
+    for top in (0..rows).step_by(height as usize) {
+        let bottom = (top + height).min(rows);
+        let band = Mat::roi(mask, core::Rect::new(0, top, cols, bottom - top))?;
+        bands.push((band, top, bottom));
+    }
+
+    let results = bands
+        .into_par_iter()
+        .map(|(band, top, bottom)| -> opencv::Result<_> {
+            let mut whole = Vector::<Vector<Point>>::new();
+            let mut cut = Vector::<Vector<Point>>::new();
+            for contour in contours(&band, settings, Point::new(0, top))? {
+                let rect = bounding_rect(&contour)?;
+                let at_edge =
+                    (rect.y == top && top > 0) || (rect.y + rect.height == bottom && bottom < rows);
+                if at_edge {
+                    cut.push(contour);
+                } else {
+                    whole.push(contour);
+                }
+            }
+            Ok((blobs(&whole, settings)?, cut))
+        })
+        .collect::<Vec<_>>();
+
+    let mut found = Vec::new();
+    let mut cut = Vector::<Vector<Point>>::new();
+    for result in results {
+        let (blobs, pieces) = result?;
+        found.extend(blobs);
+        cut.extend(pieces);
+    }
+    if cut.is_empty() {
+        return Ok(found);
+    }
+
+    let mut bounds = bounding_rect(&cut.get(0)?)?;
+    for piece in cut.iter().skip(1) {
+        bounds |= bounding_rect(&piece)?;
+    }
+    let mut joined =
+        Mat::new_rows_cols_with_default(bounds.height, bounds.width, CV_8U, Scalar::all(0.0))?;
+    draw_contours(
+        &mut joined,
+        &cut,
+        -1,
+        Scalar::all(255.0),
+        FILLED,
+        LINE_8,
+        &no_array(),
+        i32::MAX,
+        Point::new(-bounds.x, -bounds.y),
+    )?;
+    found.extend(blobs(&contours(&joined, settings, bounds.tl())?, settings)?);
+
+    Ok(found)
+}
+
+/// The blobs of `contours` big and small enough for the settings, with the ones that look like
+/// several LEDs split up.
+#[cfg(feature = "opencv")]
+fn blobs(contours: &Vector<Vector<Point>>, settings: &Settings) -> opencv::Result<Vec<Blob>> {
+    let mut found = Vec::with_capacity(contours.len());
+    for contour in contours.iter() {
+        let moments = moments(&contour, false)?;
+
+        // Calculate bounding rectangle
+        let rect = bounding_rect(&contour)?;
+
+        let blob = Blob {
+            centroid: Pos2::new(
+                (moments.m10 / moments.m00) as f32,
+                (moments.m01 / moments.m00) as f32,
+            ),
+            bounds: Rect::from_min_size(
+                Pos2::new(rect.x as f32, rect.y as f32),
+                Vec2::new(rect.width as f32, rect.height as f32),
+            ),
+            area: moments.m00 as f32,
+        };
+
+        if !blob.rect().is_finite()
+            || blob.area < settings.min_area
+            || (settings.max_area > 0.0 && blob.area > settings.max_area)
+        {
+            continue;
+        }
+
+        match split(&contour, rect, blob.area, settings.blob_area)? {
+            Some(pieces) => found.extend(pieces),
+            None => found.push(blob),
+        }
+    }
+    Ok(found)
+}
+
+/// A contour from the mask, before it's turned into the rect `POINTS` stores.
+#[derive(Clone, Copy)]
+pub struct Blob {
+    pub centroid: Pos2,
+    /// Bounding box of the contour, which the centroid isn't necessarily the middle of.
+    pub bounds: Rect,
+    pub area: f32,
+}
+
+impl Blob {
+    /// As stored in `POINTS`: the size of the bounding box, centered on the centroid.
+    pub fn rect(&self) -> Rect {
+        Rect::from_center_size(self.centroid, self.bounds.size())
+    }
+}
+
+/// Merges blobs whose centroids are within `radius` of each other, directly or through other
+/// blobs, for diffused LEDs that come out of the mask in several pieces. The merged centroid is
+/// weighted by area.
+pub fn merge_close(blobs: Vec<Blob>, radius: f32) -> Vec<Blob> {
+    if radius <= 0.0 || blobs.len() < 2 {
+        return blobs;
+    }
+
+    // Union-find over every pair that's close enough
+    let mut parent = (0..blobs.len()).collect::<Vec<_>>();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..blobs.len() {
+        for j in i + 1..blobs.len() {
+            if blobs[i].centroid.distance(blobs[j].centroid) <= radius {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+
+    let mut merged: Vec<(usize, Blob)> = Vec::new();
+    for (i, blob) in blobs.iter().enumerate() {
+        let group = root(&mut parent, i);
+        match merged.iter_mut().find(|(g, _)| *g == group) {
+            Some((_, into)) => {
+                let area = into.area + blob.area;
+                let weight = if area > 0.0 { blob.area / area } else { 0.5 };
+                into.centroid = into.centroid.lerp(blob.centroid, weight);
+                into.bounds = into.bounds.union(blob.bounds);
+                into.area = area;
+            }
+            None => merged.push((group, *blob)),
+        }
+    }
+
+    merged.into_iter().map(|(_, blob)| blob).collect()
+}
+
+/// Splits a contour that's probably several LEDs blooming into each other, going by its area
+/// compared to `expected_area`, the area of a single LED's blob. Returns `None` when it looks
+/// like one LED after all.
+///
+/// Each LED's core is where the distance to the edge of the blob peaks, and a watershed over
+/// that distance divides the blob between the cores.
+#[cfg(feature = "opencv")]
+fn split(
+    contour: &Vector<Point>,
+    bounds: core::Rect,
+    area: f32,
+    expected_area: f32,
+) -> opencv::Result<Option<Vec<Blob>>> {
+    if expected_area <= 0.0 || area < expected_area * 1.5 {
+        return Ok(None);
+    }
+
+    // Just this contour, with a pixel of border so the distance transform sees its edges
+    let mut local = Mat::new_rows_cols_with_default(
+        bounds.height + 2,
+        bounds.width + 2,
+        CV_8U,
+        Scalar::all(0.0),
+    )?;
+    draw_contours(
+        &mut local,
+        &Vector::<Vector<Point>>::from_iter([contour.clone()]),
+        -1,
+        Scalar::all(255.0),
+        FILLED,
+        LINE_8,
+        &no_array(),
+        i32::MAX,
+        Point::new(1 - bounds.x, 1 - bounds.y),
+    )?;
+
+    let mut distance = Mat::default();
+    distance_transform(&local, &mut distance, DIST_L2, 5, CV_32F)?;
+    let mut max = 0.0;
+    min_max_loc(&distance, None, Some(&mut max), None, None, &no_array())?;
+    if max <= 0.0 {
+        return Ok(None);
+    }
+
+    // Cores are what's left well inside a single LED's radius, necks between LEDs are narrower
+    let radius = (expected_area / std::f32::consts::PI).sqrt() as f64;
+    let mut cores = Mat::default();
+    threshold(&distance, &mut cores, (radius * 0.6).min(max * 0.7), 255.0, THRESH_BINARY)?;
+    let mut cores_8u = Mat::default();
+    cores.convert_to(&mut cores_8u, CV_8U, 1.0, 0.0)?;
+
+    let mut markers = Mat::default();
+    let labels = connected_components(&cores_8u, &mut markers, 8, CV_32S)?;
+    if labels <= 2 {
+        return Ok(None);
+    }
+
+    // Everything outside the contour is one more basin, so the cores can't flood into it
+    let mut outside = Mat::default();
+    compare(&local, &Scalar::all(0.0), &mut outside, CMP_EQ)?;
+    markers.set_to(&Scalar::all(labels as f64), &outside)?;
+
+    // Flood from the cores downhill, with the ridges where the distance is lowest
+    let mut relief = Mat::default();
+    distance.convert_to(&mut relief, CV_8U, -255.0 / max, 255.0)?;
+    let mut relief_bgr = Mat::default();
+    cvt_color(&relief, &mut relief_bgr, COLOR_GRAY2BGR, 0)?;
+    watershed(&relief_bgr, &mut markers)?;
+
+    let offset = Vec2::new((bounds.x - 1) as f32, (bounds.y - 1) as f32);
+    let mut pieces = Vec::with_capacity(labels as usize - 1);
+    for label in 1..labels {
+        let mut region = Mat::default();
+        compare(&markers, &Scalar::all(label as f64), &mut region, CMP_EQ)?;
+
+        let moments = moments(&region, true)?;
+        if moments.m00 <= 0.0 {
+            continue;
+        }
+        let rect = bounding_rect(&region)?;
+
+        pieces.push(Blob {
+            centroid: Pos2::new(
+                (moments.m10 / moments.m00) as f32,
+                (moments.m01 / moments.m00) as f32,
+            ) + offset,
+            bounds: Rect::from_min_size(
+                Pos2::new(rect.x as f32, rect.y as f32) + offset,
+                Vec2::new(rect.width as f32, rect.height as f32),
+            ),
+            area: moments.m00 as f32,
+        });
+    }
+
+    Ok((pieces.len() > 1).then_some(pieces))
+}
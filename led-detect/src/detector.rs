@@ -0,0 +1,137 @@
+//! What the live detection runs on each frame, picked per profile with `Settings::detector`.
+//! Detectors may keep state between calls, so each is owned by whoever feeds it frames.
+//!
+//! Scans call `detect_rgb` directly instead, since they take care of what's lit in each frame
+//! themselves.
+
+use crate::{template, Rect, Result, Settings, Yuv420};
+
+/// Turns frames into detections, one rect per LED centered on it.
+pub trait Detector: Send {
+    /// Detects the LEDs in a packed RGB24 frame `width` pixels wide.
+    fn detect_rgb(&mut self, rgb: &[u8], width: usize, settings: &Settings) -> Result<Vec<Rect>>;
+
+    /// Detects the LEDs in a YUV frame, converting it to RGB unless the detector knows better.
+    fn detect_yuv(&mut self, yuv: &Yuv420, settings: &Settings) -> Result<Vec<Rect>> {
+        self.detect_rgb(&yuv.to_rgb(), yuv.width, settings)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DetectorKind {
+    /// The blobs in the thresholded frame, see `Blobs`.
+    Blobs,
+    /// The blobs in what got brighter since the frame before, see `Difference`.
+    Difference,
+    /// Wherever the captured template matches, see `TemplateMatch`.
+    Template,
+    /// The peaks of an ONNX model's score map, see `neural`. Without the `onnx` feature it only
+    /// fails, so settings that pick it still load.
+    Neural,
+}
+
+impl DetectorKind {
+    #[cfg(not(feature = "onnx"))]
+    pub const ALL: [Self; 3] = [Self::Blobs, Self::Difference, Self::Template];
+    #[cfg(feature = "onnx")]
+    pub const ALL: [Self; 4] = [Self::Blobs, Self::Difference, Self::Template, Self::Neural];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Blobs => "Color blobs",
+            Self::Difference => "Frame difference",
+            Self::Template => "Template",
+            Self::Neural => "Neural network",
+        }
+    }
+
+    pub fn detector(self) -> Box<dyn Detector> {
+        match self {
+            Self::Blobs => Box::new(Blobs),
+            Self::Difference => Box::<Difference>::default(),
+            Self::Template => Box::new(TemplateMatch),
+            #[cfg(feature = "onnx")]
+            Self::Neural => Box::<crate::Neural>::default(),
+            #[cfg(not(feature = "onnx"))]
+            Self::Neural => Box::new(NoRuntime),
+        }
+    }
+}
+
+/// Thresholds each frame by the detection mode and finds the blobs in the mask.
+pub struct Blobs;
+
+impl Detector for Blobs {
+    fn detect_rgb(&mut self, rgb: &[u8], width: usize, settings: &Settings) -> Result<Vec<Rect>> {
+        crate::detect_rgb(rgb, width, settings)
+    }
+
+    fn detect_yuv(&mut self, yuv: &Yuv420, settings: &Settings) -> Result<Vec<Rect>> {
+        crate::detect_yuv(yuv, settings)
+    }
+}
+
+/// Like `Blobs`, but on how much brighter each pixel got since the frame before, so only LEDs
+/// that just turned on show up. Picks out a blinking or chasing LED in a room where everything
+/// else is lit too. Finds nothing in the first frame, or when the frame size changes.
+#[derive(Default)]
+pub struct Difference {
+    previous: Option<(Vec<u8>, usize)>,
+}
+
+impl Detector for Difference {
+    fn detect_rgb(&mut self, rgb: &[u8], width: usize, settings: &Settings) -> Result<Vec<Rect>> {
+        let previous = self.previous.replace((rgb.to_vec(), width));
+        match previous {
+            Some((previous, previous_width))
+                if previous.len() == rgb.len() && previous_width == width =>
+            {
+                let brighter = rgb
+                    .iter()
+                    .zip(&previous)
+                    .map(|(pixel, previous)| pixel.saturating_sub(*previous))
+                    .collect::<Vec<_>>();
+                crate::detect_rgb(&brighter, width, settings)
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Slides `Settings::template` over each frame, for LEDs with a bloom shape of their own that
+/// thresholding keeps mixing up with reflections. Finds nothing until there's a template.
+pub struct TemplateMatch;
+
+impl Detector for TemplateMatch {
+    fn detect_rgb(&mut self, rgb: &[u8], width: usize, settings: &Settings) -> Result<Vec<Rect>> {
+        let Some(template) = &settings.template else {
+            return Ok(Vec::new());
+        };
+        let luma = rgb
+            .chunks_exact(3)
+            .map(|pixel| template::luma([pixel[0], pixel[1], pixel[2]]))
+            .collect::<Vec<_>>();
+        template::find(&luma, width, template, settings.template_score)
+    }
+
+    fn detect_yuv(&mut self, yuv: &Yuv420, settings: &Settings) -> Result<Vec<Rect>> {
+        match &settings.template {
+            Some(template) => template::find(&yuv.y, yuv.width, template, settings.template_score),
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+/// Stands in for `neural::Neural` in builds without the `onnx` feature.
+#[cfg(not(feature = "onnx"))]
+struct NoRuntime;
+
+#[cfg(not(feature = "onnx"))]
+impl Detector for NoRuntime {
+    fn detect_rgb(&mut self, _: &[u8], _: usize, _: &Settings) -> Result<Vec<Rect>> {
+        Err(crate::error(
+            "This build can't run neural networks, it needs the `onnx` feature".to_owned(),
+        ))
+    }
+}
@@ -0,0 +1,341 @@
+//! Finding LEDs in camera frames: thresholding a frame by color or brightness, then turning the
+//! blobs in the mask into one rect per LED.
+//!
+//! Frames go in as packed RGB24 or planar YUV 4:2:0, and detections come out as rects centered
+//! on each blob's centroid. The threading, the stream and what to do with the detections are up
+//! to the caller.
+//!
+//! The detection itself runs on OpenCV, or with the `opencv` feature off and `pure` on, on the
+//! imageproc backend in `pure`, which takes the same settings but cuts a few corners.
+
+#[cfg(not(any(feature = "opencv", feature = "pure")))]
+compile_error!("led-detect needs a detection backend, turn on the `opencv` or the `pure` feature");
+
+mod blobs;
+mod detector;
+#[cfg(feature = "onnx")]
+mod neural;
+pub mod preprocess;
+#[cfg(not(feature = "opencv"))]
+mod pure;
+mod simd;
+pub mod synthetic;
+pub mod template;
+mod threshold;
+mod yuv;
+
+pub use detector::{Blobs, Detector, DetectorKind, Difference, TemplateMatch};
+pub use emath::{Pos2, Rect, Vec2};
+#[cfg(feature = "onnx")]
+pub use neural::Neural;
+#[cfg(feature = "opencv")]
+use opencv::{core::Mat, prelude::*};
+#[cfg(feature = "opencv")]
+pub use opencv::{Error, Result};
+#[cfg(not(feature = "opencv"))]
+pub use pure::{Error, Result};
+pub use yuv::Yuv420;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DetectionMode {
+    /// Pixels inside the HSV bounds, for picking out one color.
+    Hsv,
+    /// Pixels brighter than a threshold, for scanning in a dark room.
+    Brightness,
+    /// Pixels within some CIE76 delta-E of a target color. Copes better with red, where hue wraps
+    /// around, and with white balance shifts.
+    Lab,
+}
+
+impl DetectionMode {
+    pub const ALL: [Self; 3] = [Self::Hsv, Self::Brightness, Self::Lab];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Hsv => "HSV range",
+            Self::Brightness => "Brightness",
+            Self::Lab => "Lab color",
+        }
+    }
+}
+
+/// How the brightness channel is thresholded: value in `Hsv` mode, luma in `Brightness` mode.
+/// `Lab` mode always uses its tolerance.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ThresholdMethod {
+    /// The V bounds, or the brightness threshold.
+    Fixed,
+    /// A single threshold picked per frame from the histogram.
+    Otsu,
+    /// Brighter than the surrounding block by some offset, for uneven lighting.
+    Adaptive,
+}
+
+impl ThresholdMethod {
+    pub const ALL: [Self; 3] = [Self::Fixed, Self::Otsu, Self::Adaptive];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fixed => "Fixed",
+            Self::Otsu => "Otsu",
+            Self::Adaptive => "Adaptive",
+        }
+    }
+}
+
+/// Blur applied before thresholding, so per-pixel noise doesn't make blob edges and centroids
+/// jitter from frame to frame.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Denoise {
+    Off,
+    Gaussian,
+    /// Slower, but keeps the edges of the LEDs sharp.
+    Median,
+}
+
+impl Denoise {
+    pub const ALL: [Self; 3] = [Self::Off, Self::Gaussian, Self::Median];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Gaussian => "Gaussian",
+            Self::Median => "Median",
+        }
+    }
+}
+
+/// Which contours of the mask become blobs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Retrieval {
+    /// Only the outlines, ignoring holes and anything inside them.
+    External,
+    /// Every contour, holes included, for LEDs that come out as rings behind a diffuser. Best
+    /// with a merge radius, so a ring and its hole count as one.
+    List,
+}
+
+impl Retrieval {
+    pub const ALL: [Self; 2] = [Self::External, Self::List];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::External => "Outer only",
+            Self::List => "All",
+        }
+    }
+}
+
+/// How the points of a contour are stored, which changes its area and centroid a little.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Approximation {
+    /// Every point along the edge.
+    None,
+    /// Straight runs down to their ends.
+    Simple,
+    /// The Teh-Chin chain approximation, smoothing out jagged edges.
+    TehChin,
+}
+
+impl Approximation {
+    pub const ALL: [Self; 3] = [Self::None, Self::Simple, Self::TehChin];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Simple => "Simple",
+            Self::TehChin => "Teh-Chin",
+        }
+    }
+}
+
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+// Settings saved before a field existed get its default
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct Settings {
+    /// What the live detection runs, see `Detector`. Scans always find blobs.
+    pub detector: DetectorKind,
+    pub mode: DetectionMode,
+    pub lower_h: f64,
+    pub lower_s: f64,
+    pub lower_v: f64,
+    pub upper_h: f64,
+    pub upper_s: f64,
+    pub upper_v: f64,
+    /// Minimum luma in `Brightness` mode.
+    pub brightness: f64,
+    /// The LED color as the camera sees it, for `Lab` mode.
+    pub lab_target: [u8; 3],
+    /// Largest delta-E from `lab_target` that still counts, for `Lab` mode.
+    pub lab_tolerance: f64,
+    pub method: ThresholdMethod,
+    /// Size of the neighbourhood for `Adaptive`, in pixels. Has to be odd.
+    pub adaptive_block: i32,
+    /// How much brighter than its neighbourhood a pixel has to be for `Adaptive`. Lit LEDs stand
+    /// well clear of it, the edges of anything else bright in the scene don't.
+    pub adaptive_offset: f64,
+    /// Blobs closer together than this are taken to be one LED, in pixels. 0 turns merging off.
+    pub merge_radius: f32,
+    /// Area of a single LED's blob in pixels. Blobs well over this are split up, since they're
+    /// probably neighbouring LEDs blooming together. 0 turns splitting off.
+    pub blob_area: f32,
+    /// Multipliers for red, green and blue before thresholding, see `preprocess`.
+    pub gains: [f32; 3],
+    /// Above 1 brightens the darker tones, pulling dim LEDs up towards the threshold.
+    pub gamma: f32,
+    /// Stretch around mid gray, above 1 for more contrast.
+    pub contrast: f32,
+    pub denoise: Denoise,
+    /// Size of the blur in pixels, made odd.
+    pub denoise_kernel: i32,
+    /// Contours smaller than this many pixels are dropped, 0 to keep them all.
+    pub min_area: f32,
+    /// Contours bigger than this many pixels are dropped, 0 for no limit.
+    pub max_area: f32,
+    pub retrieval: Retrieval,
+    pub approximation: Approximation,
+    /// What a lit LED looks like, for `DetectorKind::Template`.
+    pub template: Option<template::Template>,
+    /// How well the template has to match, as a normalized correlation up to 1.
+    pub template_score: f32,
+    /// The ONNX model `DetectorKind::Neural` runs, see `neural`.
+    pub model: Option<std::path::PathBuf>,
+    /// Lowest peak in the model's score map that counts as an LED.
+    pub model_score: f32,
+}
+
+impl Settings {
+    /// Settings for green LEDs, as good a starting point as any.
+    pub const DEFAULT: Self = Self {
+        detector: DetectorKind::Blobs,
+        mode: DetectionMode::Hsv,
+        lower_h: 40.0,
+        lower_s: 100.0,
+        lower_v: 100.0,
+        upper_h: 70.0,
+        upper_s: 255.0,
+        upper_v: 255.0,
+        brightness: 200.0,
+        lab_target: [0, 255, 0],
+        lab_tolerance: 30.0,
+        method: ThresholdMethod::Fixed,
+        adaptive_block: 51,
+        adaptive_offset: 60.0,
+        merge_radius: 0.0,
+        blob_area: 0.0,
+        gains: [1.0; 3],
+        gamma: 1.0,
+        contrast: 1.0,
+        denoise: Denoise::Off,
+        denoise_kernel: 5,
+        min_area: 0.0,
+        max_area: 0.0,
+        retrieval: Retrieval::External,
+        approximation: Approximation::Simple,
+        template: None,
+        template_score: 0.8,
+        model: None,
+        model_score: 0.5,
+    };
+}
+
+impl Settings {
+    /// Sets the color bounds to white blobs, for white LEDs and the white channel of RGBW
+    /// strips: bright and barely saturated in HSV, and white in Lab. Colored light around them,
+    /// like a status LED, stays out.
+    pub fn target_white(&mut self) {
+        if self.mode == DetectionMode::Brightness {
+            self.mode = DetectionMode::Hsv;
+        }
+        (self.lower_h, self.upper_h) = (0.0, 180.0);
+        (self.lower_s, self.upper_s) = (0.0, 60.0);
+        (self.lower_v, self.upper_v) = (200.0, 255.0);
+        self.lab_target = [255, 255, 255];
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Detects the LEDs in a packed RGB24 frame `width` pixels wide.
+pub fn detect_rgb(rgb: &[u8], width: usize, settings: &Settings) -> Result<Vec<Rect>> {
+    #[cfg(feature = "opencv")]
+    return blobs::find(&threshold::rgb(rgb, width, settings)?, settings);
+    #[cfg(not(feature = "opencv"))]
+    return Ok(pure::find(&pure::rgb(rgb, width, settings)?, settings));
+}
+
+/// Detects the LEDs in a YUV frame, skipping the conversion to RGB where the mode allows.
+pub fn detect_yuv(yuv: &Yuv420, settings: &Settings) -> Result<Vec<Rect>> {
+    #[cfg(feature = "opencv")]
+    return blobs::find(&threshold::yuv(yuv, settings)?, settings);
+    #[cfg(not(feature = "opencv"))]
+    return Ok(pure::find(&pure::yuv(yuv, settings)?, settings));
+}
+
+/// `message` as the backend's error, for what fails outside of it.
+fn error(message: String) -> Error {
+    #[cfg(feature = "opencv")]
+    return Error::new(opencv::core::StsError, message);
+    #[cfg(not(feature = "opencv"))]
+    return Error::from(message);
+}
+
+/// Which pixels of a frame matched the settings, before they're turned into blobs.
+pub struct Mask {
+    pub width: usize,
+    pub height: usize,
+    /// 255 where the pixel matched and 0 elsewhere, row by row.
+    pub data: Vec<u8>,
+}
+
+#[cfg(feature = "opencv")]
+impl Mask {
+    fn from_mat(mat: &Mat) -> Result<Self> {
+        Ok(Self {
+            width: mat.cols() as usize,
+            height: mat.rows() as usize,
+            data: mat.data_bytes()?.to_vec(),
+        })
+    }
+}
+
+/// The mask `detect_rgb` finds the blobs in, for showing what detection sees.
+pub fn mask_rgb(rgb: &[u8], width: usize, settings: &Settings) -> Result<Mask> {
+    #[cfg(feature = "opencv")]
+    return Mask::from_mat(&threshold::rgb(rgb, width, settings)?);
+    #[cfg(not(feature = "opencv"))]
+    return pure::rgb(rgb, width, settings);
+}
+
+/// The mask `detect_yuv` finds the blobs in.
+pub fn mask_yuv(yuv: &Yuv420, settings: &Settings) -> Result<Mask> {
+    #[cfg(feature = "opencv")]
+    return Mask::from_mat(&threshold::yuv(yuv, settings)?);
+    #[cfg(not(feature = "opencv"))]
+    return pure::yuv(yuv, settings);
+}
+
+/// Takes `dark`, a frame with every LED off and its width, off `frame`, unless the two differ in
+/// size.
+pub fn subtract_dark(
+    mut frame: Vec<u8>,
+    width: usize,
+    (dark, dark_width): &(Vec<u8>, usize),
+) -> Vec<u8> {
+    if frame.len() == dark.len() && width == *dark_width {
+        for (pixel, dark) in frame.iter_mut().zip(dark) {
+            *pixel = pixel.saturating_sub(*dark);
+        }
+    }
+    frame
+}
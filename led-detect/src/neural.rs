@@ -0,0 +1,220 @@
+//! Detection by a small neural network, for scenes thresholding can't cope with: busy
+//! backgrounds, daylight, LEDs behind heavy diffusion. The model is an ONNX file run with tract,
+//! picked with `Settings::model`.
+//!
+//! The model takes the frame as a `1×3×H×W` tensor of RGB from 0 to 1 and gives back a score
+//! map, `1×1×h×w`, that peaks where the LEDs are. It has to be fully convolutional, as it runs
+//! at whatever size the frames are, and the map can be smaller than the frame by a whole stride.
+//! Every patch of the map at or above `Settings::model_score` is an LED, placed at the patch's
+//! score weighted centroid.
+
+use std::path::{Path, PathBuf};
+
+use emath::{Pos2, Rect, Vec2};
+use tract_onnx::prelude::*;
+
+use crate::{Detector, Result, Settings};
+
+type Model = SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
+
+/// Runs `Settings::model`, loading it again when the path or the frame size changes.
+#[derive(Default)]
+pub struct Neural {
+    loaded: Option<(PathBuf, [usize; 2], Model)>,
+}
+
+impl Detector for Neural {
+    fn detect_rgb(&mut self, rgb: &[u8], width: usize, settings: &Settings) -> Result<Vec<Rect>> {
+        let Some(path) = &settings.model else {
+            return Ok(Vec::new());
+        };
+        let height = rgb.len() / 3 / width.max(1);
+        if height == 0 {
+            return Ok(Vec::new());
+        }
+
+        let size = [width, height];
+        if !matches!(&self.loaded, Some((loaded, at, _)) if loaded == path && *at == size) {
+            self.loaded = None;
+            let model = load(path, size).map_err(|e| {
+                crate::error(format!("Failed to load the model {}: {e:#}", path.display()))
+            })?;
+            self.loaded = Some((path.clone(), size, model));
+        }
+        let (.., model) = self.loaded.as_ref().expect("Loaded above");
+
+        let input = tract_ndarray::Array4::from_shape_fn((1, 3, height, width), |(_, c, y, x)| {
+            rgb[(y * width + x) * 3 + c] as f32 / 255.0
+        });
+        let outputs = model
+            .run(tvec!(input.into_tensor().into()))
+            .map_err(|e| crate::error(format!("The model failed: {e:#}")))?;
+        let map = outputs[0]
+            .to_array_view::<f32>()
+            .map_err(|e| crate::error(format!("The model's output isn't a score map: {e:#}")))?;
+
+        let shape = map.shape();
+        let (map_width, map_height) = match shape {
+            [.., h, w] if *h > 0 && *w > 0 => (*w, *h),
+            _ => return Err(crate::error(format!("The model's output is {shape:?}, not a map"))),
+        };
+        // The first channel, if there are more
+        let scores = map
+            .iter()
+            .take(map_width * map_height)
+            .copied()
+            .collect::<Vec<_>>();
+        let stride = Vec2::new(width as f32 / map_width as f32, height as f32 / map_height as f32);
+
+        Ok(peaks(&scores, map_width, settings.model_score)
+            .into_iter()
+            // Cell centers in frame pixels, whose centers are on whole coordinates
+            .map(|cell| {
+                let center = (cell.to_vec2() + Vec2::splat(0.5)) * stride - Vec2::splat(0.5);
+                Rect::from_center_size(center.to_pos2(), stride * 3.0)
+            })
+            .collect())
+    }
+}
+
+fn load(path: &Path, [width, height]: [usize; 2]) -> TractResult<Model> {
+    tract_onnx::onnx()
+        .model_for_path(path)?
+        .with_input_fact(0, f32::fact([1, 3, height, width]).into())?
+        .into_optimized()?
+        .into_runnable()
+}
+
+/// The patches of `scores`, a map `width` cells across, of at least `min_score`, each at its
+/// score weighted centroid in cells. Models tend to saturate on an LED, so its peak can be a
+/// plateau a few cells across.
+fn peaks(scores: &[f32], width: usize, min_score: f32) -> Vec<Pos2> {
+    let height = scores.len() / width;
+    let mut seen = vec![false; scores.len()];
+    let mut stack = Vec::new();
+    let mut found = Vec::new();
+    for start in 0..scores.len() {
+        if seen[start] || scores[start] < min_score {
+            continue;
+        }
+
+        seen[start] = true;
+        stack.push(start);
+        let (mut sum, mut weighted) = (0.0, Vec2::ZERO);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            sum += scores[i];
+            weighted += Vec2::new(x as f32, y as f32) * scores[i];
+            for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let neighbour = ny * width + nx;
+                    if !seen[neighbour] && scores[neighbour] >= min_score {
+                        seen[neighbour] = true;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+        found.push((weighted / sum).to_pos2());
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+    use tract_onnx::pb::{
+        self, tensor_shape_proto::dimension::Value as Dimension, type_proto::Value as Type,
+    };
+
+    use super::*;
+    use crate::synthetic::{evaluate, Scene};
+
+    /// A model whose score is the brightest channel, where LEDs in a dim scene peak.
+    fn brightest_channel() -> Vec<u8> {
+        let shape = |channels| pb::TensorShapeProto {
+            dim: [Dimension::DimValue(1), Dimension::DimValue(channels)]
+                .into_iter()
+                .chain(["height", "width"].map(|name| Dimension::DimParam(name.to_owned())))
+                .map(|value| pb::tensor_shape_proto::Dimension {
+                    value: Some(value),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+        let value = |name: &str, channels| pb::ValueInfoProto {
+            name: name.to_owned(),
+            r#type: Some(pb::TypeProto {
+                value: Some(Type::TensorType(pb::type_proto::Tensor {
+                    elem_type: pb::tensor_proto::DataType::Float as i32,
+                    shape: Some(shape(channels)),
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        pb::ModelProto {
+            ir_version: 7,
+            opset_import: vec![pb::OperatorSetIdProto { version: 13, ..Default::default() }],
+            graph: Some(pb::GraphProto {
+                node: vec![pb::NodeProto {
+                    input: vec!["frame".to_owned()],
+                    output: vec!["scores".to_owned()],
+                    op_type: "ReduceMax".to_owned(),
+                    attribute: vec![pb::AttributeProto {
+                        name: "axes".to_owned(),
+                        ints: vec![1],
+                        r#type: pb::attribute_proto::AttributeType::Ints as i32,
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                input: vec![value("frame", 3)],
+                output: vec![value("scores", 1)],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    #[test]
+    fn finds_the_peaks() {
+        let path = std::env::temp_dir().join(format!("brightest-{}.onnx", std::process::id()));
+        std::fs::write(&path, brightest_channel()).unwrap();
+        let settings = Settings {
+            model: Some(path.clone()),
+            model_score: 0.8,
+            ..Settings::DEFAULT
+        };
+
+        let mut detector = Neural::default();
+        for seed in 0..3 {
+            let scene = Scene::random(320, 240, 10, seed);
+            let detections = detector
+                .detect_rgb(&scene.render(), scene.width, &settings)
+                .unwrap();
+            let accuracy = evaluate(&scene.leds, &detections, 3.0);
+
+            assert_eq!(accuracy.found, scene.leds.len(), "seed {seed}");
+            assert_eq!(accuracy.spurious, 0, "seed {seed}");
+            assert!(accuracy.mean_error < 0.3, "seed {seed}: {:.2} px", accuracy.mean_error);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn patches_count_once() {
+        // A plateau of two equal cells, and a slope of two more
+        let scores = [
+            0.0, 0.9, 0.9, 0.0, 0.0, //
+            0.0, 0.0, 0.0, 0.0, 0.0, //
+            0.0, 0.0, 0.0, 0.6, 0.9, //
+        ];
+        let found = peaks(&scores, 5, 0.5);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].distance(Pos2::new(1.5, 0.0)) < 1e-4, "{:?}", found[0]);
+        assert!(found[1].distance(Pos2::new(3.6, 2.0)) < 1e-4, "{:?}", found[1]);
+    }
+}
@@ -0,0 +1,89 @@
+//! Adjusting frames before they're thresholded, for cameras whose settings can't be changed or
+//! are wrong in a way that's locked in.
+//!
+//! White balance multiplies each channel by its gain, which puts an LED's hue back where the HSV
+//! range expects it when the camera's balance is off. Then gamma and contrast go through a lookup
+//! table, for dim LEDs on streams compressed too hard to be brightened at the camera. Last, an
+//! optional blur takes out the noise of low light.
+
+use emath::Rect;
+#[cfg(feature = "opencv")]
+use opencv::{
+    core::{lut, multiply, Mat, Scalar, Size, BORDER_DEFAULT},
+    imgproc::{gaussian_blur, median_blur},
+};
+
+use crate::{Denoise, Settings};
+
+/// Whether `settings` change the frame at all, so it can skip straight to thresholding.
+pub fn active(settings: &Settings) -> bool {
+    settings.gains != [1.0; 3]
+        || settings.gamma != 1.0
+        || settings.contrast != 1.0
+        || settings.denoise != Denoise::Off
+}
+
+/// `image`, an 8-bit RGB frame, adjusted by `settings`.
+#[cfg(feature = "opencv")]
+pub fn rgb(mut image: Mat, settings: &Settings) -> opencv::Result<Mat> {
+    if !active(settings) {
+        return Ok(image);
+    }
+
+    if settings.gains != [1.0; 3] {
+        let [r, g, b] = settings.gains.map(f64::from);
+        let mut balanced = Mat::default();
+        multiply(&image, &Scalar::new(r, g, b, 0.0), &mut balanced, 1.0, -1)?;
+        image = balanced;
+    }
+
+    if settings.gamma != 1.0 || settings.contrast != 1.0 {
+        let table = tone_curve(settings.gamma, settings.contrast);
+        let mut adjusted = Mat::default();
+        lut(&image, &Mat::from_slice(&table)?, &mut adjusted)?;
+        image = adjusted;
+    }
+
+    let kernel = settings.denoise_kernel.max(1) | 1;
+    let mut blurred = Mat::default();
+    match settings.denoise {
+        Denoise::Off => return Ok(image),
+        Denoise::Gaussian => {
+            let size = Size::new(kernel, kernel);
+            gaussian_blur(&image, &mut blurred, size, 0.0, 0.0, BORDER_DEFAULT)?;
+        }
+        Denoise::Median => median_blur(&image, &mut blurred, kernel)?,
+    }
+    Ok(blurred)
+}
+
+/// What each 8-bit value becomes with `gamma` and then `contrast` applied.
+pub fn tone_curve(gamma: f32, contrast: f32) -> [u8; 256] {
+    let mut table = [0; 256];
+    for (value, out) in table.iter_mut().enumerate() {
+        let corrected = (value as f32 / 255.0).powf(1.0 / gamma.max(0.01));
+        let stretched = (corrected - 0.5) * contrast + 0.5;
+        *out = (stretched * 255.0).round().clamp(0.0, 255.0) as u8;
+    }
+    table
+}
+
+/// Gains that make `region` of a packed RGB24 frame `width` pixels wide gray, with green left
+/// at 1. `None` if the region is empty or has a channel with nothing in it.
+pub fn white_balance(rgb: &[u8], width: usize, region: Rect) -> Option<[f32; 3]> {
+    let height = rgb.len() / width / 3;
+    let (left, right) = (region.min.x.max(0.0) as usize, (region.max.x as usize).min(width));
+    let (top, bottom) = (region.min.y.max(0.0) as usize, (region.max.y as usize).min(height));
+
+    let mut sum = [0.0f64; 3];
+    for row in top..bottom {
+        for pixel in rgb[(row * width + left) * 3..(row * width + right) * 3].chunks(3) {
+            for (sum, &c) in sum.iter_mut().zip(pixel) {
+                *sum += c as f64;
+            }
+        }
+    }
+
+    let [r, g, b] = sum;
+    (r > 0.0 && g > 0.0 && b > 0.0).then(|| [(g / r) as f32, 1.0, (g / b) as f32])
+}
@@ -0,0 +1,435 @@
+//! Detection without OpenCV, on `image` and `imageproc`, for platforms where OpenCV is a pain to
+//! build. This stands in for `threshold`, `preprocess::rgb` and the contour finding in `blobs`
+//! when the `opencv` feature is off and `pure` is on.
+//!
+//! Fixed and Otsu thresholds match the OpenCV backend's masks, and so does the HSV conversion,
+//! see `simd`. Adaptive thresholds and the Gaussian denoise blur with the sigma OpenCV picks for
+//! the block size, though imageproc sizes the kernel by the sigma rather than the block, so the
+//! edges of the mask can be a pixel off. Blobs are the 8-connected regions of the mask instead of its
+//! contours, so `retrieval` and `approximation` do nothing and areas count pixels, a bit more than
+//! a contour encloses. Blobs well over `blob_area` are split by a watershed like OpenCV's.
+
+use std::{borrow::Cow, cmp::Ordering, collections::BinaryHeap, convert::Infallible, fmt};
+
+use emath::{Pos2, Rect};
+use image::{GrayImage, Luma, RgbImage};
+use imageproc::{
+    contrast::otsu_level,
+    distance_transform::euclidean_squared_distance_transform,
+    filter::{gaussian_blur_f32, median_filter},
+    region_labelling::{connected_components, Connectivity},
+};
+
+use crate::{
+    blobs::{self, Blob},
+    preprocess,
+    simd::HsvRange,
+    template::luma,
+    threshold, Denoise, DetectionMode, Mask, Settings, ThresholdMethod, Yuv420,
+};
+
+/// Detection failing without OpenCV: a frame that doesn't fit its width, or a neural network
+/// that doesn't run.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Self(message)
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Masks the pixels of an RGB frame that match the current detection mode.
+pub fn rgb(image_data: &[u8], width: usize, settings: &Settings) -> Result<Mask> {
+    if width == 0 || !image_data.len().is_multiple_of(width * 3) {
+        return Err(Error(format!(
+            "A frame of {} bytes isn't whole rows of {width} pixels",
+            image_data.len()
+        )));
+    }
+    let height = image_data.len() / width / 3;
+    let image = adjust(image_data, width, height, settings);
+    let pixels = image.chunks_exact(3).map(|p| [p[0], p[1], p[2]]);
+
+    let data = match settings.mode {
+        DetectionMode::Hsv if settings.method == ThresholdMethod::Fixed => {
+            HsvRange::new(settings).mask_rgb(&image)
+        }
+        DetectionMode::Hsv => {
+            // Hue and saturation as usual, value relative to the rest of the frame
+            let any_value = Settings {
+                lower_v: 0.0,
+                upper_v: 255.0,
+                ..settings.clone()
+            };
+            let color = HsvRange::new(&any_value).mask_rgb(&image);
+            let value = pixels.map(|[r, g, b]| r.max(g).max(b)).collect::<Vec<_>>();
+            let bright = brightness(&value, width, settings);
+            color
+                .iter()
+                .zip(bright)
+                .map(|(color, bright)| color & bright)
+                .collect()
+        }
+        DetectionMode::Brightness => {
+            let gray = pixels.map(luma).collect::<Vec<_>>();
+            brightness(&gray, width, settings)
+        }
+        DetectionMode::Lab => {
+            let [l, a, b] = threshold::srgb_to_lab(settings.lab_target);
+            let max = settings.lab_tolerance * settings.lab_tolerance;
+            pixels
+                .map(|rgb| {
+                    let [pl, pa, pb] = threshold::srgb_to_lab(rgb);
+                    matched((pl - l).powi(2) + (pa - a).powi(2) + (pb - b).powi(2) <= max)
+                })
+                .collect()
+        }
+    };
+
+    Ok(Mask { width, height, data })
+}
+
+/// On the YUV path the luma stands in for V when thresholding isn't fixed.
+pub fn yuv(yuv: &Yuv420, settings: &Settings) -> Result<Mask> {
+    let to_mask = |data| Mask {
+        width: yuv.width,
+        height: yuv.height,
+        data,
+    };
+
+    match (settings.mode, settings.method) {
+        // Preprocessing works on RGB, and Lab needs the whole color conversion anyway
+        _ if preprocess::active(settings) => rgb(&yuv.to_rgb(), yuv.width, settings),
+        (DetectionMode::Lab, _) => rgb(&yuv.to_rgb(), yuv.width, settings),
+
+        (DetectionMode::Hsv, ThresholdMethod::Fixed) => Ok(to_mask(yuv.threshold_hsv(settings))),
+        (DetectionMode::Hsv, _) => {
+            let any_value = Settings {
+                lower_v: 0.0,
+                upper_v: 255.0,
+                ..settings.clone()
+            };
+            let color = yuv.threshold_hsv(&any_value);
+            let bright = brightness(&yuv.y, yuv.width, settings);
+            Ok(to_mask(
+                color
+                    .iter()
+                    .zip(bright)
+                    .map(|(color, bright)| color & bright)
+                    .collect(),
+            ))
+        }
+
+        (DetectionMode::Brightness, ThresholdMethod::Fixed) => {
+            Ok(to_mask(yuv.threshold_luma(settings.brightness)))
+        }
+        (DetectionMode::Brightness, _) => Ok(to_mask(brightness(&yuv.y, yuv.width, settings))),
+    }
+}
+
+/// Finds the blobs in a mask, as rects centered on their centroid.
+pub fn find(mask: &Mask, settings: &Settings) -> Vec<Rect> {
+    let labels =
+        connected_components(&gray(&mask.data, mask.width), Connectivity::Eight, Luma([0]));
+
+    let mut found = Vec::new();
+    for blob in regions(&labels) {
+        let area = blob.blob.area;
+        if area < settings.min_area || (settings.max_area > 0.0 && area > settings.max_area) {
+            continue;
+        }
+        match split(&labels, &blob, settings.blob_area) {
+            Some(pieces) => found.extend(pieces),
+            None => found.push(blob.blob),
+        }
+    }
+
+    blobs::merge_close(found, settings.merge_radius)
+        .iter()
+        .map(Blob::rect)
+        .collect()
+}
+
+/// A blob with the label it has in the labelled mask.
+struct Region {
+    label: u32,
+    blob: Blob,
+}
+
+/// The blobs of every label but the background's, in the order of their labels.
+fn regions(labels: &image::ImageBuffer<Luma<u32>, Vec<u32>>) -> Vec<Region> {
+    // Pixel count, sums of x and y, top left and bottom right, per label
+    let mut moments = Vec::<(usize, f64, f64, [u32; 2], [u32; 2])>::new();
+    for (x, y, &Luma([label])) in labels.enumerate_pixels() {
+        if label == 0 {
+            continue;
+        }
+        let index = label as usize - 1;
+        if index >= moments.len() {
+            moments.resize(index + 1, (0, 0.0, 0.0, [u32::MAX; 2], [0; 2]));
+        }
+        let (area, sum_x, sum_y, min, max) = &mut moments[index];
+        *area += 1;
+        *sum_x += x as f64;
+        *sum_y += y as f64;
+        *min = [min[0].min(x), min[1].min(y)];
+        *max = [max[0].max(x), max[1].max(y)];
+    }
+
+    moments
+        .into_iter()
+        .zip(1..)
+        .filter(|((area, ..), _)| *area > 0)
+        .map(|((area, sum_x, sum_y, min, max), label)| Region {
+            label,
+            blob: Blob {
+                centroid: Pos2::new((sum_x / area as f64) as f32, (sum_y / area as f64) as f32),
+                bounds: Rect::from_min_max(
+                    Pos2::new(min[0] as f32, min[1] as f32),
+                    Pos2::new(max[0] as f32 + 1.0, max[1] as f32 + 1.0),
+                ),
+                area: area as f32,
+            },
+        })
+        .collect()
+}
+
+/// Splits a blob that's probably several LEDs blooming into each other, like `blobs::split`:
+/// each LED's core is where the distance to the edge of the blob peaks, and a watershed over that
+/// distance divides the blob between the cores. `None` when it looks like one LED after all.
+fn split(
+    labels: &image::ImageBuffer<Luma<u32>, Vec<u32>>,
+    region: &Region,
+    expected_area: f32,
+) -> Option<Vec<Blob>> {
+    if expected_area <= 0.0 || region.blob.area < expected_area * 1.5 {
+        return None;
+    }
+
+    // Just this blob, with a pixel of border so the distance transform sees its edges. The
+    // transform measures the distance to the nearest set pixel, so the outside is what's set
+    let bounds = region.blob.bounds;
+    let (left, top) = (bounds.min.x as u32, bounds.min.y as u32);
+    let (width, height) = (bounds.width() as u32 + 2, bounds.height() as u32 + 2);
+    let inside = |x: u32, y: u32| {
+        (1..width - 1).contains(&x)
+            && (1..height - 1).contains(&y)
+            && labels.get_pixel(left + x - 1, top + y - 1)[0] == region.label
+    };
+    let outside =
+        GrayImage::from_fn(width, height, |x, y| Luma([if inside(x, y) { 0 } else { 255 }]));
+    let distance = euclidean_squared_distance_transform(&outside)
+        .into_raw()
+        .into_iter()
+        .map(|squared| squared.sqrt() as f32)
+        .collect::<Vec<_>>();
+    let max = distance.iter().copied().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return None;
+    }
+
+    // Cores are what's left well inside a single LED's radius, necks between LEDs are narrower
+    let radius = (expected_area / std::f32::consts::PI).sqrt();
+    let floor = (radius * 0.6).min(max * 0.7);
+    let cores = GrayImage::from_fn(width, height, |x, y| {
+        Luma([if distance[(y * width + x) as usize] >= floor {
+            255
+        } else {
+            0
+        }])
+    });
+    let cores = connected_components(&cores, Connectivity::Eight, Luma([0]));
+    let count = cores.iter().copied().max().unwrap_or(0);
+    if count < 2 {
+        return None;
+    }
+
+    // Flood from the cores downhill, always taking the deepest pixel next to one next
+    let mut owner = cores.into_raw();
+    let mut queue = BinaryHeap::new();
+    for (i, &label) in owner.iter().enumerate() {
+        if label != 0 {
+            queue.push(Deepest(distance[i], i as u32));
+        }
+    }
+    while let Some(Deepest(_, i)) = queue.pop() {
+        let (x, y) = (i % width, i / width);
+        for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+            let neighbour = (ny * width + nx) as usize;
+            if inside(nx, ny) && owner[neighbour] == 0 {
+                owner[neighbour] = owner[i as usize];
+                queue.push(Deepest(distance[neighbour], neighbour as u32));
+            }
+        }
+    }
+
+    let owner = image::ImageBuffer::from_raw(width, height, owner)?;
+    let offset = emath::vec2(left as f32 - 1.0, top as f32 - 1.0);
+    let pieces = regions(&owner)
+        .into_iter()
+        .map(|piece| Blob {
+            centroid: piece.blob.centroid + offset,
+            bounds: piece.blob.bounds.translate(offset),
+            area: piece.blob.area,
+        })
+        .collect::<Vec<_>>();
+    (pieces.len() > 1).then_some(pieces)
+}
+
+/// A pixel of the watershed, ordered by its distance from the edge of the blob.
+struct Deepest(f32, u32);
+
+impl PartialEq for Deepest {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Deepest {}
+
+impl PartialOrd for Deepest {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Deepest {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(other.1.cmp(&self.1))
+    }
+}
+
+/// A single 8-bit channel `width` pixels wide, as an image.
+fn gray(channel: &[u8], width: usize) -> GrayImage {
+    let height = channel.len() / width.max(1);
+    GrayImage::from_raw(width as u32, height as u32, channel.to_vec()).expect("Whole rows")
+}
+
+/// The sigma OpenCV blurs with for a kernel `size` pixels across, when it isn't given one.
+fn sigma(size: usize) -> f32 {
+    0.3 * ((size as f32 - 1.0) * 0.5 - 1.0) + 0.8
+}
+
+fn matched(matched: bool) -> u8 {
+    if matched {
+        255
+    } else {
+        0
+    }
+}
+
+/// Thresholds a single 8-bit channel `width` pixels wide with the configured method.
+fn brightness(channel: &[u8], width: usize, settings: &Settings) -> Vec<u8> {
+    match settings.method {
+        ThresholdMethod::Fixed => channel
+            .iter()
+            .map(|&value| matched(value as f64 > settings.brightness))
+            .collect(),
+        ThresholdMethod::Otsu => {
+            let Ok(threshold) = threshold::otsu(channel, |pixels| {
+                Ok::<_, Infallible>(otsu_level(&gray(pixels, pixels.len())))
+            });
+            channel
+                .iter()
+                .map(|&value| matched(value > threshold))
+                .collect()
+        }
+        ThresholdMethod::Adaptive => {
+            // Against the Gaussian weighted mean of the block, like ADAPTIVE_THRESH_GAUSSIAN_C
+            let block = (settings.adaptive_block.max(3) | 1) as usize;
+            gaussian_blur_f32(&gray(channel, width), sigma(block))
+                .into_raw()
+                .into_iter()
+                .zip(channel)
+                .map(|(mean, &value)| {
+                    matched(value as f64 > mean as f64 + settings.adaptive_offset)
+                })
+                .collect()
+        }
+    }
+}
+
+/// `image`, an 8-bit RGB frame `width` by `height`, adjusted by `settings` like
+/// `preprocess::rgb` does.
+fn adjust<'a>(image: &'a [u8], width: usize, height: usize, settings: &Settings) -> Cow<'a, [u8]> {
+    if !preprocess::active(settings) {
+        return Cow::Borrowed(image);
+    }
+
+    let table = preprocess::tone_curve(settings.gamma, settings.contrast);
+    let adjusted = image
+        .chunks_exact(3)
+        .flat_map(|pixel| {
+            let mut pixel = [pixel[0], pixel[1], pixel[2]];
+            for (c, gain) in pixel.iter_mut().zip(settings.gains) {
+                *c = table[(*c as f32 * gain).round().clamp(0.0, 255.0) as usize];
+            }
+            pixel
+        })
+        .collect::<Vec<_>>();
+
+    let kernel = (settings.denoise_kernel.max(1) | 1) as usize;
+    if settings.denoise == Denoise::Off || kernel == 1 {
+        return Cow::Owned(adjusted);
+    }
+    let adjusted = RgbImage::from_raw(width as u32, height as u32, adjusted).expect("Whole rows");
+    let blurred = match settings.denoise {
+        Denoise::Off => adjusted,
+        Denoise::Gaussian => gaussian_blur_f32(&adjusted, sigma(kernel)),
+        Denoise::Median => median_filter(&adjusted, kernel as u32 / 2, kernel as u32 / 2),
+    };
+    Cow::Owned(blurred.into_raw())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two discs of `radius` 12 pixels apart, overlapping into one blob.
+    fn two_discs(radius: f32) -> Mask {
+        let (width, height) = (50, 40);
+        let centers = [Pos2::new(19.0, 20.0), Pos2::new(31.0, 20.0)];
+        let data = (0..width * height)
+            .map(|i| {
+                let pixel = Pos2::new((i % width) as f32, (i / width) as f32);
+                matched(
+                    centers
+                        .iter()
+                        .any(|center| center.distance(pixel) <= radius),
+                )
+            })
+            .collect();
+        Mask { width, height, data }
+    }
+
+    #[test]
+    fn splits_leds_blooming_together() {
+        let radius = 6.5;
+        let settings = Settings {
+            blob_area: std::f32::consts::PI * radius * radius,
+            merge_radius: 0.0,
+            ..Settings::DEFAULT
+        };
+
+        let mut found = find(&two_discs(radius), &settings);
+        found.sort_by(|a, b| a.center().x.total_cmp(&b.center().x));
+        assert_eq!(found.len(), 2);
+        for (rect, x) in found.iter().zip([19.0, 31.0]) {
+            assert!(rect.center().distance(Pos2::new(x, 20.0)) < 1.0, "{:?}", rect.center());
+        }
+
+        let whole = Settings { blob_area: 0.0, ..settings };
+        assert_eq!(find(&two_discs(radius), &whole).len(), 1);
+    }
+}
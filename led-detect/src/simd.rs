@@ -0,0 +1,562 @@
+//! The HSV range test four pixels at a time, for the thresholding that doesn't go through OpenCV:
+//! the YUV path and the pure Rust backend. It has to keep up with 1080p30 on a Raspberry Pi 4,
+//! where the pixel at a time version falls well short.
+//!
+//! The lanes are SSE2 on x86-64 and NEON on AArch64, which is what both guarantee without
+//! detecting anything at runtime. Anywhere else they're plain arrays, which the compiler
+//! vectorizes as well as it can.
+//!
+//! The conversion is OpenCV's 8-bit one to the bit, fixed point tables and all, so a pixel on the
+//! edge of the bounds is in or out the same with either backend.
+
+use std::ops::{Add, BitAnd, Div, Mul, Sub};
+
+#[cfg(target_arch = "aarch64")]
+use neon::F32x4;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+use scalar::F32x4;
+#[cfg(target_arch = "x86_64")]
+use sse2::F32x4;
+
+use crate::Settings;
+
+/// OpenCV's HSV conversion works in 4096ths.
+const SHIFT: f32 = 4096.0;
+
+/// What the HSV test needs of four lanes, so each kind can be checked against the others.
+trait Lanes:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<Output = Self> + Div<Output = Self>
+{
+    type Mask: MaskLanes;
+
+    fn new(lanes: [f32; 4]) -> Self;
+    fn splat(value: f32) -> Self;
+    #[cfg(test)]
+    fn to_array(self) -> [f32; 4];
+    fn max(self, other: Self) -> Self;
+    fn min(self, other: Self) -> Self;
+    /// Rounded towards zero.
+    fn trunc(self) -> Self;
+    /// `a` in the lanes where `mask` is set, `b` in the rest.
+    fn select(mask: Self::Mask, a: Self, b: Self) -> Self;
+    fn eq(self, other: Self) -> Self::Mask;
+    fn lt(self, other: Self) -> Self::Mask;
+    fn gt(self, other: Self) -> Self::Mask;
+    fn ge(self, other: Self) -> Self::Mask;
+    fn le(self, other: Self) -> Self::Mask;
+}
+
+trait MaskLanes: Copy + BitAnd<Output = Self> {
+    /// 255 for the lanes that are set, 0 for the rest.
+    fn to_bytes(self) -> [u8; 4];
+}
+
+/// The HSV bounds of the settings, on OpenCV's 8-bit scale like `yuv::hsv`.
+pub struct HsvRange {
+    lower: [f32; 3],
+    upper: [f32; 3],
+}
+
+impl HsvRange {
+    pub fn new(settings: &Settings) -> Self {
+        let bounds = |bounds: [f64; 3]| bounds.map(|bound| bound as f32);
+        Self {
+            lower: bounds([settings.lower_h, settings.lower_s, settings.lower_v]),
+            upper: bounds([settings.upper_h, settings.upper_s, settings.upper_v]),
+        }
+    }
+
+    /// 255 for each of the four pixels inside the bounds, 0 for the rest. Takes the red, green and
+    /// blue of the four, each from 0 to 255, with fractions dropped like the conversion to RGB24
+    /// drops them.
+    pub fn test(&self, rgb: [[f32; 4]; 3]) -> [u8; 4] {
+        self.test_lanes::<F32x4>(rgb)
+    }
+
+    fn test_lanes<L: Lanes>(&self, rgb: [[f32; 4]; 3]) -> [u8; 4] {
+        let inside = |channel: usize, value: L| {
+            value.ge(L::splat(self.lower[channel])) & value.le(L::splat(self.upper[channel]))
+        };
+        let [h, s, v] = hsv::<L>(rgb);
+        (inside(0, h) & inside(1, s) & inside(2, v)).to_bytes()
+    }
+
+    /// The mask of packed RGB24 `rgb`.
+    #[cfg(not(feature = "opencv"))]
+    pub fn mask_rgb(&self, rgb: &[u8]) -> Vec<u8> {
+        let mut mask = Vec::with_capacity(rgb.len() / 3);
+        for pixels in rgb.chunks(12) {
+            let (mut r, mut g, mut b) = ([0.0; 4], [0.0; 4], [0.0; 4]);
+            for (i, pixel) in pixels.chunks_exact(3).enumerate() {
+                (r[i], g[i], b[i]) = (pixel[0] as f32, pixel[1] as f32, pixel[2] as f32);
+            }
+            mask.extend_from_slice(&self.test([r, g, b])[..pixels.len() / 3]);
+        }
+        mask
+    }
+}
+
+/// Hue from 0 to 180, saturation and value the way `cvtColor` with `COLOR_RGB2HSV` has them.
+///
+/// Everything stays a whole number below 2^24, which f32 holds exactly, so the fixed point
+/// arithmetic comes out the same as OpenCV's integer one. Shifting right rounds down, so the hue
+/// is shifted up to stay positive, rounded towards zero, then shifted back.
+fn hsv<L: Lanes>(rgb: [[f32; 4]; 3]) -> [L; 3] {
+    let [r, g, b] = rgb.map(|channel| L::new(channel).trunc());
+    let zero = L::splat(0.0);
+    let half = L::splat(SHIFT / 2.0);
+
+    let v = r.max(g).max(b);
+    let diff = v - r.min(g).min(b);
+
+    let s_table = round_even(L::splat(255.0 * SHIFT) / v);
+    let s = ((diff * s_table + half) / L::splat(SHIFT)).trunc();
+    let s = L::select(v.gt(zero), s, zero);
+
+    let from_r = g - b;
+    let from_g = b - r + diff * L::splat(2.0);
+    let from_b = r - g + diff * L::splat(4.0);
+    let h = L::select(v.eq(r), from_r, L::select(v.eq(g), from_g, from_b));
+    let h_table = round_even(L::splat(180.0 * SHIFT / 6.0) / diff);
+    let offset = L::splat(32.0);
+    let h = ((h * h_table + half + offset * L::splat(SHIFT)) / L::splat(SHIFT)).trunc() - offset;
+    let h = L::select(diff.eq(zero), zero, h);
+    let h = L::select(h.lt(zero), h + L::splat(180.0), h);
+
+    [h, s, v]
+}
+
+/// `x` rounded to the nearest whole number, ties to even like OpenCV's tables, for `x` from 0
+/// to 2^23. Beyond 2^23 an f32 has no fractions left, so adding it rounds them off.
+fn round_even<L: Lanes>(x: L) -> L {
+    let magic = L::splat(8_388_608.0);
+    (x + magic) - magic
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sse2 {
+    use std::{
+        arch::x86_64::*,
+        ops::{Add, BitAnd, Div, Mul, Sub},
+    };
+
+    #[derive(Clone, Copy)]
+    pub struct F32x4(__m128);
+
+    /// All ones in the lanes that are set.
+    #[derive(Clone, Copy)]
+    pub struct Mask4(__m128);
+
+    impl super::Lanes for F32x4 {
+        type Mask = Mask4;
+
+        fn new(lanes: [f32; 4]) -> Self {
+            Self(unsafe { _mm_loadu_ps(lanes.as_ptr()) })
+        }
+
+        fn splat(value: f32) -> Self {
+            Self(unsafe { _mm_set1_ps(value) })
+        }
+
+        #[cfg(test)]
+        fn to_array(self) -> [f32; 4] {
+            let mut lanes = [0.0; 4];
+            unsafe { _mm_storeu_ps(lanes.as_mut_ptr(), self.0) };
+            lanes
+        }
+
+        fn max(self, other: Self) -> Self {
+            Self(unsafe { _mm_max_ps(self.0, other.0) })
+        }
+
+        fn min(self, other: Self) -> Self {
+            Self(unsafe { _mm_min_ps(self.0, other.0) })
+        }
+
+        fn trunc(self) -> Self {
+            Self(unsafe { _mm_cvtepi32_ps(_mm_cvttps_epi32(self.0)) })
+        }
+
+        fn select(mask: Mask4, a: Self, b: Self) -> Self {
+            Self(unsafe { _mm_or_ps(_mm_and_ps(mask.0, a.0), _mm_andnot_ps(mask.0, b.0)) })
+        }
+
+        fn eq(self, other: Self) -> Mask4 {
+            Mask4(unsafe { _mm_cmpeq_ps(self.0, other.0) })
+        }
+
+        fn lt(self, other: Self) -> Mask4 {
+            Mask4(unsafe { _mm_cmplt_ps(self.0, other.0) })
+        }
+
+        fn gt(self, other: Self) -> Mask4 {
+            Mask4(unsafe { _mm_cmpgt_ps(self.0, other.0) })
+        }
+
+        fn ge(self, other: Self) -> Mask4 {
+            Mask4(unsafe { _mm_cmpge_ps(self.0, other.0) })
+        }
+
+        fn le(self, other: Self) -> Mask4 {
+            Mask4(unsafe { _mm_cmple_ps(self.0, other.0) })
+        }
+    }
+
+    impl super::MaskLanes for Mask4 {
+        fn to_bytes(self) -> [u8; 4] {
+            let bits = unsafe { _mm_movemask_ps(self.0) };
+            [1, 2, 4, 8].map(|lane| if bits & lane != 0 { 255 } else { 0 })
+        }
+    }
+
+    impl BitAnd for Mask4 {
+        type Output = Self;
+        fn bitand(self, other: Self) -> Self {
+            Self(unsafe { _mm_and_ps(self.0, other.0) })
+        }
+    }
+
+    impl Add for F32x4 {
+        type Output = Self;
+        fn add(self, other: Self) -> Self {
+            Self(unsafe { _mm_add_ps(self.0, other.0) })
+        }
+    }
+
+    impl Sub for F32x4 {
+        type Output = Self;
+        fn sub(self, other: Self) -> Self {
+            Self(unsafe { _mm_sub_ps(self.0, other.0) })
+        }
+    }
+
+    impl Mul for F32x4 {
+        type Output = Self;
+        fn mul(self, other: Self) -> Self {
+            Self(unsafe { _mm_mul_ps(self.0, other.0) })
+        }
+    }
+
+    impl Div for F32x4 {
+        type Output = Self;
+        fn div(self, other: Self) -> Self {
+            Self(unsafe { _mm_div_ps(self.0, other.0) })
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::{
+        arch::aarch64::*,
+        ops::{Add, BitAnd, Div, Mul, Sub},
+    };
+
+    #[derive(Clone, Copy)]
+    pub struct F32x4(float32x4_t);
+
+    /// All ones in the lanes that are set.
+    #[derive(Clone, Copy)]
+    pub struct Mask4(uint32x4_t);
+
+    impl super::Lanes for F32x4 {
+        type Mask = Mask4;
+
+        fn new(lanes: [f32; 4]) -> Self {
+            Self(unsafe { vld1q_f32(lanes.as_ptr()) })
+        }
+
+        fn splat(value: f32) -> Self {
+            Self(unsafe { vdupq_n_f32(value) })
+        }
+
+        #[cfg(test)]
+        fn to_array(self) -> [f32; 4] {
+            let mut lanes = [0.0; 4];
+            unsafe { vst1q_f32(lanes.as_mut_ptr(), self.0) };
+            lanes
+        }
+
+        fn max(self, other: Self) -> Self {
+            Self(unsafe { vmaxq_f32(self.0, other.0) })
+        }
+
+        fn min(self, other: Self) -> Self {
+            Self(unsafe { vminq_f32(self.0, other.0) })
+        }
+
+        fn trunc(self) -> Self {
+            Self(unsafe { vrndq_f32(self.0) })
+        }
+
+        fn select(mask: Mask4, a: Self, b: Self) -> Self {
+            Self(unsafe { vbslq_f32(mask.0, a.0, b.0) })
+        }
+
+        fn eq(self, other: Self) -> Mask4 {
+            Mask4(unsafe { vceqq_f32(self.0, other.0) })
+        }
+
+        fn lt(self, other: Self) -> Mask4 {
+            Mask4(unsafe { vcltq_f32(self.0, other.0) })
+        }
+
+        fn gt(self, other: Self) -> Mask4 {
+            Mask4(unsafe { vcgtq_f32(self.0, other.0) })
+        }
+
+        fn ge(self, other: Self) -> Mask4 {
+            Mask4(unsafe { vcgeq_f32(self.0, other.0) })
+        }
+
+        fn le(self, other: Self) -> Mask4 {
+            Mask4(unsafe { vcleq_f32(self.0, other.0) })
+        }
+    }
+
+    impl super::MaskLanes for Mask4 {
+        fn to_bytes(self) -> [u8; 4] {
+            // Narrowing twice keeps the low byte of each lane, all ones or all zeroes
+            let narrowed = unsafe { vmovn_u16(vcombine_u16(vmovn_u32(self.0), vdup_n_u16(0))) };
+            let mut bytes = [0; 8];
+            unsafe { vst1_u8(bytes.as_mut_ptr(), narrowed) };
+            [bytes[0], bytes[1], bytes[2], bytes[3]]
+        }
+    }
+
+    impl BitAnd for Mask4 {
+        type Output = Self;
+        fn bitand(self, other: Self) -> Self {
+            Self(unsafe { vandq_u32(self.0, other.0) })
+        }
+    }
+
+    impl Add for F32x4 {
+        type Output = Self;
+        fn add(self, other: Self) -> Self {
+            Self(unsafe { vaddq_f32(self.0, other.0) })
+        }
+    }
+
+    impl Sub for F32x4 {
+        type Output = Self;
+        fn sub(self, other: Self) -> Self {
+            Self(unsafe { vsubq_f32(self.0, other.0) })
+        }
+    }
+
+    impl Mul for F32x4 {
+        type Output = Self;
+        fn mul(self, other: Self) -> Self {
+            Self(unsafe { vmulq_f32(self.0, other.0) })
+        }
+    }
+
+    impl Div for F32x4 {
+        type Output = Self;
+        fn div(self, other: Self) -> Self {
+            Self(unsafe { vdivq_f32(self.0, other.0) })
+        }
+    }
+}
+
+// Built for the tests everywhere, to check the others against
+#[cfg(any(test, not(any(target_arch = "x86_64", target_arch = "aarch64"))))]
+mod scalar {
+    use std::ops::{Add, BitAnd, Div, Mul, Sub};
+
+    #[derive(Clone, Copy)]
+    pub struct F32x4([f32; 4]);
+
+    #[derive(Clone, Copy)]
+    pub struct Mask4([bool; 4]);
+
+    impl F32x4 {
+        fn zip(self, other: Self, op: impl Fn(f32, f32) -> f32) -> Self {
+            Self(std::array::from_fn(|i| op(self.0[i], other.0[i])))
+        }
+
+        fn compare(self, other: Self, op: impl Fn(f32, f32) -> bool) -> Mask4 {
+            Mask4(std::array::from_fn(|i| op(self.0[i], other.0[i])))
+        }
+    }
+
+    impl super::Lanes for F32x4 {
+        type Mask = Mask4;
+
+        fn new(lanes: [f32; 4]) -> Self {
+            Self(lanes)
+        }
+
+        fn splat(value: f32) -> Self {
+            Self([value; 4])
+        }
+
+        #[cfg(test)]
+        fn to_array(self) -> [f32; 4] {
+            self.0
+        }
+
+        fn max(self, other: Self) -> Self {
+            self.zip(other, f32::max)
+        }
+
+        fn min(self, other: Self) -> Self {
+            self.zip(other, f32::min)
+        }
+
+        fn trunc(self) -> Self {
+            Self(self.0.map(f32::trunc))
+        }
+
+        fn select(mask: Mask4, a: Self, b: Self) -> Self {
+            Self(std::array::from_fn(|i| if mask.0[i] { a.0[i] } else { b.0[i] }))
+        }
+
+        fn eq(self, other: Self) -> Mask4 {
+            self.compare(other, |a, b| a == b)
+        }
+
+        fn lt(self, other: Self) -> Mask4 {
+            self.compare(other, |a, b| a < b)
+        }
+
+        fn gt(self, other: Self) -> Mask4 {
+            self.compare(other, |a, b| a > b)
+        }
+
+        fn ge(self, other: Self) -> Mask4 {
+            self.compare(other, |a, b| a >= b)
+        }
+
+        fn le(self, other: Self) -> Mask4 {
+            self.compare(other, |a, b| a <= b)
+        }
+    }
+
+    impl super::MaskLanes for Mask4 {
+        fn to_bytes(self) -> [u8; 4] {
+            self.0.map(|set| if set { 255 } else { 0 })
+        }
+    }
+
+    impl BitAnd for Mask4 {
+        type Output = Self;
+        fn bitand(self, other: Self) -> Self {
+            Self(std::array::from_fn(|i| self.0[i] && other.0[i]))
+        }
+    }
+
+    impl Add for F32x4 {
+        type Output = Self;
+        fn add(self, other: Self) -> Self {
+            self.zip(other, |a, b| a + b)
+        }
+    }
+
+    impl Sub for F32x4 {
+        type Output = Self;
+        fn sub(self, other: Self) -> Self {
+            self.zip(other, |a, b| a - b)
+        }
+    }
+
+    impl Mul for F32x4 {
+        type Output = Self;
+        fn mul(self, other: Self) -> Self {
+            self.zip(other, |a, b| a * b)
+        }
+    }
+
+    impl Div for F32x4 {
+        type Output = Self;
+        fn div(self, other: Self) -> Self {
+            self.zip(other, |a, b| a / b)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `cvtColor`'s 8-bit RGB to HSV, as OpenCV writes it.
+    fn opencv_hsv([r, g, b]: [u8; 3]) -> [f32; 3] {
+        let table = |numerator: f64, i: i32| match i {
+            0 => 0,
+            i => (numerator / i as f64).round_ties_even() as i32,
+        };
+        let [r, g, b] = [r, g, b].map(i32::from);
+        let v = r.max(g).max(b);
+        let diff = v - r.min(g).min(b);
+
+        let s = (diff * table((255 << 12) as f64, v) + (1 << 11)) >> 12;
+        let h = if v == r {
+            g - b
+        } else if v == g {
+            b - r + 2 * diff
+        } else {
+            r - g + 4 * diff
+        };
+        let h = (h * table((180 << 12) as f64, 6 * diff) + (1 << 11)) >> 12;
+        let h = if h < 0 { h + 180 } else { h };
+        [h, s, v].map(|value| value as f32)
+    }
+
+    fn lanes<L: Lanes>(pixels: &[[u8; 3]; 4]) -> [[f32; 3]; 4] {
+        let channel = |c: usize| pixels.map(|pixel| pixel[c] as f32);
+        let [h, s, v] = hsv::<L>([channel(0), channel(1), channel(2)]).map(L::to_array);
+        std::array::from_fn(|i| [h[i], s[i], v[i]])
+    }
+
+    /// Every red and green with blue at a spread of levels, in every order of the channels.
+    fn sweep() -> impl Iterator<Item = [u8; 3]> {
+        (0..=255u8).step_by(15).flat_map(|b| {
+            (0..=255u8)
+                .flat_map(move |r| (0..=255u8).flat_map(move |g| [[r, g, b], [b, r, g], [g, b, r]]))
+        })
+    }
+
+    #[test]
+    fn hsv_matches_opencv() {
+        let pixels = sweep().collect::<Vec<_>>();
+        for four in pixels.chunks_exact(4) {
+            let four = [four[0], four[1], four[2], four[3]];
+            let expected = four.map(opencv_hsv);
+            assert_eq!(lanes::<F32x4>(&four), expected, "{four:?}");
+            assert_eq!(lanes::<scalar::F32x4>(&four), expected, "{four:?}");
+        }
+    }
+
+    #[test]
+    fn fractions_are_dropped() {
+        let rgb = [[10.9, 200.5, 0.0, 254.99], [200.2, 10.7, 0.0, 254.99], [0.0; 4]];
+        let truncated = rgb.map(|channel| channel.map(f32::trunc));
+        let [h, s, v] = hsv::<F32x4>(rgb).map(F32x4::to_array);
+        let [eh, es, ev] = hsv::<F32x4>(truncated).map(F32x4::to_array);
+        assert_eq!((h, s, v), (eh, es, ev));
+    }
+
+    #[test]
+    fn masks_match_opencv_bounds() {
+        let range = HsvRange::new(&Settings::DEFAULT);
+        let inside = |[h, s, v]: [f32; 3]| {
+            let settings = &Settings::DEFAULT;
+            let within = |value: f32, lower: f64, upper: f64| {
+                value as f64 >= lower.ceil() && value as f64 <= upper.floor()
+            };
+            within(h, settings.lower_h, settings.upper_h)
+                && within(s, settings.lower_s, settings.upper_s)
+                && within(v, settings.lower_v, settings.upper_v)
+        };
+
+        let pixels = sweep().collect::<Vec<_>>();
+        for four in pixels.chunks_exact(4) {
+            let channel = |c: usize| [0, 1, 2, 3].map(|i| four[i][c] as f32);
+            let rgb = [channel(0), channel(1), channel(2)];
+            let expected = [0, 1, 2, 3].map(|i| if inside(opencv_hsv(four[i])) { 255 } else { 0 });
+            assert_eq!(range.test(rgb), expected, "{four:?}");
+            assert_eq!(range.test_lanes::<scalar::F32x4>(rgb), expected, "{four:?}");
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Made up frames with LEDs at known positions, for measuring how well detection finds them.
+//!
+//! A scene is a dark background with some clutter, LEDs as soft spots, then optical blur and
+//! sensor noise on top. Everything random comes from the seed, so a scene renders the same every
+//! time.
+//!
+//! Positions are in the same pixel coordinates as the detections, where a pixel's center is at
+//! its whole coordinates, like OpenCV's.
+
+use emath::{Pos2, Rect};
+
+#[derive(Clone)]
+pub struct Scene {
+    pub width: usize,
+    pub height: usize,
+    pub leds: Vec<Pos2>,
+    /// Radius of an LED's spot, in pixels.
+    pub radius: f32,
+    pub color: [u8; 3],
+    /// Standard deviation of the blur, in pixels.
+    pub blur: f32,
+    /// Standard deviation of the noise added to every channel, on the 0-255 scale.
+    pub noise: f32,
+    /// Number of dim rectangles scattered over the background, like furniture or reflections.
+    pub clutter: usize,
+    pub seed: u64,
+}
+
+impl Scene {
+    /// A scene with `count` green LEDs placed at random, far enough apart to be told apart.
+    pub fn random(width: usize, height: usize, count: usize, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let radius = 4.0;
+        let margin = radius * 4.0;
+        let mut leds: Vec<Pos2> = Vec::with_capacity(count);
+
+        // Give up on spacing them out after a while, for more LEDs than fit
+        for _ in 0..count * 100 {
+            if leds.len() == count {
+                break;
+            }
+
+            let pos = Pos2::new(
+                margin + rng.next_f32() * (width as f32 - 2.0 * margin),
+                margin + rng.next_f32() * (height as f32 - 2.0 * margin),
+            );
+            if leds.iter().all(|led| led.distance(pos) > margin) {
+                leds.push(pos);
+            }
+        }
+
+        Self {
+            width,
+            height,
+            leds,
+            radius,
+            color: [0, 255, 0],
+            blur: 1.0,
+            noise: 4.0,
+            clutter: 10,
+            seed,
+        }
+    }
+
+    /// The scene as packed RGB24.
+    pub fn render(&self) -> Vec<u8> {
+        self.render_lit(&vec![self.color; self.leds.len()], self.seed)
+    }
+
+    /// The scene with each LED lit in its color from `colors`, and those past the end of it off.
+    /// The noise comes from `noise_seed`, so consecutive frames of the same scene can differ like
+    /// a real camera's do.
+    pub fn render_lit(&self, colors: &[[u8; 3]], noise_seed: u64) -> Vec<u8> {
+        let mut rng = Rng::new(self.seed ^ 0x5eed);
+        let mut image = vec![[0f32; 3]; self.width * self.height];
+
+        // A slight gradient, as rooms are never evenly lit
+        for (i, pixel) in image.iter_mut().enumerate() {
+            let y = (i / self.width) as f32 / self.height as f32;
+            *pixel = [20.0 + 10.0 * y; 3];
+        }
+
+        for _ in 0..self.clutter {
+            let min =
+                Pos2::new(rng.next_f32() * self.width as f32, rng.next_f32() * self.height as f32);
+            let size = emath::vec2(rng.next_f32() * 200.0, rng.next_f32() * 200.0);
+            let color = [0; 3].map(|_: u8| 30.0 + rng.next_f32() * 60.0);
+            self.fill(&mut image, Rect::from_min_size(min, size), color);
+        }
+
+        // The blur widens each spot the same way convolving the whole frame would
+        let sigma = (self.radius / 2.0).hypot(self.blur);
+        let reach = (sigma * 3.0).ceil() as i64;
+        for (led, color) in self.leds.iter().zip(colors) {
+            if *color == [0; 3] {
+                continue;
+            }
+
+            let (center_x, center_y) = (led.x.round() as i64, led.y.round() as i64);
+            for y in center_y - reach..=center_y + reach {
+                for x in center_x - reach..=center_x + reach {
+                    let Some(pixel) = self.pixel(&mut image, x, y) else {
+                        continue;
+                    };
+
+                    let d2 = (x as f32 - led.x).powi(2) + (y as f32 - led.y).powi(2);
+                    let intensity = (-d2 / (2.0 * sigma * sigma)).exp();
+                    for (channel, led) in pixel.iter_mut().zip(color) {
+                        *channel += *led as f32 * intensity;
+                    }
+                }
+            }
+        }
+
+        let mut rng = Rng::new(noise_seed);
+        image
+            .into_iter()
+            .flat_map(|pixel| {
+                pixel.map(|c| (c + rng.gaussian() * self.noise).clamp(0.0, 255.0) as u8)
+            })
+            .collect()
+    }
+
+    fn fill(&self, image: &mut [[f32; 3]], rect: Rect, color: [f32; 3]) {
+        for y in rect.min.y as i64..rect.max.y as i64 {
+            for x in rect.min.x as i64..rect.max.x as i64 {
+                if let Some(pixel) = self.pixel(image, x, y) {
+                    *pixel = color;
+                }
+            }
+        }
+    }
+
+    fn pixel<'a>(&self, image: &'a mut [[f32; 3]], x: i64, y: i64) -> Option<&'a mut [f32; 3]> {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return None;
+        }
+        image.get_mut(y as usize * self.width + x as usize)
+    }
+}
+
+/// How detections compare to where the LEDs really are.
+#[derive(Clone, Debug, Default)]
+pub struct Accuracy {
+    pub leds: usize,
+    /// LEDs with a detection within the tolerance.
+    pub found: usize,
+    /// Detections that aren't near any LED, or that another detection already took the LED of.
+    pub spurious: usize,
+    /// Distance from the found LEDs to their detection, in pixels.
+    pub mean_error: f32,
+    pub max_error: f32,
+}
+
+impl Accuracy {
+    /// Share of the LEDs that were found.
+    pub fn recall(&self) -> f32 {
+        self.found as f32 / self.leds.max(1) as f32
+    }
+}
+
+/// Pairs each LED with the nearest detection within `tolerance` pixels, closest pairs first.
+pub fn evaluate(leds: &[Pos2], detections: &[Rect], tolerance: f32) -> Accuracy {
+    let mut pairs = leds
+        .iter()
+        .enumerate()
+        .flat_map(|(led, pos)| {
+            detections
+                .iter()
+                .enumerate()
+                .map(move |(detection, rect)| (pos.distance(rect.center()), led, detection))
+        })
+        .filter(|(distance, _, _)| *distance <= tolerance)
+        .collect::<Vec<_>>();
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut led_taken = vec![false; leds.len()];
+    let mut detection_taken = vec![false; detections.len()];
+    let mut errors = Vec::new();
+    for (distance, led, detection) in pairs {
+        if !led_taken[led] && !detection_taken[detection] {
+            led_taken[led] = true;
+            detection_taken[detection] = true;
+            errors.push(distance);
+        }
+    }
+
+    Accuracy {
+        leds: leds.len(),
+        found: errors.len(),
+        spurious: detections.len() - errors.len(),
+        mean_error: errors.iter().sum::<f32>() / errors.len().max(1) as f32,
+        max_error: errors.iter().copied().fold(0.0, f32::max),
+    }
+}
+
+/// xorshift64*, which is plenty random for placing LEDs and doesn't need a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero is the one state xorshift never leaves
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniform in 0..1.
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Standard normal, by Box-Muller.
+    fn gaussian(&mut self) -> f32 {
+        let u = self.next_f32().max(f32::MIN_POSITIVE);
+        let v = self.next_f32();
+        (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DetectionMode, Settings, ThresholdMethod};
+
+    #[test]
+    fn default_settings_find_every_led() {
+        for seed in 0..5 {
+            let scene = Scene::random(640, 480, 30, seed);
+            let detections =
+                crate::detect_rgb(&scene.render(), scene.width, &Settings::DEFAULT).unwrap();
+            let accuracy = evaluate(&scene.leds, &detections, 3.0);
+
+            assert_eq!(accuracy.found, scene.leds.len(), "seed {seed}");
+            assert!(accuracy.mean_error < 0.3, "seed {seed}: {:.2} px", accuracy.mean_error);
+        }
+    }
+
+    #[test]
+    fn relative_thresholds_find_the_leds() {
+        let modes = [
+            (DetectionMode::Hsv, ThresholdMethod::Otsu, 1.0),
+            (DetectionMode::Hsv, ThresholdMethod::Adaptive, 1.0),
+            // A clutter rectangle touching an LED can swallow it
+            (DetectionMode::Brightness, ThresholdMethod::Otsu, 0.9),
+            (DetectionMode::Brightness, ThresholdMethod::Adaptive, 1.0),
+        ];
+        for (mode, method, recall) in modes {
+            let settings = Settings {
+                mode,
+                method,
+                // Noise pushes single pixels over a relative threshold
+                min_area: 4.0,
+                ..Settings::DEFAULT
+            };
+            for seed in 0..5 {
+                let scene = Scene::random(640, 480, 30, seed);
+                let detections =
+                    crate::detect_rgb(&scene.render(), scene.width, &settings).unwrap();
+                let accuracy = evaluate(&scene.leds, &detections, 3.0);
+
+                let case = format!("{} {}, seed {seed}", mode.name(), method.name());
+                assert!(accuracy.recall() >= recall, "{case}: {:.2}", accuracy.recall());
+                assert!(accuracy.mean_error < 0.3, "{case}: {:.2} px", accuracy.mean_error);
+                assert!(accuracy.spurious <= 5, "{case}: {} spurious", accuracy.spurious);
+            }
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! Finding LEDs by how a lit one looks instead of by its color, sliding a crop of one across the
+//! frame and taking the places it matches best. Works on luma only, so a template captured in one
+//! color finds LEDs in any other.
+
+use emath::{Pos2, Rect, Vec2};
+#[cfg(feature = "opencv")]
+use opencv::{
+    core::{no_array, Mat, Mat_AUTO_STEP, CV_8U},
+    imgproc::{match_template, TM_CCOEFF_NORMED},
+    prelude::*,
+};
+
+use crate::Result;
+
+/// Templates bigger than this on either side take too long to match on every frame.
+pub const MAX_SIZE: usize = 96;
+
+/// A crop of a lit LED and the bloom around it, in luma.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Template {
+    pub width: usize,
+    /// Row by row.
+    pub luma: Vec<u8>,
+}
+
+impl Template {
+    /// Crops `region` out of a packed RGB frame `width` pixels wide. `None` if the crop is under
+    /// 3 or over `MAX_SIZE` pixels on a side, or all one brightness, which matches nothing.
+    pub fn capture(rgb: &[u8], width: usize, region: Rect) -> Option<Self> {
+        let height = rgb.len() / width / 3;
+        let (left, right) = (region.min.x.max(0.0) as usize, (region.max.x as usize).min(width));
+        let (top, bottom) = (region.min.y.max(0.0) as usize, (region.max.y as usize).min(height));
+        let size = right.saturating_sub(left).max(bottom.saturating_sub(top));
+        if right < left + 3 || bottom < top + 3 || size > MAX_SIZE {
+            return None;
+        }
+
+        let luma = (top..bottom)
+            .flat_map(|row| rgb[(row * width + left) * 3..(row * width + right) * 3].chunks(3))
+            .map(|pixel| luma([pixel[0], pixel[1], pixel[2]]))
+            .collect::<Vec<_>>();
+        let first = luma[0];
+        luma.iter()
+            .any(|&value| value != first)
+            .then_some(Self { width: right - left, luma })
+    }
+
+    pub fn height(&self) -> usize {
+        self.luma.len() / self.width
+    }
+}
+
+/// The same weights as OpenCV's RGB to gray conversion.
+pub fn luma([r, g, b]: [u8; 3]) -> u8 {
+    (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32).round() as u8
+}
+
+/// Where `template` matches a luma frame `width` pixels wide with a normalized correlation of at
+/// least `min_score`, as rects the size of the template. Each patch of matching positions counts
+/// once, at its best match.
+pub fn find(luma: &[u8], width: usize, template: &Template, min_score: f32) -> Result<Vec<Rect>> {
+    let height = luma.len() / width;
+    let (template_width, template_height) = (template.width, template.height());
+    if width < template_width || height < template_height {
+        return Ok(Vec::new());
+    }
+
+    let map_width = width - template_width + 1;
+    let map = scores(luma, width, template)?;
+
+    let mut seen = vec![false; map.len()];
+    let mut stack = Vec::new();
+    let mut found = Vec::new();
+    for start in 0..map.len() {
+        if seen[start] || map[start] < min_score {
+            continue;
+        }
+
+        // Flood fill over the positions that match, keeping the best of them
+        seen[start] = true;
+        stack.push(start);
+        let mut best = start;
+        while let Some(i) = stack.pop() {
+            if map[i] > map[best] {
+                best = i;
+            }
+            let (x, y) = (i % map_width, i / map_width);
+            for ny in y.saturating_sub(1)..(y + 2).min(map.len() / map_width) {
+                for nx in x.saturating_sub(1)..(x + 2).min(map_width) {
+                    let neighbour = ny * map_width + nx;
+                    if !seen[neighbour] && map[neighbour] >= min_score {
+                        seen[neighbour] = true;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+
+        found.push(Rect::from_min_size(
+            Pos2::new((best % map_width) as f32, (best / map_width) as f32),
+            Vec2::new(template_width as f32, template_height as f32),
+        ));
+    }
+    Ok(found)
+}
+
+/// The normalized correlation of `template` at every position it fits in the frame, by its top
+/// left corner, row by row.
+#[cfg(feature = "opencv")]
+fn scores(luma: &[u8], width: usize, template: &Template) -> Result<Vec<f32>> {
+    let mat = |data: &[u8], width: usize| unsafe {
+        Mat::new_rows_cols_with_data(
+            (data.len() / width) as i32,
+            width as i32,
+            CV_8U,
+            data.as_ptr() as *mut _,
+            Mat_AUTO_STEP,
+        )
+    };
+
+    let mut result = Mat::default();
+    match_template(
+        &mat(luma, width)?,
+        &mat(&template.luma, template.width)?,
+        &mut result,
+        TM_CCOEFF_NORMED,
+        &no_array(),
+    )?;
+    Ok(result.data_typed::<f32>()?.to_vec())
+}
+
+/// Without OpenCV positions where the frame is flat, most of a dark room, are skipped rather than
+/// correlated, since they can't match a template that isn't.
+#[cfg(not(feature = "opencv"))]
+fn scores(luma: &[u8], width: usize, template: &Template) -> Result<Vec<f32>> {
+    let height = luma.len() / width;
+    let (template_width, template_height) = (template.width, template.height());
+    let (map_width, map_height) = (width - template_width + 1, height - template_height + 1);
+    let count = (template_width * template_height) as f64;
+
+    let mean = template.luma.iter().map(|&v| v as f64).sum::<f64>() / count;
+    let centered = template
+        .luma
+        .iter()
+        .map(|&v| (v as f64 - mean) as f32)
+        .collect::<Vec<_>>();
+    let template_norm = centered.iter().map(|&v| v as f64 * v as f64).sum::<f64>();
+
+    // Sums and sums of squares of everything above and to the left, a row and column of zeroes
+    // first, for the mean and variance under the template anywhere
+    let stride = width + 1;
+    let mut sums = vec![0.0f64; stride * (height + 1)];
+    let mut squares = vec![0.0f64; stride * (height + 1)];
+    for y in 0..height {
+        let (mut row, mut row_squares) = (0.0, 0.0);
+        for x in 0..width {
+            let value = luma[y * width + x] as f64;
+            row += value;
+            row_squares += value * value;
+            sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row;
+            squares[(y + 1) * stride + x + 1] = squares[y * stride + x + 1] + row_squares;
+        }
+    }
+    let window = |table: &[f64], x: usize, y: usize| {
+        let (right, bottom) = (x + template_width, y + template_height);
+        table[bottom * stride + right] + table[y * stride + x]
+            - table[y * stride + right]
+            - table[bottom * stride + x]
+    };
+
+    let mut map = vec![0.0; map_width * map_height];
+    for y in 0..map_height {
+        for x in 0..map_width {
+            let sum = window(&sums, x, y);
+            let variance = window(&squares, x, y) - sum * sum / count;
+            // Less than a level of difference per pixel
+            if variance < count {
+                continue;
+            }
+
+            // The template is centered, so the frame's mean drops out
+            let cross = (0..template_height)
+                .map(|row| {
+                    let start = (y + row) * width + x;
+                    luma[start..start + template_width]
+                        .iter()
+                        .zip(&centered[row * template_width..(row + 1) * template_width])
+                        .map(|(&value, &weight)| value as f32 * weight)
+                        .sum::<f32>() as f64
+                })
+                .sum::<f64>();
+            map[y * map_width + x] = (cross / (variance * template_norm).sqrt()) as f32;
+        }
+    }
+    Ok(map)
+}
@@ -0,0 +1,206 @@
+//! Masking the pixels that match the detection settings.
+
+#[cfg(feature = "opencv")]
+use opencv::{
+    core::{
+        bitwise_and_def, extract_channel, in_range, multiply_def, subtract_def, transform,
+        Mat_AUTO_STEP, Scalar, CV_32FC3, CV_8U, CV_8UC3,
+    },
+    imgproc::{
+        adaptive_threshold, cvt_color, threshold, COLOR_RGB2Lab, ADAPTIVE_THRESH_GAUSSIAN_C,
+        COLOR_RGB2GRAY, COLOR_RGB2HSV, THRESH_BINARY, THRESH_BINARY_INV, THRESH_OTSU,
+    },
+    prelude::*,
+};
+
+#[cfg(feature = "opencv")]
+use crate::{preprocess, DetectionMode, Settings, ThresholdMethod, Yuv420};
+
+/// Masks the pixels of an RGB frame that match the current detection mode.
+#[cfg(feature = "opencv")]
+pub fn rgb(image_data: &[u8], width: usize, settings: &Settings) -> opencv::Result<Mat> {
+    let image = unsafe {
+        Mat::new_rows_cols_with_data(
+            (image_data.len() / width / 3) as i32,
+            width as i32,
+            CV_8UC3,
+            image_data.as_ptr() as *mut _,
+            Mat_AUTO_STEP,
+        )?
+    };
+    let image = preprocess::rgb(image, settings)?;
+
+    let mut mask = Mat::default();
+
+    match settings.mode {
+        DetectionMode::Hsv => {
+            let mut hsv_image = Mat::default();
+            cvt_color(&image, &mut hsv_image, COLOR_RGB2HSV, 0)?;
+            drop(image);
+
+            if settings.method == ThresholdMethod::Fixed {
+                let lower = Scalar::new(settings.lower_h, settings.lower_s, settings.lower_v, 0.0);
+                let upper = Scalar::new(settings.upper_h, settings.upper_s, settings.upper_v, 0.0);
+                in_range(&hsv_image, &lower, &upper, &mut mask)?;
+            } else {
+                // Hue and saturation as usual, value relative to the rest of the frame
+                let lower = Scalar::new(settings.lower_h, settings.lower_s, 0.0, 0.0);
+                let upper = Scalar::new(settings.upper_h, settings.upper_s, 255.0, 0.0);
+                let mut color = Mat::default();
+                in_range(&hsv_image, &lower, &upper, &mut color)?;
+
+                let mut value = Mat::default();
+                extract_channel(&hsv_image, &mut value, 2)?;
+                bitwise_and_def(&color, &brightness(&value, settings)?, &mut mask)?;
+            }
+        }
+        DetectionMode::Brightness => {
+            let mut gray = Mat::default();
+            cvt_color(&image, &mut gray, COLOR_RGB2GRAY, 0)?;
+            drop(image);
+
+            mask = brightness(&gray, settings)?;
+        }
+        DetectionMode::Lab => {
+            // Float input gets real Lab units out, 8-bit would squash L and offset a and b
+            let mut float = Mat::default();
+            image.convert_to(&mut float, CV_32FC3, 1.0 / 255.0, 0.0)?;
+            drop(image);
+
+            let mut lab = Mat::default();
+            cvt_color(&float, &mut lab, COLOR_RGB2Lab, 0)?;
+
+            let [l, a, b] = srgb_to_lab(settings.lab_target);
+            let mut diff = Mat::default();
+            subtract_def(&lab, &Scalar::new(l, a, b, 0.0), &mut diff)?;
+            let mut squared = Mat::default();
+            multiply_def(&diff, &diff, &mut squared)?;
+
+            // Sum the channels into one
+            let mut distance = Mat::default();
+            transform(&squared, &mut distance, &Mat::from_slice_rows_cols(&[1f32; 3], 1, 3)?)?;
+
+            let mut close = Mat::default();
+            let max = settings.lab_tolerance * settings.lab_tolerance;
+            threshold(&distance, &mut close, max, 255.0, THRESH_BINARY_INV)?;
+            close.convert_to(&mut mask, CV_8U, 1.0, 0.0)?;
+        }
+    }
+
+    Ok(mask)
+}
+
+/// Converts an sRGB color to CIELAB under D65, the same way OpenCV does.
+pub fn srgb_to_lab(rgb: [u8; 3]) -> [f64; 3] {
+    let [r, g, b] = rgb.map(|c| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+
+    let x = (0.412453 * r + 0.357580 * g + 0.180423 * b) / 0.950456;
+    let y = 0.212671 * r + 0.715160 * g + 0.072169 * b;
+    let z = (0.019334 * r + 0.119193 * g + 0.950227 * b) / 1.088754;
+
+    let f = |t: f64| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// The level an Otsu threshold masks `channel` above. Lit LEDs take up a small part of the
+/// frame, and in a scene with anything else bright in it Otsu's level falls between that and the
+/// background, so it's taken again over the pixels above it for as long as those are more than
+/// a twentieth of the frame. `level` is Otsu's level for some pixels.
+pub fn otsu<E>(channel: &[u8], level: impl Fn(&[u8]) -> Result<u8, E>) -> Result<u8, E> {
+    let mut threshold = level(channel)?;
+    loop {
+        let above = channel
+            .iter()
+            .copied()
+            .filter(|&value| value > threshold)
+            .collect::<Vec<_>>();
+        if above.len() * 20 <= channel.len() {
+            return Ok(threshold);
+        }
+        match level(&above)? {
+            next if next > threshold => threshold = next,
+            _ => return Ok(threshold),
+        }
+    }
+}
+
+/// On the YUV path the luma stands in for V when thresholding isn't fixed.
+#[cfg(feature = "opencv")]
+pub fn yuv(yuv: &Yuv420, settings: &Settings) -> opencv::Result<Mat> {
+    let to_mat = |mask: &[u8]| Mat::from_slice_rows_cols(mask, yuv.height, yuv.width);
+
+    match (settings.mode, settings.method) {
+        // Preprocessing works on RGB, and Lab needs the whole color conversion anyway
+        _ if preprocess::active(settings) => rgb(&yuv.to_rgb(), yuv.width, settings),
+        (DetectionMode::Lab, _) => rgb(&yuv.to_rgb(), yuv.width, settings),
+
+        (DetectionMode::Hsv, ThresholdMethod::Fixed) => to_mat(&yuv.threshold_hsv(settings)),
+        (DetectionMode::Hsv, _) => {
+            let any_value = Settings {
+                lower_v: 0.0,
+                upper_v: 255.0,
+                ..settings.clone()
+            };
+            let color = to_mat(&yuv.threshold_hsv(&any_value))?;
+            let bright = brightness(&to_mat(&yuv.y)?, settings)?;
+
+            let mut mask = Mat::default();
+            bitwise_and_def(&color, &bright, &mut mask)?;
+            Ok(mask)
+        }
+
+        (DetectionMode::Brightness, ThresholdMethod::Fixed) => {
+            to_mat(&yuv.threshold_luma(settings.brightness))
+        }
+        (DetectionMode::Brightness, _) => brightness(&to_mat(&yuv.y)?, settings),
+    }
+}
+
+/// Thresholds a single 8-bit channel with the configured method.
+#[cfg(feature = "opencv")]
+fn brightness(channel: &Mat, settings: &Settings) -> opencv::Result<Mat> {
+    let mut mask = Mat::default();
+
+    match settings.method {
+        ThresholdMethod::Fixed => {
+            threshold(channel, &mut mask, settings.brightness, 255.0, THRESH_BINARY)?;
+        }
+        ThresholdMethod::Otsu => {
+            let level = otsu(channel.data_bytes()?, |pixels| {
+                let pixels = Mat::from_slice(pixels)?;
+                let level = threshold(&pixels, &mut Mat::default(), 0.0, 255.0, THRESH_OTSU)?;
+                Ok::<_, opencv::Error>(level as u8)
+            })?;
+            threshold(channel, &mut mask, level as f64, 255.0, THRESH_BINARY)?;
+        }
+        ThresholdMethod::Adaptive => {
+            // OpenCV subtracts the offset from the local mean, so it goes in negated
+            adaptive_threshold(
+                channel,
+                &mut mask,
+                255.0,
+                ADAPTIVE_THRESH_GAUSSIAN_C,
+                THRESH_BINARY,
+                settings.adaptive_block | 1,
+                -settings.adaptive_offset,
+            )?;
+        }
+    }
+
+    Ok(mask)
+}
@@ -0,0 +1,89 @@
+//! Thresholding decoded YUV 4:2:0 frames against the HSV bounds directly, so detection doesn't
+//! need an RGB copy of every frame.
+
+use crate::{simd::HsvRange, Settings};
+
+/// A planar 4:2:0 frame with the row padding removed. The chroma planes are half the luma size,
+/// rounded up.
+pub struct Yuv420 {
+    pub width: usize,
+    pub height: usize,
+    /// JPEG style 0..255 luma instead of broadcast 16..235.
+    pub full_range: bool,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+}
+
+impl Yuv420 {
+    /// BT.601, which is what nearly every camera stream uses.
+    fn rgb_at(&self, row: usize, col: usize) -> [f32; 3] {
+        let y = self.y[row * self.width + col] as f32;
+        let chroma = (row / 2) * self.width.div_ceil(2) + col / 2;
+        let u = self.u[chroma] as f32 - 128.0;
+        let v = self.v[chroma] as f32 - 128.0;
+
+        if self.full_range {
+            [y + 1.402 * v, y - 0.344 * u - 0.714 * v, y + 1.772 * u]
+        } else {
+            let y = (y - 16.0) * 1.164;
+            [y + 1.596 * v, y - 0.392 * u - 0.813 * v, y + 2.017 * u]
+        }
+        .map(|c| c.clamp(0.0, 255.0))
+    }
+
+    /// Packed RGB24, for the detection modes that need the full conversion anyway.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        self.write_rgb(&mut rgb);
+        rgb
+    }
+
+    /// Appends the frame as packed RGB24 to `out`.
+    pub fn write_rgb(&self, out: &mut Vec<u8>) {
+        for row in 0..self.height {
+            for col in 0..self.width {
+                out.extend(self.rgb_at(row, col).map(|c| c as u8));
+            }
+        }
+    }
+
+    /// Builds a mask with 255 wherever the luma is above `threshold`, on a 0..255 scale whatever
+    /// the range of the stream.
+    pub fn threshold_luma(&self, threshold: f64) -> Vec<u8> {
+        // Compare in the stream's own range instead of rescaling every pixel
+        let threshold = if self.full_range {
+            threshold
+        } else {
+            16.0 + threshold / 1.164
+        };
+
+        self.y
+            .iter()
+            .map(|&y| if y as f64 > threshold { 255 } else { 0 })
+            .collect()
+    }
+
+    /// Builds a mask with 255 wherever the pixel falls inside the HSV bounds, using OpenCV's 8-bit
+    /// HSV scale (hue 0..180) so the same settings work for both paths.
+    pub fn threshold_hsv(&self, settings: &Settings) -> Vec<u8> {
+        let range = HsvRange::new(settings);
+        let mut mask = Vec::with_capacity(self.width * self.height);
+
+        for row in 0..self.height {
+            for col in (0..self.width).step_by(4) {
+                let count = (self.width - col).min(4);
+                let mut rgb = [[0.0; 4]; 3];
+                for (lane, [r, g, b]) in (col..col + count)
+                    .map(|col| self.rgb_at(row, col))
+                    .enumerate()
+                {
+                    (rgb[0][lane], rgb[1][lane], rgb[2][lane]) = (r, g, b);
+                }
+                mask.extend_from_slice(&range.test(rgb)[..count]);
+            }
+        }
+
+        mask
+    }
+}
@@ -0,0 +1,155 @@
+//! Sounds and desktop notifications for long scans left to run on their own: when one ends, when
+//! it stops getting anywhere, when nothing is detected anymore, like when the camera got knocked
+//! or covered, and when the video freezes.
+//!
+//! Both go through the programs the desktop already has rather than linking to it, so they're
+//! simply skipped where those aren't installed.
+
+use std::{
+    process::{Command, Stdio},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{debug, info};
+
+use crate::{
+    freeze,
+    pipeline::POINTS,
+    scan::{self, State},
+};
+
+pub static SOUND: AtomicBool = AtomicBool::new(false);
+pub static DESKTOP: AtomicBool = AtomicBool::new(false);
+
+/// A running scan that stays on the same LED this long is stuck.
+const STALL_AFTER: Duration = Duration::from_secs(60);
+
+/// A running scan that detects nothing at all for this long has lost sight of the LEDs. Longer
+/// than the dark steps some scans take on purpose.
+const EMPTY_AFTER: Duration = Duration::from_secs(15);
+
+const POLL: Duration = Duration::from_millis(500);
+
+/// Alerts that the scan ended in `state`, from the scan's thread so it goes out before a scan
+/// from the command line exits.
+pub fn scan_ended(state: &State) {
+    let found = || {
+        let map = scan::MAP.read().unwrap();
+        format!("Found {} of {} LEDs", map.iter().flatten().count(), map.len())
+    };
+    match state {
+        State::Finished => alert("Scan finished", &found()),
+        State::Stopped => alert("Scan stopped", &found()),
+        State::Failed(error) => alert("Scan failed", error),
+        State::Idle | State::Running | State::Stopping | State::Aborted => {}
+    }
+}
+
+/// Watches the scans for the rest of the program's life for ones that stall or lose the LEDs.
+pub fn spawn() {
+    thread::spawn(|| {
+        let mut current = (usize::MAX, Instant::now());
+        let mut detected = Instant::now();
+        let (mut stalled, mut empty, mut frozen) = (false, false, false);
+
+        loop {
+            thread::sleep(POLL);
+
+            if !scan::RUNNING.load(Ordering::Relaxed) {
+                current = (usize::MAX, Instant::now());
+                detected = Instant::now();
+                (stalled, empty, frozen) = (false, false, false);
+                continue;
+            }
+
+            match freeze::frozen() {
+                Some((cause, _)) if !frozen => {
+                    frozen = true;
+                    let message = format!("The video froze, {}", cause.describe());
+                    alert("Scan video frozen", &message);
+                }
+                Some(_) => {}
+                None => frozen = false,
+            }
+
+            let index = scan::CURRENT.load(Ordering::Relaxed);
+            if index != current.0 {
+                current = (index, Instant::now());
+                stalled = false;
+            } else if !stalled && current.1.elapsed() > STALL_AFTER {
+                stalled = true;
+                let message =
+                    format!("Stuck on LED {} for {} seconds", index + 1, STALL_AFTER.as_secs());
+                alert("Scan stalled", &message);
+            }
+
+            if !POINTS.read().unwrap().is_empty() {
+                detected = Instant::now();
+                empty = false;
+            } else if !empty && detected.elapsed() > EMPTY_AFTER {
+                empty = true;
+                let message = format!(
+                    "Nothing detected for {} seconds, is the camera still pointed at the LEDs?",
+                    EMPTY_AFTER.as_secs()
+                );
+                alert("Scan lost the LEDs", &message);
+            }
+        }
+    });
+}
+
+/// Plays a sound and shows a notification, whichever are on.
+fn alert(title: &str, message: &str) {
+    info!("{title}: {message}");
+
+    if DESKTOP.load(Ordering::Relaxed) {
+        notify(title, message);
+    }
+    if SOUND.load(Ordering::Relaxed) {
+        sound();
+    }
+}
+
+fn notify(title: &str, message: &str) {
+    let mut command = if cfg!(target_os = "macos") {
+        let script = format!("display notification {message:?} with title {title:?}");
+        let mut command = Command::new("osascript");
+        command.args(["-e", &script]);
+        command
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args(["--app-name", "LED Position Calibrator", title, message]);
+        command
+    };
+    run(&mut command);
+}
+
+fn sound() {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = Command::new("afplay");
+        command.arg("/System/Library/Sounds/Glass.aiff");
+        command
+    } else {
+        let mut command = Command::new("canberra-gtk-play");
+        command.args(["--id", "complete"]);
+        command
+    };
+    run(&mut command);
+}
+
+/// Starts `command` and waits on it elsewhere, as neither is worth holding up the watcher for.
+fn run(command: &mut Command) {
+    let result = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    match result {
+        Ok(mut child) => {
+            thread::spawn(move || child.wait());
+        }
+        Err(e) => debug!("Failed to run {:?}: {e}", command.get_program()),
+    }
+}
@@ -0,0 +1,182 @@
+//! Saves of the session every so often to a recovery file, so a crash in the middle of a long
+//! session only loses the last few seconds of it. Detection settings are kept by eframe too, but
+//! only from a clean exit or its own autosave, and the map and ignored sources not at all.
+//!
+//! The file is in the working directory like the scan journal, and removed on a clean exit, so
+//! finding one on launch means the last session never got to close.
+
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use eframe::epaint::Pos2;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    ignored::{self, Source},
+    lens::{self, Lens},
+    pipeline::{Settings, SETTINGS},
+    scan::{self, ScanSettings},
+    segments::Segment,
+};
+
+pub const PATH: &str = "recovery.json";
+
+/// Everything a restore puts back, which is also all a project keeps to itself, see `projects`.
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    /// Seconds since the Unix epoch.
+    pub saved: u64,
+    settings: Settings,
+    scan_settings: ScanSettings,
+    /// By index like `scan::MAP`.
+    pub map: Vec<Option<[f32; 2]>>,
+    /// Position, radius and presence of each ignored source.
+    ignored: Vec<([f32; 2], f32, f32)>,
+    segments: Vec<Segment>,
+    /// The camera's angle of view, see `lens`.
+    lens: Option<Lens>,
+}
+
+impl Session {
+    pub fn current(segments: &[Segment]) -> Self {
+        Self {
+            saved: 0,
+            settings: SETTINGS.read().unwrap().clone(),
+            scan_settings: scan::SETTINGS.read().unwrap().clone(),
+            map: scan::MAP
+                .read()
+                .unwrap()
+                .iter()
+                .map(|pos| pos.map(|pos| [pos.x, pos.y]))
+                .collect(),
+            ignored: ignored::IGNORED
+                .read()
+                .unwrap()
+                .iter()
+                .map(|source| {
+                    ([source.position.x, source.position.y], source.radius, source.presence)
+                })
+                .collect(),
+            segments: segments.to_vec(),
+            lens: *lens::LENS.read().unwrap(),
+        }
+    }
+
+    /// The current settings and segments with nothing scanned or ignored yet, for a new project.
+    pub fn fresh(segments: &[Segment]) -> Self {
+        Self {
+            map: Vec::new(),
+            ignored: Vec::new(),
+            ..Self::current(segments)
+        }
+    }
+
+    /// Puts the settings, map and ignored sources back, and hands back the segments for the
+    /// controller panel.
+    pub fn restore(self) -> Vec<Segment> {
+        *SETTINGS.write().unwrap() = self.settings;
+        *scan::SETTINGS.write().unwrap() = self.scan_settings;
+        *lens::LENS.write().unwrap() = self.lens;
+
+        scan::reset(self.map.len());
+        *scan::MAP.write().unwrap() = self
+            .map
+            .into_iter()
+            .map(|pos| pos.map(|[x, y]| Pos2::new(x, y)))
+            .collect();
+
+        *ignored::IGNORED.write().unwrap() = self
+            .ignored
+            .into_iter()
+            .map(|([x, y], radius, presence)| Source {
+                position: Pos2::new(x, y),
+                radius,
+                presence,
+            })
+            .collect();
+
+        self.segments
+    }
+}
+
+/// Writes the session out every `interval`, when anything changed.
+pub struct Autosave {
+    interval: Duration,
+    last: Instant,
+    /// The last session written, to tell whether there's anything new.
+    written: String,
+}
+
+impl Autosave {
+    /// A zero `interval` turns autosaving off.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: Instant::now(),
+            written: String::new(),
+        }
+    }
+
+    /// Saves the session along with `segments` if it's time.
+    pub fn tick(&mut self, segments: &[Segment]) {
+        if self.interval.is_zero() || self.last.elapsed() < self.interval {
+            return;
+        }
+        self.last = Instant::now();
+
+        let mut session = Session::current(segments);
+        let Ok(unchanged) = serde_json::to_string(&session) else {
+            return;
+        };
+        if unchanged == self.written {
+            return;
+        }
+
+        session.saved = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        match write(&session) {
+            Ok(()) => self.written = unchanged,
+            Err(e) => warn!("Failed to autosave to {PATH}: {e}"),
+        }
+    }
+}
+
+/// Writes next to the recovery file first so a crash while writing leaves the last one whole.
+fn write(session: &Session) -> anyhow::Result<()> {
+    let partial = format!("{PATH}.partial");
+    fs::write(&partial, serde_json::to_vec(session)?)?;
+    fs::rename(partial, PATH)?;
+    Ok(())
+}
+
+/// The session a crash left behind, if there is one.
+pub fn load() -> Option<Session> {
+    if !Path::new(PATH).exists() {
+        return None;
+    }
+
+    match fs::read(PATH)
+        .map_err(anyhow::Error::from)
+        .and_then(|data| Ok(serde_json::from_slice(&data)?))
+    {
+        Ok(session) => Some(session),
+        Err(e) => {
+            warn!("Ignoring unreadable recovery file {PATH}: {e}");
+            None
+        }
+    }
+}
+
+/// Removes the recovery file, after a clean exit or when the session isn't wanted back.
+pub fn discard() {
+    if Path::new(PATH).exists() {
+        if let Err(e) = fs::remove_file(PATH) {
+            warn!("Failed to remove {PATH}: {e}");
+        }
+    }
+}
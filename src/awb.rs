@@ -0,0 +1,128 @@
+//! Holding the camera's white balance still for the length of a scan. Auto white balance follows
+//! the LEDs lighting up different parts of the view, shifting the color of everything and with it
+//! what the thresholds let through, so a scan can quietly stop finding LEDs halfway.
+//!
+//! It's locked by turning the camera's automatic white balance off, which leaves it at whatever it
+//! had settled on, and turned back on after the scan. That works on the V4L2 device being read,
+//! through `v4l2-ctl`, and on the ONVIF camera connected for PTZ, through its imaging service.
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use anyhow::Context;
+use tracing::{info, warn};
+
+use crate::{
+    ptz::{self, Ptz},
+    v4l2,
+};
+
+/// Whether scans lock the white balance.
+pub static LOCK: AtomicBool = AtomicBool::new(false);
+
+const V4L2_CTL: &str = "v4l2-ctl";
+
+/// What the auto white balance control is called, by newer kernels first.
+const V4L2_CONTROLS: [&str; 2] = ["white_balance_automatic", "white_balance_temperature_auto"];
+
+/// A camera whose white balance was locked, to unlock after the scan.
+pub enum Locked {
+    V4l2 { device: PathBuf, control: &'static str },
+    Onvif(Arc<Ptz>),
+}
+
+/// Locks the white balance of the cameras in use that balance automatically. Failures are only
+/// logged, scanning with it unlocked beats not scanning.
+pub fn lock() -> Vec<Locked> {
+    let mut locked = Vec::new();
+
+    if let Some(device) = v4l2::OPENED.read().unwrap().clone() {
+        match lock_v4l2(&device) {
+            Ok(Some(control)) => locked.push(Locked::V4l2 { device, control }),
+            Ok(None) => {}
+            Err(e) => warn!("Can't lock the white balance of {}: {e:#}", device.display()),
+        }
+    }
+
+    if let Some(camera) = ptz::CAMERA.read().unwrap().clone() {
+        match camera.auto_white_balance() {
+            Ok(Some(true)) => match camera.set_auto_white_balance(false) {
+                Ok(()) => {
+                    info!("Locked the white balance of the ONVIF camera");
+                    locked.push(Locked::Onvif(camera));
+                }
+                Err(e) => warn!("Can't lock the white balance of the ONVIF camera: {e:#}"),
+            },
+            Ok(_) => {}
+            Err(e) => warn!("Can't read the white balance of the ONVIF camera: {e:#}"),
+        }
+    }
+
+    locked
+}
+
+/// Turns automatic white balance back on for everything `lock` turned it off for.
+pub fn unlock(locked: Vec<Locked>) {
+    for locked in locked {
+        let result = match &locked {
+            Locked::V4l2 { device, control } => set_v4l2(device, control, 1),
+            Locked::Onvif(camera) => camera.set_auto_white_balance(true),
+        };
+        match result {
+            Ok(()) => info!("Unlocked the white balance"),
+            Err(e) => warn!("Can't unlock the white balance: {e:#}"),
+        }
+    }
+}
+
+/// Turns auto white balance off if it's on, returning the control it did that with.
+fn lock_v4l2(device: &Path) -> anyhow::Result<Option<&'static str>> {
+    for control in V4L2_CONTROLS {
+        let Some(value) = get_v4l2(device, control)? else {
+            continue;
+        };
+        if value == 0 {
+            return Ok(None);
+        }
+        set_v4l2(device, control, 0)?;
+        info!("Locked the white balance of {}", device.display());
+        return Ok(Some(control));
+    }
+    Ok(None)
+}
+
+/// The value of `control`, `None` if the device doesn't have it.
+fn get_v4l2(device: &Path, control: &str) -> anyhow::Result<Option<i64>> {
+    let output = Command::new(V4L2_CTL)
+        .arg("-d")
+        .arg(device)
+        .arg(format!("--get-ctrl={control}"))
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run {V4L2_CTL}, is it installed?"))?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    // Like `white_balance_automatic: 1`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout
+        .rsplit_once(':')
+        .and_then(|(_, value)| value.trim().parse().ok()))
+}
+
+fn set_v4l2(device: &Path, control: &str, value: i64) -> anyhow::Result<()> {
+    let status = Command::new(V4L2_CTL)
+        .arg("-d")
+        .arg(device)
+        .arg(format!("--set-ctrl={control}={value}"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .with_context(|| format!("Failed to run {V4L2_CTL}, is it installed?"))?;
+    anyhow::ensure!(status.success(), "{V4L2_CTL} failed to set {control}");
+    Ok(())
+}
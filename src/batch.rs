@@ -0,0 +1,220 @@
+//! Mapping recorded scans without a camera or controller attached, for calibrating a run of
+//! identical props unattended.
+//!
+//! Each session in the directory is one of:
+//!
+//! - a directory of PNG or JPEG frames, one per LED with only that LED lit, in the order of their
+//!   file names
+//! - a video of a sequential scan with a JSON sidecar of the same name, `prop-1.mp4` and
+//!   `prop-1.json`, saying when each LED was lit:
+//!
+//! ```json
+//! { "led_count": 50, "start": 2.0, "interval": 0.5 }
+//! ```
+//!
+//! LED `i` is taken from the frame in the middle of its interval, from `start + i * interval` to
+//! the next. Either kind of session can have a `settings` object in its sidecar with the
+//! detection settings to use, a directory of frames in a `session.json` inside it.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::{info, warn};
+use video_rs::{Decoder, Locator};
+
+use crate::{
+    pipeline::{self, Settings},
+    scan, stats, Led,
+};
+
+/// Extensions of the files taken as frames, what `image` is built to read.
+const FRAME_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+const VIDEO_EXTENSIONS: [&str; 5] = ["mp4", "mkv", "mov", "avi", "webm"];
+/// Name of the sidecar inside a directory of frames.
+const FRAMES_SIDECAR: &str = "session.json";
+
+#[derive(Deserialize, Default)]
+struct Sidecar {
+    /// Needed for videos, a directory of frames has one LED per frame.
+    led_count: Option<usize>,
+    /// When the first LED came on, in seconds into the video.
+    #[serde(default)]
+    start: f64,
+    /// How long each LED was lit for, in seconds.
+    interval: Option<f64>,
+    settings: Option<Settings>,
+}
+
+enum Source {
+    Frames(Vec<PathBuf>),
+    Video(PathBuf),
+}
+
+pub struct Session {
+    pub name: String,
+    source: Source,
+    sidecar: Sidecar,
+}
+
+/// The sessions in `directory`, by name. Videos without a sidecar and anything else are skipped
+/// with a warning.
+pub fn sessions(directory: &Path) -> anyhow::Result<Vec<Session>> {
+    let mut entries = fs::read_dir(directory)
+        .with_context(|| format!("Failed to read {}", directory.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    let mut sessions = Vec::new();
+    for path in entries {
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let name = name.to_owned();
+
+        if path.is_dir() {
+            let frames = files_with(&path, &FRAME_EXTENSIONS)?;
+            if frames.is_empty() {
+                warn!("Skipping {}, there are no frames in it", path.display());
+                continue;
+            }
+            let sidecar = read_sidecar(&path.join(FRAMES_SIDECAR))?.unwrap_or_default();
+            sessions.push(Session {
+                name,
+                source: Source::Frames(frames),
+                sidecar,
+            });
+        } else if has_extension(&path, &VIDEO_EXTENSIONS) {
+            let Some(sidecar) = read_sidecar(&path.with_extension("json"))? else {
+                warn!(
+                    "Skipping {}, it has no sidecar saying when the LEDs were lit",
+                    path.display()
+                );
+                continue;
+            };
+            sessions.push(Session {
+                name,
+                source: Source::Video(path),
+                sidecar,
+            });
+        }
+    }
+    Ok(sessions)
+}
+
+impl Session {
+    /// Detects the LEDs, with the settings from the sidecar or else the current ones. LEDs not
+    /// found in their frame are left out of the map.
+    pub fn map(&self) -> anyhow::Result<Vec<Led>> {
+        let settings = self
+            .sidecar
+            .settings
+            .clone()
+            .unwrap_or_else(|| pipeline::SETTINGS.read().unwrap().clone());
+
+        let mut leds = Vec::new();
+        let mut locate = |index: usize, rgb: &[u8], width: usize| -> anyhow::Result<()> {
+            let points = led_detect::detect_rgb(rgb, width, &settings)?;
+            if let Some(position) = scan::pick(&points, index) {
+                leds.push(Led {
+                    segment: 0,
+                    index,
+                    position: [position.x, position.y, 0.0],
+                });
+            }
+            Ok(())
+        };
+
+        match &self.source {
+            Source::Frames(frames) => {
+                for (index, path) in frames.iter().enumerate() {
+                    let image = image::open(path)
+                        .with_context(|| format!("Failed to read {}", path.display()))?
+                        .into_rgb8();
+                    locate(index, image.as_raw(), image.width() as usize)?;
+                }
+            }
+            Source::Video(path) => {
+                let led_count = self
+                    .sidecar
+                    .led_count
+                    .context("The sidecar needs a led_count")?;
+                let interval = self
+                    .sidecar
+                    .interval
+                    .context("The sidecar needs an interval")?;
+                anyhow::ensure!(interval > 0.0, "The interval has to be more than 0 seconds");
+
+                let mut decoder = Decoder::new(&Locator::Path(path.clone()))
+                    .map_err(|e| anyhow::anyhow!("Failed to open {}: {e}", path.display()))?;
+                let time_base = stats::seconds(decoder.time_base());
+                let mut rgb = Vec::new();
+
+                let mut index = 0;
+                for frame in decoder.decode_raw_iter() {
+                    if index == led_count {
+                        break;
+                    }
+                    let frame = frame.map_err(|e| anyhow::anyhow!("Failed to decode: {e}"))?;
+                    let Some(pts) = frame.pts() else {
+                        continue;
+                    };
+                    let middle = self.sidecar.start + (index as f64 + 0.5) * interval;
+                    if (pts as f64 * time_base) < middle {
+                        continue;
+                    }
+
+                    let (width, height) = (frame.width() as usize, frame.height() as usize);
+                    rgb.clear();
+                    pipeline::packed_rgb(frame.data(0), frame.stride(0), width, height, &mut rgb);
+                    locate(index, &rgb, width)?;
+                    index += 1;
+                }
+                if index < led_count {
+                    warn!("{} ended after {index} of {led_count} LEDs", path.display());
+                }
+            }
+        }
+
+        Ok(leds)
+    }
+
+    /// How many LEDs the session should find.
+    pub fn led_count(&self) -> Option<usize> {
+        match &self.source {
+            Source::Frames(frames) => Some(frames.len()),
+            Source::Video(_) => self.sidecar.led_count,
+        }
+    }
+}
+
+/// `None` when there's no sidecar at `path`.
+fn read_sidecar(path: &Path) -> anyhow::Result<Option<Sidecar>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = fs::read_to_string(path)?;
+    let sidecar = serde_json::from_str(&json)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    info!("Read {}", path.display());
+    Ok(Some(sidecar))
+}
+
+fn files_with(directory: &Path, extensions: &[&str]) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = fs::read_dir(directory)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    files.retain(|path| has_extension(path, extensions));
+    files.sort();
+    Ok(files)
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension.to_ascii_lowercase().as_str()))
+}
@@ -0,0 +1,62 @@
+# Imports LEDs exported by LED Position Calibrator into Blender.
+#
+# Open this in Blender's text editor and press Run Script, or run
+# `blender --python <this file>`. It makes an "LEDs" collection with an empty
+# per LED, named and ordered by its index, and an "LED points" mesh with a
+# vertex per LED for geometry nodes. Both carry the index as `index`, plus
+# `segment` and `segment_index` for projects with several strips.
+#
+# Running it again replaces what an earlier run made.
+
+import json
+
+import bpy
+
+# Written by the export, point it at another map if it moved.
+MAP_PATH = None
+
+# Blender units per unit of the map, which is camera pixels unless the export
+# scaled it. 0.01 makes a 1920 pixel wide view about 19 m across.
+SCALE = 0.01
+
+# Size the LED empties are shown at, in Blender units.
+EMPTY_SIZE = 0.02
+
+
+def main():
+    with open(MAP_PATH) as file:
+        leds = json.load(file)["leds"]
+
+    collection = bpy.data.collections.get("LEDs")
+    if collection is None:
+        collection = bpy.data.collections.new("LEDs")
+        bpy.context.scene.collection.children.link(collection)
+    for obj in list(collection.objects):
+        bpy.data.objects.remove(obj)
+
+    for led in leds:
+        obj = bpy.data.objects.new("LED %05d" % led["index"], None)
+        obj.empty_display_type = "SPHERE"
+        obj.empty_display_size = EMPTY_SIZE
+        obj.location = [c * SCALE for c in led["position"]]
+        obj["index"] = led["index"]
+        obj["segment"] = led["segment"]
+        obj["segment_index"] = led["segment_index"]
+        collection.objects.link(obj)
+
+    mesh = bpy.data.meshes.get("LED points") or bpy.data.meshes.new("LED points")
+    mesh.clear_geometry()
+    mesh.from_pydata([[c * SCALE for c in led["position"]] for led in leds], [], [])
+    for name in ("index", "segment", "segment_index"):
+        attribute = mesh.attributes.get(name) or mesh.attributes.new(name, "INT", "POINT")
+        attribute.data.foreach_set("value", [led[name] for led in leds])
+    mesh.update()
+
+    # Removed with the rest of the collection above
+    points = bpy.data.objects.new("LED points", mesh)
+    collection.objects.link(points)
+
+    print("Imported %d LEDs from %s" % (len(leds), MAP_PATH))
+
+
+main()
@@ -0,0 +1,1242 @@
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use eframe::epaint::{Pos2, TextureHandle};
+use led_detect::synthetic::{self, Accuracy, Scene};
+use tracing::{error, info, warn};
+use video_rs::Url;
+
+use crate::{
+    alerts, awb, batch,
+    controller::{discovery, Channels, ControllerConfig, ControllerKind, PixelOrder},
+    depth, detected_leds,
+    diff::Diff,
+    drift::DriftAction,
+    export::{self, ExportFormat},
+    exporters, grid,
+    i18n::Language,
+    ignored, issues,
+    lens::{self, Lens},
+    ordering,
+    pipeline::{self, Denoise, DetectionMode, Transport},
+    pixel_order, preview, ptz, recording, reflections, report, rpicam,
+    scan::{self, Priors, ScanMode, ScanOrder},
+    segments::{self, Segment},
+    simulator, stereo, timelapse,
+    transform::Transform,
+    v4l2, web, Led,
+};
+
+pub const DEFAULT_URL: &str = "rtsp://192.168.0.101";
+
+/// How long the headless commands wait for the stream to deliver a frame.
+const STREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often `serve` checks whether a scan finished.
+const SERVE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// Options for the GUI when no subcommand is given
+    #[command(flatten)]
+    pub gui: GuiArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Open the calibrator window (the default)
+    Gui(GuiArgs),
+
+    /// Scan every LED without opening a window
+    Scan {
+        // Boxed, as the scan takes so many options that it would make every command as big
+        #[command(flatten)]
+        stream: Box<StreamArgs>,
+        #[command(flatten)]
+        controller: ControllerArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        scan_settings: ScanSettingsArgs,
+        /// Rhai scan script to run instead of the sequential scan
+        #[arg(long)]
+        script: Option<PathBuf>,
+        /// Detect on the difference between a frame with each LED off and one with it on
+        #[arg(long, conflicts_with = "script")]
+        strobe: bool,
+        /// Scan all segments at once, each lit in its own color, six at a time with more segments
+        #[arg(long, conflicts_with_all = ["script", "strobe"])]
+        interleave: bool,
+        /// Light all LEDs at once in a sequence of colors spelling out their index
+        #[arg(long, conflicts_with_all = ["script", "strobe", "interleave"])]
+        color_code: bool,
+        /// Earlier map of the same installation, only detections near each LED's old position
+        /// are accepted
+        #[arg(long)]
+        prior: Option<PathBuf>,
+        /// How far an LED may have moved since the prior map, in pixels
+        #[arg(long, default_value_t = 40.0)]
+        prior_radius: f32,
+        /// Blink this LED first to measure the latency, and pace the scan by that
+        #[arg(long)]
+        measure_latency: Option<usize>,
+        /// Don't watch the installation with every LED off first to learn which light sources to
+        /// ignore
+        #[arg(long)]
+        no_baseline: bool,
+        /// Light every LED red, green and blue after the scan to check for swapped channels and
+        /// color faults, which end up in --issues
+        #[arg(long)]
+        check_colors: bool,
+        /// Show a desktop notification when the scan ends, stalls or stops detecting anything
+        #[arg(long)]
+        notify: bool,
+        /// Play a sound when the scan ends, stalls or stops detecting anything
+        #[arg(long)]
+        sound: bool,
+        /// Light each segment red and green first and set its pixel order from what the camera
+        /// sees, which needs a detection mode that passes colored light
+        #[arg(long)]
+        detect_pixel_order: bool,
+        /// When more than one blob lights up with an LED in a sequential scan, like an LED and
+        /// its reflection, blink it and keep the blob that follows it the most
+        #[arg(long)]
+        resolve_reflections: bool,
+        /// Turn the camera's auto white balance off while scanning, so it doesn't shift the
+        /// colors halfway: a V4L2 device through v4l2-ctl, or the ONVIF camera of --regions
+        #[arg(long)]
+        lock_white_balance: bool,
+        /// Capture a frame with every LED off first and subtract it from the frames scanned
+        #[arg(long)]
+        dark_frame: bool,
+        /// Point a PTZ camera at each of the regions in this JSON in turn, as saved from the PTZ
+        /// window, and stitch the maps of all of them together
+        #[arg(long, conflicts_with_all = ["script", "strobe", "interleave", "color_code"])]
+        regions: Option<PathBuf>,
+        /// Scan at half resolution first, then light each LED found again and place it precisely
+        /// from a full resolution crop around it
+        #[arg(long, conflicts_with_all = ["script", "strobe", "interleave", "color_code", "regions"])]
+        coarse_to_fine: bool,
+        /// Light several LEDs far enough apart per step, going by --prior, and give each the blob
+        /// near where it's expected. LEDs that aren't clear are lit again on their own.
+        #[arg(
+            long,
+            requires = "prior",
+            conflicts_with_all = ["script", "strobe", "interleave", "color_code", "regions", "coarse_to_fine"]
+        )]
+        grouped: bool,
+        /// Continue the interrupted scan in scan-progress.jsonl instead of starting over
+        #[arg(long, conflicts_with_all = ["script", "strobe", "color_code", "coarse_to_fine"])]
+        resume: bool,
+        /// Serve the web UI, the HTTP API and metrics on this address while scanning, like
+        /// 0.0.0.0:8080
+        #[arg(long)]
+        web: Option<String>,
+        /// Save an annotated frame for every LED captured to this directory, as numbered PNGs
+        #[arg(long)]
+        record: Option<PathBuf>,
+        /// Save every captured frame blended into one long exposure to this PNG
+        #[arg(long)]
+        timelapse: Option<PathBuf>,
+        /// Write the dead and dim LEDs noticed during the scan to this text file
+        #[arg(long)]
+        issues: Option<PathBuf>,
+        /// Write the peak brightness of every LED found to this CSV file
+        #[arg(long)]
+        brightness: Option<PathBuf>,
+        /// Also write a calibration report with the layout, every LED, the failures and the scan
+        /// settings, as an HTML file named after --output
+        #[arg(long)]
+        report: bool,
+    },
+
+    /// Wait for scans to be started over the HTTP API, see the web UI, writing the map out after
+    /// each one that finishes
+    Serve {
+        #[command(flatten)]
+        stream: StreamArgs,
+        #[command(flatten)]
+        controller: ControllerArgs,
+        #[command(flatten)]
+        output: OutputArgs,
+        #[command(flatten)]
+        scan_settings: ScanSettingsArgs,
+        /// Address to serve the web UI and API on
+        #[arg(long, default_value = "0.0.0.0:8080")]
+        listen: String,
+    },
+
+    /// Detect blobs in the stream and write them out, unordered
+    Detect {
+        #[command(flatten)]
+        stream: StreamArgs,
+        /// How long to let the stream settle before taking the detections
+        #[arg(long, default_value_t = 2.0)]
+        seconds: f64,
+        /// File to write, prints JSON to stdout if left out
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        #[arg(long, value_enum)]
+        format: Option<ExportFormat>,
+        #[command(flatten)]
+        transform: Transform,
+        /// Fail unless exactly this many blobs are detected
+        #[arg(long)]
+        expect: Option<usize>,
+        /// Number the blobs along a path through them instead, for a single strip with every LED
+        /// lit
+        #[arg(long)]
+        order: bool,
+        /// Where the strip starts, as X,Y in pixels, for --order
+        #[arg(long, requires = "order", value_delimiter = ',', num_args = 2)]
+        start: Option<Vec<f32>>,
+        /// Where the strip ends, as X,Y in pixels, for --order
+        #[arg(long, requires = "order", value_delimiter = ',', num_args = 2)]
+        end: Option<Vec<f32>>,
+    },
+
+    /// Convert a map saved as JSON to another format
+    Export {
+        input: PathBuf,
+        #[command(flatten)]
+        output: OutputArgs,
+    },
+
+    /// Compare two maps saved as JSON LED by LED, failing when an LED moved further than
+    /// --max-shift
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+        /// Write where each LED went and how far to this CSV file
+        #[arg(long)]
+        csv: Option<PathBuf>,
+        /// Fail if any LED moved further than this, in the maps' units
+        #[arg(long)]
+        max_shift: Option<f32>,
+    },
+
+    /// Map every recorded session in a directory without a camera or controller, see `batch` for
+    /// what a session looks like
+    Batch {
+        directory: PathBuf,
+        /// Directory to write the maps to, each named after its session
+        #[arg(long, short)]
+        output: PathBuf,
+        #[arg(long, value_enum, default_value = "json")]
+        format: ExportFormat,
+        /// Snap each map to the best fitting grid first, for LED matrices
+        #[arg(long)]
+        grid: bool,
+        #[command(flatten)]
+        transform: Transform,
+        /// JSON with the detection settings for sessions without their own, as a sidecar's
+        /// `settings`
+        #[arg(long)]
+        settings: Option<PathBuf>,
+    },
+
+    /// Run detection on made up frames with LEDs at known positions and report how close it gets,
+    /// failing when it's worse than the given limits
+    Accuracy {
+        /// Number of frames, each with the LEDs placed differently
+        #[arg(long, default_value_t = 10)]
+        frames: u64,
+        #[arg(long, default_value_t = 50)]
+        leds: usize,
+        #[arg(long, value_delimiter = 'x', num_args = 2, default_values_t = [1280, 720])]
+        size: Vec<usize>,
+        /// Radius of an LED's spot, in pixels
+        #[arg(long, default_value_t = 4.0)]
+        radius: f32,
+        /// Standard deviation of the blur, in pixels
+        #[arg(long, default_value_t = 1.0)]
+        blur: f32,
+        /// Standard deviation of the sensor noise, on the 0-255 scale
+        #[arg(long, default_value_t = 4.0)]
+        noise: f32,
+        /// Number of dim rectangles in the background
+        #[arg(long, default_value_t = 10)]
+        clutter: usize,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        /// How far a detection may be from an LED to count as finding it, in pixels
+        #[arg(long, default_value_t = 3.0)]
+        tolerance: f32,
+        /// Fail if less than this share of the LEDs is found
+        #[arg(long)]
+        min_recall: Option<f32>,
+        /// Fail if the found LEDs are off by more than this on average, in pixels
+        #[arg(long)]
+        max_error: Option<f32>,
+    },
+
+    /// Look for WLED and ESPixelStick controllers on the LAN over mDNS, printing each as a line of
+    /// JSON with its name, kind, address and LED count if it says
+    Discover {
+        /// How long to wait for answers, in seconds
+        #[arg(long, default_value_t = 3.0)]
+        seconds: f64,
+    },
+}
+
+#[derive(Args)]
+pub struct GuiArgs {
+    #[command(flatten)]
+    pub stream: StreamArgs,
+    /// Also serve a web UI with a preview and the main controls on this address, like
+    /// 0.0.0.0:8080
+    #[arg(long)]
+    pub web: Option<String>,
+    /// Start full screen with only the video and overlay, for showing on a projector. F11
+    /// toggles it, Escape leaves it
+    #[arg(long)]
+    pub fullscreen: bool,
+    /// Screen position of the monitor to go full screen on, as X,Y, like 1920,0 for the one to
+    /// the right of a 1080p main monitor
+    #[arg(long, value_delimiter = ',', num_args = 2, value_names = ["X", "Y"])]
+    pub monitor: Option<Vec<f32>>,
+    /// Scale the interface by this factor instead of following the system
+    #[arg(long)]
+    pub ui_scale: Option<f32>,
+    /// Language of the window, the one last picked or the system's by default
+    #[arg(long, value_enum)]
+    pub language: Option<Language>,
+    /// Save the session to a recovery file this often, in seconds, to restore after a crash. 0
+    /// turns it off
+    #[arg(long, default_value_t = 30)]
+    pub autosave: u64,
+}
+
+#[derive(Args, Clone)]
+pub struct StreamArgs {
+    /// Video stream to read frames from
+    #[arg(long, default_value = DEFAULT_URL)]
+    pub url: Url,
+    /// User to log in to the stream as, instead of putting it in --url
+    #[arg(long)]
+    pub username: Option<String>,
+    /// Password for --username
+    #[arg(long, requires = "username")]
+    pub password: Option<String>,
+    /// How an RTSP stream is carried
+    #[arg(long, value_enum, default_value_t)]
+    pub transport: Transport,
+    /// Show this stream in the window instead of --url, like the camera's low resolution
+    /// substream, while --url goes on feeding detection and scans at full resolution. It has to
+    /// show the same view
+    #[arg(long)]
+    pub preview_url: Option<Url>,
+    /// Update the video in the window at most this many times a second
+    #[arg(long)]
+    pub preview_fps: Option<f32>,
+    /// Show the video in the window at 1/N of its resolution across and down
+    #[arg(long, value_name = "N")]
+    pub preview_downscale: Option<usize>,
+    /// Threshold the decoded YUV frames directly instead of converting every frame to RGB first
+    #[arg(long)]
+    pub yuv: bool,
+    /// Read from this Raspberry Pi camera module through rpicam-vid instead of --url
+    #[arg(long, num_args = 0..=1, default_missing_value = "0")]
+    pub rpicam: Option<usize>,
+    /// Show the LEDs of the simulated controller instead of reading a stream, to try things out
+    /// without any hardware
+    #[arg(long, conflicts_with = "rpicam")]
+    pub simulate: bool,
+    /// Read color and depth from a RealSense camera instead of --url, for LEDs placed in 3D
+    #[cfg(feature = "realsense")]
+    #[arg(long, conflicts_with_all = ["rpicam", "simulate", "stereo_url"])]
+    pub realsense: bool,
+    /// Read from this local capture device, like /dev/video0, instead of --url
+    #[arg(long, conflicts_with_all = ["rpicam", "simulate"])]
+    pub device: Option<PathBuf>,
+    /// Resolution to capture from --device at, the largest it can by default
+    #[arg(long, value_delimiter = 'x', num_args = 2, requires = "device")]
+    pub device_size: Option<Vec<usize>>,
+    /// Second camera of a rectified stereo pair, to the right of the first, for placing the LEDs
+    /// in 3D as they're scanned
+    #[arg(long, requires = "stereo_calibration")]
+    pub stereo_url: Option<Url>,
+    /// JSON with the baseline of the stereo pair, and its focal length and principal point unless
+    /// --fov or --focal-length gives them
+    #[arg(long, requires = "stereo_url")]
+    pub stereo_calibration: Option<PathBuf>,
+    /// Horizontal angle of view of the camera in degrees, standing in for a calibration in rough
+    /// setups, see --stereo-calibration and --distance
+    #[arg(long, conflicts_with = "focal_length")]
+    pub fov: Option<f32>,
+    /// Focal length of the camera in millimeters instead of --fov, a 35 mm equivalent unless
+    /// --sensor-width is given
+    #[arg(long)]
+    pub focal_length: Option<f32>,
+    /// Width of the camera's sensor in millimeters, for --focal-length
+    #[arg(long, requires = "focal_length")]
+    pub sensor_width: Option<f32>,
+    /// Resolution to run the Raspberry Pi camera at, the width a multiple of 64
+    #[arg(long, value_delimiter = 'x', num_args = 2, default_values_t = [1280, 720])]
+    pub rpicam_size: Vec<usize>,
+    #[arg(long, default_value_t = 30.0)]
+    pub rpicam_framerate: f64,
+    /// Detect anything brighter than this luma (0-255) instead of using the HSV range
+    #[arg(long)]
+    pub brightness: Option<f64>,
+    /// Detect bright, barely saturated blobs, for white LEDs and the white channel of RGBW strips
+    #[arg(long, conflicts_with = "brightness")]
+    pub white_leds: bool,
+    /// Treat blobs closer together than this many pixels as a single LED
+    #[arg(long)]
+    pub merge_radius: Option<f32>,
+    /// Area of a single LED's blob in pixels, blobs well over this are split into several LEDs
+    #[arg(long)]
+    pub blob_area: Option<f32>,
+    /// Multiply red, green and blue by these before thresholding, for a camera with its white
+    /// balance off
+    #[arg(long, value_delimiter = ',', num_args = 3)]
+    pub gains: Option<Vec<f32>>,
+    /// Gamma to apply before thresholding, above 1 to bring up dim LEDs
+    #[arg(long)]
+    pub gamma: Option<f32>,
+    /// Contrast to apply before thresholding, stretching around mid gray
+    #[arg(long)]
+    pub contrast: Option<f32>,
+    /// Gaussian blur this many pixels wide before thresholding, to steady blobs on noisy streams
+    #[arg(long)]
+    pub blur: Option<i32>,
+    /// Median blur this many pixels wide before thresholding, which keeps edges sharper
+    #[arg(long, conflicts_with = "blur")]
+    pub median_blur: Option<i32>,
+    /// Drop blobs smaller than this many pixels
+    #[arg(long)]
+    pub min_area: Option<f32>,
+    /// Drop blobs bigger than this many pixels
+    #[arg(long)]
+    pub max_area: Option<f32>,
+}
+
+impl StreamArgs {
+    /// Starts reading frames from wherever these point, see `pipeline::spawn_decoder`, and the
+    /// substream for the window if there's one. The threads stop with `pipeline::switch_source`.
+    pub fn spawn_decoder(&self, texture: Option<TextureHandle>) -> Vec<JoinHandle<()>> {
+        if let Some(device) = &self.device {
+            let size = self
+                .device_size
+                .as_deref()
+                .and_then(|size| size.try_into().ok());
+            return vec![pipeline::spawn_v4l2(device.clone(), size, texture)];
+        }
+        if self.simulate {
+            return vec![simulator::spawn_camera(texture)];
+        }
+        #[cfg(feature = "realsense")]
+        if self.realsense {
+            return vec![pipeline::spawn_realsense(texture)];
+        }
+
+        match self.rpicam {
+            Some(index) => vec![pipeline::spawn_rpicam(
+                rpicam::Camera {
+                    index,
+                    width: self.rpicam_size[0],
+                    height: self.rpicam_size[1],
+                    framerate: self.rpicam_framerate,
+                },
+                texture,
+            )],
+            None => {
+                // The window gets the substream, the main stream only feeds detection and scans
+                let mut handles = Vec::new();
+                let texture = match (&self.preview_url, texture) {
+                    (Some(preview), Some(texture)) => {
+                        handles.push(preview::spawn_substream(
+                            self.with_login(preview),
+                            self.transport,
+                            texture,
+                        ));
+                        None
+                    }
+                    (_, texture) => texture,
+                };
+                handles.push(pipeline::spawn_decoder(
+                    self.url(),
+                    self.transport,
+                    self.yuv,
+                    texture,
+                ));
+                handles
+            }
+        }
+    }
+
+    /// Whether none of the sources were picked, leaving the default stream.
+    pub fn is_default_source(&self) -> bool {
+        #[cfg(feature = "realsense")]
+        if self.realsense {
+            return false;
+        }
+
+        self.device.is_none()
+            && self.rpicam.is_none()
+            && !self.simulate
+            && self.url.as_str().trim_end_matches('/') == DEFAULT_URL
+    }
+
+    /// What detection settings are remembered by, the same for the same camera across runs.
+    pub fn profile_key(&self) -> String {
+        #[cfg(feature = "realsense")]
+        if self.realsense {
+            return "realsense".to_owned();
+        }
+
+        if let Some(path) = &self.device {
+            // By name, as the paths change with the order devices are plugged in
+            return match v4l2::scan().into_iter().find(|device| &device.path == path) {
+                Some(device) => format!("device:{}", device.name),
+                None => format!("device:{}", path.display()),
+            };
+        }
+        if self.simulate {
+            return "simulated".to_owned();
+        }
+        if let Some(index) = self.rpicam {
+            return format!("rpicam:{index}");
+        }
+        pipeline::redacted(&self.url).to_string()
+    }
+
+    /// Starts the second camera if there is one.
+    pub fn spawn_stereo(&self) -> anyhow::Result<Option<JoinHandle<()>>> {
+        let (Some(url), Some(calibration)) = (&self.stereo_url, &self.stereo_calibration) else {
+            return Ok(None);
+        };
+        let calibration = stereo::Calibration::load(calibration)?;
+        Ok(Some(stereo::spawn(url.clone(), self.transport, calibration)))
+    }
+
+    /// `url` with the credentials filled in.
+    fn url(&self) -> Url {
+        self.with_login(&self.url)
+    }
+
+    /// `url` with the credentials filled in, for streams from the same camera.
+    fn with_login(&self, url: &Url) -> Url {
+        let mut url = url.clone();
+        if let Some(username) = &self.username {
+            // Only fails for URLs that can't have credentials, which can't be streamed from
+            let _ = url.set_username(username);
+            let _ = url.set_password(self.password.as_deref());
+        }
+        url
+    }
+
+    /// Applies the detection options to `pipeline::SETTINGS`, the preview's to
+    /// `preview::SETTINGS` and the angle of view to `lens::LENS`.
+    pub fn apply(&self) {
+        let mut preview = preview::SETTINGS.write().unwrap();
+        if let Some(fps) = self.preview_fps {
+            preview.max_fps = fps;
+        }
+        if let Some(downscale) = self.preview_downscale {
+            preview.downscale = downscale.max(1);
+        }
+        drop(preview);
+
+        let sensor_width = self.sensor_width.unwrap_or(lens::FULL_FRAME_WIDTH);
+        let lens = match (self.fov, self.focal_length) {
+            (Some(fov), _) => Some(Lens { fov }),
+            (None, Some(focal_length)) => Some(Lens::from_focal_length(focal_length, sensor_width)),
+            (None, None) => None,
+        };
+        if lens.is_some() {
+            *lens::LENS.write().unwrap() = lens;
+        }
+
+        let mut settings = pipeline::SETTINGS.write().unwrap();
+
+        if let Some(brightness) = self.brightness {
+            settings.mode = DetectionMode::Brightness;
+            settings.brightness = brightness;
+        }
+        if self.white_leds {
+            settings.target_white();
+        }
+        if let Some(radius) = self.merge_radius {
+            settings.merge_radius = radius;
+        }
+        if let Some(area) = self.blob_area {
+            settings.blob_area = area;
+        }
+        if let Some(gains) = self
+            .gains
+            .as_deref()
+            .and_then(|gains| gains.try_into().ok())
+        {
+            settings.gains = gains;
+        }
+        if let Some(gamma) = self.gamma {
+            settings.gamma = gamma;
+        }
+        if let Some(contrast) = self.contrast {
+            settings.contrast = contrast;
+        }
+        if let Some(kernel) = self.blur {
+            settings.denoise = Denoise::Gaussian;
+            settings.denoise_kernel = kernel;
+        }
+        if let Some(kernel) = self.median_blur {
+            settings.denoise = Denoise::Median;
+            settings.denoise_kernel = kernel;
+        }
+        if let Some(area) = self.min_area {
+            settings.min_area = area;
+        }
+        if let Some(area) = self.max_area {
+            settings.max_area = area;
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ControllerArgs {
+    #[arg(long, value_enum, required_unless_present = "segments")]
+    controller: Option<ControllerKind>,
+    /// Controller address, see the GUI for what each type expects
+    #[arg(long, default_value = "")]
+    address: String,
+    /// Number of LEDs on the strip
+    #[arg(long, required_unless_present = "segments")]
+    leds: Option<usize>,
+    /// Which color each channel sent drives, for strips not wired RGB
+    #[arg(long, value_enum, default_value = "rgb", conflicts_with = "segments")]
+    pixel_order: PixelOrder,
+    /// What each LED has: rgb, rgbw for a white channel after the colors, or white for white-only
+    /// strips
+    #[arg(long, value_enum, default_value = "rgb", conflicts_with = "segments")]
+    channels: Channels,
+    /// JSON list of segments for projects with several strips, each with a name, kind, address,
+    /// led_count and optionally pixel_order, channels and zone: the min and max corner in frame
+    /// pixels the segment's LEDs are in, with a depth for the zones in front, and for sACN
+    /// optionally addressing: a list of first_led, universe, channel and order
+    #[arg(long, conflicts_with_all = ["controller", "leds"])]
+    segments: Option<PathBuf>,
+}
+
+impl ControllerArgs {
+    fn segments(self) -> anyhow::Result<Vec<Segment>> {
+        match (self.segments, self.controller, self.leds) {
+            (Some(path), _, _) => {
+                let file = std::fs::File::open(&path)
+                    .with_context(|| format!("Failed to open {}", path.display()))?;
+                serde_json::from_reader(std::io::BufReader::new(file))
+                    .with_context(|| format!("Failed to read {}", path.display()))
+            }
+            (None, Some(kind), Some(led_count)) => Ok(vec![Segment {
+                name: String::new(),
+                config: ControllerConfig {
+                    kind,
+                    address: self.address,
+                    led_count,
+                    addressing: Vec::new(),
+                    pixel_order: self.pixel_order,
+                    channels: self.channels,
+                },
+                zone: None,
+            }]),
+            _ => unreachable!("clap requires a controller or segments"),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ScanSettingsArgs {
+    /// Seconds to wait after lighting each LED, unless the latency is measured
+    #[arg(long)]
+    settle: Option<f64>,
+    /// Seconds each LED stays lit at least in the sequential scan
+    #[arg(long)]
+    on_time: Option<f64>,
+    /// Color to light each LED in, as R,G,B
+    #[arg(long, value_delimiter = ',', num_args = 3)]
+    scan_color: Option<Vec<u8>>,
+    /// Light only the white channel of each LED instead of --scan-color, for RGBW and white-only
+    /// strips
+    #[arg(long, conflicts_with = "scan_color")]
+    white_channel: bool,
+    /// Scale every color the scan lights by this, from 0 to 1, for LEDs that bloom at full
+    /// brightness
+    #[arg(long)]
+    scan_brightness: Option<f32>,
+    /// Capture each LED of a sequential scan a second time at this share of its color, for
+    /// scenes with LEDs near the camera that bloom and LEDs far away that only show at full
+    #[arg(long)]
+    second_brightness: Option<f32>,
+    /// Order the scans that light one LED at a time go through the LEDs in: sequential,
+    /// reverse, serpentine for every other segment backwards, random to spread drift over the
+    /// whole map, or a list of the only LEDs to scan like 0-49,100,199-150
+    #[arg(long)]
+    order: Option<String>,
+    /// LEDs a grouped scan lights per step at most
+    #[arg(long)]
+    group_size: Option<usize>,
+    /// How far apart LEDs lit together in a grouped scan are expected to be at least, in pixels
+    #[arg(long)]
+    group_separation: Option<f32>,
+    /// What to do when the camera moves during a scan: stop it so it can be put back and the
+    /// scan resumed, compensate by shifting the positions found after, or off
+    #[arg(long, value_enum)]
+    drift: Option<DriftAction>,
+}
+
+impl ScanSettingsArgs {
+    /// Applies the options to `scan::SETTINGS`.
+    fn apply(&self) -> anyhow::Result<()> {
+        let mut settings = scan::SETTINGS.write().unwrap();
+
+        let seconds = |seconds: f64| {
+            Duration::try_from_secs_f64(seconds).context("Times have to be positive seconds")
+        };
+        if let Some(settle) = self.settle {
+            settings.settle = seconds(settle)?;
+        }
+        if let Some(on_time) = self.on_time {
+            settings.on_time = seconds(on_time)?;
+        }
+        if let Some(color) = &self.scan_color {
+            settings.color = [color[0], color[1], color[2]];
+        }
+        if self.white_channel {
+            settings.white = true;
+        }
+        if let Some(brightness) = self.scan_brightness {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&brightness),
+                "--scan-brightness has to be between 0 and 1"
+            );
+            settings.brightness = brightness;
+        }
+        if let Some(share) = self.second_brightness {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&share),
+                "--second-brightness has to be between 0 and 1"
+            );
+            settings.second_brightness = Some(share);
+        }
+        if let Some(order) = &self.order {
+            let fixed = ScanOrder::FIXED
+                .into_iter()
+                .find(|fixed| fixed.name().eq_ignore_ascii_case(order));
+            settings.order = match fixed {
+                Some(fixed) => fixed,
+                None => {
+                    scan::parse_list(order).context("Bad --order")?;
+                    ScanOrder::Custom(order.clone())
+                }
+            };
+        }
+        if let Some(size) = self.group_size {
+            anyhow::ensure!(size > 0, "--group-size has to be at least 1");
+            settings.group_size = size;
+        }
+        if let Some(separation) = self.group_separation {
+            settings.group_separation = separation;
+        }
+        if let Some(drift) = self.drift {
+            settings.drift = drift;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Args)]
+pub struct OutputArgs {
+    /// File to write, the format is picked from the extension unless --format is given
+    #[arg(long, short)]
+    output: PathBuf,
+    #[arg(long, value_enum)]
+    format: Option<ExportFormat>,
+    /// Write with this exporter from the exporters directory instead of a built-in format, see
+    /// the GUI for the ones found
+    #[arg(long, conflicts_with = "format")]
+    exporter: Option<String>,
+    /// Snap the map to the best fitting grid first, for LED matrices
+    #[arg(long)]
+    grid: bool,
+    #[command(flatten)]
+    transform: Transform,
+}
+
+impl OutputArgs {
+    /// A file beside the output with the same name up to the extension, then `-suffix`.
+    fn next_to(&self, suffix: &str) -> PathBuf {
+        let stem = self
+            .output
+            .file_stem()
+            .map_or("map".into(), |stem| stem.to_string_lossy());
+        self.output.with_file_name(format!("{stem}-{suffix}"))
+    }
+
+    fn write(&self, leds: &[Led]) -> anyhow::Result<()> {
+        let exporter = match &self.exporter {
+            Some(name) => Some(exporters::find(name).with_context(|| {
+                format!("There's no exporter called {name} in {}", exporters::DIR)
+            })?),
+            None => None,
+        };
+        let format = self
+            .format
+            .or_else(|| ExportFormat::from_path(&self.output));
+        // Built-in formats go first by extension, an exporter only takes what they don't
+        let exporter = exporter.or_else(|| {
+            format
+                .is_none()
+                .then(|| exporters::from_path(&self.output))
+                .flatten()
+        });
+        let leds = if self.grid {
+            let fit = grid::fit(leds)?;
+            info!("{}", fit.summary());
+            fit.leds
+        } else {
+            leds.to_vec()
+        };
+
+        let leds = self.transform.apply(&leds);
+        match (exporter, format) {
+            (Some(exporter), _) => exporters::write(&*exporter, &self.output, &leds),
+            (None, Some(format)) => format.write(&self.output, &leds),
+            (None, None) => anyhow::bail!(
+                "Can't tell the format from the output file name, pass --format or --exporter"
+            ),
+        }
+    }
+}
+
+/// Runs one of the headless subcommands.
+pub fn run(command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::Gui(_) => unreachable!("the GUI is started from main"),
+
+        Command::Scan {
+            stream,
+            controller,
+            output,
+            scan_settings,
+            script,
+            strobe,
+            interleave,
+            color_code,
+            prior,
+            prior_radius,
+            measure_latency,
+            no_baseline,
+            check_colors,
+            notify,
+            sound,
+            detect_pixel_order,
+            resolve_reflections,
+            lock_white_balance,
+            dark_frame,
+            regions,
+            coarse_to_fine,
+            grouped,
+            resume,
+            web,
+            record,
+            timelapse,
+            issues,
+            brightness,
+            report,
+        } => {
+            scan_settings.apply()?;
+            ignored::LEARN_BEFORE_SCAN.store(!no_baseline, Ordering::Relaxed);
+            issues::CHECK_COLORS.store(check_colors, Ordering::Relaxed);
+            reflections::RESOLVE.store(resolve_reflections, Ordering::Relaxed);
+            awb::LOCK.store(lock_white_balance, Ordering::Relaxed);
+            alerts::DESKTOP.store(notify, Ordering::Relaxed);
+            alerts::SOUND.store(sound, Ordering::Relaxed);
+            if notify || sound {
+                alerts::spawn();
+            }
+            *recording::DIRECTORY.write().unwrap() = record;
+
+            let mode = match script {
+                Some(path) => ScanMode::Script(std::fs::read_to_string(path)?),
+                None if strobe => ScanMode::StrobeDiff,
+                None if interleave => ScanMode::Interleaved,
+                None if color_code => ScanMode::ColorCoded,
+                None if regions.is_some() => ScanMode::Regions,
+                None if coarse_to_fine => ScanMode::CoarseToFine,
+                None if grouped => ScanMode::Grouped,
+                None => ScanMode::Sequential,
+            };
+            if let Some(path) = regions {
+                let setup = ptz::Setup::load(&path)?;
+                let camera = ptz::Ptz::connect(&setup.address, &setup.username, &setup.password)?;
+                *ptz::CAMERA.write().unwrap() = Some(Arc::new(camera));
+                *ptz::REGIONS.write().unwrap() = setup.regions;
+            }
+
+            let mut segments = controller.segments()?;
+            let controller = Arc::new(Mutex::new(segments::connect(&segments)?));
+            let led_count = controller.lock().unwrap().len();
+
+            // After connecting, so the old positions are placed by the segment layout
+            if let Some(path) = prior {
+                let leds = export::read_json(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                *scan::PRIORS.write().unwrap() = Some(Priors::new(&leds, prior_radius));
+            }
+
+            start_pipeline(&stream)?;
+            if let Some(address) = web {
+                web::spawn(&address)?;
+            }
+            if dark_frame {
+                scan::capture_dark_frame(&controller)?;
+            }
+            if let Some(index) = measure_latency {
+                scan::measure_latency(&controller, index)?;
+            }
+            if detect_pixel_order {
+                let orders = pixel_order::detect(&controller, &segments)?;
+                for (segment, order) in segments.iter_mut().zip(orders) {
+                    segment.config.pixel_order = order.unwrap_or(segment.config.pixel_order);
+                }
+                *controller.lock().unwrap() = segments::connect(&segments)?;
+            }
+
+            // In the background like in the GUI, so it can be stopped over the API
+            *scan::CONTROLLER.write().unwrap() = Some(controller.clone());
+            let job = if resume {
+                scan::start_resume(controller)
+            } else {
+                scan::start(controller, mode)
+            };
+            if let Some(job) = job {
+                job.join()
+                    .map_err(|_| anyhow::anyhow!("The scan thread panicked"))?;
+            }
+
+            match scan::STATE.read().unwrap().clone() {
+                scan::State::Finished => {}
+                scan::State::Failed(e) => anyhow::bail!(e),
+                _ => anyhow::bail!("The scan was stopped before it finished"),
+            }
+
+            if let Some(path) = timelapse {
+                timelapse::save(&path)
+                    .with_context(|| format!("Failed to save {}", path.display()))?;
+            }
+
+            let hardware = issues::report();
+            if hardware.is_empty() {
+                info!("No dead or dim LEDs");
+            } else {
+                warn!("Hardware issues: {}", hardware.summary());
+            }
+            if let Some(uniformity) = &hardware.uniformity {
+                info!("Peak brightness {}", uniformity.summary());
+            }
+            if let Some(path) = issues {
+                hardware.save(&path)?;
+            }
+            if let Some(path) = brightness {
+                issues::save_brightness(&path)?;
+            }
+            if report {
+                let path = output.next_to("report.html");
+                report::save(&path)?;
+                info!("Saved the report to {}", path.display());
+            }
+
+            let leds = scan::leds();
+            info!("Found {} of {led_count} LEDs", leds.len());
+            output.write(&depth::to_3d(&stereo::to_3d(&leds)))
+        }
+
+        Command::Serve {
+            stream,
+            controller,
+            output,
+            scan_settings,
+            listen,
+        } => {
+            scan_settings.apply()?;
+            let controller = Arc::new(Mutex::new(segments::connect(&controller.segments()?)?));
+            *scan::CONTROLLER.write().unwrap() = Some(controller);
+
+            start_pipeline(&stream)?;
+            web::spawn(&listen)?;
+
+            let mut last = scan::State::Idle;
+            loop {
+                thread::sleep(SERVE_POLL_INTERVAL);
+
+                let state = scan::STATE.read().unwrap().clone();
+                if state != last && state == scan::State::Finished {
+                    let leds = depth::to_3d(&stereo::to_3d(&scan::leds()));
+                    match output.write(&leds) {
+                        Ok(()) => info!("Wrote {} LEDs to {}", leds.len(), output.output.display()),
+                        Err(e) => error!("Failed to write {}: {e:#}", output.output.display()),
+                    }
+                }
+                last = state;
+            }
+        }
+
+        Command::Detect {
+            stream,
+            seconds,
+            output,
+            format,
+            transform,
+            expect,
+            order,
+            start,
+            end,
+        } => {
+            start_pipeline(&stream)?;
+            thread::sleep(Duration::from_secs_f64(seconds));
+
+            let mut leds = detected_leds();
+            if order {
+                let hint = |point: Option<Vec<f32>>| point.map(|p| Pos2::new(p[0], p[1]));
+                let points = leds
+                    .iter()
+                    .map(|led| Pos2::new(led.position[0], led.position[1]))
+                    .collect::<Vec<_>>();
+                leds = ordering::order(&points, hint(start), hint(end))
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, pos)| Led {
+                        segment: 0,
+                        index,
+                        position: [pos.x, pos.y, 0.0],
+                    })
+                    .collect();
+            }
+
+            if let Some(expected) = expect {
+                anyhow::ensure!(
+                    leds.len() == expected,
+                    "Detected {} blobs, expected {expected}, check the thresholds",
+                    leds.len()
+                );
+            }
+
+            match output {
+                Some(output) => OutputArgs {
+                    output,
+                    format,
+                    exporter: None,
+                    grid: false,
+                    transform,
+                }
+                .write(&leds),
+                None => {
+                    for led in transform.apply(&leds) {
+                        println!("{}", serde_json::to_string(&led)?);
+                    }
+                    Ok(())
+                }
+            }
+        }
+
+        Command::Export { input, output } => {
+            if let Some(metadata) = export::read_metadata(&input)? {
+                let resolution = match metadata.resolution {
+                    Some([width, height]) => format!("{width}x{height}"),
+                    None => "an unknown resolution".to_owned(),
+                };
+                info!(
+                    "{} is of {} LEDs, scanned by version {} at {resolution}",
+                    input.display(),
+                    metadata.led_count,
+                    metadata.app_version
+                );
+            }
+            output.write(&export::read_json(&input)?)
+        }
+
+        Command::Diff { old, new, csv, max_shift } => {
+            let diff = Diff::load(&old, &new)?;
+            info!("{}", diff.summary());
+            if let Some(path) = csv {
+                diff.write_csv(&path)?;
+            }
+
+            if let (Some(limit), Some(stats)) = (max_shift, diff.stats()) {
+                let (segment, index) = stats.furthest;
+                anyhow::ensure!(
+                    stats.max <= limit,
+                    "LED {index} of segment {segment} moved {:.2}, more than {limit}",
+                    stats.max
+                );
+            }
+            Ok(())
+        }
+
+        Command::Batch {
+            directory,
+            output,
+            format,
+            grid,
+            transform,
+            settings,
+        } => {
+            if let Some(path) = settings {
+                let json = std::fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                *pipeline::SETTINGS.write().unwrap() = serde_json::from_str(&json)
+                    .with_context(|| format!("Failed to parse {}", path.display()))?;
+            }
+
+            let sessions = batch::sessions(&directory)?;
+            anyhow::ensure!(
+                !sessions.is_empty(),
+                "There are no sessions in {}",
+                directory.display()
+            );
+            std::fs::create_dir_all(&output)
+                .with_context(|| format!("Failed to create {}", output.display()))?;
+
+            // One bad session shouldn't hold up the rest
+            let mut failed = 0;
+            for session in &sessions {
+                let path = output.join(format!("{}.{}", session.name, format.extension()));
+                let output = OutputArgs {
+                    output: path.clone(),
+                    format: Some(format),
+                    exporter: None,
+                    grid,
+                    transform: transform.clone(),
+                };
+                match session
+                    .map()
+                    .and_then(|leds| Ok((leds.len(), output.write(&leds)?)))
+                {
+                    Ok((found, ())) => {
+                        let expected = session
+                            .led_count()
+                            .map_or(String::new(), |count| format!(" of {count}"));
+                        info!(
+                            "{}: found {found}{expected} LEDs, wrote {}",
+                            session.name,
+                            path.display()
+                        );
+                    }
+                    Err(e) => {
+                        error!("{}: {e:#}", session.name);
+                        failed += 1;
+                    }
+                }
+            }
+
+            anyhow::ensure!(failed == 0, "{failed} of {} sessions failed", sessions.len());
+            Ok(())
+        }
+
+        Command::Accuracy {
+            frames,
+            leds,
+            size,
+            radius,
+            blur,
+            noise,
+            clutter,
+            seed,
+            tolerance,
+            min_recall,
+            max_error,
+        } => {
+            let settings = pipeline::SETTINGS.read().unwrap().clone();
+            let mut total = Accuracy::default();
+
+            for frame in 0..frames {
+                let scene = Scene {
+                    radius,
+                    blur,
+                    noise,
+                    clutter,
+                    ..Scene::random(size[0], size[1], leds, seed + frame)
+                };
+                let detections = led_detect::detect_rgb(&scene.render(), scene.width, &settings)?;
+                let accuracy = synthetic::evaluate(&scene.leds, &detections, tolerance);
+                info!(
+                    "Frame {frame}: found {} of {}, {} spurious, off by {:.2} px on average",
+                    accuracy.found, accuracy.leds, accuracy.spurious, accuracy.mean_error
+                );
+
+                total.mean_error = (total.mean_error * total.found as f32
+                    + accuracy.mean_error * accuracy.found as f32)
+                    / (total.found + accuracy.found).max(1) as f32;
+                total.max_error = total.max_error.max(accuracy.max_error);
+                total.leds += accuracy.leds;
+                total.found += accuracy.found;
+                total.spurious += accuracy.spurious;
+            }
+
+            println!(
+                "{}",
+                serde_json::json!({
+                    "leds": total.leds,
+                    "found": total.found,
+                    "spurious": total.spurious,
+                    "recall": total.recall(),
+                    "mean_error": total.mean_error,
+                    "max_error": total.max_error,
+                })
+            );
+
+            if let Some(min) = min_recall {
+                anyhow::ensure!(
+                    total.recall() >= min,
+                    "Found {:.1}% of the LEDs, less than the {:.1}% required",
+                    total.recall() * 100.0,
+                    min * 100.0
+                );
+            }
+            if let Some(max) = max_error {
+                anyhow::ensure!(
+                    total.mean_error <= max,
+                    "Detections were off by {:.2} px on average, more than the {max} px allowed",
+                    total.mean_error
+                );
+            }
+            Ok(())
+        }
+
+        Command::Discover { seconds } => {
+            let timeout = Duration::try_from_secs_f64(seconds).context("Bad --seconds")?;
+            let found = discovery::discover(timeout)?;
+            info!("Found {} controllers", found.len());
+            for found in found {
+                println!(
+                    "{}",
+                    serde_json::json!({
+                        "name": found.name,
+                        "kind": found.kind,
+                        "address": found.address,
+                        "led_count": found.led_count,
+                    })
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+fn start_pipeline(stream: &StreamArgs) -> anyhow::Result<()> {
+    stream.apply();
+    stream.spawn_decoder(None);
+    stream.spawn_stereo()?;
+    pipeline::spawn_detection();
+    pipeline::wait_for_first_frame(STREAM_TIMEOUT)
+}
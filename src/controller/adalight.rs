@@ -0,0 +1,56 @@
+use std::{io::Write, time::Duration};
+
+use eframe::epaint::Color32;
+
+use super::LedController;
+
+/// The Adalight serial protocol spoken by most Arduino-based ambilight sketches.
+pub struct Adalight {
+    port: Box<dyn serialport::SerialPort>,
+    pixels: Vec<[u8; 3]>,
+}
+
+const BAUD_RATE: u32 = 115_200;
+
+impl Adalight {
+    pub fn new(path: &str, led_count: usize) -> anyhow::Result<Self> {
+        let port = serialport::new(path, BAUD_RATE)
+            .timeout(Duration::from_millis(500))
+            .open()?;
+
+        Ok(Self {
+            port,
+            pixels: vec![[0; 3]; led_count],
+        })
+    }
+}
+
+impl LedController for Adalight {
+    fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Color32) {
+        if let Some(pixel) = self.pixels.get_mut(index) {
+            *pixel = [color.r(), color.g(), color.b()];
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        let [hi, lo] = (self.pixels.len().saturating_sub(1) as u16).to_be_bytes();
+
+        let mut packet = Vec::with_capacity(6 + self.pixels.len() * 3);
+        packet.extend(b"Ada");
+        packet.extend([hi, lo, hi ^ lo ^ 0x55]);
+        packet.extend(self.pixels.iter().flatten());
+
+        self.port.write_all(&packet)?;
+        Ok(())
+    }
+
+    fn latency_hint(&self) -> Duration {
+        // Time to push the frame through the UART, 10 bits per byte
+        let bytes = 6 + self.pixels.len() as u64 * 3;
+        Duration::from_micros(bytes * 10 * 1_000_000 / BAUD_RATE as u64)
+    }
+}
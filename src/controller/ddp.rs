@@ -0,0 +1,97 @@
+use std::net::UdpSocket;
+
+use eframe::epaint::Color32;
+
+use super::{resolve, Channels, LedController};
+
+/// The Distributed Display Protocol, a thin UDP framing supported by WLED, FPP and LedFx.
+pub struct Ddp {
+    socket: UdpSocket,
+    sequence: u8,
+    channels: Channels,
+    /// `channels` wide.
+    pixels: Vec<u8>,
+}
+
+const PORT: u16 = 4048;
+const FLAGS_VERSION_1: u8 = 0x40;
+const FLAGS_PUSH: u8 = 0x01;
+/// RGB, RGBW and grayscale, 8 bits per channel.
+const DATA_TYPE_RGB24: u8 = 0x0b;
+const DATA_TYPE_RGBW32: u8 = 0x1b;
+const DATA_TYPE_GRAY8: u8 = 0x23;
+const ID_DISPLAY: u8 = 1;
+/// Keeps packets under a typical 1500 byte MTU, and a multiple of 3 and 4 so no pixel is split.
+const MAX_DATA_LEN: usize = 1440;
+
+impl Ddp {
+    pub fn new(address: &str, led_count: usize, channels: Channels) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(resolve(address, PORT)?)?;
+
+        Ok(Self {
+            socket,
+            sequence: 0,
+            channels,
+            pixels: vec![0; led_count * channels.width()],
+        })
+    }
+
+    fn pixel(&mut self, index: usize) -> Option<&mut [u8]> {
+        let width = self.channels.width();
+        self.pixels.get_mut(index * width..(index + 1) * width)
+    }
+}
+
+impl LedController for Ddp {
+    fn len(&self) -> usize {
+        self.pixels.len() / self.channels.width()
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Color32) {
+        let channels = self.channels;
+        if let Some(pixel) = self.pixel(index) {
+            channels.pixel(color, pixel);
+        }
+    }
+
+    fn set_white(&mut self, index: usize, white: u8) {
+        let channels = self.channels;
+        if let Some(pixel) = self.pixel(index) {
+            channels.white(white, pixel);
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        let data = &self.pixels;
+        let chunks = data.chunks(MAX_DATA_LEN).count();
+        let data_type = match self.channels {
+            Channels::Rgb => DATA_TYPE_RGB24,
+            Channels::Rgbw => DATA_TYPE_RGBW32,
+            Channels::White => DATA_TYPE_GRAY8,
+        };
+
+        // Sequence numbers run 1..=15, 0 means the receiver shouldn't check them
+        self.sequence = self.sequence % 15 + 1;
+
+        for (chunk_index, chunk) in data.chunks(MAX_DATA_LEN).enumerate() {
+            let offset = (chunk_index * MAX_DATA_LEN) as u32;
+
+            // Only the last packet tells the receiver to show the frame
+            let mut flags = FLAGS_VERSION_1;
+            if chunk_index == chunks - 1 {
+                flags |= FLAGS_PUSH;
+            }
+
+            let mut packet = Vec::with_capacity(10 + chunk.len());
+            packet.extend([flags, self.sequence, data_type, ID_DISPLAY]);
+            packet.extend(offset.to_be_bytes());
+            packet.extend((chunk.len() as u16).to_be_bytes());
+            packet.extend(chunk);
+
+            self.socket.send(&packet)?;
+        }
+
+        Ok(())
+    }
+}
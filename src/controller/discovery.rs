@@ -0,0 +1,231 @@
+//! Finding controllers on the LAN over mDNS, so they can be picked from a list instead of typing
+//! in addresses. WLED announces itself as `_wled._tcp` and is asked for its LED count over its
+//! JSON API. ESPixelStick only announces a web server, so it's told apart by its name, and takes
+//! DDP.
+//!
+//! Only as much DNS is spoken as a one-shot query needs: the question goes out from an ephemeral
+//! port, which has responders answer straight back rather than to the whole network.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::{debug, error, info};
+
+use super::ControllerKind;
+use crate::toasts;
+
+const MDNS: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+const SERVICES: [&str; 2] = ["_wled._tcp.local", "_http._tcp.local"];
+const TYPE_PTR: u16 = 12;
+/// Asks for the answer to come back to the port the question came from.
+const UNICAST_RESPONSE: u16 = 0x8000;
+const CLASS_IN: u16 = 1;
+/// For asking each device over HTTP what it is.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What the last search in the background found.
+pub static FOUND: RwLock<Vec<Found>> = RwLock::new(Vec::new());
+pub static SEARCHING: AtomicBool = AtomicBool::new(false);
+
+/// How long the window's searches wait for answers.
+const SEARCH_TIME: Duration = Duration::from_secs(3);
+
+#[derive(Clone)]
+pub struct Found {
+    /// What the device calls itself.
+    pub name: String,
+    pub kind: ControllerKind,
+    pub address: IpAddr,
+    /// `None` if the device doesn't say.
+    pub led_count: Option<usize>,
+}
+
+/// Searches in the background, replacing `FOUND` when done.
+pub fn spawn_search() -> JoinHandle<()> {
+    SEARCHING.store(true, Ordering::Relaxed);
+    thread::spawn(|| {
+        match discover(SEARCH_TIME) {
+            Ok(found) => {
+                info!("Found {} controllers on the network", found.len());
+                *FOUND.write().unwrap() = found;
+            }
+            Err(e) => {
+                error!("Looking for controllers failed: {e:#}");
+                toasts::error(format!("Looking for controllers failed: {e:#}"), None);
+            }
+        }
+        SEARCHING.store(false, Ordering::Relaxed);
+    })
+}
+
+/// Asks the LAN for controllers and collects the answers for `timeout`.
+pub fn discover(timeout: Duration) -> anyhow::Result<Vec<Found>> {
+    let socket = UdpSocket::bind("0.0.0.0:0").context("Failed to open a socket for mDNS")?;
+    for service in SERVICES {
+        socket
+            .send_to(&query(service), MDNS)
+            .context("Failed to send the mDNS query")?;
+    }
+
+    let start = Instant::now();
+    let mut announced: Vec<(IpAddr, String, bool)> = Vec::new();
+    let mut buffer = [0; 9000];
+    while let Some(left) = timeout.checked_sub(start.elapsed()) {
+        socket.set_read_timeout(Some(left.max(Duration::from_millis(1))))?;
+        let Ok((length, from)) = socket.recv_from(&mut buffer) else {
+            break;
+        };
+        for (service, instance) in pointers(&buffer[..length]) {
+            let wled = service == SERVICES[0];
+            match announced
+                .iter_mut()
+                .find(|(address, ..)| *address == from.ip())
+            {
+                Some(known) => known.2 |= wled,
+                None => announced.push((from.ip(), instance, wled)),
+            }
+        }
+    }
+
+    let agent = ureq::AgentBuilder::new().timeout(HTTP_TIMEOUT).build();
+    let mut found = announced
+        .into_iter()
+        .filter_map(|(address, instance, wled)| identify(&agent, address, instance, wled))
+        .collect::<Vec<_>>();
+    found.sort_by_key(|found| found.address);
+    Ok(found)
+}
+
+/// What the device at `address` is, `None` for anything that isn't a controller.
+fn identify(agent: &ureq::Agent, address: IpAddr, instance: String, wled: bool) -> Option<Found> {
+    #[derive(Deserialize)]
+    struct Info {
+        name: String,
+        leds: Leds,
+    }
+    #[derive(Deserialize)]
+    struct Leds {
+        count: usize,
+    }
+
+    // Not all WLED builds announce `_wled`, but they all answer this
+    let info = agent
+        .get(&format!("http://{address}/json/info"))
+        .call()
+        .ok()
+        .and_then(|response| response.into_json::<Info>().ok());
+    if let Some(info) = info {
+        return Some(Found {
+            name: info.name,
+            kind: ControllerKind::Wled,
+            address,
+            led_count: Some(info.leds.count),
+        });
+    }
+    if wled {
+        debug!("{instance} at {address} announces WLED but didn't answer");
+        return Some(Found {
+            name: instance,
+            kind: ControllerKind::Wled,
+            address,
+            led_count: None,
+        });
+    }
+
+    let lower = instance.to_lowercase();
+    (lower.contains("espixelstick") || lower.starts_with("esps-")).then_some(Found {
+        name: instance,
+        kind: ControllerKind::Ddp,
+        address,
+        led_count: None,
+    })
+}
+
+/// A question for the PTR records of `service`.
+fn query(service: &str) -> Vec<u8> {
+    // No id, no flags, one question
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in service.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend(TYPE_PTR.to_be_bytes());
+    packet.extend((CLASS_IN | UNICAST_RESPONSE).to_be_bytes());
+    packet
+}
+
+/// The instances of `SERVICES` the PTR records in `packet` point to, with their service. Anything
+/// malformed ends the records early.
+fn pointers(packet: &[u8]) -> Vec<(&'static str, String)> {
+    let mut found = Vec::new();
+    let count = |at: usize| Some(u16::from_be_bytes([*packet.get(at)?, *packet.get(at + 1)?]));
+    let (Some(questions), Some(answers), Some(authorities), Some(additional)) =
+        (count(4), count(6), count(8), count(10))
+    else {
+        return found;
+    };
+
+    let mut at = 12;
+    for _ in 0..questions {
+        let Some((_, end)) = name(packet, at) else {
+            return found;
+        };
+        at = end + 4;
+    }
+    for _ in 0..answers as usize + authorities as usize + additional as usize {
+        let Some((owner, end)) = name(packet, at) else {
+            break;
+        };
+        let (Some(kind), Some(length)) = (count(end), count(end + 8)) else {
+            break;
+        };
+        let data = end + 10;
+        if kind == TYPE_PTR {
+            let service = SERVICES.into_iter().find(|s| owner.eq_ignore_ascii_case(s));
+            if let Some((service, (instance, _))) = service.zip(name(packet, data)) {
+                // The instance's own label, before the service it's of
+                let label = instance.split('.').next().unwrap_or_default().to_owned();
+                found.push((service, label));
+            }
+        }
+        at = data + length as usize;
+    }
+    found
+}
+
+/// The possibly compressed name starting at `at`, and where the data after it starts.
+fn name(packet: &[u8], mut at: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    // Pointers only go back, so this many jumps means a loop
+    for _ in 0..128 {
+        let length = *packet.get(at)? as usize;
+        match length {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(at + 1)));
+            }
+            length if length & 0xc0 == 0xc0 => {
+                let offset = (length & 0x3f) << 8 | *packet.get(at + 1)? as usize;
+                end.get_or_insert(at + 2);
+                at = offset;
+            }
+            length => {
+                let label = packet.get(at + 1..at + 1 + length)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                at += 1 + length;
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,271 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use eframe::epaint::Color32;
+
+use super::{resolve, wled::Wled, Channels, LedController};
+
+/// An addressable ESPHome light with a `wled` effect configured.
+///
+/// The native API has no per-pixel commands, so it is only used to switch the light to its WLED
+/// effect; pixel data then goes out over WLED's UDP realtime protocol, which that effect listens
+/// for. Only the plaintext API is supported, not the encrypted (noise) one.
+pub struct Esphome {
+    api: TcpStream,
+    pixels: Wled,
+}
+
+const PORT: u16 = 6053;
+
+const HELLO_REQUEST: u32 = 1;
+const HELLO_RESPONSE: u32 = 2;
+const CONNECT_REQUEST: u32 = 3;
+const CONNECT_RESPONSE: u32 = 4;
+const PING_REQUEST: u32 = 7;
+const PING_RESPONSE: u32 = 8;
+const LIST_ENTITIES_REQUEST: u32 = 11;
+const LIST_ENTITIES_LIGHT_RESPONSE: u32 = 15;
+const LIST_ENTITIES_DONE_RESPONSE: u32 = 19;
+const LIGHT_COMMAND_REQUEST: u32 = 32;
+
+impl Esphome {
+    /// `address` is `[password@]host[:port]`.
+    pub fn new(address: &str, led_count: usize, channels: Channels) -> anyhow::Result<Self> {
+        let (password, host) = address.rsplit_once('@').unwrap_or(("", address));
+
+        let mut api = TcpStream::connect_timeout(&resolve(host, PORT)?, Duration::from_secs(2))?;
+        api.set_read_timeout(Some(Duration::from_secs(5)))?;
+
+        let mut hello = Vec::new();
+        put_string(&mut hello, 1, "LED Position Calibrator");
+        put_varint_field(&mut hello, 2, 1);
+        put_varint_field(&mut hello, 3, 9);
+        write_message(&mut api, HELLO_REQUEST, &hello)?;
+        expect_message(&mut api, HELLO_RESPONSE)?;
+
+        let mut connect = Vec::new();
+        put_string(&mut connect, 1, password);
+        write_message(&mut api, CONNECT_REQUEST, &connect)?;
+        let response = expect_message(&mut api, CONNECT_RESPONSE)?;
+        if fields(&response)?.any(|(field, value)| field == 1 && value == Value::Varint(1)) {
+            bail!("ESPHome rejected the API password");
+        }
+
+        write_message(&mut api, LIST_ENTITIES_REQUEST, &[])?;
+        let mut light = None;
+        loop {
+            let (kind, payload) = read_message(&mut api)?;
+            match kind {
+                LIST_ENTITIES_LIGHT_RESPONSE if light.is_none() => {
+                    let mut key = None;
+                    let mut effect = None;
+                    for (field, value) in fields(&payload)? {
+                        match (field, value) {
+                            (2, Value::Fixed32(k)) => key = Some(k),
+                            (11, Value::Bytes(name)) => {
+                                let name = String::from_utf8_lossy(name);
+                                if name.to_lowercase().contains("wled") {
+                                    effect = Some(name.into_owned());
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    light = key.zip(effect);
+                }
+                LIST_ENTITIES_DONE_RESPONSE => break,
+                _ => {}
+            }
+        }
+        let (key, effect) = light.context("No light with a WLED effect found on this device")?;
+
+        let mut command = Vec::new();
+        put_fixed32(&mut command, 1, key);
+        put_varint_field(&mut command, 2, 1); // has_state
+        put_varint_field(&mut command, 3, 1); // state
+        put_varint_field(&mut command, 18, 1); // has_effect
+        put_string(&mut command, 19, &effect);
+        write_message(&mut api, LIGHT_COMMAND_REQUEST, &command)?;
+
+        // From here on only pings are expected, answered whenever pixels are flushed
+        api.set_nonblocking(true)?;
+
+        let wled_host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+        Ok(Self {
+            api,
+            pixels: Wled::new(wled_host, led_count, channels)?,
+        })
+    }
+
+    fn answer_pings(&mut self) -> anyhow::Result<()> {
+        let mut peek = [0];
+        loop {
+            match self.api.peek(&mut peek) {
+                Ok(0) => bail!("ESPHome closed the API connection"),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+
+            // A frame has started arriving, read the rest of it blocking
+            self.api.set_nonblocking(false)?;
+            let (kind, _) = read_message(&mut self.api)?;
+            if kind == PING_REQUEST {
+                write_message(&mut self.api, PING_RESPONSE, &[])?;
+            }
+            self.api.set_nonblocking(true)?;
+        }
+    }
+}
+
+impl LedController for Esphome {
+    fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Color32) {
+        self.pixels.set_pixel(index, color);
+    }
+
+    fn set_white(&mut self, index: usize, white: u8) {
+        self.pixels.set_white(index, white);
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.answer_pings()?;
+        self.pixels.flush()
+    }
+
+    fn latency_hint(&self) -> Duration {
+        self.pixels.latency_hint()
+    }
+}
+
+// Plaintext frames are a zero byte, the payload length and the message type as varints, then the
+// protobuf-encoded payload.
+
+fn write_message(stream: &mut TcpStream, kind: u32, payload: &[u8]) -> anyhow::Result<()> {
+    let mut frame = vec![0];
+    put_varint(&mut frame, payload.len() as u64);
+    put_varint(&mut frame, kind as u64);
+    frame.extend(payload);
+
+    stream.write_all(&frame)?;
+    Ok(())
+}
+
+fn read_message(stream: &mut TcpStream) -> anyhow::Result<(u32, Vec<u8>)> {
+    let mut preamble = [0];
+    stream.read_exact(&mut preamble)?;
+    if preamble[0] != 0 {
+        bail!("Unexpected ESPHome frame, is API encryption enabled?");
+    }
+
+    let len = read_varint(stream)? as usize;
+    let kind = read_varint(stream)? as u32;
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+
+    Ok((kind, payload))
+}
+
+fn expect_message(stream: &mut TcpStream, expected: u32) -> anyhow::Result<Vec<u8>> {
+    let (kind, payload) = read_message(stream)?;
+    if kind != expected {
+        bail!("Expected ESPHome message {expected}, got {kind}");
+    }
+    Ok(payload)
+}
+
+fn read_varint(stream: &mut TcpStream) -> anyhow::Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0];
+        stream.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("Varint too long")
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn put_varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    put_varint(buf, (field << 3) as u64);
+    put_varint(buf, value);
+}
+
+fn put_fixed32(buf: &mut Vec<u8>, field: u32, value: u32) {
+    put_varint(buf, (field << 3 | 5) as u64);
+    buf.extend(value.to_le_bytes());
+}
+
+fn put_string(buf: &mut Vec<u8>, field: u32, value: &str) {
+    put_varint(buf, (field << 3 | 2) as u64);
+    put_varint(buf, value.len() as u64);
+    buf.extend(value.as_bytes());
+}
+
+#[derive(PartialEq)]
+enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Iterates over the top-level fields of a protobuf message.
+fn fields(mut buf: &[u8]) -> anyhow::Result<impl Iterator<Item = (u32, Value<'_>)>> {
+    fn varint(buf: &mut &[u8]) -> anyhow::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = buf.split_first().context("Truncated protobuf")?;
+            *buf = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Varint too long")
+    }
+
+    fn take<'a>(buf: &mut &'a [u8], len: usize) -> anyhow::Result<&'a [u8]> {
+        if buf.len() < len {
+            bail!("Truncated protobuf");
+        }
+        let (value, rest) = buf.split_at(len);
+        *buf = rest;
+        Ok(value)
+    }
+
+    let mut fields = Vec::new();
+    while !buf.is_empty() {
+        let tag = varint(&mut buf)?;
+        let field = (tag >> 3) as u32;
+        let value = match tag & 7 {
+            0 => Value::Varint(varint(&mut buf)?),
+            1 => Value::Fixed64(u64::from_le_bytes(take(&mut buf, 8)?.try_into()?)),
+            2 => {
+                let len = varint(&mut buf)? as usize;
+                Value::Bytes(take(&mut buf, len)?)
+            }
+            5 => Value::Fixed32(u32::from_le_bytes(take(&mut buf, 4)?.try_into()?)),
+            wire_type => bail!("Unsupported protobuf wire type {wire_type}"),
+        };
+        fields.push((field, value));
+    }
+
+    Ok(fields.into_iter())
+}
@@ -0,0 +1,311 @@
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    time::Duration,
+};
+
+use anyhow::Context;
+use eframe::epaint::Color32;
+use serde::{Deserialize, Serialize};
+
+mod adalight;
+mod ddp;
+pub mod discovery;
+mod esphome;
+mod opc;
+mod sacn;
+pub mod simulated;
+mod wled;
+
+pub use sacn::{ChannelOrder, DmxRange};
+
+/// Something that can light individual LEDs, used by both the scan sequencer and verify mode.
+///
+/// Colors are buffered by `set_pixel`/`set_all` and only sent out on `flush`, so a backend can
+/// batch a whole strip into as few packets as its protocol allows.
+pub trait LedController: Send {
+    /// Number of LEDs on the strip.
+    fn len(&self) -> usize;
+
+    fn set_pixel(&mut self, index: usize, color: Color32);
+
+    /// Lights only the white channel of LED `index`, for RGBW and white-only strips. Strips
+    /// without one show it as gray.
+    fn set_white(&mut self, index: usize, white: u8) {
+        self.set_pixel(index, Color32::from_gray(white));
+    }
+
+    fn set_all(&mut self, color: Color32) {
+        for index in 0..self.len() {
+            self.set_pixel(index, color);
+        }
+    }
+
+    /// Sends the buffered colors to the LEDs.
+    fn flush(&mut self) -> anyhow::Result<()>;
+
+    /// Rough time between `flush` returning and the LEDs actually changing, not counting the
+    /// camera.
+    fn latency_hint(&self) -> Duration {
+        Duration::from_millis(20)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ControllerKind {
+    Wled,
+    Sacn,
+    Adalight,
+    Opc,
+    Ddp,
+    Esphome,
+    Simulated,
+}
+
+impl ControllerKind {
+    pub const ALL: [Self; 7] = [
+        Self::Wled,
+        Self::Sacn,
+        Self::Adalight,
+        Self::Opc,
+        Self::Ddp,
+        Self::Esphome,
+        Self::Simulated,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Wled => "WLED (UDP realtime)",
+            Self::Sacn => "sACN / E1.31",
+            Self::Adalight => "Adalight (serial)",
+            Self::Opc => "Open Pixel Control",
+            Self::Ddp => "DDP",
+            Self::Esphome => "ESPHome",
+            Self::Simulated => "Simulated",
+        }
+    }
+
+    /// What the address field means for this kind of controller.
+    pub fn address_hint(self) -> &'static str {
+        match self {
+            Self::Wled => "host[:port]",
+            Self::Sacn => "host[:port], empty for multicast",
+            Self::Adalight => "serial port, e.g. /dev/ttyUSB0",
+            Self::Opc | Self::Ddp => "host[:port]",
+            Self::Esphome => "[password@]host[:port], light needs a wled effect",
+            Self::Simulated => "unused, shows up in the simulated camera",
+        }
+    }
+}
+
+/// What the LEDs of a strip have besides red, green and blue, which decides how many channels each
+/// takes. sACN with addressing has it per range instead, see `ChannelOrder`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Channels {
+    #[default]
+    Rgb,
+    /// A white channel after the three colors.
+    Rgbw,
+    /// Only a white channel, for white LED strings.
+    White,
+}
+
+impl Channels {
+    pub const ALL: [Self; 3] = [Self::Rgb, Self::Rgbw, Self::White];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rgb => "RGB",
+            Self::Rgbw => "RGBW",
+            Self::White => "White only",
+        }
+    }
+
+    /// Channels per LED.
+    fn width(self) -> usize {
+        match self {
+            Self::Rgb => 3,
+            Self::Rgbw => 4,
+            Self::White => 1,
+        }
+    }
+
+    /// Writes the channels showing `color` to `out`, `width` of them. Whatever the three colors
+    /// have in common goes to the white channel of RGBW, and white-only LEDs show the brightest.
+    fn pixel(self, color: Color32, out: &mut [u8]) {
+        let [r, g, b, _] = color.to_array();
+        match self {
+            Self::Rgb => out.copy_from_slice(&[r, g, b]),
+            Self::Rgbw => {
+                let w = r.min(g).min(b);
+                out.copy_from_slice(&[r - w, g - w, b - w, w]);
+            }
+            Self::White => out[0] = r.max(g).max(b),
+        }
+    }
+
+    /// Writes the channels of an LED with only its white channel lit to `out`.
+    fn white(self, white: u8, out: &mut [u8]) {
+        match self {
+            Self::Rgb => out.copy_from_slice(&[white; 3]),
+            Self::Rgbw => out.copy_from_slice(&[0, 0, 0, white]),
+            Self::White => out[0] = white,
+        }
+    }
+}
+
+/// Which color each of the three channels sent for an LED drives, for strips not wired RGB. Swaps
+/// the channels around on top of whatever the controller does, sACN's own channel order included.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelOrder {
+    #[default]
+    Rgb,
+    Rbg,
+    Grb,
+    Gbr,
+    Brg,
+    Bgr,
+}
+
+impl PixelOrder {
+    pub const ALL: [Self; 6] = [Self::Rgb, Self::Rbg, Self::Grb, Self::Gbr, Self::Brg, Self::Bgr];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rgb => "RGB",
+            Self::Rbg => "RBG",
+            Self::Grb => "GRB",
+            Self::Gbr => "GBR",
+            Self::Brg => "BRG",
+            Self::Bgr => "BGR",
+        }
+    }
+
+    /// The color each channel sent drives, 0 to 2 for red, green and blue.
+    fn channels(self) -> [usize; 3] {
+        match self {
+            Self::Rgb => [0, 1, 2],
+            Self::Rbg => [0, 2, 1],
+            Self::Grb => [1, 0, 2],
+            Self::Gbr => [1, 2, 0],
+            Self::Brg => [2, 0, 1],
+            Self::Bgr => [2, 1, 0],
+        }
+    }
+
+    /// What to send for the strip to show `color`.
+    fn apply(self, color: Color32) -> Color32 {
+        let rgb = [color.r(), color.g(), color.b()];
+        let [a, b, c] = self.channels().map(|channel| rgb[channel]);
+        Color32::from_rgb(a, b, c)
+    }
+
+    /// The order the strip is really wired in when, sending in this order, red, green and blue
+    /// showed up as the colors in `shown`.
+    pub fn corrected(self, shown: [usize; 3]) -> Option<Self> {
+        let channels = self.channels().map(|channel| shown[channel]);
+        Self::ALL
+            .into_iter()
+            .find(|order| order.channels() == channels)
+    }
+}
+
+/// A controller with the channels of every LED swapped around, see `PixelOrder`.
+struct Reordered {
+    controller: Box<dyn LedController>,
+    order: PixelOrder,
+}
+
+impl LedController for Reordered {
+    fn len(&self) -> usize {
+        self.controller.len()
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Color32) {
+        self.controller.set_pixel(index, self.order.apply(color));
+    }
+
+    fn set_all(&mut self, color: Color32) {
+        self.controller.set_all(self.order.apply(color));
+    }
+
+    fn set_white(&mut self, index: usize, white: u8) {
+        self.controller.set_white(index, white);
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.controller.flush()
+    }
+
+    fn latency_hint(&self) -> Duration {
+        self.controller.latency_hint()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ControllerConfig {
+    pub kind: ControllerKind,
+    pub address: String,
+    pub led_count: usize,
+    /// Where each LED is on the DMX side, for sACN. Empty packs them from universe 1 on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addressing: Vec<DmxRange>,
+    #[serde(default)]
+    pub pixel_order: PixelOrder,
+    #[serde(default)]
+    pub channels: Channels,
+}
+
+impl ControllerConfig {
+    pub fn connect(&self) -> anyhow::Result<Box<dyn LedController>> {
+        let (count, channels) = (self.led_count, self.channels);
+        let controller: Box<dyn LedController> = match self.kind {
+            ControllerKind::Wled => Box::new(wled::Wled::new(&self.address, count, channels)?),
+            ControllerKind::Sacn => {
+                // Without addressing every LED is packed the same way
+                let order = match channels {
+                    Channels::Rgb => ChannelOrder::Rgb,
+                    Channels::Rgbw => ChannelOrder::Rgbw,
+                    Channels::White => ChannelOrder::White,
+                };
+                let addressing = match self.addressing.is_empty() {
+                    true => vec![DmxRange { order, ..DmxRange::default() }],
+                    false => self.addressing.clone(),
+                };
+                Box::new(sacn::Sacn::new(&self.address, count, &addressing)?)
+            }
+            ControllerKind::Adalight | ControllerKind::Opc if channels != Channels::Rgb => {
+                anyhow::bail!("{} only carries RGB", self.kind.name())
+            }
+            ControllerKind::Adalight => Box::new(adalight::Adalight::new(&self.address, count)?),
+            ControllerKind::Opc => Box::new(opc::Opc::new(&self.address, count)?),
+            ControllerKind::Ddp => Box::new(ddp::Ddp::new(&self.address, count, channels)?),
+            ControllerKind::Esphome => {
+                Box::new(esphome::Esphome::new(&self.address, count, channels)?)
+            }
+            ControllerKind::Simulated => Box::new(simulated::Simulated::new(count)),
+        };
+
+        Ok(match self.pixel_order {
+            PixelOrder::Rgb => controller,
+            order => Box::new(Reordered { controller, order }),
+        })
+    }
+}
+
+/// Resolves `host` or `host:port`, falling back to the protocol's default port.
+fn resolve(address: &str, default_port: u16) -> anyhow::Result<SocketAddr> {
+    let address = if address.contains(':') {
+        address.to_owned()
+    } else {
+        format!("{address}:{default_port}")
+    };
+
+    address
+        .to_socket_addrs()
+        .with_context(|| format!("Failed to resolve {address}"))?
+        .next()
+        .with_context(|| format!("No addresses found for {address}"))
+}
@@ -0,0 +1,52 @@
+use std::{io::Write, net::TcpStream, time::Duration};
+
+use eframe::epaint::Color32;
+
+use super::{resolve, LedController};
+
+/// Open Pixel Control over TCP, as spoken by fcserver, Gl_Server and most art-installation rigs.
+pub struct Opc {
+    stream: TcpStream,
+    pixels: Vec<[u8; 3]>,
+}
+
+const PORT: u16 = 7890;
+/// Channel 0 broadcasts to every output of the server.
+const CHANNEL: u8 = 0;
+const COMMAND_SET_PIXEL_COLORS: u8 = 0;
+
+impl Opc {
+    pub fn new(address: &str, led_count: usize) -> anyhow::Result<Self> {
+        let stream = TcpStream::connect_timeout(&resolve(address, PORT)?, Duration::from_secs(2))?;
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            stream,
+            pixels: vec![[0; 3]; led_count],
+        })
+    }
+}
+
+impl LedController for Opc {
+    fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Color32) {
+        if let Some(pixel) = self.pixels.get_mut(index) {
+            *pixel = [color.r(), color.g(), color.b()];
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        let len = (self.pixels.len() * 3) as u16;
+
+        let mut packet = Vec::with_capacity(4 + len as usize);
+        packet.extend([CHANNEL, COMMAND_SET_PIXEL_COLORS]);
+        packet.extend(len.to_be_bytes());
+        packet.extend(self.pixels.iter().flatten());
+
+        self.stream.write_all(&packet)?;
+        Ok(())
+    }
+}
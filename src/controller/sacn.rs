@@ -0,0 +1,237 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::{BuildHasher, Hasher},
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+};
+
+use eframe::epaint::Color32;
+use serde::{Deserialize, Serialize};
+
+use super::{resolve, LedController};
+
+/// Streaming ACN (E1.31). By default three channels per LED packed into consecutive universes
+/// starting at 1, or wherever `addressing` puts them.
+pub struct Sacn {
+    socket: UdpSocket,
+    /// Unicast target, or `None` to send each universe to its multicast group.
+    target: Option<SocketAddr>,
+    cid: [u8; 16],
+    sequence: u8,
+    /// Universe, first channel and channel order of each LED.
+    slots: Vec<(u16, usize, ChannelOrder)>,
+    /// Channel data of every universe the LEDs are in.
+    universes: BTreeMap<u16, Vec<u8>>,
+}
+
+const PORT: u16 = 5568;
+const CHANNELS: usize = 512;
+const FIRST_UNIVERSE: u16 = 1;
+const SOURCE_NAME: &str = "LED Position Calibrator";
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelOrder {
+    Rgb,
+    Grb,
+    Rgbw,
+    /// A single channel, for white-only fixtures.
+    White,
+}
+
+impl ChannelOrder {
+    pub const ALL: [Self; 4] = [Self::Rgb, Self::Grb, Self::Rgbw, Self::White];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rgb => "RGB",
+            Self::Grb => "GRB",
+            Self::Rgbw => "RGBW",
+            Self::White => "White",
+        }
+    }
+
+    fn width(self) -> usize {
+        match self {
+            Self::Rgb | Self::Grb => 3,
+            Self::Rgbw => 4,
+            Self::White => 1,
+        }
+    }
+}
+
+/// Where a run of LEDs starts on the DMX side. It covers every LED up to the next range's
+/// `first_led`, each following the one before and moving on to the next universe when one is
+/// full, as pixel controllers never split an LED across universes.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DmxRange {
+    pub first_led: usize,
+    pub universe: u16,
+    /// 1-based, like on the fixtures.
+    pub channel: usize,
+    pub order: ChannelOrder,
+}
+
+impl Default for DmxRange {
+    fn default() -> Self {
+        Self {
+            first_led: 0,
+            universe: FIRST_UNIVERSE,
+            channel: 1,
+            order: ChannelOrder::Rgb,
+        }
+    }
+}
+
+/// Universe, first channel (0-based) and order of each of `led_count` LEDs.
+fn slots(addressing: &[DmxRange], led_count: usize) -> Vec<(u16, usize, ChannelOrder)> {
+    let default = [DmxRange::default()];
+    let mut ranges = if addressing.is_empty() {
+        &default[..]
+    } else {
+        addressing
+    }
+    .to_vec();
+    ranges.sort_by_key(|range| range.first_led);
+
+    let mut slots = Vec::with_capacity(led_count);
+    let (mut universe, mut channel, mut order) = (FIRST_UNIVERSE, 0, ChannelOrder::Rgb);
+    let mut ranges = ranges.iter().peekable();
+    for index in 0..led_count {
+        while let Some(range) = ranges.next_if(|range| range.first_led <= index) {
+            universe = range.universe;
+            channel = range.channel.clamp(1, CHANNELS) - 1;
+            order = range.order;
+        }
+        if channel + order.width() > CHANNELS {
+            universe = universe.wrapping_add(1);
+            channel = 0;
+        }
+        slots.push((universe, channel, order));
+        channel += order.width();
+    }
+    slots
+}
+
+impl Sacn {
+    pub fn new(address: &str, led_count: usize, addressing: &[DmxRange]) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        let target = match address.trim() {
+            "" => None,
+            address => Some(resolve(address, PORT)?),
+        };
+
+        // Senders are identified by a random UUID; it only has to be stable for this session
+        let mut cid = [0; 16];
+        for half in cid.chunks_mut(8) {
+            half.copy_from_slice(&RandomState::new().build_hasher().finish().to_be_bytes());
+        }
+
+        let slots = slots(addressing, led_count);
+        let mut universes = BTreeMap::<u16, Vec<u8>>::new();
+        for &(universe, channel, order) in &slots {
+            let data = universes.entry(universe).or_default();
+            data.resize(data.len().max(channel + order.width()), 0);
+        }
+
+        Ok(Self {
+            socket,
+            target,
+            cid,
+            sequence: 0,
+            slots,
+            universes,
+        })
+    }
+
+    fn packet(&self, universe: u16, data: &[u8]) -> Vec<u8> {
+        let len = 126 + data.len();
+        let pdu_len = |offset: usize| (0x7000 | (len - offset) as u16).to_be_bytes();
+
+        let mut packet = Vec::with_capacity(len);
+
+        // Root layer
+        packet.extend(0x0010u16.to_be_bytes());
+        packet.extend(0x0000u16.to_be_bytes());
+        packet.extend(b"ASC-E1.17\0\0\0");
+        packet.extend(pdu_len(16));
+        packet.extend(0x0000_0004u32.to_be_bytes());
+        packet.extend(self.cid);
+
+        // Framing layer
+        packet.extend(pdu_len(38));
+        packet.extend(0x0000_0002u32.to_be_bytes());
+        let mut source_name = [0; 64];
+        source_name[..SOURCE_NAME.len()].copy_from_slice(SOURCE_NAME.as_bytes());
+        packet.extend(source_name);
+        packet.push(100); // priority
+        packet.extend(0u16.to_be_bytes()); // synchronization address
+        packet.push(self.sequence);
+        packet.push(0); // options
+        packet.extend(universe.to_be_bytes());
+
+        // DMP layer
+        packet.extend(pdu_len(115));
+        packet.push(0x02);
+        packet.push(0xa1);
+        packet.extend(0u16.to_be_bytes()); // first property address
+        packet.extend(1u16.to_be_bytes()); // address increment
+        packet.extend((data.len() as u16 + 1).to_be_bytes());
+        packet.push(0); // DMX start code
+        packet.extend(data);
+
+        packet
+    }
+}
+
+impl LedController for Sacn {
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Color32) {
+        let Some(&(universe, channel, order)) = self.slots.get(index) else {
+            return;
+        };
+        let [r, g, b, _] = color.to_array();
+        let data = self.universes.get_mut(&universe).unwrap();
+        match order {
+            ChannelOrder::Rgb => data[channel..channel + 3].copy_from_slice(&[r, g, b]),
+            ChannelOrder::Grb => data[channel..channel + 3].copy_from_slice(&[g, r, b]),
+            ChannelOrder::Rgbw => {
+                // Whatever the three have in common goes to the white channel
+                let w = r.min(g).min(b);
+                data[channel..channel + 4].copy_from_slice(&[r - w, g - w, b - w, w]);
+            }
+            ChannelOrder::White => data[channel] = r.max(g).max(b),
+        }
+    }
+
+    fn set_white(&mut self, index: usize, white: u8) {
+        let Some(&(universe, channel, order)) = self.slots.get(index) else {
+            return;
+        };
+        let data = self.universes.get_mut(&universe).unwrap();
+        match order {
+            ChannelOrder::Rgb | ChannelOrder::Grb => {
+                data[channel..channel + 3].copy_from_slice(&[white; 3]);
+            }
+            ChannelOrder::Rgbw => data[channel..channel + 4].copy_from_slice(&[0, 0, 0, white]),
+            ChannelOrder::White => data[channel] = white,
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        for (&universe, data) in &self.universes {
+            let packet = self.packet(universe, data);
+
+            let target = self.target.unwrap_or_else(|| {
+                let [hi, lo] = universe.to_be_bytes();
+                SocketAddr::from((Ipv4Addr::new(239, 255, hi, lo), PORT))
+            });
+            self.socket.send_to(&packet, target)?;
+        }
+
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::{Arc, Mutex, Weak};
+
+use eframe::epaint::Color32;
+
+use super::LedController;
+
+/// Every simulated strip still connected, in the order they were connected, so the simulated
+/// camera can show them chained like segments.
+static STRIPS: Mutex<Vec<Weak<Pixels>>> = Mutex::new(Vec::new());
+
+type Pixels = Mutex<Vec<[u8; 3]>>;
+
+/// A strip that only exists in the simulated camera, see `simulator`.
+pub struct Simulated {
+    pixels: Vec<[u8; 3]>,
+    /// What the camera sees, updated on `flush`.
+    shown: Arc<Pixels>,
+}
+
+impl Simulated {
+    pub fn new(led_count: usize) -> Self {
+        let shown = Arc::new(Mutex::new(vec![[0; 3]; led_count]));
+
+        let mut strips = STRIPS.lock().unwrap();
+        strips.retain(|strip| strip.strong_count() > 0);
+        strips.push(Arc::downgrade(&shown));
+
+        Self {
+            pixels: vec![[0; 3]; led_count],
+            shown,
+        }
+    }
+}
+
+/// Colors of every simulated LED as last flushed, across all connected strips.
+pub fn shown() -> Vec<[u8; 3]> {
+    STRIPS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(Weak::upgrade)
+        .flat_map(|strip| strip.lock().unwrap().clone())
+        .collect()
+}
+
+impl LedController for Simulated {
+    fn len(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Color32) {
+        if let Some(pixel) = self.pixels.get_mut(index) {
+            *pixel = [color.r(), color.g(), color.b()];
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.shown.lock().unwrap().clone_from(&self.pixels);
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use std::net::UdpSocket;
+
+use eframe::epaint::Color32;
+
+use super::{resolve, Channels, LedController};
+
+/// WLED's UDP realtime protocol, using the DNRGB variant so strips longer than one packet work,
+/// or DRGBW for RGBW strips. White-only strips take RGB, WLED turns it into white itself.
+pub struct Wled {
+    socket: UdpSocket,
+    channels: Channels,
+    /// `channels` wide, where white-only strips take RGB.
+    pixels: Vec<u8>,
+}
+
+const PORT: u16 = 21324;
+const PROTOCOL_DRGBW: u8 = 3;
+const PROTOCOL_DNRGB: u8 = 4;
+/// Seconds without packets before WLED goes back to its own effects.
+const TIMEOUT: u8 = 2;
+const LEDS_PER_PACKET: usize = 489;
+/// DRGBW has no start index, so everything has to fit in one packet.
+const MAX_RGBW_LEDS: usize = 367;
+
+impl Wled {
+    pub fn new(address: &str, led_count: usize, channels: Channels) -> anyhow::Result<Self> {
+        let channels = match channels {
+            Channels::White => Channels::Rgb,
+            channels => channels,
+        };
+        anyhow::ensure!(
+            channels != Channels::Rgbw || led_count <= MAX_RGBW_LEDS,
+            "WLED only takes {MAX_RGBW_LEDS} RGBW LEDs over UDP"
+        );
+
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(resolve(address, PORT)?)?;
+
+        Ok(Self {
+            socket,
+            channels,
+            pixels: vec![0; led_count * channels.width()],
+        })
+    }
+
+    fn pixel(&mut self, index: usize) -> Option<&mut [u8]> {
+        let width = self.channels.width();
+        self.pixels.get_mut(index * width..(index + 1) * width)
+    }
+}
+
+impl LedController for Wled {
+    fn len(&self) -> usize {
+        self.pixels.len() / self.channels.width()
+    }
+
+    fn set_pixel(&mut self, index: usize, color: Color32) {
+        let channels = self.channels;
+        if let Some(pixel) = self.pixel(index) {
+            channels.pixel(color, pixel);
+        }
+    }
+
+    fn set_white(&mut self, index: usize, white: u8) {
+        let channels = self.channels;
+        if let Some(pixel) = self.pixel(index) {
+            channels.white(white, pixel);
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if self.channels == Channels::Rgbw {
+            let mut packet = Vec::with_capacity(2 + self.pixels.len());
+            packet.extend([PROTOCOL_DRGBW, TIMEOUT]);
+            packet.extend(&self.pixels);
+            self.socket.send(&packet)?;
+            return Ok(());
+        }
+
+        for (chunk_index, chunk) in self.pixels.chunks(LEDS_PER_PACKET * 3).enumerate() {
+            let start = (chunk_index * LEDS_PER_PACKET) as u16;
+
+            let mut packet = Vec::with_capacity(4 + chunk.len());
+            packet.extend([PROTOCOL_DNRGB, TIMEOUT]);
+            packet.extend(start.to_be_bytes());
+            packet.extend(chunk);
+
+            self.socket.send(&packet)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Depth for each LED as it's scanned, from a depth camera aligned to the color one, so it comes
+//! out in 3D without a second camera or session. See `realsense` for where the frames come from.
+
+use std::sync::RwLock;
+
+use eframe::epaint::Pos2;
+
+use crate::{segments, Led};
+
+/// How far around a detection to look for depth, in pixels. The LED itself often has none, as
+/// bright and shiny spots confuse the depth sensor.
+const SAMPLE_RADIUS: i64 = 3;
+
+/// A depth frame with the same size and viewpoint as the color frames.
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// Distance of each pixel in meters, zero where there's no depth.
+    pub meters: Vec<f32>,
+    /// In pixels, of the color camera.
+    pub focal_length: [f32; 2],
+    pub principal_point: [f32; 2],
+}
+
+impl Frame {
+    /// Position in meters relative to the color camera of what's at `position`, from the median
+    /// depth around it. `None` when there's no depth anywhere near.
+    fn deproject(&self, position: Pos2) -> Option<[f32; 3]> {
+        let (x, y) = (position.x as i64, position.y as i64);
+        let mut samples = (y - SAMPLE_RADIUS..=y + SAMPLE_RADIUS)
+            .flat_map(|y| (x - SAMPLE_RADIUS..=x + SAMPLE_RADIUS).map(move |x| (x, y)))
+            .filter(|&(x, y)| {
+                x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+            })
+            .map(|(x, y)| self.meters[y as usize * self.width + x as usize])
+            .filter(|&z| z > 0.0)
+            .collect::<Vec<_>>();
+        if samples.is_empty() {
+            return None;
+        }
+
+        let middle = samples.len() / 2;
+        let z = *samples.select_nth_unstable_by(middle, f32::total_cmp).1;
+
+        let [fx, fy] = self.focal_length;
+        let [cx, cy] = self.principal_point;
+        Some([(position.x - cx) * z / fx, (position.y - cy) * z / fy, z])
+    }
+}
+
+/// The latest depth frame, set while a depth camera is running.
+pub static FRAME: RwLock<Option<Frame>> = RwLock::new(None);
+
+/// Where each LED is in 3D by index, like `scan::MAP`.
+pub static MAP: RwLock<Vec<Option<[f32; 3]>>> = RwLock::new(Vec::new());
+
+/// Clears the map for a scan of `count` LEDs.
+pub fn reset(count: usize) {
+    *MAP.write().unwrap() = vec![None; count];
+}
+
+/// Records the depth of LED `index`, found at `position`.
+pub fn capture(index: usize, position: Option<Pos2>) {
+    let frame = FRAME.read().unwrap();
+    let Some(frame) = frame.as_ref() else {
+        return;
+    };
+
+    if let Some(slot) = MAP.write().unwrap().get_mut(index) {
+        *slot = position.and_then(|position| frame.deproject(position));
+    }
+}
+
+/// `leds` placed in 3D relative to the color camera, for those with a depth. The rest are left
+/// alone, as with `stereo::to_3d`.
+pub fn to_3d(leds: &[Led]) -> Vec<Led> {
+    if FRAME.read().unwrap().is_none() {
+        return leds.to_vec();
+    }
+
+    let layout = segments::LAYOUT.read().unwrap();
+    let map = MAP.read().unwrap();
+
+    leds.iter()
+        .map(|led| match map.get(layout.global(led.segment, led.index)) {
+            Some(&Some(position)) => Led { position, ..*led },
+            _ => *led,
+        })
+        .collect()
+}
@@ -0,0 +1,280 @@
+//! Comparing two maps of the same installation LED by LED, to check a scan repeats or to find what
+//! moved since the last one.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use eframe::{
+    egui::{Sense, Slider, Ui},
+    epaint::{Color32, Pos2, Rect, Stroke, Vec2},
+};
+
+use crate::{export, overlay, Led};
+
+/// An LED found in both maps.
+pub struct Displacement {
+    pub segment: usize,
+    pub index: usize,
+    pub from: [f32; 3],
+    pub to: [f32; 3],
+}
+
+impl Displacement {
+    pub fn distance(&self) -> f32 {
+        self.from
+            .iter()
+            .zip(&self.to)
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+pub struct Diff {
+    /// By segment and index.
+    pub moved: Vec<Displacement>,
+    /// LEDs only the old map found.
+    pub only_old: Vec<Led>,
+    /// LEDs only the new map found.
+    pub only_new: Vec<Led>,
+}
+
+pub struct Stats {
+    pub mean: f32,
+    pub median: f32,
+    pub max: f32,
+    /// Segment and index of the LED that moved most.
+    pub furthest: (usize, usize),
+}
+
+impl Diff {
+    pub fn new(old: &[Led], new: &[Led]) -> Self {
+        let find = |leds: &[Led], led: &Led| {
+            leds.iter()
+                .find(|other| other.segment == led.segment && other.index == led.index)
+                .copied()
+        };
+
+        let mut moved = old
+            .iter()
+            .filter_map(|from| {
+                let to = find(new, from)?;
+                Some(Displacement {
+                    segment: from.segment,
+                    index: from.index,
+                    from: from.position,
+                    to: to.position,
+                })
+            })
+            .collect::<Vec<_>>();
+        moved.sort_by_key(|displacement| (displacement.segment, displacement.index));
+
+        Self {
+            moved,
+            only_old: old
+                .iter()
+                .filter(|led| find(new, led).is_none())
+                .copied()
+                .collect(),
+            only_new: new
+                .iter()
+                .filter(|led| find(old, led).is_none())
+                .copied()
+                .collect(),
+        }
+    }
+
+    pub fn load(old: &Path, new: &Path) -> anyhow::Result<Self> {
+        let read = |path: &Path| {
+            export::read_json(path).with_context(|| format!("Failed to read {}", path.display()))
+        };
+        Ok(Self::new(&read(old)?, &read(new)?))
+    }
+
+    /// `None` when no LED is in both maps.
+    pub fn stats(&self) -> Option<Stats> {
+        let mut distances = self
+            .moved
+            .iter()
+            .map(Displacement::distance)
+            .collect::<Vec<_>>();
+        let furthest = self
+            .moved
+            .iter()
+            .max_by(|a, b| a.distance().total_cmp(&b.distance()))?;
+
+        let mean = distances.iter().sum::<f32>() / distances.len() as f32;
+        let middle = distances.len() / 2;
+        let median = *distances.select_nth_unstable_by(middle, f32::total_cmp).1;
+
+        Some(Stats {
+            mean,
+            median,
+            max: furthest.distance(),
+            furthest: (furthest.segment, furthest.index),
+        })
+    }
+
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} LEDs in both, {} only in the old map, {} only in the new",
+            self.moved.len(),
+            self.only_old.len(),
+            self.only_new.len()
+        );
+        if let Some(stats) = self.stats() {
+            let (segment, index) = stats.furthest;
+            summary += &format!(
+                ", moved {:.2} on average, {:.2} median, at most {:.2} (LED {index} of segment \
+                 {segment})",
+                stats.mean, stats.median, stats.max
+            );
+        }
+        summary
+    }
+
+    /// Writes each LED in both maps with where it went and how far, as CSV.
+    pub fn write_csv(&self, path: &Path) -> anyhow::Result<()> {
+        let write = || -> std::io::Result<()> {
+            let mut out = BufWriter::new(File::create(path)?);
+            writeln!(out, "segment,index,x,y,z,dx,dy,dz,distance")?;
+            for displacement in &self.moved {
+                let [x, y, z] = displacement.from;
+                let [dx, dy, dz] =
+                    [0, 1, 2].map(|axis| displacement.to[axis] - displacement.from[axis]);
+                let distance = displacement.distance();
+                writeln!(
+                    out,
+                    "{},{},{x},{y},{z},{dx},{dy},{dz},{distance}",
+                    displacement.segment, displacement.index
+                )?;
+            }
+            out.flush()
+        };
+        write().with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// The diff window's state.
+pub struct View {
+    pub old_path: String,
+    pub new_path: String,
+    diff: Option<Diff>,
+    status: String,
+    /// How much longer than the real displacement the arrows are drawn, as small shifts are
+    /// otherwise invisible.
+    exaggerate: f32,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            old_path: "leds.json".to_owned(),
+            new_path: "leds.json".to_owned(),
+            diff: None,
+            status: String::new(),
+            exaggerate: 1.0,
+        }
+    }
+}
+
+impl View {
+    pub fn show(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Old");
+            ui.text_edit_singleline(&mut self.old_path);
+        });
+        ui.horizontal(|ui| {
+            ui.label("New");
+            ui.text_edit_singleline(&mut self.new_path);
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Compare").clicked() {
+                match Diff::load(self.old_path.as_ref(), self.new_path.as_ref()) {
+                    Ok(diff) => {
+                        self.status = diff.summary();
+                        self.diff = Some(diff);
+                    }
+                    Err(e) => {
+                        self.status = format!("{e:#}");
+                        self.diff = None;
+                    }
+                }
+            }
+            if let Some(diff) = &self.diff {
+                if ui.button("Save CSV").clicked() {
+                    let path = Path::new(&self.new_path).with_extension("diff.csv");
+                    self.status = match diff.write_csv(&path) {
+                        Ok(()) => format!("Saved {}", path.display()),
+                        Err(e) => format!("{e:#}"),
+                    };
+                }
+            }
+            ui.add(
+                Slider::new(&mut self.exaggerate, 1.0..=20.0)
+                    .logarithmic(true)
+                    .text("Exaggerate"),
+            );
+        });
+
+        ui.label(&self.status);
+
+        if let Some(diff) = &self.diff {
+            self.paint(ui, diff);
+        }
+    }
+
+    /// Both maps fit into the space left, with an arrow from where each LED was to where it is,
+    /// colored from blue for the least moved to red for the most.
+    fn paint(&self, ui: &mut Ui, diff: &Diff) {
+        let (rect, _) =
+            ui.allocate_exact_size(ui.available_size().max(Vec2::splat(100.0)), Sense::hover());
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_gray(16));
+
+        let points = diff
+            .moved
+            .iter()
+            .flat_map(|displacement| [displacement.from, displacement.to])
+            .chain(
+                diff.only_old
+                    .iter()
+                    .chain(&diff.only_new)
+                    .map(|led| led.position),
+            )
+            .map(|[x, y, _]| Pos2::new(x, y))
+            .collect::<Vec<_>>();
+        if points.is_empty() {
+            return;
+        }
+        let bounds = Rect::from_points(&points);
+
+        let scale =
+            (rect.shrink(10.0).size() / bounds.size().max(Vec2::splat(f32::EPSILON))).min_elem();
+        let place =
+            |[x, y, _]: [f32; 3]| rect.center() + (Pos2::new(x, y) - bounds.center()) * scale;
+
+        let max = diff
+            .stats()
+            .map_or(0.0, |stats| stats.max)
+            .max(f32::EPSILON);
+        for displacement in &diff.moved {
+            let from = place(displacement.from);
+            let to = from + (place(displacement.to) - from) * self.exaggerate;
+            let color = overlay::gradient(displacement.distance() / max);
+            painter.circle_filled(from, 2.0, Color32::GRAY);
+            painter.arrow(from, to - from, Stroke::new(1.5, color));
+        }
+        for led in &diff.only_old {
+            painter.circle_stroke(place(led.position), 4.0, Stroke::new(1.5, Color32::RED));
+        }
+        for led in &diff.only_new {
+            painter.circle_stroke(place(led.position), 4.0, Stroke::new(1.5, Color32::GREEN));
+        }
+    }
+}
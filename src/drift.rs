@@ -0,0 +1,402 @@
+//! Noticing when the camera moves during a scan. A camera that gets bumped halfway through puts
+//! the second half of the map off from the first by however far it moved, without a single
+//! error, and nothing about the positions alone gives it away.
+//!
+//! The scan takes a small grayscale reference of the scene when it starts, and every few seconds
+//! compares the latest frame with it for the shift that lines them up best, coarse to fine. Past
+//! `THRESHOLD` the camera counts as moved, and depending on `ScanSettings::drift` the positions
+//! found from then on are shifted back, or the scan stops with a warning so the camera can be put
+//! back and the scan resumed. A resumed scan keeps the reference of the one it continues, and a
+//! region scan takes a new one after pointing the camera at each region. LEDs found between the
+//! move and the comparison that catches it are off either way.
+//!
+//! The LED lit at the time barely figures in a comparison of the whole frame, but a scene too
+//! dark to have anything else in it can't be tracked, which is only logged.
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use eframe::epaint::{Pos2, Vec2};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{pipeline, scan, toasts};
+
+/// A shift of more than this many pixels means the camera moved.
+pub const THRESHOLD: f32 = 3.0;
+
+/// How often the frames are compared with the reference.
+const INTERVAL: Duration = Duration::from_secs(2);
+
+/// Frame pixels per pixel of the coarse and the fine comparison.
+const COARSE: usize = 8;
+const FINE: usize = 2;
+
+/// How far the coarse comparison searches in each direction, in its own pixels, and how far the
+/// fine one searches around what the coarse one found.
+const COARSE_RANGE: i32 = 12;
+const FINE_RANGE: i32 = 3;
+
+/// Brighter is clipped to this, so the lit LED and its bloom don't outweigh the scene.
+const CLIP: u8 = 200;
+
+/// A reference with less spread in brightness than this has nothing to line up.
+const MIN_CONTRAST: f32 = 4.0;
+
+/// What a scan does when the camera moves.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+pub enum DriftAction {
+    /// Don't compare the frames at all.
+    Off,
+    /// Shift the positions found after the move back to where they'd have been.
+    Compensate,
+    /// Stop the scan, keeping it resumable.
+    #[default]
+    Stop,
+}
+
+impl DriftAction {
+    pub const ALL: [Self; 3] = [Self::Off, Self::Compensate, Self::Stop];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "Ignore",
+            Self::Compensate => "Compensate",
+            Self::Stop => "Stop the scan",
+        }
+    }
+}
+
+/// Grayscale at two resolutions.
+struct Reference {
+    coarse: Gray,
+    fine: Gray,
+}
+
+struct Gray {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+struct State {
+    reference: Option<Reference>,
+    /// How far the scene moved from the reference, as of the last comparison.
+    shift: Vec2,
+    checked: Option<Instant>,
+    /// Whether the move was already warned about.
+    warned: bool,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    reference: None,
+    shift: Vec2::ZERO,
+    checked: None,
+    warned: false,
+});
+
+/// Takes the reference from the latest frame, for a scan that starts out fresh. Keeps the one
+/// there is when `resuming`.
+pub fn begin(resuming: bool) {
+    let mut state = STATE.lock().unwrap();
+    state.checked = None;
+    state.warned = false;
+    if resuming && state.reference.is_some() {
+        return;
+    }
+
+    state.shift = Vec2::ZERO;
+    state.reference = None;
+    if scan::SETTINGS.read().unwrap().drift == DriftAction::Off {
+        return;
+    }
+    let Some((frame, width)) = pipeline::latest_rgb() else {
+        return;
+    };
+    let fine = Gray::from_rgb(&frame, width, FINE);
+    if fine.contrast() < MIN_CONTRAST {
+        info!("The scene is too dark to notice the camera moving during the scan");
+        return;
+    }
+    state.reference = Some(Reference {
+        coarse: fine.downscaled(COARSE / FINE),
+        fine,
+    });
+}
+
+/// How far the camera moved since the scan started, once it's past `THRESHOLD`.
+pub fn shift() -> Option<Vec2> {
+    let shift = STATE.lock().unwrap().shift;
+    (shift.length() > THRESHOLD).then_some(shift)
+}
+
+/// `position` as it would have been found before the camera moved, when the scan compensates.
+pub fn compensate(position: Pos2) -> Pos2 {
+    match (scan::SETTINGS.read().unwrap().drift, shift()) {
+        (DriftAction::Compensate, Some(shift)) => position - shift,
+        _ => position,
+    }
+}
+
+/// Compares the latest frame with the reference when it's time to, and acts on a move. Called
+/// by the scan before every LED it stores, which it shouldn't when this returns false.
+pub fn check() -> bool {
+    let action = scan::SETTINGS.read().unwrap().drift;
+    let mut state = STATE.lock().unwrap();
+    // The rest of the step the scan stopped in is off too
+    if action == DriftAction::Stop && state.warned {
+        return false;
+    }
+    if action == DriftAction::Off
+        || state.reference.is_none()
+        || state
+            .checked
+            .is_some_and(|checked| checked.elapsed() < INTERVAL)
+    {
+        return true;
+    }
+    state.checked = Some(Instant::now());
+
+    let Some((frame, width)) = pipeline::latest_rgb() else {
+        return true;
+    };
+    let Some(reference) = &state.reference else {
+        return true;
+    };
+    let fine = Gray::from_rgb(&frame, width, FINE);
+    if fine.width != reference.fine.width || fine.height != reference.fine.height {
+        return true;
+    }
+    let coarse = fine.downscaled(COARSE / FINE);
+
+    let scale = (COARSE / FINE) as i32;
+    let around = best_shift(&reference.coarse, &coarse, [0, 0], COARSE_RANGE).0;
+    let shift = refined(&reference.fine, &fine, around.map(|d| d * scale), FINE_RANGE);
+    state.shift = shift * FINE as f32;
+
+    if state.shift.length() <= THRESHOLD {
+        if state.warned {
+            state.warned = false;
+            info!("The camera is back where the scan started");
+        }
+        return true;
+    }
+    if state.warned {
+        return true;
+    }
+    state.warned = true;
+
+    let moved = format!("{:.0}, {:.0}", state.shift.x, state.shift.y);
+    match action {
+        DriftAction::Off => true,
+        DriftAction::Compensate => {
+            warn!("The camera moved by {moved} pixels, shifting the positions found from now on");
+            true
+        }
+        DriftAction::Stop => {
+            let message = format!(
+                "The camera moved by {moved} pixels during the scan. Put it back and resume the \
+                 scan, or have it compensate in the scan settings"
+            );
+            warn!("{message}");
+            toasts::error(message, None);
+            drop(state);
+            scan::stop();
+            false
+        }
+    }
+}
+
+impl Gray {
+    /// `frame`, packed RGB `width` pixels across, averaged over `step` by `step` blocks.
+    fn from_rgb(frame: &[u8], width: usize, step: usize) -> Self {
+        let height = frame.len() / 3 / width;
+        let (small_width, small_height) = (width / step, height / step);
+        let mut pixels = Vec::with_capacity(small_width * small_height);
+        for y in 0..small_height {
+            for x in 0..small_width {
+                let mut sum = 0;
+                for row in y * step..(y + 1) * step {
+                    let start = (row * width + x * step) * 3;
+                    for rgb in frame[start..start + step * 3].chunks_exact(3) {
+                        let luma =
+                            (rgb[0] as u32 * 77 + rgb[1] as u32 * 150 + rgb[2] as u32 * 29) >> 8;
+                        sum += luma.min(CLIP as u32);
+                    }
+                }
+                pixels.push((sum / (step * step) as u32) as u8);
+            }
+        }
+        Self {
+            width: small_width,
+            height: small_height,
+            pixels,
+        }
+    }
+
+    fn downscaled(&self, step: usize) -> Self {
+        let (width, height) = (self.width / step, self.height / step);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let sum = (y * step..(y + 1) * step)
+                    .flat_map(|row| {
+                        let start = row * self.width + x * step;
+                        &self.pixels[start..start + step]
+                    })
+                    .map(|&value| value as u32)
+                    .sum::<u32>();
+                pixels.push((sum / (step * step) as u32) as u8);
+            }
+        }
+        Self { width, height, pixels }
+    }
+
+    /// Standard deviation of the brightness.
+    fn contrast(&self) -> f32 {
+        let count = self.pixels.len().max(1) as f32;
+        let mean = self.pixels.iter().map(|&value| value as f32).sum::<f32>() / count;
+        let variance = self
+            .pixels
+            .iter()
+            .map(|&value| (value as f32 - mean).powi(2))
+            .sum::<f32>()
+            / count;
+        variance.sqrt()
+    }
+}
+
+/// Mean absolute difference between `reference` and `current` moved by `shift`, over where they
+/// overlap. `None` when they overlap by less than half.
+fn difference(reference: &Gray, current: &Gray, [dx, dy]: [i32; 2]) -> Option<f32> {
+    let (width, height) = (reference.width as i32, reference.height as i32);
+    let xs = 0.max(-dx)..width.min(width - dx);
+    let ys = 0.max(-dy)..height.min(height - dy);
+    let area = xs.len() * ys.len();
+    if area * 2 < reference.pixels.len() {
+        return None;
+    }
+
+    let mut sum = 0;
+    for y in ys {
+        let (from, to) = (y * width, (y + dy) * width + dx);
+        let reference = &reference.pixels[(from + xs.start) as usize..(from + xs.end) as usize];
+        let current = &current.pixels[(to + xs.start) as usize..(to + xs.end) as usize];
+        sum += reference
+            .iter()
+            .zip(current)
+            .map(|(&a, &b)| a.abs_diff(b) as u32)
+            .sum::<u32>();
+    }
+    Some(sum as f32 / area as f32)
+}
+
+/// The shift within `range` of `around` that lines `current` up with `reference` best, with
+/// the differences at every shift searched, indexed from the top left.
+fn best_shift(
+    reference: &Gray,
+    current: &Gray,
+    [x, y]: [i32; 2],
+    range: i32,
+) -> ([i32; 2], Vec<Option<f32>>) {
+    let side = range * 2 + 1;
+    let differences = (0..side * side)
+        .map(|i| difference(reference, current, [x + i % side - range, y + i / side - range]))
+        .collect::<Vec<_>>();
+    let best = (0..differences.len())
+        .filter(|&i| differences[i].is_some())
+        .min_by(|&a, &b| differences[a].partial_cmp(&differences[b]).unwrap())
+        .map_or([x, y], |i| {
+            let i = i as i32;
+            [x + i % side - range, y + i / side - range]
+        });
+    (best, differences)
+}
+
+/// `best_shift` to a fraction of a pixel, from a parabola through the differences either side.
+fn refined(reference: &Gray, current: &Gray, around: [i32; 2], range: i32) -> Vec2 {
+    let (best, differences) = best_shift(reference, current, around, range);
+    let side = range * 2 + 1;
+    let at = |[x, y]: [i32; 2]| {
+        let [x, y] = [x - around[0] + range, y - around[1] + range];
+        ((0..side).contains(&x) && (0..side).contains(&y))
+            .then(|| differences[(y * side + x) as usize])
+            .flatten()
+    };
+    let offset = |before: Option<f32>, middle: Option<f32>, after: Option<f32>| {
+        let (Some(before), Some(middle), Some(after)) = (before, middle, after) else {
+            return 0.0;
+        };
+        let curve = before - 2.0 * middle + after;
+        match curve > 0.0 {
+            true => ((before - after) / curve / 2.0).clamp(-0.5, 0.5),
+            false => 0.0,
+        }
+    };
+
+    let [x, y] = best;
+    let middle = at(best);
+    Vec2::new(
+        x as f32 + offset(at([x - 1, y]), middle, at([x + 1, y])),
+        y as f32 + offset(at([x, y - 1]), middle, at([x, y + 1])),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame of smooth blobs at `offset` from where they'd be, so shifts aren't all whole pixels.
+    fn scene(offset: Vec2) -> Vec<u8> {
+        let (width, height) = (320, 240);
+        let blobs = [(60.0, 50.0, 20.0), (200.0, 80.0, 30.0), (120.0, 180.0, 25.0)];
+        (0..width * height)
+            .flat_map(|i| {
+                let (x, y) = ((i % width) as f32 - offset.x, (i / width) as f32 - offset.y);
+                let value = blobs.iter().fold(30.0, |value, &(bx, by, size)| {
+                    let d2 = (x - bx).powi(2) + (y - by).powi(2);
+                    value + 150.0 * (-d2 / (2.0 * size * size)).exp()
+                });
+                [value.min(255.0) as u8; 3]
+            })
+            .collect()
+    }
+
+    fn estimate(reference: &[u8], current: &[u8]) -> Vec2 {
+        let reference = Gray::from_rgb(reference, 320, FINE);
+        let current = Gray::from_rgb(current, 320, FINE);
+        let scale = (COARSE / FINE) as i32;
+        let around = best_shift(
+            &reference.downscaled(COARSE / FINE),
+            &current.downscaled(COARSE / FINE),
+            [0, 0],
+            COARSE_RANGE,
+        )
+        .0;
+        refined(&reference, &current, around.map(|d| d * scale), FINE_RANGE) * FINE as f32
+    }
+
+    #[test]
+    fn estimates_the_shift() {
+        let reference = scene(Vec2::ZERO);
+        for offset in [
+            Vec2::ZERO,
+            Vec2::new(5.0, 0.0),
+            Vec2::new(-12.0, 7.0),
+            Vec2::new(3.0, -25.0),
+            Vec2::new(40.0, 30.0),
+        ] {
+            let shift = estimate(&reference, &scene(offset));
+            assert!((shift - offset).length() < 1.0, "{offset:?} estimated as {shift:?}");
+        }
+    }
+
+    #[test]
+    fn flat_scenes_have_no_contrast() {
+        let flat = Gray::from_rgb(&[40; 320 * 240 * 3], 320, FINE);
+        assert!(flat.contrast() < MIN_CONTRAST);
+        assert!(Gray::from_rgb(&scene(Vec2::ZERO), 320, FINE).contrast() >= MIN_CONTRAST);
+    }
+}
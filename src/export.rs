@@ -0,0 +1,596 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    pipeline::{self, Settings},
+    scan::{self, ScanMode},
+    segments::{self, Layout},
+    Led,
+};
+
+/// Version of the native format, bumped whenever `Map` changes in a way that needs migrating.
+/// Files from before it was versioned are version 0, see `migrate`.
+pub const VERSION: u64 = 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Ply,
+    Obj,
+    /// A WLED 2D `ledmap.json`.
+    Wled,
+    /// An OpenRGB zone matrix map.
+    Openrgb,
+    /// A SignalRGB custom component.
+    Signalrgb,
+    /// A Falcon Player `virtualdisplaymap`.
+    Fpp,
+    /// JSON for Blender, written with a script next to it that imports it.
+    Blender,
+}
+
+impl ExportFormat {
+    pub const ALL: [Self; 9] = [
+        Self::Json,
+        Self::Csv,
+        Self::Ply,
+        Self::Obj,
+        Self::Wled,
+        Self::Openrgb,
+        Self::Signalrgb,
+        Self::Fpp,
+        Self::Blender,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Json => "JSON",
+            Self::Csv => "CSV",
+            Self::Ply => "PLY",
+            Self::Obj => "OBJ",
+            Self::Wled => "WLED ledmap",
+            Self::Openrgb => "OpenRGB matrix map",
+            Self::Signalrgb => "SignalRGB component",
+            Self::Fpp => "FPP virtual display",
+            Self::Blender => "Blender",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Ply => "ply",
+            Self::Obj => "obj",
+            Self::Wled => "ledmap.json",
+            Self::Openrgb => "openrgb.json",
+            Self::Signalrgb => "signalrgb.json",
+            Self::Fpp => "virtualdisplaymap",
+            Self::Blender => "blender.json",
+        }
+    }
+
+    /// Picks the format by the longest extension that matches, so `x.ledmap.json` is a ledmap
+    /// rather than plain JSON. FPP's map has no extension, so its file name counts too.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        Self::ALL
+            .into_iter()
+            .filter(|format| {
+                name == format.extension() || name.ends_with(&format!(".{}", format.extension()))
+            })
+            .max_by_key(|format| format.extension().len())
+    }
+
+    pub fn write(self, path: &Path, leds: &[Led]) -> anyhow::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_to(&mut out, leds)?;
+        out.flush()?;
+
+        if self == Self::Blender {
+            write_blender_script(path)?;
+        }
+        Ok(())
+    }
+
+    /// The file `write` would write, for the clipboard.
+    pub fn to_text(self, leds: &[Led]) -> anyhow::Result<String> {
+        let mut out = Vec::new();
+        self.write_to(&mut out, leds)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    fn write_to(self, mut out: &mut impl Write, leds: &[Led]) -> anyhow::Result<()> {
+        match self {
+            Self::Json => serde_json::to_writer_pretty(&mut out, &Map {
+                version: VERSION,
+                metadata: Some(Metadata::current()),
+                leds: leds.to_vec(),
+            })?,
+            Self::Csv => write_csv(&mut out, leds)?,
+            Self::Ply => write_ply(&mut out, leds)?,
+            Self::Obj => write_obj(&mut out, leds)?,
+            Self::Wled => write_wled(&mut out, leds)?,
+            Self::Openrgb => write_openrgb(&mut out, leds)?,
+            Self::Signalrgb => write_signalrgb(&mut out, leds)?,
+            Self::Fpp => write_fpp(&mut out, leds)?,
+            Self::Blender => write_blender(&mut out, leds)?,
+        }
+        Ok(())
+    }
+}
+
+/// The native format, which is also what `read_json` loads back.
+#[derive(Serialize, Deserialize)]
+struct Map {
+    version: u64,
+    /// Only missing from files written before it existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<Metadata>,
+    leds: Vec<Led>,
+}
+
+/// Where a map came from, for telling files apart and reproducing a scan.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    pub app_version: String,
+    /// Seconds since the Unix epoch.
+    pub created: u64,
+    /// Width and height of the camera frames the positions were found in, if there were any.
+    pub resolution: Option<[usize; 2]>,
+    /// LEDs on the controller, found or not.
+    pub led_count: usize,
+    /// How the last scan lit the LEDs, if there was one.
+    pub scan_mode: Option<ScanMode>,
+    pub latency_ms: Option<u64>,
+    pub settings: Settings,
+}
+
+impl Metadata {
+    pub fn current() -> Self {
+        Self {
+            app_version: env!("CARGO_PKG_VERSION").to_owned(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs()),
+            resolution: pipeline::latest_rgb()
+                .map(|(frame, width)| [width, frame.len() / width / 3]),
+            led_count: scan::MAP.read().unwrap().len(),
+            scan_mode: scan::LAST_MODE.read().unwrap().clone(),
+            latency_ms: scan::MEASURED_LATENCY
+                .read()
+                .unwrap()
+                .map(|latency| latency.as_millis() as u64),
+            settings: pipeline::SETTINGS.read().unwrap().clone(),
+        }
+    }
+}
+
+pub fn read_json(path: &Path) -> anyhow::Result<Vec<Led>> {
+    Ok(read_map(path)?.leds)
+}
+
+/// The metadata of a native map, `None` for files from before it was kept.
+pub fn read_metadata(path: &Path) -> anyhow::Result<Option<Metadata>> {
+    Ok(read_map(path)?.metadata)
+}
+
+fn read_map(path: &Path) -> anyhow::Result<Map> {
+    let mut map: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+
+    let version = map
+        .get("version")
+        .map_or(Some(0), Value::as_u64)
+        .context("Bad version")?;
+    anyhow::ensure!(
+        version <= VERSION,
+        "{} is version {version} of the format, this build only reads up to {VERSION}",
+        path.display()
+    );
+    migrate(&mut map, version);
+
+    Ok(serde_json::from_value(map)?)
+}
+
+/// Brings `map` from `version` up to `VERSION`, one version at a time.
+fn migrate(map: &mut Value, version: u64) {
+    for version in version..VERSION {
+        match version {
+            // Just the LEDs, some from before segments, which `Led` already defaults
+            0 => map["version"] = 1.into(),
+            _ => unreachable!("no migration from version {version}"),
+        }
+    }
+}
+
+/// Packs an LED index into a 24-bit color, so the index survives tools that only keep vertex
+/// colors. Index 0x01_02_03 becomes rgb(1, 2, 3).
+pub fn index_to_rgb(index: usize) -> [u8; 3] {
+    [(index >> 16) as u8, (index >> 8) as u8, index as u8]
+}
+
+/// Whether the LEDs come from more than one segment, in which case the formats without a place
+/// for it get an extra segment column.
+fn segmented(leds: &[Led]) -> bool {
+    leds.iter().any(|led| led.segment != 0)
+}
+
+fn write_csv(out: &mut impl Write, leds: &[Led]) -> std::io::Result<()> {
+    let segmented = segmented(leds);
+
+    if segmented {
+        writeln!(out, "segment,index,x,y,z")?;
+    } else {
+        writeln!(out, "index,x,y,z")?;
+    }
+
+    for &Led { segment, index, position: [x, y, z] } in leds {
+        if segmented {
+            write!(out, "{segment},")?;
+        }
+        writeln!(out, "{index},{x},{y},{z}")?;
+    }
+
+    Ok(())
+}
+
+fn write_ply(out: &mut impl Write, leds: &[Led]) -> std::io::Result<()> {
+    let segmented = segmented(leds);
+
+    writeln!(out, "ply")?;
+    writeln!(out, "format ascii 1.0")?;
+    writeln!(out, "comment vertex color encodes the LED index as 0xRRGGBB")?;
+    writeln!(out, "element vertex {}", leds.len())?;
+    writeln!(out, "property float x")?;
+    writeln!(out, "property float y")?;
+    writeln!(out, "property float z")?;
+    writeln!(out, "property uchar red")?;
+    writeln!(out, "property uchar green")?;
+    writeln!(out, "property uchar blue")?;
+    if segmented {
+        writeln!(out, "property ushort segment")?;
+    }
+    writeln!(out, "end_header")?;
+
+    for &Led { segment, index, position: [x, y, z] } in leds {
+        let [r, g, b] = index_to_rgb(index);
+        if segmented {
+            writeln!(out, "{x} {y} {z} {r} {g} {b} {segment}")?;
+        } else {
+            writeln!(out, "{x} {y} {z} {r} {g} {b}")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// OBJ has no official vertex colors, but MeshLab and Blender both read the common `v x y z r g b`
+/// extension with colors in 0..1.
+fn write_obj(out: &mut impl Write, leds: &[Led]) -> std::io::Result<()> {
+    writeln!(out, "# vertex color encodes the LED index as 0xRRGGBB")?;
+
+    let segmented = segmented(leds);
+    let mut current = None;
+    for &Led { segment, index, position: [x, y, z] } in leds {
+        // Vertices can't be grouped, so segment boundaries only get a comment
+        if segmented && current != Some(segment) {
+            writeln!(out, "# segment {segment}")?;
+            current = Some(segment);
+        }
+
+        let [r, g, b] = index_to_rgb(index).map(|c| c as f32 / 255.0);
+        writeln!(out, "v {x} {y} {z} {r} {g} {b}")?;
+    }
+
+    Ok(())
+}
+
+/// Largest grid side a ledmap gets, which keeps sparse layouts from blowing up into mostly empty
+/// matrices.
+const WLED_MAX_SIDE: usize = 256;
+
+/// SignalRGB scales components onto its canvas, where much more than this is too fine to place.
+const SIGNALRGB_MAX_SIDE: usize = 64;
+
+/// The LEDs laid onto a grid for the formats that only take whole cells, one cell per typical LED
+/// spacing, grown if that would make the grid more than `max_side` cells across.
+struct Raster {
+    min: [f32; 2],
+    cell: f32,
+    width: usize,
+    height: usize,
+}
+
+impl Raster {
+    fn new(leds: &[Led], max_side: usize) -> Self {
+        let (min, max) =
+            leds.iter()
+                .fold(([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]), |(min, max), led| {
+                    let [x, y, _] = led.position;
+                    ([min[0].min(x), min[1].min(y)], [max[0].max(x), max[1].max(y)])
+                });
+
+        let mut spacing = leds
+            .iter()
+            .filter_map(|a| {
+                leds.iter()
+                    .filter(|b| !std::ptr::eq(a, *b))
+                    .map(|b| distance(a.position, b.position))
+                    .filter(|&d| d > 0.0)
+                    .min_by(f32::total_cmp)
+            })
+            .collect::<Vec<_>>();
+        spacing.sort_by(f32::total_cmp);
+        let extent = (max[0] - min[0]).max(max[1] - min[1]).max(0.0);
+        let cell = spacing
+            .get(spacing.len() / 2)
+            .copied()
+            .unwrap_or(1.0)
+            .max(extent / (max_side - 1) as f32);
+
+        let mut raster = Self { min, cell, width: 0, height: 0 };
+        (raster.width, raster.height) = leds
+            .iter()
+            .map(|led| raster.cell_of(led))
+            .fold((0, 0), |(w, h), (column, row)| (w.max(column + 1), h.max(row + 1)));
+        raster
+    }
+
+    fn cell_of(&self, led: &Led) -> (usize, usize) {
+        let [x, y, _] = led.position;
+        (
+            ((x - self.min[0]) / self.cell).round() as usize,
+            ((y - self.min[1]) / self.cell).round() as usize,
+        )
+    }
+
+    /// The chained index of the LED in each cell, row by row, leaving out and counting the LEDs
+    /// that land on a cell already taken.
+    fn map(&self, leds: &[Led], layout: &Layout) -> (Vec<Option<usize>>, usize) {
+        let mut map = vec![None; self.width * self.height];
+        let mut dropped = 0;
+        for led in leds {
+            let (column, row) = self.cell_of(led);
+            match &mut map[row * self.width + column] {
+                slot @ None => *slot = Some(layout.global(led.segment, led.index)),
+                Some(_) => dropped += 1,
+            }
+        }
+        (map, dropped)
+    }
+}
+
+/// WLED's 2D ledmap: the LEDs rasterized onto a `width` by `height` grid, listing the LED in each
+/// cell and -1 for gaps.
+///
+/// WLED chains its outputs into one index space, each starting where the one before ends, so with
+/// one output per segment an LED's index is its segment's offset in `layout` plus its index on
+/// the segment.
+fn write_wled(out: &mut impl Write, leds: &[Led]) -> anyhow::Result<()> {
+    let (ledmap, dropped) = wled_ledmap(leds, &segments::LAYOUT.read().unwrap());
+    if dropped > 0 {
+        tracing::warn!("{dropped} LEDs share a ledmap cell with another and were left out");
+    }
+    serde_json::to_writer(out, &ledmap)?;
+    Ok(())
+}
+
+/// The ledmap `write_wled` writes, and how many LEDs were left out for sharing a cell.
+fn wled_ledmap(leds: &[Led], layout: &Layout) -> (Value, usize) {
+    let raster = Raster::new(leds, WLED_MAX_SIDE);
+    let (map, dropped) = raster.map(leds, layout);
+    let map = map
+        .iter()
+        .map(|index| index.map_or(-1, |index| index as i64))
+        .collect::<Vec<_>>();
+
+    let ledmap = json!({
+        "n": "calibrator",
+        "width": raster.width,
+        "height": raster.height,
+        "map": map,
+    });
+    (ledmap, dropped)
+}
+
+/// OpenRGB's zone matrix map: a `width` by `height` grid listing the LED in each cell row by row,
+/// with 0xFFFFFFFF for gaps, as OpenRGB keeps it. Chained over the segments like the ledmap, for
+/// a single zone driving them all.
+fn write_openrgb(out: &mut impl Write, leds: &[Led]) -> anyhow::Result<()> {
+    let raster = Raster::new(leds, WLED_MAX_SIDE);
+    let (map, dropped) = raster.map(leds, &segments::LAYOUT.read().unwrap());
+    if dropped > 0 {
+        tracing::warn!("{dropped} LEDs share a matrix map cell with another and were left out");
+    }
+    let map = map
+        .iter()
+        .map(|index| index.map_or(u32::MAX, |index| index as u32))
+        .collect::<Vec<_>>();
+
+    serde_json::to_writer(
+        out,
+        &json!({
+            "name": "calibrator",
+            "type": "matrix",
+            "leds_count": layout_len(leds),
+            "matrix_map": {
+                "width": raster.width,
+                "height": raster.height,
+                "map": map,
+            },
+        }),
+    )?;
+    Ok(())
+}
+
+/// A SignalRGB component, placing each LED on its grid with `LedCoordinates`. SignalRGB doesn't
+/// mind LEDs sharing a cell, so none are left out.
+fn write_signalrgb(out: &mut impl Write, leds: &[Led]) -> anyhow::Result<()> {
+    let layout = segments::LAYOUT.read().unwrap();
+    let raster = Raster::new(leds, SIGNALRGB_MAX_SIDE);
+
+    let mut leds = leds.to_vec();
+    leds.sort_by_key(|led| layout.global(led.segment, led.index));
+    let indices = leds
+        .iter()
+        .map(|led| layout.global(led.segment, led.index))
+        .collect::<Vec<_>>();
+    let coordinates = leds
+        .iter()
+        .map(|led| {
+            let (column, row) = raster.cell_of(led);
+            [column, row]
+        })
+        .collect::<Vec<_>>();
+    let names = indices
+        .iter()
+        .map(|index| format!("Led{index}"))
+        .collect::<Vec<_>>();
+
+    serde_json::to_writer_pretty(
+        out,
+        &json!({
+            "ProductName": "calibrator",
+            "DisplayName": "Calibrated layout",
+            "Brand": "LED Position Calibrator",
+            "Type": "custom",
+            "LedCount": leds.len(),
+            "Width": raster.width,
+            "Height": raster.height,
+            "LedMapping": indices,
+            "LedCoordinates": coordinates,
+            "LedNames": names,
+        }),
+    )?;
+    Ok(())
+}
+
+/// Longest side of the FPP preview, which the layout is scaled to fill.
+const FPP_PREVIEW_SIZE: f32 = 1024.0;
+
+/// Falcon Player's virtual display map, as xLights writes it: the preview size, then a line per
+/// LED with its position in the preview and its channels. FPP numbers channels from 1 across all
+/// outputs, so the segments are chained, three RGB channels per LED.
+fn write_fpp(out: &mut impl Write, leds: &[Led]) -> anyhow::Result<()> {
+    let layout = segments::LAYOUT.read().unwrap();
+
+    let (min, max) =
+        leds.iter()
+            .fold(([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]), |(min, max), led| {
+                let [x, y, _] = led.position;
+                ([min[0].min(x), min[1].min(y)], [max[0].max(x), max[1].max(y)])
+            });
+    let extent = (max[0] - min[0]).max(max[1] - min[1]);
+    let scale = if extent > 0.0 {
+        (FPP_PREVIEW_SIZE - 1.0) / extent
+    } else {
+        1.0
+    };
+    let size = |axis: usize| ((max[axis] - min[axis]).max(0.0) * scale).round() as usize + 1;
+
+    writeln!(out, "# Preview Size: {}x{}", size(0), size(1))?;
+    writeln!(out, "# X,Y,Z,Channel,ChannelCount,ColorOrder")?;
+
+    let mut leds = leds.to_vec();
+    leds.sort_by_key(|led| layout.global(led.segment, led.index));
+    for led in &leds {
+        let [x, y, _] = led.position;
+        let (x, y) = (((x - min[0]) * scale).round(), ((y - min[1]) * scale).round());
+        let channel = layout.global(led.segment, led.index) * 3 + 1;
+        writeln!(out, "{x},{y},0,{channel},3,RGB")?;
+    }
+
+    Ok(())
+}
+
+/// The import script `write_blender_script` fills in.
+const BLENDER_SCRIPT: &str = include_str!("blender_import.py");
+
+/// The LEDs by chained index, in Blender's axes: x right, y away from the camera and z up.
+fn write_blender(out: &mut impl Write, leds: &[Led]) -> anyhow::Result<()> {
+    let layout = segments::LAYOUT.read().unwrap();
+
+    let mut leds = leds.to_vec();
+    leds.sort_by_key(|led| layout.global(led.segment, led.index));
+    let leds = leds
+        .iter()
+        .map(|led| {
+            let [x, y, z] = led.position;
+            json!({
+                "index": layout.global(led.segment, led.index),
+                "segment": led.segment,
+                "segment_index": led.index,
+                "position": [x, z, -y],
+            })
+        })
+        .collect::<Vec<_>>();
+
+    serde_json::to_writer_pretty(out, &json!({ "leds": leds }))?;
+    Ok(())
+}
+
+/// Writes the import script for the Blender JSON at `path` next to it, pointed at it.
+fn write_blender_script(path: &Path) -> anyhow::Result<()> {
+    let map = std::fs::canonicalize(path)?;
+    let map = serde_json::to_string(&map.to_string_lossy())?;
+    let script = BLENDER_SCRIPT.replacen("MAP_PATH = None", &format!("MAP_PATH = {map}"), 1);
+
+    let script_path = path.with_extension("py");
+    std::fs::write(&script_path, script)
+        .with_context(|| format!("Failed to write {}", script_path.display()))
+}
+
+/// Number of LEDs on the strip the map is of, counting those that weren't found.
+fn layout_len(leds: &[Led]) -> usize {
+    let layout = segments::LAYOUT.read().unwrap();
+    leds.iter()
+        .map(|led| layout.global(led.segment, led.index) + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+fn distance(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a.iter()
+        .zip(&b)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ledmap_offsets_segments() {
+        // Five LEDs on the first segment, of which three were found, on the top row, and four on
+        // the second, running right to left along the bottom row
+        let layout = Layout::new(vec![("top".to_owned(), 5), ("bottom".to_owned(), 4)]);
+        let top = (0..3).map(|index| Led {
+            segment: 0,
+            index,
+            position: [index as f32, 0.0, 0.0],
+        });
+        let bottom = (0..4).map(|index| Led {
+            segment: 1,
+            index,
+            position: [3.0 - index as f32, 1.0, 0.0],
+        });
+        let leds = top.chain(bottom).collect::<Vec<_>>();
+
+        let (ledmap, dropped) = wled_ledmap(&leds, &layout);
+        assert_eq!(dropped, 0);
+        assert_eq!(ledmap["width"], 4);
+        assert_eq!(ledmap["height"], 2);
+        assert_eq!(ledmap["map"], json!([0, 1, 2, -1, 8, 7, 6, 5]));
+    }
+}
@@ -0,0 +1,182 @@
+//! Output formats beyond `export::ExportFormat`, for in-house LED engines that want their own
+//! without a fork of the app. Anything implementing `Exporter` can be registered.
+//!
+//! The ones users add are Rhai scripts in `exporters` in the working directory, one format per
+//! file, loaded on launch:
+//!
+//! ```rhai
+//! const NAME = "My engine";
+//! const EXTENSION = "leds.txt";
+//!
+//! fn write(leds) {
+//!     let out = "";
+//!     for led in leds {
+//!         out += `${led.index} ${led.x} ${led.y}` + "\n";
+//!     }
+//!     out
+//! }
+//! ```
+//!
+//! `write` gets the LEDs as maps with `segment`, `index`, `x`, `y` and `z`, and returns what
+//! goes in the file. `NAME` defaults to the script's file name and `EXTENSION` to `txt`.
+
+use std::{
+    fs,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, Context};
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST, FLOAT, INT};
+use tracing::{info, warn};
+
+use crate::Led;
+
+/// Where the script exporters are loaded from.
+pub const DIR: &str = "exporters";
+
+pub trait Exporter: Send + Sync {
+    /// Shown on its export button and taken by `--exporter`.
+    fn name(&self) -> &str;
+    /// Matched against the end of file names, like `ExportFormat::extension`.
+    fn extension(&self) -> &str;
+    fn export(&self, leds: &[Led]) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Every exporter registered, in order.
+pub static EXPORTERS: RwLock<Vec<Arc<dyn Exporter>>> = RwLock::new(Vec::new());
+
+/// Adds `exporter`, replacing one with the same name.
+pub fn register(exporter: Arc<dyn Exporter>) {
+    let mut exporters = EXPORTERS.write().unwrap();
+    exporters.retain(|other| other.name() != exporter.name());
+    exporters.push(exporter);
+}
+
+/// Everything registered, for listing without holding the lock.
+pub fn all() -> Vec<Arc<dyn Exporter>> {
+    EXPORTERS.read().unwrap().clone()
+}
+
+/// The exporter called `name`, ignoring case.
+pub fn find(name: &str) -> Option<Arc<dyn Exporter>> {
+    all()
+        .into_iter()
+        .find(|exporter| exporter.name().eq_ignore_ascii_case(name))
+}
+
+/// The exporter with the longest extension `path` ends in, like `ExportFormat::from_path`.
+pub fn from_path(path: &Path) -> Option<Arc<dyn Exporter>> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    all()
+        .into_iter()
+        .filter(|exporter| name.ends_with(&format!(".{}", exporter.extension().to_lowercase())))
+        .max_by_key(|exporter| exporter.extension().len())
+}
+
+pub fn write(exporter: &dyn Exporter, path: &Path, leds: &[Led]) -> anyhow::Result<()> {
+    let contents = exporter.export(leds)?;
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Registers the scripts in `DIR`, if there is one. A script that doesn't load is skipped with a
+/// warning, so one broken script doesn't take the others down with it.
+pub fn load_scripts() {
+    let Ok(entries) = fs::read_dir(DIR) else {
+        return;
+    };
+
+    let mut paths = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "rhai")
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    for path in paths {
+        match Script::load(&path) {
+            Ok(script) => {
+                info!("Loaded the {} exporter from {}", script.name, path.display());
+                register(Arc::new(script));
+            }
+            Err(e) => warn!("Failed to load the exporter {}: {e:#}", path.display()),
+        }
+    }
+}
+
+/// An exporter written in Rhai, see the top of the module.
+struct Script {
+    name: String,
+    extension: String,
+    /// Compiled again for every export, as Rhai's engine can't be shared between threads.
+    source: String,
+}
+
+impl Script {
+    fn load(path: &Path) -> anyhow::Result<Self> {
+        let source = fs::read_to_string(path)?;
+        let engine = Engine::new();
+        let (ast, mut scope) = compile(&engine, &source)?;
+        anyhow::ensure!(
+            ast.iter_functions()
+                .any(|function| function.name == "write"),
+            "There's no write function"
+        );
+
+        let mut constant = |name| scope.remove::<String>(name);
+        let name = constant("NAME").unwrap_or_else(|| {
+            path.file_stem()
+                .map_or("Script".into(), |stem| stem.to_string_lossy())
+                .into_owned()
+        });
+        let extension = constant("EXTENSION").unwrap_or_else(|| "txt".to_owned());
+        Ok(Self { name, extension, source })
+    }
+}
+
+impl Exporter for Script {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    fn export(&self, leds: &[Led]) -> anyhow::Result<Vec<u8>> {
+        let engine = Engine::new();
+        let (ast, mut scope) = compile(&engine, &self.source)?;
+
+        let leds = leds
+            .iter()
+            .map(|led| {
+                let [x, y, z] = led.position.map(FLOAT::from);
+                let mut map = Map::new();
+                map.insert("segment".into(), Dynamic::from(led.segment as INT));
+                map.insert("index".into(), Dynamic::from(led.index as INT));
+                map.insert("x".into(), Dynamic::from(x));
+                map.insert("y".into(), Dynamic::from(y));
+                map.insert("z".into(), Dynamic::from(z));
+                Dynamic::from(map)
+            })
+            .collect::<Array>();
+
+        // The top level already ran for the scope
+        let options = CallFnOptions::new().eval_ast(false);
+        let contents = engine
+            .call_fn_with_options::<String>(options, &mut scope, &ast, "write", (leds,))
+            .map_err(|e| anyhow!("{} exporter: {e}", self.name))?;
+        Ok(contents.into_bytes())
+    }
+}
+
+/// `source` compiled, with the scope its top level leaves behind.
+fn compile(engine: &Engine, source: &str) -> anyhow::Result<(AST, Scope<'static>)> {
+    let ast = engine.compile(source).map_err(|e| anyhow!("{e}"))?;
+    let mut scope = Scope::new();
+    engine
+        .run_ast_with_scope(&mut scope, &ast)
+        .map_err(|e| anyhow!("{e}"))?;
+    Ok((ast, scope))
+}
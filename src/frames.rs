@@ -0,0 +1,74 @@
+//! Handing frames from the decoder to everything else without copying them.
+//!
+//! Readers get a shared reference to the latest frame instead of a copy. The decoder writes each
+//! new frame into a buffer nobody holds anymore, so once a few are in rotation it stops
+//! allocating too.
+//!
+//! Each frame is stamped with when it was captured as best `stats` can tell, so a scan can wait
+//! for the first frame taken after lighting an LED instead of a frame that merely arrived after.
+
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    time::Instant,
+};
+
+use crate::stats;
+
+/// Buffers kept around for reuse besides the latest one. Two is enough that the decoder always
+/// finds a free one while a reader is still on the frame before last.
+const SPARES: usize = 2;
+
+pub struct FrameBuffer {
+    latest: RwLock<Option<(Arc<Vec<u8>>, Instant)>>,
+    /// Earlier frames, some maybe still being read.
+    spares: Mutex<Vec<Arc<Vec<u8>>>>,
+}
+
+impl FrameBuffer {
+    pub const fn new() -> Self {
+        Self {
+            latest: RwLock::new(None),
+            spares: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The latest frame, `None` before the first one.
+    pub fn latest(&self) -> Option<Arc<Vec<u8>>> {
+        Some(self.latest_stamped()?.0)
+    }
+
+    /// The latest frame with when it was captured.
+    pub fn latest_stamped(&self) -> Option<(Arc<Vec<u8>>, Instant)> {
+        self.latest.read().unwrap().clone()
+    }
+
+    /// Publishes a new frame, written by `write` into a cleared buffer. The decoder should have
+    /// told `stats::frame_decoded` about it first, for its timestamp.
+    pub fn publish(&self, write: impl FnOnce(&mut Vec<u8>)) {
+        let mut buffer = {
+            let mut spares = self.spares.lock().unwrap();
+            match spares
+                .iter()
+                .position(|spare| Arc::strong_count(spare) == 1)
+            {
+                Some(free) => spares.swap_remove(free),
+                None => Arc::new(Vec::new()),
+            }
+        };
+
+        // Nothing else has a reference left, so this doesn't copy
+        let data = Arc::make_mut(&mut buffer);
+        data.clear();
+        write(data);
+
+        let captured = stats::frame_captured().unwrap_or_else(Instant::now);
+        let old = self.latest.write().unwrap().replace((buffer, captured));
+
+        let mut spares = self.spares.lock().unwrap();
+        spares.extend(old.map(|(buffer, _)| buffer));
+        // Readers holding on to frames for long shouldn't grow the pool forever
+        if spares.len() > SPARES {
+            spares.remove(0);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Noticing when the stream keeps delivering the same picture. A camera or encoder that hangs can
+//! go on sending its last frame over and over, or frames whose timestamps stopped moving, and the
+//! decoder happily takes them. Scanning against that puts every LED in the same spot without a
+//! single error.
+//!
+//! Repeated timestamps are caught as frames are decoded. Repeated pictures are caught by the
+//! detection thread, which compares a sparse fingerprint of each new frame with the one before.
+//! A still, dark scene compressed hard can repeat for a while on its own, so that takes longer
+//! to count as frozen.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::Hasher,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use tracing::{info, warn};
+
+use crate::toasts::{self, Retry};
+
+/// Frames that repeated the one before, by timestamp or picture.
+pub static REPEATED: AtomicU64 = AtomicU64::new(0);
+
+/// Timestamps standing still this long mean the stream is frozen.
+const TIMESTAMPS_AFTER: Duration = Duration::from_secs(2);
+
+/// The same picture this long means the stream is frozen.
+const PICTURES_AFTER: Duration = Duration::from_secs(5);
+
+/// Bytes between the ones fingerprinted. Prime, so it doesn't line up with rows or channels.
+const STRIDE: usize = 101;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    Timestamps,
+    Pictures,
+}
+
+impl Cause {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Cause::Timestamps => "frame timestamps stopped advancing",
+            Cause::Pictures => "every frame is the same",
+        }
+    }
+}
+
+struct State {
+    last_pts: Option<f64>,
+    /// When the timestamps stopped moving.
+    pts_since: Option<Instant>,
+    /// Of the last frame fingerprinted, with when it arrived.
+    fingerprint: Option<(u64, Instant)>,
+    /// When the pictures stopped changing.
+    picture_since: Option<Instant>,
+    /// Whether the freeze was already warned about.
+    warned: bool,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    last_pts: None,
+    pts_since: None,
+    fingerprint: None,
+    picture_since: None,
+    warned: false,
+});
+
+/// Called for every frame with a timestamp, in seconds.
+pub fn timestamp(pts: f64) {
+    let mut state = STATE.lock().unwrap();
+    if state.last_pts == Some(pts) {
+        REPEATED.fetch_add(1, Ordering::Relaxed);
+        state.pts_since.get_or_insert_with(Instant::now);
+    } else {
+        state.pts_since = None;
+    }
+    state.last_pts = Some(pts);
+}
+
+/// Called by the detection thread with the frame it's on and when that arrived. Frames it already
+/// saw are left alone, detection runs faster than some cameras.
+pub fn picture(frame: &[u8], arrived: Instant) {
+    let mut state = STATE.lock().unwrap();
+    if state.fingerprint.is_some_and(|(_, last)| last == arrived) {
+        return;
+    }
+
+    let mut hasher = DefaultHasher::new();
+    for &byte in frame.iter().step_by(STRIDE) {
+        hasher.write_u8(byte);
+    }
+    let fingerprint = hasher.finish();
+
+    if state
+        .fingerprint
+        .is_some_and(|(last, _)| last == fingerprint)
+    {
+        REPEATED.fetch_add(1, Ordering::Relaxed);
+        state.picture_since.get_or_insert(arrived);
+    } else {
+        state.picture_since = None;
+    }
+    state.fingerprint = Some((fingerprint, arrived));
+}
+
+/// Why the stream looks frozen and since when, if it does.
+pub fn frozen() -> Option<(Cause, Instant)> {
+    let state = STATE.lock().unwrap();
+    let timestamps = state
+        .pts_since
+        .filter(|since| since.elapsed() > TIMESTAMPS_AFTER)
+        .map(|since| (Cause::Timestamps, since));
+    let pictures = state
+        .picture_since
+        .filter(|since| since.elapsed() > PICTURES_AFTER)
+        .map(|since| (Cause::Pictures, since));
+    timestamps.or(pictures)
+}
+
+/// Warns once when the stream freezes, and logs when it comes back. Called by the detection
+/// thread after every pass.
+pub fn check() {
+    let frozen = frozen();
+    let mut state = STATE.lock().unwrap();
+    match frozen {
+        Some((cause, _)) if !state.warned => {
+            state.warned = true;
+            let message = format!("The video looks frozen, {}", cause.describe());
+            warn!("{message}");
+            toasts::error(message, Some(Retry::Stream));
+        }
+        None if state.warned => {
+            state.warned = false;
+            info!("The video is moving again");
+        }
+        _ => {}
+    }
+}
+
+/// Forgets the last stream, for when another is opened.
+pub fn reset() {
+    let mut state = STATE.lock().unwrap();
+    state.last_pts = None;
+    state.pts_since = None;
+    state.fingerprint = None;
+    state.picture_since = None;
+    state.warned = false;
+}
@@ -0,0 +1,301 @@
+//! Snapping the map of an LED matrix onto its grid.
+//!
+//! The pitch and rotation come from the spacing between neighbouring LEDs, which on a matrix is
+//! nearly always one cell along a row or column. LEDs the scan missed are put back by working out
+//! how the panel is wired from the ones it did find.
+
+use std::{collections::HashMap, f32::consts::TAU};
+
+use tracing::info;
+
+use crate::{segments, Led};
+
+pub struct GridFit {
+    pub columns: usize,
+    pub rows: usize,
+    /// Cell size in the units of the map, usually camera pixels.
+    pub pitch: f32,
+    /// How far the rows are turned from horizontal, in radians.
+    pub angle: f32,
+    /// LEDs that weren't detected but were placed going by the wiring.
+    pub filled: usize,
+    /// LEDs that landed on a cell that was already taken and were left out.
+    pub dropped: usize,
+    /// Every LED at its cell, with integer coordinates.
+    pub leds: Vec<Led>,
+}
+
+impl GridFit {
+    pub fn summary(&self) -> String {
+        format!(
+            "{}x{} grid, {:.1} px pitch, rotated {:.1}°, {} filled in, {} dropped",
+            self.columns,
+            self.rows,
+            self.pitch,
+            self.angle.to_degrees(),
+            self.filled,
+            self.dropped
+        )
+    }
+}
+
+pub fn fit(leds: &[Led]) -> anyhow::Result<GridFit> {
+    anyhow::ensure!(leds.len() >= 4, "Need at least 4 LEDs to fit a grid");
+
+    let points = leds
+        .iter()
+        .map(|led| [led.position[0], led.position[1]])
+        .collect::<Vec<_>>();
+
+    // Offset from every LED to its nearest neighbour
+    let neighbours = points
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &[x, y])| {
+            points
+                .iter()
+                .enumerate()
+                .filter(|&(j, _)| i != j)
+                .map(|(_, &[nx, ny])| [nx - x, ny - y])
+                .filter(|&[dx, dy]| dx != 0.0 || dy != 0.0)
+                .min_by(|a, b| length(*a).total_cmp(&length(*b)))
+        })
+        .collect::<Vec<_>>();
+
+    // Neighbours are a multiple of 90° apart, so average the angles with 4x the period
+    let (sin, cos) = neighbours.iter().fold((0.0, 0.0), |(sin, cos), &[dx, dy]| {
+        let angle = dy.atan2(dx) * 4.0;
+        (sin + angle.sin(), cos + angle.cos())
+    });
+    let angle = f32::atan2(sin, cos) / 4.0;
+
+    let mut distances = neighbours.iter().copied().map(length).collect::<Vec<_>>();
+    distances.sort_by(f32::total_cmp);
+    let pitch = distances.get(distances.len() / 2).copied().unwrap_or(0.0);
+    anyhow::ensure!(pitch > 0.0, "All LEDs are in the same spot");
+
+    // Into grid units, with the rows horizontal
+    let (sin, cos) = angle.sin_cos();
+    let unrotated = points
+        .iter()
+        .map(|&[x, y]| [(x * cos + y * sin) / pitch, (y * cos - x * sin) / pitch])
+        .collect::<Vec<_>>();
+
+    // Where the cell centres fall within a cell, as the circular mean of the fractional parts
+    let phase = [0, 1].map(|axis| {
+        let (sin, cos) = unrotated.iter().fold((0.0, 0.0), |(sin, cos), point| {
+            let angle = point[axis] * TAU;
+            (sin + angle.sin(), cos + angle.cos())
+        });
+        f32::atan2(sin, cos) / TAU
+    });
+
+    let cells = unrotated
+        .iter()
+        .map(|&[u, v]| [(u - phase[0]).round() as i64, (v - phase[1]).round() as i64])
+        .collect::<Vec<_>>();
+    let min = [0, 1].map(|axis| cells.iter().map(|cell| cell[axis]).min().unwrap_or(0));
+
+    // Keep the LED nearest the centre of each cell
+    let mut occupied = HashMap::<[usize; 2], (usize, f32)>::new();
+    let mut dropped = 0;
+    for (i, (cell, point)) in cells.iter().zip(&unrotated).enumerate() {
+        let cell = [(cell[0] - min[0]) as usize, (cell[1] - min[1]) as usize];
+        let error = length([
+            point[0] - phase[0] - (cell[0] as i64 + min[0]) as f32,
+            point[1] - phase[1] - (cell[1] as i64 + min[1]) as f32,
+        ]);
+
+        match occupied.get(&cell) {
+            Some(&(_, other)) if other <= error => dropped += 1,
+            Some(_) => {
+                dropped += 1;
+                occupied.insert(cell, (i, error));
+            }
+            None => {
+                occupied.insert(cell, (i, error));
+            }
+        }
+    }
+
+    let mut placed = occupied
+        .into_iter()
+        .map(|(cell, (i, _))| (leds[i].segment, leds[i].index, cell))
+        .collect::<Vec<_>>();
+
+    let mut filled = 0;
+    let mut segments = placed
+        .iter()
+        .map(|&(segment, ..)| segment)
+        .collect::<Vec<_>>();
+    segments.sort_unstable();
+    segments.dedup();
+    for segment in segments {
+        let missing = fill(segment, &placed);
+        filled += missing.len();
+        placed.extend(missing);
+    }
+
+    placed.sort_by_key(|&(segment, index, _)| (segment, index));
+
+    let columns = placed
+        .iter()
+        .map(|(.., cell)| cell[0] + 1)
+        .max()
+        .unwrap_or(0);
+    let rows = placed
+        .iter()
+        .map(|(.., cell)| cell[1] + 1)
+        .max()
+        .unwrap_or(0);
+
+    Ok(GridFit {
+        columns,
+        rows,
+        pitch,
+        angle,
+        filled,
+        dropped,
+        leds: placed
+            .into_iter()
+            .map(|(segment, index, [column, row])| Led {
+                segment,
+                index,
+                position: [column as f32, row as f32, 0.0],
+            })
+            .collect(),
+    })
+}
+
+/// Works out how `segment` is wired from the LEDs placed on it so far, trying row and column
+/// order, plain and serpentine, from each corner, and returns where the missing LEDs must be.
+fn fill(segment: usize, placed: &[(usize, usize, [usize; 2])]) -> Vec<(usize, usize, [usize; 2])> {
+    let known = placed
+        .iter()
+        .filter(|&&(s, ..)| s == segment)
+        .map(|&(_, index, cell)| (index, cell))
+        .collect::<Vec<_>>();
+
+    let low = [0, 1].map(|axis| known.iter().map(|(_, cell)| cell[axis]).min().unwrap_or(0));
+    let high = [0, 1].map(|axis| known.iter().map(|(_, cell)| cell[axis]).max().unwrap_or(0));
+    let size = [high[0] - low[0] + 1, high[1] - low[1] + 1];
+
+    let count = segments::LAYOUT
+        .read()
+        .unwrap()
+        .count(segment)
+        .unwrap_or_else(|| known.iter().map(|&(index, _)| index + 1).max().unwrap_or(0));
+
+    let wiring = |index: usize, [by_columns, serpentine, flip_x, flip_y]: [bool; 4]| {
+        let line_length = if by_columns { size[1] } else { size[0] };
+        let (line, mut along) = (index / line_length, index % line_length);
+        if serpentine && line % 2 == 1 {
+            along = line_length - 1 - along;
+        }
+
+        let [mut x, mut y] = if by_columns {
+            [line, along]
+        } else {
+            [along, line]
+        };
+        if x >= size[0] || y >= size[1] {
+            return None;
+        }
+        if flip_x {
+            x = size[0] - 1 - x;
+        }
+        if flip_y {
+            y = size[1] - 1 - y;
+        }
+        Some([low[0] + x, low[1] + y])
+    };
+
+    let (score, best) = (0..16)
+        .map(|bits| [0, 1, 2, 3].map(|bit| bits & (1 << bit) != 0))
+        .map(|option| {
+            let score = known
+                .iter()
+                .filter(|&&(index, cell)| wiring(index, option) == Some(cell))
+                .count();
+            (score, option)
+        })
+        .max_by_key(|&(score, _)| score)
+        .unwrap();
+
+    // Without most of the LEDs agreeing, this isn't a matrix wired any way we know
+    if score * 4 < known.len() * 3 {
+        info!("Couldn't tell how segment {segment} is wired, not filling in missing LEDs");
+        return Vec::new();
+    }
+
+    let taken = placed.iter().map(|&(.., cell)| cell).collect::<Vec<_>>();
+    let have = known.iter().map(|&(index, _)| index).collect::<Vec<_>>();
+
+    (0..count)
+        .filter(|index| !have.contains(index))
+        .filter_map(|index| {
+            let cell = wiring(index, best)?;
+            (!taken.contains(&cell)).then_some((segment, index, cell))
+        })
+        .collect()
+}
+
+fn length([x, y]: [f32; 2]) -> f32 {
+    (x * x + y * y).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn led(index: usize, [x, y]: [f32; 2]) -> Led {
+        Led {
+            segment: 0,
+            index,
+            position: [x, y, 0.0],
+        }
+    }
+
+    #[test]
+    fn fits_a_rotated_grid() {
+        // 4 by 3, wired row by row, 20 px apart and turned by 10°, with LED 5 missed
+        let (sin, cos) = 10f32.to_radians().sin_cos();
+        let leds = (0..12)
+            .filter(|&index| index != 5)
+            .map(|index| {
+                let [x, y] = [(index % 4) as f32 * 20.0, (index / 4) as f32 * 20.0];
+                led(index, [100.0 + x * cos - y * sin, 50.0 + x * sin + y * cos])
+            })
+            .collect::<Vec<_>>();
+
+        let grid = fit(&leds).unwrap();
+        assert_eq!((grid.columns, grid.rows), (4, 3));
+        assert!((grid.pitch - 20.0).abs() < 0.01, "{}", grid.pitch);
+        assert!((grid.angle.to_degrees() - 10.0).abs() < 0.01, "{}", grid.angle.to_degrees());
+        assert_eq!((grid.filled, grid.dropped), (1, 0));
+        for led in &grid.leds {
+            let cell = [(led.index % 4) as f32, (led.index / 4) as f32, 0.0];
+            assert_eq!(led.position, cell, "LED {}", led.index);
+        }
+        assert_eq!(grid.leds.len(), 12);
+    }
+
+    #[test]
+    fn rejects_degenerate_maps() {
+        assert!(fit(&[led(0, [0.0, 0.0]), led(1, [10.0, 0.0])]).is_err());
+        assert!(fit(&[led(0, [5.0, 5.0]); 4]).is_err());
+    }
+
+    #[test]
+    fn fits_a_line_as_one_row() {
+        let (sin, cos) = 30f32.to_radians().sin_cos();
+        let leds = (0..6)
+            .map(|index| led(index, [index as f32 * 10.0 * cos, index as f32 * 10.0 * sin]))
+            .collect::<Vec<_>>();
+
+        let grid = fit(&leds).unwrap();
+        assert_eq!((grid.columns, grid.rows), (6, 1));
+        assert!((grid.pitch - 10.0).abs() < 0.01, "{}", grid.pitch);
+        assert_eq!((grid.filled, grid.dropped), (0, 0));
+    }
+}
@@ -0,0 +1,122 @@
+//! The grouped scan: several LEDs lit per step, each at least `group_separation` from the others
+//! going by where it's expected to be, and each given the blob near its expected position. With a
+//! prior map, or an earlier scan still on screen, a rescan takes a fraction of the steps of a
+//! sequential one.
+//!
+//! An LED with no blob near where it's expected, or more than one, is lit again on its own after
+//! the groups, as is every LED there's no expected position for.
+
+use std::sync::atomic::Ordering;
+
+use eframe::epaint::{Color32, Pos2};
+use tracing::info;
+
+use crate::{
+    pipeline,
+    scan::{self, SharedController, CURRENT, STEPS},
+};
+
+/// Scans `leds`, `first` being the step the scan is at, see `scan::run_sequential`.
+pub fn run(controller: &SharedController, leds: &[usize], first: usize) -> anyhow::Result<()> {
+    let (size, separation, light) = {
+        let settings = scan::SETTINGS.read().unwrap();
+        (settings.group_size.max(1), settings.group_separation, settings.led_light())
+    };
+
+    let mut expected = Vec::new();
+    let mut alone = Vec::new();
+    for &index in leds {
+        match scan::expected(index) {
+            Some(position) => expected.push((index, position)),
+            None => alone.push(index),
+        }
+    }
+    let groups = groups(&expected, size, separation);
+    info!(
+        "Scanning {} LEDs in {} groups, and {} without an expected position on their own",
+        expected.len(),
+        groups.len(),
+        alone.len()
+    );
+    STEPS.store(first + groups.len() + alone.len(), Ordering::Relaxed);
+
+    let mut controller = controller.lock().unwrap();
+    let step = scan::step_time(controller.latency_hint());
+    let stamped = pipeline::frames_stamped();
+    let mut current = first;
+
+    let mut unclear = Vec::new();
+    for group in &groups {
+        if scan::cancelled() {
+            break;
+        }
+        CURRENT.store(current, Ordering::Relaxed);
+        current += 1;
+
+        controller.set_all(Color32::BLACK);
+        for &(index, _) in group {
+            light.set(&mut **controller, index);
+        }
+        controller.flush()?;
+        let (frame, width) = scan::frame_after_flush(stamped, step, controller.latency_hint())?;
+        let points = pipeline::detect_frame(&frame, width)?;
+
+        if let [(index, _)] = group[..] {
+            scan::store(index, scan::pick(&points, index));
+            continue;
+        }
+        for &(index, position) in group {
+            let near = scan::candidates(&points, index)
+                .into_iter()
+                .filter(|rect| rect.center().distance(position) <= separation / 2.0)
+                .collect::<Vec<_>>();
+            match near[..] {
+                [rect] => scan::store(index, Some(rect.center())),
+                _ => unclear.push(index),
+            }
+        }
+    }
+
+    if !unclear.is_empty() {
+        info!("{} LEDs weren't clear in their groups, scanning them on their own", unclear.len());
+        STEPS.fetch_add(unclear.len(), Ordering::Relaxed);
+    }
+    for index in unclear.into_iter().chain(alone) {
+        if scan::cancelled() {
+            break;
+        }
+        CURRENT.store(current, Ordering::Relaxed);
+        current += 1;
+
+        controller.set_all(Color32::BLACK);
+        light.set(&mut **controller, index);
+        controller.flush()?;
+        let (frame, width) = scan::frame_after_flush(stamped, step, controller.latency_hint())?;
+        scan::store(index, scan::pick(&pipeline::detect_frame(&frame, width)?, index));
+    }
+
+    controller.set_all(Color32::BLACK);
+    controller.flush()
+}
+
+/// `leds` in groups of up to `size`, each LED at least `separation` from the others in its group.
+/// Takes the LEDs in order, so the groups come out in about the order of the scan.
+fn groups(leds: &[(usize, Pos2)], size: usize, separation: f32) -> Vec<Vec<(usize, Pos2)>> {
+    let mut left = leds.to_vec();
+    let mut groups = Vec::new();
+    while !left.is_empty() {
+        let mut group = Vec::<(usize, Pos2)>::with_capacity(size);
+        left.retain(|&(index, position)| {
+            let fits = group.len() < size
+                && group
+                    .iter()
+                    .all(|(_, other)| other.distance(position) >= separation);
+            if fits {
+                group.push((index, position));
+            }
+            !fits
+        });
+        groups.push(group);
+    }
+    groups
+}
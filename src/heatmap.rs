@@ -0,0 +1,121 @@
+//! Where the detection mask has been lit lately, drawn translucent over the video. An LED that's
+//! only inside the thresholds some of the time shows up cool where a solid one is hot, and noise
+//! shows up as a haze, so both can be dealt with before a scan runs into them.
+
+use std::time::Instant;
+
+use eframe::{
+    egui::{self, TextureOptions},
+    epaint::{Color32, ColorImage, Pos2, Rect, TextureHandle, Vec2},
+};
+
+use crate::{
+    overlay::{self, ViewTransform},
+    pipeline,
+};
+
+/// Frame pixels per side of a heatmap cell, which keeps it cheap to update at full resolution.
+const CELL: usize = 4;
+
+/// Seconds it takes the heat to get most of the way to how often a cell is lit now.
+const TIME_CONSTANT: f32 = 10.0;
+
+/// Opacity of the hottest cells.
+const MAX_ALPHA: f32 = 0.6;
+
+#[derive(Default)]
+pub struct Heatmap {
+    pub enabled: bool,
+    /// Share of the time each cell was lit, decaying towards how it is now.
+    heat: Vec<f32>,
+    /// Cells across and down.
+    size: [usize; 2],
+    texture: Option<TextureHandle>,
+    /// When the mask was last added, which happens as often as detection runs.
+    updated: Option<Instant>,
+}
+
+impl Heatmap {
+    pub fn show_settings(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.enabled, "Heatmap").on_hover_text(
+                "Show where pixels have been inside the thresholds lately, to spot LEDs that \
+                 flicker in and out and noisy parts of the view",
+            );
+            if self.enabled && ui.button("Clear").clicked() {
+                self.heat.fill(0.0);
+            }
+        });
+    }
+
+    /// Draws the heatmap over the video, adding the latest mask first.
+    pub fn show(&mut self, ui: &mut egui::Ui, view: &ViewTransform) {
+        if !self.enabled {
+            self.updated = None;
+            return;
+        }
+
+        self.update(ui.ctx());
+        let Some(texture) = &self.texture else {
+            return;
+        };
+
+        let [width, height] = self.size;
+        let frame =
+            Rect::from_min_size(Pos2::ZERO, Vec2::new(width as f32, height as f32) * CELL as f32);
+        ui.painter().image(
+            texture.id(),
+            view.rect_to_screen(frame),
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+            Color32::WHITE,
+        );
+    }
+
+    fn update(&mut self, ctx: &egui::Context) {
+        let interval = *pipeline::INTERVAL.read().unwrap();
+        let elapsed = match self.updated {
+            Some(updated) if updated.elapsed() < interval => return,
+            Some(updated) => updated.elapsed().as_secs_f32(),
+            None => 0.0,
+        };
+        self.updated = Some(Instant::now());
+
+        // The detection thread already reports thresholding failing
+        let Ok(Some(mask)) = pipeline::latest_mask() else {
+            return;
+        };
+        let size = [mask.width.div_ceil(CELL), mask.height.div_ceil(CELL)];
+        if size != self.size {
+            self.size = size;
+            self.heat = vec![0.0; size[0] * size[1]];
+        }
+
+        let mut lit = vec![false; self.heat.len()];
+        for (y, row) in mask.data.chunks_exact(mask.width).enumerate() {
+            for (x, &value) in row.iter().enumerate() {
+                if value > 0 {
+                    lit[y / CELL * size[0] + x / CELL] = true;
+                }
+            }
+        }
+
+        let rate = (elapsed / TIME_CONSTANT).min(1.0);
+        for (heat, lit) in self.heat.iter_mut().zip(lit) {
+            *heat += (f32::from(u8::from(lit)) - *heat) * rate;
+        }
+
+        // Rarely lit cells are what this is for, so they're made to stand out more than linearly
+        let pixels = self
+            .heat
+            .iter()
+            .map(|&heat| overlay::gradient(heat.sqrt()).gamma_multiply(heat.sqrt() * MAX_ALPHA))
+            .collect();
+        let image = ColorImage { size, pixels };
+        match &mut self.texture {
+            Some(texture) => texture.set(image, TextureOptions::NEAREST),
+            None => {
+                self.texture = Some(ctx.load_texture("heatmap", image, TextureOptions::NEAREST))
+            }
+        }
+    }
+}
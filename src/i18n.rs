@@ -0,0 +1,75 @@
+//! Translations of the window's text. English strings are their own keys, so anything not in a
+//! language's catalog shows in English, and making text translatable is a matter of wrapping it
+//! in `tr`.
+//!
+//! Each catalog is JSON in `locales`, mapping the English text to the translation, built into
+//! the binary. Adding a language takes a catalog, a `Language` and a line in `catalog`.
+
+use std::{
+    collections::HashMap,
+    sync::{OnceLock, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The language the window is in.
+pub static LANGUAGE: RwLock<Language> = RwLock::new(Language::English);
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, clap::ValueEnum)]
+pub enum Language {
+    #[default]
+    English,
+    German,
+}
+
+impl Language {
+    pub const ALL: [Self; 2] = [Self::English, Self::German];
+
+    /// What the language calls itself, so it can be found without reading the current one.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::English => "English",
+            Self::German => "Deutsch",
+        }
+    }
+
+    /// ISO 639-1.
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::German => "de",
+        }
+    }
+
+    /// The language the environment asks for, like `de_DE.UTF-8` in `LANG`, if there's a catalog
+    /// for it.
+    pub fn from_env() -> Option<Self> {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|locale| !locale.is_empty())?;
+        let code = locale.split(['_', '.', '-']).next()?.to_lowercase();
+        Self::ALL
+            .into_iter()
+            .find(|language| language.code() == code)
+    }
+
+    fn catalog(self) -> Option<&'static HashMap<String, String>> {
+        static GERMAN: OnceLock<HashMap<String, String>> = OnceLock::new();
+        let (catalog, json) = match self {
+            Self::English => return None,
+            Self::German => (&GERMAN, include_str!("locales/de.json")),
+        };
+        // A broken catalog is a build mistake, not something to carry on from
+        Some(catalog.get_or_init(|| serde_json::from_str(json).expect("Invalid catalog")))
+    }
+}
+
+/// `text` in the current language, `text` itself where there's no translation.
+pub fn tr(text: &'static str) -> &'static str {
+    let language = *LANGUAGE.read().unwrap();
+    language
+        .catalog()
+        .and_then(|catalog| catalog.get(text))
+        .map_or(text, String::as_str)
+}
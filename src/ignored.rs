@@ -0,0 +1,110 @@
+//! Light sources that show up with every LED off, like status LEDs and reflections, so the scan
+//! can leave them out instead of mistaking them for the LED it lit.
+
+use std::{
+    sync::{atomic::AtomicBool, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use eframe::epaint::{Color32, Pos2, Rect};
+use tracing::info;
+
+use crate::{
+    pipeline::{self, POINTS},
+    scan::{self, SharedController},
+};
+
+pub static IGNORED: RwLock<Vec<Source>> = RwLock::new(Vec::new());
+
+/// Whether a scan first watches the dark installation for a while to learn `IGNORED`.
+pub static LEARN_BEFORE_SCAN: AtomicBool = AtomicBool::new(true);
+
+/// How long the baseline is watched for.
+const BASELINE_TIME: Duration = Duration::from_secs(2);
+
+/// Share of the baseline passes a blob has to be in to count as persistent. Lower catches
+/// flickering sources too, at the risk of ignoring noise that happened to repeat.
+const MIN_PRESENCE: f32 = 0.5;
+
+/// Extra room around a source for the camera shaking or the blob breathing, in pixels.
+const MARGIN: f32 = 4.0;
+
+#[derive(Clone, Copy)]
+pub struct Source {
+    pub position: Pos2,
+    pub radius: f32,
+    /// Share of the baseline passes it was detected in.
+    pub presence: f32,
+}
+
+/// Whether `point` is one of the ignored sources.
+pub fn is_ignored(point: Pos2) -> bool {
+    IGNORED
+        .read()
+        .unwrap()
+        .iter()
+        .any(|source| source.position.distance(point) <= source.radius)
+}
+
+/// `points` without the ones on an ignored source.
+pub fn filter(points: &[Rect]) -> Vec<Rect> {
+    points
+        .iter()
+        .filter(|rect| !is_ignored(rect.center()))
+        .copied()
+        .collect()
+}
+
+/// Turns every LED off and records what stays lit into `IGNORED`, replacing what was there.
+pub fn learn(controller: &SharedController) -> anyhow::Result<()> {
+    {
+        let mut controller = controller.lock().unwrap();
+        controller.set_all(Color32::BLACK);
+        controller.flush()?;
+        thread::sleep(scan::step_time(controller.latency_hint()));
+    }
+
+    let interval = *pipeline::INTERVAL.read().unwrap();
+    let start = Instant::now();
+    let mut passes = 0;
+    let mut seen: Vec<(Pos2, f32, usize)> = Vec::new();
+
+    while start.elapsed() < BASELINE_TIME {
+        anyhow::ensure!(!scan::cancelled(), "Cancelled");
+
+        for rect in POINTS.read().unwrap().iter() {
+            let center = rect.center();
+            let radius = rect.size().max_elem() / 2.0 + MARGIN;
+
+            match seen
+                .iter_mut()
+                .find(|(pos, r, _)| pos.distance(center) <= r.max(radius))
+            {
+                Some((pos, r, count)) => {
+                    *pos = pos.lerp(center, 1.0 / (*count + 1) as f32);
+                    *r = r.max(radius);
+                    *count += 1;
+                }
+                None => seen.push((center, radius, 1)),
+            }
+        }
+
+        passes += 1;
+        thread::sleep(interval);
+    }
+
+    let sources = seen
+        .into_iter()
+        .map(|(position, radius, count)| Source {
+            position,
+            radius,
+            presence: (count as f32 / passes.max(1) as f32).min(1.0),
+        })
+        .filter(|source| source.presence >= MIN_PRESENCE)
+        .collect::<Vec<_>>();
+
+    info!("Ignoring {} light sources that stay on with the LEDs off", sources.len());
+    *IGNORED.write().unwrap() = sources;
+    Ok(())
+}
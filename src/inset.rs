@@ -0,0 +1,196 @@
+//! A small view in the corner of the video, of the detection mask or a magnified crop around the
+//! latest detection or the cursor, so tuning doesn't mean switching back and forth between views.
+
+use std::time::Instant;
+
+use eframe::{
+    egui::{self, ComboBox, DragValue, Image, TextureOptions},
+    epaint::{Color32, ColorImage, Pos2, Rect, Stroke, TextureHandle, Vec2},
+};
+
+use crate::{
+    overlay::ViewTransform,
+    pipeline::{self, POINTS},
+};
+
+/// Width of the inset as a share of the video's.
+const SIZE: f32 = 0.3;
+const MARGIN: f32 = 8.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Off,
+    Mask,
+    /// Around the largest detection.
+    Zoom,
+    /// Around the cursor while it's over the video, like `Zoom` otherwise.
+    Loupe,
+}
+
+impl Kind {
+    pub const ALL: [Self; 4] = [Self::Off, Self::Mask, Self::Zoom, Self::Loupe];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "Off",
+            Self::Mask => "Mask",
+            Self::Zoom => "Zoom",
+            Self::Loupe => "Loupe",
+        }
+    }
+}
+
+pub struct Inset {
+    pub kind: Kind,
+    /// Magnification of the zoom inset.
+    pub zoom: f32,
+    mask: Option<TextureHandle>,
+    /// When the mask was last thresholded, which happens as often as detection runs.
+    mask_time: Option<Instant>,
+    /// Where the zoom inset is looking, kept while nothing is detected.
+    center: Option<Pos2>,
+}
+
+impl Default for Inset {
+    fn default() -> Self {
+        Self {
+            kind: Kind::Off,
+            zoom: 4.0,
+            mask: None,
+            mask_time: None,
+            center: None,
+        }
+    }
+}
+
+impl Inset {
+    /// Shows the mask, or turns the inset off if it already does.
+    pub fn toggle_mask(&mut self) {
+        self.kind = if self.kind == Kind::Mask {
+            Kind::Off
+        } else {
+            Kind::Mask
+        };
+    }
+
+    pub fn show_settings(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ComboBox::from_label("Inset")
+                .selected_text(self.kind.name())
+                .show_ui(ui, |ui| {
+                    for kind in Kind::ALL {
+                        ui.selectable_value(&mut self.kind, kind, kind.name());
+                    }
+                });
+            if matches!(self.kind, Kind::Zoom | Kind::Loupe) {
+                ui.add(
+                    DragValue::new(&mut self.zoom)
+                        .clamp_range(1.0..=16.0)
+                        .speed(0.1)
+                        .suffix("x"),
+                );
+            }
+        });
+    }
+
+    /// Draws the inset in the bottom right corner of the video, with `video` the texture of the
+    /// main view.
+    pub fn show(&mut self, ui: &mut egui::Ui, view: &ViewTransform, video: &TextureHandle) {
+        let video_rect = view.rect;
+        let width = video_rect.width() * SIZE;
+
+        let mut crop = None;
+        let (texture, uv, height) = match self.kind {
+            Kind::Off => return,
+            Kind::Mask => {
+                self.update_mask(ui.ctx());
+                let Some(mask) = &self.mask else {
+                    return;
+                };
+                let [w, h] = mask.size();
+                (
+                    mask,
+                    Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+                    width * h as f32 / w as f32,
+                )
+            }
+            Kind::Zoom | Kind::Loupe => {
+                // The video can be a smaller preview than the frames
+                let [w, h]: [f32; 2] = (view.rect.size() / view.scale).into();
+                let largest = POINTS
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .max_by(|a, b| a.area().total_cmp(&b.area()))
+                    .map(Rect::center);
+                // Not through the windows on top of the video
+                let cursor = ui
+                    .input(|i| i.pointer.hover_pos())
+                    .filter(|&pos| video_rect.contains(pos))
+                    .filter(|&pos| ui.ctx().layer_id_at(pos) == Some(ui.layer_id()))
+                    .map(|pos| view.to_frame(pos));
+                let target = match self.kind {
+                    Kind::Loupe => cursor.or(largest),
+                    _ => largest,
+                };
+                let center = *self.center.insert(
+                    target
+                        .or(self.center)
+                        .unwrap_or(Pos2::new(w / 2.0, h / 2.0)),
+                );
+
+                let half = width / self.zoom / 2.0;
+                let area = *crop.insert(Rect::from_center_size(center, Vec2::splat(half * 2.0)));
+                let uv = Rect::from_min_max(
+                    Pos2::new(area.min.x / w, area.min.y / h),
+                    Pos2::new(area.max.x / w, area.max.y / h),
+                );
+                (video, uv, width)
+            }
+        };
+
+        let rect = Rect::from_min_size(
+            video_rect.max - Vec2::new(width, height) - Vec2::splat(MARGIN),
+            Vec2::new(width, height),
+        );
+        ui.painter().rect_filled(rect, 0.0, Color32::BLACK);
+        Image::new(texture).uv(uv).paint_at(ui, rect);
+
+        // The centroids in view, to check they sit on the LEDs
+        if let Some(crop) = crop {
+            let painter = ui.painter_at(rect);
+            let scale = rect.width() / crop.width();
+            for point in POINTS.read().unwrap().iter() {
+                let center = rect.min + (point.center() - crop.min) * scale;
+                if rect.contains(center) {
+                    let stroke = Stroke::new(1.0, Color32::RED);
+                    painter.line_segment([center - Vec2::X * 6.0, center + Vec2::X * 6.0], stroke);
+                    painter.line_segment([center - Vec2::Y * 6.0, center + Vec2::Y * 6.0], stroke);
+                }
+            }
+        }
+
+        ui.painter()
+            .rect_stroke(rect, 0.0, Stroke::new(1.0, Color32::WHITE));
+    }
+
+    fn update_mask(&mut self, ctx: &egui::Context) {
+        let interval = *pipeline::INTERVAL.read().unwrap();
+        if self.mask_time.is_some_and(|time| time.elapsed() < interval) {
+            return;
+        }
+        self.mask_time = Some(Instant::now());
+
+        let mask = match pipeline::latest_mask() {
+            Ok(Some(mask)) => mask,
+            // The detection thread already reports thresholding failing
+            Ok(None) | Err(_) => return,
+        };
+
+        let image = ColorImage::from_gray([mask.width, mask.height], &mask.data);
+        match &mut self.mask {
+            Some(texture) => texture.set(image, TextureOptions::NEAREST),
+            None => self.mask = Some(ctx.load_texture("mask", image, TextureOptions::NEAREST)),
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! A readout of the pixel under the cursor: its color, and whether detection picks it up, for
+//! tuning the thresholds against what's actually in the frame.
+
+use std::time::Instant;
+
+use eframe::{
+    egui::{self, Align2},
+    epaint::{Color32, FontId, Rect, Vec2},
+};
+use led_detect::Mask;
+
+use crate::{overlay::ViewTransform, pipeline, segments};
+
+/// Where the readout goes relative to the cursor.
+const OFFSET: Vec2 = Vec2::new(16.0, 16.0);
+
+#[derive(Default)]
+pub struct Inspector {
+    pub enabled: bool,
+    /// The latest mask, redone as often as detection runs.
+    mask: Option<Mask>,
+    mask_time: Option<Instant>,
+}
+
+impl Inspector {
+    pub fn show_settings(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "Inspect pixels")
+            .on_hover_text("Show the color under the cursor and whether it's detected");
+    }
+
+    /// Draws the readout next to the cursor while it's over the video.
+    pub fn show(&mut self, ui: &mut egui::Ui, view: &ViewTransform) {
+        let video_rect = view.rect;
+        if !self.enabled {
+            return;
+        }
+
+        // Not through the windows on top of the video
+        let Some(pointer) = ui.input(|i| i.pointer.hover_pos()) else {
+            return;
+        };
+        if !video_rect.contains(pointer) || ui.ctx().layer_id_at(pointer) != Some(ui.layer_id()) {
+            return;
+        }
+
+        let Some((frame, width)) = pipeline::latest_rgb() else {
+            return;
+        };
+        let height = frame.len() / width / 3;
+        let pixel = view.to_frame(pointer);
+        let (x, y) = (pixel.x as usize, pixel.y as usize);
+        if x >= width || y >= height {
+            return;
+        }
+
+        let offset = (y * width + x) * 3;
+        let rgb = [frame[offset], frame[offset + 1], frame[offset + 2]];
+        let [h, s, v] = hsv(rgb);
+
+        self.update_mask();
+        let matched = self
+            .mask
+            .as_ref()
+            .filter(|mask| mask.width == width && mask.height == height)
+            .map(|mask| mask.data[y * width + x] > 0);
+
+        let [r, g, b] = rgb;
+        let (verdict, color) = match matched {
+            Some(true) => ("inside the thresholds", Color32::GREEN),
+            Some(false) => ("outside the thresholds", Color32::LIGHT_RED),
+            None => ("", Color32::WHITE),
+        };
+        let text = format!("{x}, {y}\nRGB {r} {g} {b}\nHSV {h} {s} {v}\n{verdict}");
+
+        let painter = ui.painter();
+        let galley =
+            painter.layout_no_wrap(text.trim_end().to_owned(), FontId::monospace(12.0), color);
+        let mut rect = Rect::from_min_size(pointer + OFFSET, galley.size()).expand(3.0);
+        // Flip to the other side near the right and bottom edges
+        if rect.max.x > video_rect.max.x {
+            rect = rect.translate(Vec2::new(-rect.width() - OFFSET.x * 2.0, 0.0));
+        }
+        if rect.max.y > video_rect.max.y {
+            rect = rect.translate(Vec2::new(0.0, -rect.height() - OFFSET.y * 2.0));
+        }
+
+        painter.rect_filled(rect, 2.0, Color32::from_black_alpha(200));
+        painter.rect_filled(
+            Rect::from_min_size(rect.right_top() + Vec2::new(-14.0, 4.0), Vec2::splat(10.0)),
+            0.0,
+            Color32::from_rgb(r, g, b),
+        );
+        painter.galley(rect.min + Vec2::splat(3.0), galley);
+        painter.text(pointer, Align2::CENTER_CENTER, "+", FontId::monospace(12.0), Color32::WHITE);
+    }
+
+    fn update_mask(&mut self) {
+        let interval = *pipeline::INTERVAL.read().unwrap();
+        if self.mask_time.is_some_and(|time| time.elapsed() < interval) {
+            return;
+        }
+        self.mask_time = Some(Instant::now());
+
+        // The detection thread already reports thresholding failing
+        self.mask = pipeline::latest_mask().ok().flatten();
+    }
+}
+
+/// `rgb` in OpenCV's 8-bit HSV, hue from 0 to 179 and the rest up to 255, like the thresholds.
+fn hsv(rgb: [u8; 3]) -> [u8; 3] {
+    let [r, g, b] = rgb.map(f32::from);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+
+    let s = if max > 0.0 {
+        (max - min) / max * 255.0
+    } else {
+        0.0
+    };
+    let h = segments::hue([r, g, b]) / 2.0;
+    [h.round() as u8 % 180, s.round() as u8, max as u8]
+}
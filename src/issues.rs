@@ -0,0 +1,422 @@
+//! Dead and dim LEDs, noticed while scanning as that's when every LED gets lit and looked at on
+//! its own anyway, and how evenly bright the rest are, as strips fade along their length when
+//! they don't get enough power. An optional pass after the scan checks each LED shows the right
+//! colors too.
+
+use std::{
+    fmt::Write as _,
+    io::{BufWriter, Write as _},
+    path::Path,
+    sync::{atomic::AtomicBool, RwLock},
+};
+
+use anyhow::Context;
+use eframe::epaint::Pos2;
+
+use crate::segments;
+
+/// How far around a detection to add up the light, in pixels. Wide enough to take in the glow,
+/// since the middle of any LED that's on saturates.
+const SAMPLE_RADIUS: i64 = 8;
+
+/// How many LEDs either side an LED is compared to.
+const NEIGHBORS: usize = 5;
+
+/// Below this share of the median of its neighbors an LED counts as dim.
+const DIM_RATIO: f32 = 0.4;
+
+/// Share of a segment at either end compared for the fade along it.
+const END_SHARE: f32 = 0.1;
+
+/// Below this saturation a color check shows no clear color, like when a channel is shorted to
+/// another.
+const MIN_SATURATION: f32 = 0.3;
+
+/// Below this a color check shows no light at all, from 0 to 255.
+const MIN_LEVEL: f32 = 20.0;
+
+const CHANNEL_NAMES: [char; 3] = ['R', 'G', 'B'];
+
+/// Whether a scan is followed by lighting everything red, green and blue in turn to check the
+/// colors of each LED, see `scan::check_colors`.
+pub static CHECK_COLORS: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+enum Sample {
+    /// Not captured yet.
+    Missing,
+    /// Captured, but nothing was detected.
+    Dark,
+    /// Total and brightest luma around the detection, see `measure`.
+    Lit { total: f32, peak: f32 },
+}
+
+/// What each LED looked like when it was captured, by index like `scan::MAP`.
+static SAMPLES: RwLock<Vec<Sample>> = RwLock::new(Vec::new());
+
+/// Mean color around each LED found, with every LED lit red, green and blue in turn.
+pub type Colors = Vec<Option<[[f32; 3]; 3]>>;
+
+/// The last color check, by index. Empty when there wasn't one.
+static COLORS: RwLock<Colors> = RwLock::new(Vec::new());
+
+/// Clears the samples for a scan of `count` LEDs.
+pub fn reset(count: usize) {
+    *SAMPLES.write().unwrap() = vec![Sample::Missing; count];
+    COLORS.write().unwrap().clear();
+}
+
+pub fn set_colors(colors: Colors) {
+    *COLORS.write().unwrap() = colors;
+}
+
+/// Which channel `rgb` comes out as, by nearest primary hue, or `None` if it's too dark or
+/// washed out to tell.
+fn channel(rgb: [f32; 3]) -> Option<usize> {
+    let max = rgb.iter().copied().fold(0.0, f32::max);
+    let min = rgb.iter().copied().fold(f32::INFINITY, f32::min);
+    if max < MIN_LEVEL || (max - min) / max < MIN_SATURATION {
+        return None;
+    }
+
+    Some(((segments::hue(rgb) + 60.0) / 120.0) as usize % 3)
+}
+
+/// Records how bright LED `index` was in `frame`, found at `position`.
+pub fn capture(index: usize, position: Option<Pos2>, frame: &[u8], width: usize) {
+    let sample = match position {
+        Some(position) => {
+            let (total, peak) = measure(frame, width, position);
+            Sample::Lit { total, peak }
+        }
+        None => Sample::Dark,
+    };
+
+    if let Some(slot) = SAMPLES.write().unwrap().get_mut(index) {
+        *slot = sample;
+    }
+}
+
+/// Sum of the luma around `position`, from 0 to 1 per pixel, and the brightest of it, from 0 to
+/// 255.
+fn measure(frame: &[u8], width: usize, position: Pos2) -> (f32, f32) {
+    let height = frame.len() / width / 3;
+    let (x, y) = (position.x as i64, position.y as i64);
+
+    (y - SAMPLE_RADIUS..=y + SAMPLE_RADIUS)
+        .flat_map(|y| (x - SAMPLE_RADIUS..=x + SAMPLE_RADIUS).map(move |x| (x, y)))
+        .filter(|&(x, y)| x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height)
+        .map(|(x, y)| {
+            let offset = (y as usize * width + x as usize) * 3;
+            let [r, g, b] = [0, 1, 2].map(|c| frame[offset + c] as f32);
+            0.299 * r + 0.587 * g + 0.114 * b
+        })
+        .fold((0.0, 0.0), |(total, peak), luma| (total + luma / 255.0, f32::max(peak, luma)))
+}
+
+/// The total of LED `index` as a share of the median of its neighbors, `None` if it wasn't found
+/// or there are too few neighbors to tell what normal looks like there.
+fn relative(samples: &[Sample], index: usize) -> Option<f32> {
+    let Sample::Lit { total, .. } = samples[index] else {
+        return None;
+    };
+
+    let around = index.saturating_sub(NEIGHBORS)..(index + NEIGHBORS + 1).min(samples.len());
+    let mut neighbors = around
+        .filter(|&i| i != index)
+        .filter_map(|i| match samples[i] {
+            Sample::Lit { total, .. } => Some(total),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    if neighbors.len() < 2 {
+        return None;
+    }
+
+    let middle = neighbors.len() / 2;
+    let median = *neighbors.select_nth_unstable_by(middle, f32::total_cmp).1;
+    Some(total / median)
+}
+
+/// How bright an LED came out in the last scan.
+pub struct Brightness {
+    /// Brightest luma around it, from 0 to 255.
+    pub peak: f32,
+    /// Sum of the luma around it, from 0 to 1 per pixel.
+    pub total: f32,
+    /// `total` as a share of the median of its neighbors, see `relative`.
+    pub relative: Option<f32>,
+}
+
+/// The brightness of every LED the last scan found, by index like `scan::MAP`.
+pub fn brightness() -> Vec<Option<Brightness>> {
+    let samples = SAMPLES.read().unwrap();
+    (0..samples.len())
+        .map(|index| match samples[index] {
+            Sample::Lit { total, peak } => Some(Brightness {
+                peak,
+                total,
+                relative: relative(&samples, index),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Peaks of the LEDs among `samples` that were found.
+fn peaks(samples: &[Sample]) -> Vec<f32> {
+    samples
+        .iter()
+        .filter_map(|sample| match sample {
+            Sample::Lit { peak, .. } => Some(*peak),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The suspect LEDs of the last scan.
+pub struct Report {
+    pub count: usize,
+    /// LEDs that were captured but never detected.
+    pub dead: Vec<usize>,
+    /// LEDs noticeably dimmer than those around them, with their share of their neighbors'
+    /// brightness.
+    pub dim: Vec<(usize, f32)>,
+    /// `None` when nothing was found.
+    pub uniformity: Option<Uniformity>,
+    /// LEDs that didn't show red, green and blue when lit so, with the channel each came out as.
+    pub colors: Vec<(usize, [Option<usize>; 3])>,
+}
+
+/// How evenly bright the LEDs that were found are, going by their peaks.
+pub struct Uniformity {
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    /// Standard deviation as a share of the mean.
+    pub variation: f32,
+    /// Per segment, the mean peak of its last LEDs as a share of its first, when there are
+    /// enough to tell. Well under 1 means voltage drop.
+    pub fade: Vec<Option<f32>>,
+}
+
+/// Goes over the samples of the last scan for dead and dim LEDs.
+pub fn report() -> Report {
+    let samples = SAMPLES.read().unwrap();
+
+    let dead = samples
+        .iter()
+        .enumerate()
+        .filter(|(_, sample)| matches!(sample, Sample::Dark))
+        .map(|(index, _)| index)
+        .collect();
+
+    let dim = (0..samples.len())
+        .filter_map(|index| {
+            let ratio = relative(&samples, index)?;
+            (ratio < DIM_RATIO).then_some((index, ratio))
+        })
+        .collect();
+
+    let colors = COLORS
+        .read()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, colors)| {
+            let shown = colors.as_ref()?.map(channel);
+            (shown != [Some(0), Some(1), Some(2)]).then_some((index, shown))
+        })
+        .collect();
+
+    Report {
+        count: samples.len(),
+        dead,
+        dim,
+        uniformity: uniformity(&samples),
+        colors,
+    }
+}
+
+fn uniformity(samples: &[Sample]) -> Option<Uniformity> {
+    let peaks = peaks(samples);
+    if peaks.is_empty() {
+        return None;
+    }
+
+    let mean = peaks.iter().sum::<f32>() / peaks.len() as f32;
+    let variance = peaks.iter().map(|peak| (peak - mean).powi(2)).sum::<f32>() / peaks.len() as f32;
+
+    let mean_peak = |samples: &[Sample]| {
+        let peaks = self::peaks(samples);
+        (!peaks.is_empty()).then(|| peaks.iter().sum::<f32>() / peaks.len() as f32)
+    };
+
+    let layout = segments::LAYOUT.read().unwrap();
+    let fade = (0..layout.len())
+        .map(|segment| {
+            let offset = layout.offset(segment);
+            let count = layout.count(segment).unwrap_or(samples.len());
+            let strip = samples.get(offset..(offset + count).min(samples.len()))?;
+            let end = ((strip.len() as f32 * END_SHARE) as usize).max(1);
+            if strip.len() < end * 2 {
+                return None;
+            }
+
+            let first = mean_peak(&strip[..end])?;
+            let last = mean_peak(&strip[strip.len() - end..])?;
+            (first > 0.0).then(|| last / first)
+        })
+        .collect();
+
+    Some(Uniformity {
+        min: peaks.iter().copied().fold(f32::INFINITY, f32::min),
+        max: peaks.iter().copied().fold(0.0, f32::max),
+        mean,
+        variation: if mean > 0.0 {
+            variance.sqrt() / mean
+        } else {
+            0.0
+        },
+        fade,
+    })
+}
+
+impl Uniformity {
+    pub fn summary(&self) -> String {
+        format!(
+            "{:.0} to {:.0}, mean {:.0}, varying by {:.0}%",
+            self.min,
+            self.max,
+            self.mean,
+            self.variation * 100.0
+        )
+    }
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.dead.is_empty() && self.dim.is_empty() && self.colors.is_empty()
+    }
+
+    /// One line for the log.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} dead, {} dim and {} with wrong colors of {} LEDs",
+            self.dead.len(),
+            self.dim.len(),
+            self.colors.len(),
+            self.count
+        )
+    }
+
+    /// The report as text, listing the suspect LEDs by index, and by segment when there are
+    /// several.
+    pub fn text(&self) -> String {
+        let layout = segments::LAYOUT.read().unwrap();
+        let name = |index: usize| {
+            if layout.len() == 1 {
+                return index.to_string();
+            }
+            let (segment, local) = layout.split(index);
+            format!("{index} ({} LED {local})", layout.name(segment))
+        };
+
+        let mut text = format!("Hardware issues found scanning {} LEDs\n", self.count);
+
+        let _ = writeln!(text, "\nDead, never detected ({}):", self.dead.len());
+        for &index in &self.dead {
+            let _ = writeln!(text, "  {}", name(index));
+        }
+
+        let _ = writeln!(
+            text,
+            "\nDim, under {:.0}% of their neighbors ({}):",
+            DIM_RATIO * 100.0,
+            self.dim.len()
+        );
+        for &(index, ratio) in &self.dim {
+            let _ = writeln!(text, "  {} at {:.0}%", name(index), ratio * 100.0);
+        }
+
+        if !COLORS.read().unwrap().is_empty() {
+            let _ = writeln!(
+                text,
+                "\nWrong colors, red, green and blue shown as ({}):",
+                self.colors.len()
+            );
+            for &(index, shown) in &self.colors {
+                let letters = shown
+                    .map(|channel| channel.map_or('-', |channel| CHANNEL_NAMES[channel]))
+                    .iter()
+                    .collect::<String>();
+                let _ = write!(text, "  {} as {letters}", name(index));
+
+                let mut sorted = shown;
+                sorted.sort();
+                if sorted == [Some(0), Some(1), Some(2)] {
+                    let _ = write!(text, ", wired {letters}");
+                }
+                let _ = writeln!(text);
+            }
+        }
+
+        if let Some(uniformity) = &self.uniformity {
+            let _ = writeln!(text, "\nPeak brightness:");
+            let _ = writeln!(text, "  {}", uniformity.summary());
+            for (segment, fade) in uniformity.fade.iter().enumerate() {
+                let Some(fade) = fade else {
+                    continue;
+                };
+                let _ = write!(text, "  ");
+                if layout.len() > 1 {
+                    let _ = write!(text, "{}: ", layout.name(segment));
+                }
+                let _ = writeln!(
+                    text,
+                    "the last {:.0}% at {:.0}% of the first",
+                    END_SHARE * 100.0,
+                    fade * 100.0
+                );
+            }
+        }
+
+        text
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.text())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Writes the peak and total brightness of every LED the last scan found to a CSV file, to plot
+/// along the strip.
+pub fn save_brightness(path: &Path) -> anyhow::Result<()> {
+    let samples = SAMPLES.read().unwrap();
+    let layout = segments::LAYOUT.read().unwrap();
+    let segmented = layout.len() > 1;
+
+    let write = || -> std::io::Result<()> {
+        let mut out = BufWriter::new(std::fs::File::create(path)?);
+        if segmented {
+            writeln!(out, "segment,index,peak,total")?;
+        } else {
+            writeln!(out, "index,peak,total")?;
+        }
+
+        for (index, sample) in samples.iter().enumerate() {
+            let Sample::Lit { total, peak } = sample else {
+                continue;
+            };
+            let (segment, index) = layout.split(index);
+            if segmented {
+                write!(out, "{segment},")?;
+            }
+            writeln!(out, "{index},{peak},{total}")?;
+        }
+
+        out.flush()
+    };
+    write().with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,146 @@
+//! Incremental record of a running scan, so one interrupted by a crash or a dead camera can be
+//! picked up where it stopped instead of starting over.
+//!
+//! The journal is JSON lines in the working directory: a header with the LED count and scan
+//! mode, then one line per captured LED. It's removed once a scan runs to the end.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use eframe::epaint::Pos2;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::scan::ScanMode;
+
+pub const PATH: &str = "scan-progress.jsonl";
+
+static FILE: Mutex<Option<File>> = Mutex::new(None);
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    led_count: usize,
+    mode: ScanMode,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    index: usize,
+    position: Option<[f32; 2]>,
+}
+
+/// What an interrupted scan got through, as read back from the journal.
+pub struct Interrupted {
+    pub led_count: usize,
+    pub mode: ScanMode,
+    pub map: Vec<Option<Pos2>>,
+    /// Which LEDs were captured, found or not, by index.
+    pub captured: Vec<bool>,
+}
+
+/// Starts a new journal, replacing any old one.
+pub fn begin(led_count: usize, mode: &ScanMode) -> anyhow::Result<()> {
+    let mut file = File::create(PATH)?;
+    serde_json::to_writer(&mut file, &Header { led_count, mode: mode.clone() })?;
+    writeln!(file)?;
+
+    *FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+/// Appends to the existing journal, for a resumed scan.
+pub fn reopen() -> anyhow::Result<()> {
+    *FILE.lock().unwrap() = Some(OpenOptions::new().append(true).open(PATH)?);
+    Ok(())
+}
+
+/// Records one capture. Does nothing when no journal is open.
+pub fn record(index: usize, position: Option<Pos2>) {
+    let mut file = FILE.lock().unwrap();
+    let Some(file) = file.as_mut() else {
+        return;
+    };
+
+    let entry = Entry {
+        index,
+        position: position.map(|pos| [pos.x, pos.y]),
+    };
+    let result = serde_json::to_writer(&mut *file, &entry)
+        .map_err(anyhow::Error::from)
+        .and_then(|()| Ok(writeln!(file)?));
+
+    if let Err(e) = result {
+        warn!("Failed to write to the scan journal: {e}");
+    }
+}
+
+/// Stops journaling. With `complete`, the journal is deleted since there's nothing to resume.
+pub fn close(complete: bool) {
+    *FILE.lock().unwrap() = None;
+
+    if complete {
+        if let Err(e) = fs::remove_file(PATH) {
+            warn!("Failed to remove the scan journal: {e}");
+        }
+    }
+}
+
+/// Closes and deletes the journal, if there is one, so the scan can't be resumed.
+pub fn discard() {
+    *FILE.lock().unwrap() = None;
+
+    if Path::new(PATH).exists() {
+        close(true);
+    }
+}
+
+/// Reads back the journal of an interrupted scan, if there is one.
+pub fn load() -> Option<Interrupted> {
+    if !Path::new(PATH).exists() {
+        return None;
+    }
+
+    match read() {
+        Ok(interrupted) => Some(interrupted),
+        Err(e) => {
+            warn!("Ignoring unreadable scan journal {PATH}: {e}");
+            None
+        }
+    }
+}
+
+fn read() -> anyhow::Result<Interrupted> {
+    let mut lines = BufReader::new(File::open(PATH)?).lines();
+
+    let header: Header = serde_json::from_str(
+        &lines
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Journal is empty"))??,
+    )?;
+
+    let mut map = vec![None; header.led_count];
+    let mut captured = vec![false; header.led_count];
+
+    for line in lines {
+        // The last line may be cut short if the app died while writing it
+        let Ok(entry) = serde_json::from_str::<Entry>(&line?) else {
+            break;
+        };
+
+        if let Some(slot) = map.get_mut(entry.index) {
+            *slot = entry.position.map(|[x, y]| Pos2::new(x, y));
+            captured[entry.index] = true;
+        }
+    }
+
+    Ok(Interrupted {
+        led_count: header.led_count,
+        mode: header.mode,
+        map,
+        captured,
+    })
+}
@@ -0,0 +1,257 @@
+//! Keyboard shortcuts for the common actions, rebindable in the Keys window and saved with the
+//! rest of the window state.
+
+use eframe::egui::{self, Event, Key, KeyboardShortcut, Modifiers};
+use serde::{Deserialize, Serialize};
+
+const STORAGE_KEY: &str = "keymap";
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Action {
+    StartScan,
+    StopScan,
+    PauseDetection,
+    Snapshot,
+    Fullscreen,
+    ToggleStats,
+    CopyMap,
+    /// Between the mask inset and none.
+    ToggleMask,
+    /// Takes back the latest edit in the review.
+    Undo,
+    NextLed,
+    PrevLed,
+    NextRow,
+    PrevRow,
+    AcceptLed,
+    RejectLed,
+}
+
+impl Action {
+    pub const ALL: [Self; 15] = [
+        Self::StartScan,
+        Self::StopScan,
+        Self::PauseDetection,
+        Self::Snapshot,
+        Self::Fullscreen,
+        Self::ToggleStats,
+        Self::CopyMap,
+        Self::ToggleMask,
+        Self::Undo,
+        Self::NextLed,
+        Self::PrevLed,
+        Self::NextRow,
+        Self::PrevRow,
+        Self::AcceptLed,
+        Self::RejectLed,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::StartScan => "Start scan",
+            Self::StopScan => "Stop scan",
+            Self::PauseDetection => "Pause detection",
+            Self::Snapshot => "Save snapshot",
+            Self::Fullscreen => "Projector mode",
+            Self::ToggleStats => "Show stats",
+            Self::CopyMap => "Copy map as JSON",
+            Self::ToggleMask => "Show mask",
+            Self::Undo => "Undo review edit",
+            Self::NextLed => "Next LED in review",
+            Self::PrevLed => "Previous LED in review",
+            Self::NextRow => "Next row in review",
+            Self::PrevRow => "Previous row in review",
+            Self::AcceptLed => "Accept LED in review",
+            Self::RejectLed => "Reject LED in review",
+        }
+    }
+
+    /// Whether the action only works in the Review window, and only while it's shown.
+    pub fn in_review(self) -> bool {
+        matches!(
+            self,
+            Self::NextLed
+                | Self::PrevLed
+                | Self::NextRow
+                | Self::PrevRow
+                | Self::AcceptLed
+                | Self::RejectLed
+        )
+    }
+
+    fn default_shortcut(self) -> KeyboardShortcut {
+        match self {
+            Self::StartScan => KeyboardShortcut::new(Modifiers::NONE, Key::F5),
+            Self::StopScan => KeyboardShortcut::new(Modifiers::SHIFT, Key::F5),
+            Self::PauseDetection => KeyboardShortcut::new(Modifiers::NONE, Key::P),
+            Self::Snapshot => KeyboardShortcut::new(Modifiers::COMMAND, Key::S),
+            Self::Fullscreen => KeyboardShortcut::new(Modifiers::NONE, Key::F11),
+            Self::ToggleStats => KeyboardShortcut::new(Modifiers::NONE, Key::F3),
+            Self::CopyMap => {
+                KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::C)
+            }
+            Self::ToggleMask => KeyboardShortcut::new(Modifiers::NONE, Key::M),
+            Self::Undo => KeyboardShortcut::new(Modifiers::COMMAND, Key::Z),
+            Self::NextLed => KeyboardShortcut::new(Modifiers::NONE, Key::ArrowRight),
+            Self::PrevLed => KeyboardShortcut::new(Modifiers::NONE, Key::ArrowLeft),
+            Self::NextRow => KeyboardShortcut::new(Modifiers::NONE, Key::ArrowDown),
+            Self::PrevRow => KeyboardShortcut::new(Modifiers::NONE, Key::ArrowUp),
+            Self::AcceptLed => KeyboardShortcut::new(Modifiers::NONE, Key::A),
+            Self::RejectLed => KeyboardShortcut::new(Modifiers::NONE, Key::R),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Binding {
+    action: Action,
+    modifiers: Modifiers,
+    /// `None` when the action was unbound.
+    key: Option<Key>,
+}
+
+impl Binding {
+    fn shortcut(&self) -> Option<KeyboardShortcut> {
+        Some(KeyboardShortcut::new(self.modifiers, self.key?))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Keymap {
+    bindings: Vec<Binding>,
+    /// The action waiting for a key press in the editor.
+    #[serde(skip)]
+    capturing: Option<Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            bindings: Action::ALL
+                .into_iter()
+                .map(|action| {
+                    let shortcut = action.default_shortcut();
+                    Binding {
+                        action,
+                        modifiers: shortcut.modifiers,
+                        key: Some(shortcut.key),
+                    }
+                })
+                .collect(),
+            capturing: None,
+        }
+    }
+}
+
+impl Keymap {
+    /// The keymap saved in `storage`, with the default shortcuts for actions added since.
+    pub fn load(storage: Option<&dyn eframe::Storage>) -> Self {
+        let mut keymap: Self = storage
+            .and_then(|storage| eframe::get_value(storage, STORAGE_KEY))
+            .unwrap_or_default();
+
+        for binding in Self::default().bindings {
+            if !keymap.bindings.iter().any(|b| b.action == binding.action) {
+                keymap.bindings.push(binding);
+            }
+        }
+        keymap
+    }
+
+    pub fn save(&self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, STORAGE_KEY, self);
+    }
+
+    /// Actions whose shortcut was pressed this frame, either the review's or all the others, see
+    /// `Action::in_review`. Nothing fires while a text field has focus, or while a new shortcut
+    /// is being picked.
+    pub fn pressed(&mut self, ctx: &egui::Context, in_review: bool) -> Vec<Action> {
+        if ctx.wants_keyboard_input() || self.capturing.is_some() {
+            return Vec::new();
+        }
+
+        // Most specific first, so Shift+F5 doesn't also count as F5
+        let mut shortcuts = self
+            .bindings
+            .iter()
+            .filter(|binding| binding.action.in_review() == in_review)
+            .filter_map(|binding| Some((binding.action, binding.shortcut()?)))
+            .collect::<Vec<_>>();
+        shortcuts.sort_by_key(|(_, shortcut)| {
+            let m = shortcut.modifiers;
+            std::cmp::Reverse(m.alt as u8 + m.ctrl as u8 + m.shift as u8 + m.command as u8)
+        });
+
+        ctx.input_mut(|input| {
+            shortcuts
+                .into_iter()
+                .filter(|(_, shortcut)| input.consume_shortcut(shortcut))
+                .map(|(action, _)| action)
+                .collect()
+        })
+    }
+
+    /// The shortcut for `action` as the Keys window shows it.
+    pub fn describe(&self, ctx: &egui::Context, action: Action) -> String {
+        self.bindings
+            .iter()
+            .find(|binding| binding.action == action)
+            .and_then(Binding::shortcut)
+            .map_or_else(|| "None".to_owned(), |shortcut| ctx.format_shortcut(&shortcut))
+    }
+
+    /// Every action with its shortcut. Clicking one waits for the next key press to rebind it.
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        if let Some(action) = self.capturing {
+            let pressed = ui.input(|input| {
+                input.events.iter().find_map(|event| match *event {
+                    Event::Key { key, pressed: true, modifiers, .. } => Some((key, modifiers)),
+                    _ => None,
+                })
+            });
+
+            match pressed {
+                Some((Key::Escape, _)) => self.capturing = None,
+                Some((key, modifiers)) => {
+                    if let Some(binding) = self.bindings.iter_mut().find(|b| b.action == action) {
+                        binding.key = Some(key);
+                        binding.modifiers = modifiers;
+                    }
+                    self.capturing = None;
+                }
+                None => {}
+            }
+        }
+
+        egui::Grid::new("keys").num_columns(3).show(ui, |ui| {
+            for binding in &mut self.bindings {
+                ui.label(binding.action.name());
+
+                let text = if self.capturing == Some(binding.action) {
+                    "Press a key…".to_owned()
+                } else {
+                    match binding.shortcut() {
+                        Some(shortcut) => ui.ctx().format_shortcut(&shortcut),
+                        None => "None".to_owned(),
+                    }
+                };
+                if ui
+                    .button(text)
+                    .on_hover_text("Click, then press the new shortcut. Escape cancels.")
+                    .clicked()
+                {
+                    self.capturing = Some(binding.action);
+                }
+
+                if ui.button("Clear").clicked() {
+                    binding.key = None;
+                }
+                ui.end_row();
+            }
+        });
+
+        if ui.button("Reset to defaults").clicked() {
+            *self = Self::default();
+        }
+    }
+}
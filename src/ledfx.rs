@@ -0,0 +1,233 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde_json::{json, Value};
+use tracing::{error, info};
+
+use crate::{segments, Led};
+
+/// Dummy device that fills the grid cells without an LED, since LedFx matrices have to be dense.
+const BLANK_DEVICE: &str = "calibrator-blank";
+
+/// Most cells a grid gets, however few rows `columns` leaves room for.
+const MAX_CELLS: usize = 1 << 20;
+
+/// Whether a layout is being pushed to LedFx.
+pub static PUSHING: AtomicBool = AtomicBool::new(false);
+
+/// How the last push went, until the window shows it.
+pub static PUSHED: Mutex<Option<String>> = Mutex::new(None);
+
+/// The layout rasterized onto a grid, as a LedFx matrix virtual: `rows` rows of pixels, filled
+/// row by row from the segments in order.
+pub struct LedfxLayout {
+    pub rows: usize,
+    pub columns: usize,
+    /// `[device, first pixel, last pixel, inverted]`, as in LedFx's virtual config.
+    pub segments: Vec<(String, usize, usize, bool)>,
+    /// LEDs that landed in a cell that was already taken and didn't make it into the layout.
+    pub dropped: usize,
+}
+
+impl LedfxLayout {
+    /// Square cells, `columns` of them across the wider or taller side of the layout, whichever
+    /// is longer.
+    pub fn new(leds: &[Led], device: &str, columns: usize) -> Self {
+        let (min, max) =
+            leds.iter()
+                .fold(([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]), |(min, max), led| {
+                    let [x, y, _] = led.position;
+                    ([min[0].min(x), min[1].min(y)], [max[0].max(x), max[1].max(y)])
+                });
+
+        let columns = columns.clamp(1, MAX_CELLS);
+        let extent = (max[0] - min[0]).max(max[1] - min[1]);
+        let cell = (extent / (columns - 1).max(1) as f32).max(f32::EPSILON);
+        let rows = if leds.is_empty() {
+            1
+        } else {
+            (((max[1] - min[1]) / cell) as usize + 1).min(MAX_CELLS / columns)
+        };
+
+        // LedFx sees the chained strip as one device, so pixels go by chained index
+        let layout = segments::LAYOUT.read().unwrap();
+
+        let mut grid = vec![None; rows * columns];
+        let mut dropped = 0;
+        for led in leds {
+            let [x, y, _] = led.position;
+            let column = (((x - min[0]) / cell).round() as usize).min(columns - 1);
+            let row = (((y - min[1]) / cell).round() as usize).min(rows - 1);
+
+            match &mut grid[row * columns + column] {
+                slot @ None => *slot = Some(layout.global(led.segment, led.index)),
+                Some(_) => dropped += 1,
+            }
+        }
+
+        // Merge runs of consecutive pixels into segments. Runs that count down become inverted
+        // segments, which keeps serpentine wiring compact.
+        let mut segments: Vec<(String, usize, usize, bool)> = Vec::new();
+        for cell in grid {
+            let merged = match (cell, segments.last_mut()) {
+                (None, Some((last_device, _, last, _)))
+                    if last_device == BLANK_DEVICE && *last + 1 < columns =>
+                {
+                    *last += 1;
+                    true
+                }
+                (Some(index), Some((last_device, first, last, inverted)))
+                    if last_device == device =>
+                {
+                    if !*inverted && index == *last + 1 {
+                        *last = index;
+                        true
+                    } else if (*inverted || first == last) && index + 1 == *first {
+                        *first = index;
+                        *inverted = true;
+                        true
+                    } else {
+                        false
+                    }
+                }
+                _ => false,
+            };
+
+            if !merged {
+                segments.push(match cell {
+                    Some(index) => (device.to_owned(), index, index, false),
+                    None => (BLANK_DEVICE.to_owned(), 0, 0, false),
+                });
+            }
+        }
+
+        Self { rows, columns, segments, dropped }
+    }
+
+    /// The end of a status line about the layout, on the LEDs that didn't fit.
+    pub fn dropped_note(&self) -> String {
+        match self.dropped {
+            0 => String::new(),
+            n => format!(", {n} LEDs shared a cell and were dropped"),
+        }
+    }
+
+    pub fn virtual_config(&self, name: &str) -> Value {
+        json!({
+            "config": { "name": name, "rows": self.rows },
+            "segments": self.segments,
+            "blank_device": {
+                "type": "dummy",
+                "config": { "name": BLANK_DEVICE, "pixel_count": self.columns },
+            },
+        })
+    }
+
+    pub fn save(&self, path: &Path, name: &str) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(&self.virtual_config(name))?)?;
+        Ok(())
+    }
+
+    /// `push` off the UI thread, with the outcome in `PUSHED`.
+    pub fn spawn_push(self, url: String, name: String) -> JoinHandle<()> {
+        PUSHING.store(true, Ordering::Relaxed);
+        thread::spawn(move || {
+            let status = match self.push(&url, &name) {
+                Ok(()) => {
+                    info!("Created LedFx virtual {name:?}");
+                    format!("Created LedFx virtual{}", self.dropped_note())
+                }
+                Err(e) => {
+                    error!("Failed to push to LedFx: {e:#}");
+                    format!("Failed to push to LedFx: {e:#}")
+                }
+            };
+            *PUSHED.lock().unwrap() = Some(status);
+            PUSHING.store(false, Ordering::Relaxed);
+        })
+    }
+
+    /// Creates the blank device if needed and a new matrix virtual on a running LedFx instance.
+    pub fn push(&self, url: &str, name: &str) -> anyhow::Result<()> {
+        let url = url.trim_end_matches('/');
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(5))
+            .build();
+
+        let devices: Value = agent
+            .get(&format!("{url}/api/devices"))
+            .call()?
+            .into_json()?;
+        if devices["devices"].get(BLANK_DEVICE).is_none() {
+            agent.post(&format!("{url}/api/devices")).send_json(json!({
+                "type": "dummy",
+                "config": { "name": BLANK_DEVICE, "pixel_count": self.columns },
+            }))?;
+        }
+
+        let created: Value = agent
+            .post(&format!("{url}/api/virtuals"))
+            .send_json(json!({ "config": { "name": name, "rows": self.rows } }))?
+            .into_json()?;
+        let id = created["virtual"]["id"]
+            .as_str()
+            .context("LedFx didn't return the new virtual's id")?;
+
+        agent
+            .post(&format!("{url}/api/virtuals/{id}"))
+            .send_json(json!({ "segments": self.segments }))?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leds(positions: &[[f32; 2]]) -> Vec<Led> {
+        positions
+            .iter()
+            .enumerate()
+            .map(|(index, &[x, y])| Led {
+                segment: 0,
+                index,
+                position: [x, y, 0.0],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vertical_strips_get_a_column() {
+        let strip = leds(&(0..50).map(|i| [3.0, i as f32 * 10.0]).collect::<Vec<_>>());
+        let layout = LedfxLayout::new(&strip, "wled", 32);
+        assert_eq!(layout.columns, 32);
+        assert_eq!(layout.rows, 32);
+        assert_eq!(layout.dropped, 50 - 32);
+    }
+
+    #[test]
+    fn rows_follow_the_spacing() {
+        let grid = (0..4)
+            .flat_map(|y| (0..8).map(move |x| [x as f32 * 5.0, y as f32 * 5.0]))
+            .collect::<Vec<_>>();
+        let layout = LedfxLayout::new(&leds(&grid), "wled", 8);
+        assert_eq!((layout.rows, layout.columns, layout.dropped), (4, 8, 0));
+        // Wired row by row, the whole grid is one run
+        assert_eq!(layout.segments[0], ("wled".to_owned(), 0, 31, false));
+    }
+
+    #[test]
+    fn single_points_fit_one_cell() {
+        let layout = LedfxLayout::new(&leds(&[[1.0, 1.0], [1.0, 1.0]]), "wled", 16);
+        assert_eq!((layout.rows, layout.dropped), (1, 1));
+    }
+}
@@ -0,0 +1,54 @@
+//! The camera's angle of view, for rough setups that don't have a full calibration. It's enough
+//! to stand in for the intrinsics: the focal length in pixels follows from it and the frame
+//! width, with the principal point taken to be the middle of the frame.
+//!
+//! That gives the stereo pair its focal length and principal point when the calibration leaves
+//! them out, and lets the export place a flat installation in meters at a known distance from
+//! the camera, see `transform::Transform::distance`. Lens distortion is ignored, so wide lenses
+//! get less accurate towards the edges.
+
+use std::sync::RwLock;
+
+use serde::{Deserialize, Serialize};
+
+use crate::pipeline;
+
+/// Width of a 35 mm film frame, what focal lengths are usually given as the equivalent of.
+pub const FULL_FRAME_WIDTH: f32 = 36.0;
+
+/// The angle of view of the camera, set per project.
+pub static LENS: RwLock<Option<Lens>> = RwLock::new(None);
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Lens {
+    /// Horizontal angle of view, in degrees.
+    pub fov: f32,
+}
+
+impl Lens {
+    /// From a focal length and the width of the sensor it's for, both in millimeters.
+    pub fn from_focal_length(focal_length: f32, sensor_width: f32) -> Self {
+        Self {
+            fov: (2.0 * (sensor_width / 2.0 / focal_length).atan()).to_degrees(),
+        }
+    }
+
+    /// Focal length in pixels, for frames `width` pixels across.
+    pub fn focal_length(self, width: f32) -> f32 {
+        width / 2.0 / (self.fov.to_radians() / 2.0).tan()
+    }
+
+    /// The focal length in millimeters, as a 35 mm equivalent.
+    pub fn equivalent_focal_length(self) -> f32 {
+        self.focal_length(FULL_FRAME_WIDTH)
+    }
+}
+
+/// Focal length and principal point in pixels from `LENS` and the size of the latest frame.
+/// `None` without either.
+pub fn intrinsics() -> Option<(f32, [f32; 2])> {
+    let lens = (*LENS.read().unwrap())?;
+    let [width, height] = pipeline::frame_size()?;
+    let (width, height) = (width as f32, height as f32);
+    Some((lens.focal_length(width), [width / 2.0, height / 2.0]))
+}
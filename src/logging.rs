@@ -0,0 +1,73 @@
+//! Logging to stderr as well as to the in-app log panel.
+
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+use tracing::{field::Field, Event, Level, Subscriber};
+use tracing_subscriber::{field::Visit, layer::Context, prelude::*, EnvFilter, Layer};
+
+pub struct LogLine {
+    /// Time since logging was set up.
+    pub time: Duration,
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+/// The most recent log lines, oldest first.
+pub static LOG: Mutex<VecDeque<LogLine>> = Mutex::new(VecDeque::new());
+
+const MAX_LINES: usize = 1000;
+
+static START: OnceLock<Instant> = OnceLock::new();
+
+/// Installs the global subscriber. `RUST_LOG` overrides the default of info and up.
+pub fn init() {
+    START.get_or_init(Instant::now);
+
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(PanelLayer)
+        .init();
+}
+
+struct PanelLayer;
+
+impl<S: Subscriber> Layer<S> for PanelLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let mut message = MessageVisitor(String::new());
+        event.record(&mut message);
+
+        let metadata = event.metadata();
+        let mut log = LOG.lock().unwrap();
+        if log.len() == MAX_LINES {
+            log.pop_front();
+        }
+        log.push_back(LogLine {
+            time: START.get_or_init(Instant::now).elapsed(),
+            level: *metadata.level(),
+            target: metadata.target().to_owned(),
+            message: message.0,
+        });
+    }
+}
+
+/// Formats the message followed by any other fields as `name=value`.
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, " {}={value:?}", field.name());
+        }
+    }
+}
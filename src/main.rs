@@ -1,213 +1,3137 @@
 use std::{
-    sync::{
-        atomic::{AtomicUsize, Ordering},
-        RwLock,
-    },
-    thread,
-    time::Duration,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc, Mutex},
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
+use clap::Parser;
 use eframe::{
-    egui::{self, Area, DragValue, Image, TextureOptions, Window},
+    egui::{
+        self, Align2, Area, Button, Checkbox, CollapsingHeader, ComboBox, DragValue, FontId, Frame,
+        Id, Image, Key, LayerId, Order, ProgressBar, ScrollArea, Sense, SidePanel, Slider,
+        TextEdit, TextureOptions, TopBottomPanel, ViewportCommand, Window,
+    },
     epaint::{Color32, ColorImage, Pos2, Rect, Stroke, TextureHandle, Vec2},
 };
-use opencv::{
-    core::{in_range, Mat_AUTO_STEP, Point, Scalar, Vector, CV_8UC3},
-    imgproc::{
-        bounding_rect, cvt_color, find_contours, moments, CHAIN_APPROX_SIMPLE, COLOR_RGB2HSV,
-        RETR_EXTERNAL,
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn, Level};
+
+use crate::{
+    cli::{Cli, Command, GuiArgs, StreamArgs},
+    controller::{
+        discovery, ChannelOrder, Channels, ControllerConfig, ControllerKind, DmxRange, PixelOrder,
+    },
+    drift::DriftAction,
+    export::ExportFormat,
+    i18n::{tr, Language},
+    keys::Action,
+    ledfx::LedfxLayout,
+    lens::Lens,
+    overlay::ViewTransform,
+    patterns::Pattern,
+    pipeline::{
+        Approximation, Denoise, DetectionMode, DetectorKind, Retrieval, Settings, ThresholdMethod,
+        POINTS, SETTINGS,
     },
-    prelude::*,
+    scan::{Priors, ScanMode, ScanOrder, ScanSettings, SharedController},
+    segments::Segment,
+    toasts::{Retry, TOASTS},
+    transform::{Rotation, Transform},
+    zones::Zone,
 };
-use video_rs::{Decoder, Locator, Url};
 
-fn main() {
-    let native_options = eframe::NativeOptions::default();
+mod alerts;
+mod autosave;
+mod awb;
+mod batch;
+mod cli;
+mod controller;
+mod depth;
+mod diff;
+mod drift;
+mod export;
+mod exporters;
+mod frames;
+mod freeze;
+mod grid;
+mod grouped;
+mod heatmap;
+mod i18n;
+mod ignored;
+mod inset;
+mod inspect;
+mod issues;
+mod journal;
+mod keys;
+mod ledfx;
+mod lens;
+mod logging;
+mod metrics;
+mod ordering;
+mod outliers;
+mod overlay;
+mod patterns;
+mod pipeline;
+mod pixel_order;
+mod playback;
+mod preview;
+mod projects;
+mod ptz;
+#[cfg(feature = "realsense")]
+mod realsense;
+mod recording;
+mod refine;
+mod reflections;
+mod regions;
+mod report;
+mod review;
+mod rpicam;
+mod scan;
+mod script;
+mod segments;
+mod simulator;
+mod snapshot;
+mod stats;
+mod stereo;
+mod timelapse;
+mod toasts;
+mod transform;
+mod v4l2;
+mod viewport;
+mod web;
+mod yuv;
+mod zones;
+
+fn main() -> anyhow::Result<()> {
+    logging::init();
+
+    let cli = Cli::parse();
+    exporters::load_scripts();
+    match cli.command {
+        None => gui(cli.gui),
+        Some(Command::Gui(args)) => gui(args),
+        Some(command) => cli::run(command),
+    }
+}
+
+/// How long closing the window waits for the worker threads, which can be stuck on a read.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+
+const UI_SCALE_KEY: &str = "ui_scale";
+const LANGUAGE_KEY: &str = "language";
+const DOCK_SETTINGS_KEY: &str = "dock_settings";
+const MARKER_STYLE_KEY: &str = "marker_style";
+const DEVICES_KEY: &str = "devices";
+const PROFILES_KEY: &str = "profiles";
+const SCAN_PROFILES_KEY: &str = "scan_profiles";
+const LENSES_KEY: &str = "lenses";
+const PROJECTS_KEY: &str = "projects";
+
+/// How far from a captured LED a click may land to order it, in screen points.
+const HAND_PICK_RADIUS: f32 = 16.0;
+
+/// How long the detection count may be off before it's flagged, in seconds.
+const COUNT_GRACE: f64 = 1.0;
+
+/// How long without a frame before the status bar calls the stream stalled.
+const STREAM_STALLED: Duration = Duration::from_secs(2);
+
+/// Extensions of dropped files opened as the source, anything ffmpeg reads frames from.
+const DROPPED_MEDIA: [&str; 10] =
+    ["mp4", "mkv", "mov", "avi", "webm", "png", "jpg", "jpeg", "bmp", "tiff"];
+
+fn interrupted_scan() -> Option<(usize, usize)> {
+    journal::load().map(|interrupted| {
+        let captured = interrupted
+            .captured
+            .iter()
+            .filter(|&&captured| captured)
+            .count();
+        (captured, interrupted.led_count)
+    })
+}
+
+fn gui(args: GuiArgs) -> anyhow::Result<()> {
+    let mut native_options = eframe::NativeOptions::default();
+    if let Some(&[x, y]) = args.monitor.as_deref() {
+        native_options.viewport = native_options.viewport.with_position([x, y]);
+    }
+    native_options.viewport = native_options.viewport.with_fullscreen(args.fullscreen);
+
+    alerts::spawn();
     eframe::run_native(
         "LED Position Calibrator",
         native_options,
-        Box::new(|cc| Box::new(CalibratorApp::new(cc))),
+        Box::new(|cc| Box::new(CalibratorApp::new(cc, args))),
     )
-    .unwrap();
+    .map_err(|e| anyhow::anyhow!("{e}"))
 }
 
-struct CalibratorApp {
-    image: TextureHandle,
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Hint {
+    Start,
+    End,
 }
 
-static IMAGE: RwLock<Vec<u8>> = RwLock::new(Vec::new());
-static IMAGE_WIDTH: AtomicUsize = AtomicUsize::new(0);
-
-static POINTS: RwLock<Vec<Rect>> = RwLock::new(Vec::new());
-
-struct Settings {
-    lower_h: f64,
-    lower_s: f64,
-    lower_v: f64,
-    upper_h: f64,
-    upper_s: f64,
-    upper_v: f64,
+struct CalibratorApp {
+    stream: StreamArgs,
+    image: TextureHandle,
+    viewport: viewport::Viewport3d,
+    map_diff: diff::View,
+    ptz: ptz::Panel,
+    export_path: String,
+    projects: projects::Projects,
+    export_status: String,
+    snapshot_overlay: bool,
+    /// Applied to everything exported, except snapshots which stay in camera space.
+    transform: Transform,
+    snap_to_grid: bool,
+    ledfx_device: String,
+    ledfx_columns: usize,
+    ledfx_url: String,
+    verify_pattern: Pattern,
+    /// `ctx.input().time` at which the verify pattern started playing.
+    verify_started: Option<f64>,
+    /// Draw the verify pattern as it was a latency ago, which is what the LEDs in the video show.
+    verify_sync: bool,
+    segments: Vec<Segment>,
+    controller: Option<SharedController>,
+    controller_status: String,
+    use_scan_script: bool,
+    strobe_diff: bool,
+    interleave: bool,
+    color_coded: bool,
+    scan_regions: bool,
+    coarse_to_fine: bool,
+    grouped: bool,
+    latency_led: usize,
+    prior_path: String,
+    prior_radius: f32,
+    prior_status: String,
+    scan_script: String,
+    /// Hints for `ordering::order`, and which one the next click on the video sets.
+    order_start: Option<Pos2>,
+    order_end: Option<Pos2>,
+    picking: Option<Hint>,
+    /// Dragging out a gray region to white balance on, a lit LED to capture as the template or
+    /// the zone of a segment, and the region so far.
+    picking_gray: bool,
+    picking_template: bool,
+    picking_zone: Option<usize>,
+    region: Option<Rect>,
+    /// The model path as it's being typed, applied once it's done, so the detector doesn't load
+    /// every path on the way.
+    model_path: Option<String>,
+    /// The last free-run order, drawn over the video.
+    order_path: Vec<Pos2>,
+    /// Every detection of the last free-run capture as found, and which of them were clicked in
+    /// order so far while ordering by hand.
+    captured: Vec<Pos2>,
+    clicked: Vec<usize>,
+    ordering_by_hand: bool,
+    /// How many LEDs the scan left in the journal got through, and how many it was for.
+    interrupted: Option<(usize, usize)>,
+    /// The session a crash left behind, until it's restored or discarded. Autosaving waits for
+    /// that, as it would overwrite it.
+    recovery: Option<autosave::Session>,
+    autosave: autosave::Autosave,
+    was_scanning: bool,
+    /// Textures for `scan::THUMBNAILS`, by LED index and whether it was found.
+    thumbnails: Vec<(usize, bool, TextureHandle)>,
+    expect_count: bool,
+    expected_count: usize,
+    /// `ctx.input().time` since which the detection count has been off, and whether that got
+    /// logged yet.
+    count_mismatch: Option<(f64, bool)>,
+    stats: stats::Overlay,
+    show_stats: bool,
+    /// For the status bar, sampled separately from the overlay's.
+    rates: stats::Rates,
+    /// Write the scanned index next to each LED.
+    show_labels: bool,
+    label_size: f32,
+    /// Ring each scanned LED in a color along its index.
+    color_by_index: bool,
+    marker_style: overlay::MarkerStyle,
+    /// An old map drawn under the live view, empty when none is loaded.
+    ghost: Vec<Led>,
+    ghost_path: String,
+    ghost_status: String,
+    inset: inset::Inset,
+    inspector: inspect::Inspector,
+    heatmap: heatmap::Heatmap,
+    review: review::Review,
+    /// The recorded scan being gone over, if one was opened.
+    playback: Option<playback::Playback>,
+    /// Only the video and overlay, full screen, for showing the calibration on a projector.
+    fullscreen: bool,
+    keymap: keys::Keymap,
+    /// Points per pixel, `None` to follow the system.
+    ui_scale: Option<f32>,
+    /// Show the settings in a side panel instead of a window over the video.
+    dock_settings: bool,
+    /// Where to move the window before going full screen, which picks the monitor it goes full
+    /// screen on.
+    monitor: Option<Pos2>,
+    /// The capture device last picked in each working directory.
+    devices: HashMap<PathBuf, v4l2::Remembered>,
+    /// Detection settings by `StreamArgs::profile_key`, and the key of the current source.
+    profiles: HashMap<String, Settings>,
+    /// Scan settings by the same key, as what blooms differs per camera too.
+    scan_profiles: HashMap<String, ScanSettings>,
+    /// Angles of view by the same key, for the sources they're known for.
+    lenses: HashMap<String, Lens>,
+    profile: String,
+    /// Decoder, detection and scan threads, joined on exit.
+    workers: Vec<JoinHandle<()>>,
 }
-static mut SETTINGS: Settings = Settings {
-    lower_h: 40.0,
-    lower_s: 100.0,
-    lower_v: 100.0,
-    upper_h: 70.0,
-    upper_s: 255.0,
-    upper_v: 255.0,
-};
 
 impl CalibratorApp {
-    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    fn new(cc: &eframe::CreationContext<'_>, args: GuiArgs) -> Self {
+        let GuiArgs {
+            mut stream,
+            web,
+            fullscreen,
+            monitor,
+            ui_scale,
+            language,
+            autosave,
+        } = args;
         let ctx = &cc.egui_ctx;
+        *i18n::LANGUAGE.write().unwrap() = language
+            .or_else(|| {
+                cc.storage
+                    .and_then(|storage| eframe::get_value(storage, LANGUAGE_KEY))
+            })
+            .or_else(Language::from_env)
+            .unwrap_or_default();
         let image = ctx.load_texture("video feed", ColorImage::example(), TextureOptions::LINEAR);
 
-        thread::spawn({
-            let mut image = image.clone();
-            move || {
-                let opts = video_rs::Options::new_with_rtsp_transport_tcp_and_sane_timeouts();
-                let mut decoder = Decoder::new_with_options(
-                    &Locator::Url(Url::parse("rtsp://192.168.0.101").unwrap()),
-                    &opts,
+        let devices: HashMap<PathBuf, v4l2::Remembered> = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, DEVICES_KEY))
+            .unwrap_or_default();
+        // Back to the camera used here last time, unless told to use another source
+        let remembered = std::env::current_dir()
+            .ok()
+            .and_then(|dir| devices.get(&dir))
+            .filter(|_| stream.is_default_source());
+        if let Some((remembered, device)) =
+            remembered.and_then(|remembered| Some((remembered, remembered.find()?)))
+        {
+            info!("Using {} as last time in this directory", device.name);
+            stream.device = Some(device.path);
+            stream.device_size = remembered.size.map(Vec::from);
+        }
+
+        // Options on the command line still win over the camera's profile
+        let profiles: HashMap<String, Settings> = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, PROFILES_KEY))
+            .unwrap_or_default();
+        let profile = stream.profile_key();
+        if let Some(settings) = profiles.get(&profile) {
+            info!("Using the detection settings last used with {profile}");
+            *SETTINGS.write().unwrap() = settings.clone();
+        }
+        let scan_profiles: HashMap<String, ScanSettings> = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, SCAN_PROFILES_KEY))
+            .unwrap_or_default();
+        if let Some(settings) = scan_profiles.get(&profile) {
+            *scan::SETTINGS.write().unwrap() = settings.clone();
+        }
+        let lenses: HashMap<String, Lens> = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, LENSES_KEY))
+            .unwrap_or_default();
+        *lens::LENS.write().unwrap() = lenses.get(&profile).copied();
+        stream.apply();
+
+        let projects = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, PROJECTS_KEY))
+            .unwrap_or_else(|| projects::Projects::new("leds"));
+
+        let mut workers = stream.spawn_decoder(Some(image.clone()));
+        workers.extend([pipeline::spawn_detection(), v4l2::spawn_watcher()]);
+        match stream.spawn_stereo() {
+            Ok(stereo) => workers.extend(stereo),
+            Err(e) => toasts::error(format!("{e:#}"), None),
+        }
+        if let Some(address) = web {
+            match web::spawn(&address) {
+                Ok(server) => workers.push(server),
+                Err(e) => toasts::error(format!("{e:#}"), None),
+            }
+        }
+
+        Self {
+            stream,
+            image,
+            viewport: Default::default(),
+            map_diff: Default::default(),
+            ptz: Default::default(),
+            export_path: projects.export_path().to_owned(),
+            projects,
+            export_status: String::new(),
+            snapshot_overlay: true,
+            transform: Transform::default(),
+            snap_to_grid: false,
+            ledfx_device: "wled".to_owned(),
+            ledfx_columns: 32,
+            ledfx_url: "http://localhost:8888".to_owned(),
+            verify_pattern: Pattern::SweepX,
+            verify_started: None,
+            verify_sync: true,
+            segments: vec![Segment {
+                name: "strip 1".to_owned(),
+                config: ControllerConfig {
+                    kind: ControllerKind::Wled,
+                    address: String::new(),
+                    led_count: 50,
+                    addressing: Vec::new(),
+                    pixel_order: PixelOrder::Rgb,
+                    channels: Channels::Rgb,
+                },
+                zone: None,
+            }],
+            controller: None,
+            controller_status: String::new(),
+            use_scan_script: false,
+            strobe_diff: false,
+            interleave: false,
+            color_coded: false,
+            scan_regions: false,
+            coarse_to_fine: false,
+            grouped: false,
+            latency_led: 0,
+            prior_path: "leds.json".to_owned(),
+            prior_radius: 40.0,
+            prior_status: String::new(),
+            scan_script: script::EXAMPLE.to_owned(),
+            order_start: None,
+            order_end: None,
+            picking: None,
+            picking_gray: false,
+            picking_template: false,
+            picking_zone: None,
+            region: None,
+            model_path: None,
+            order_path: Vec::new(),
+            captured: Vec::new(),
+            clicked: Vec::new(),
+            ordering_by_hand: false,
+            interrupted: interrupted_scan(),
+            recovery: autosave::load(),
+            autosave: autosave::Autosave::new(Duration::from_secs(autosave)),
+            was_scanning: false,
+            thumbnails: Vec::new(),
+            expect_count: false,
+            expected_count: 1,
+            count_mismatch: None,
+            stats: Default::default(),
+            show_stats: false,
+            rates: Default::default(),
+            show_labels: true,
+            label_size: 12.0,
+            color_by_index: true,
+            marker_style: cc
+                .storage
+                .and_then(|storage| eframe::get_value(storage, MARKER_STYLE_KEY))
+                .unwrap_or_default(),
+            ghost: Vec::new(),
+            ghost_path: "leds.json".to_owned(),
+            ghost_status: String::new(),
+            inset: Default::default(),
+            inspector: Default::default(),
+            heatmap: Default::default(),
+            review: Default::default(),
+            playback: None,
+            fullscreen,
+            keymap: keys::Keymap::load(cc.storage),
+            ui_scale: ui_scale.or_else(|| {
+                cc.storage
+                    .and_then(|storage| eframe::get_value(storage, UI_SCALE_KEY))
+                    .flatten()
+            }),
+            dock_settings: cc
+                .storage
+                .and_then(|storage| eframe::get_value(storage, DOCK_SETTINGS_KEY))
+                .unwrap_or(false),
+            monitor: monitor.map(|m| Pos2::new(m[0], m[1])),
+            devices,
+            profiles,
+            scan_profiles,
+            lenses,
+            profile,
+            workers,
+        }
+    }
+
+    /// Thumbnails of every capture so far, newest on the right, missed LEDs outlined in red.
+    fn show_gallery(&mut self, ui: &mut egui::Ui) {
+        {
+            let thumbnails = scan::THUMBNAILS.lock().unwrap();
+
+            // A new scan started
+            if thumbnails.len() < self.thumbnails.len() {
+                self.thumbnails.clear();
+            }
+
+            for thumbnail in &thumbnails[self.thumbnails.len()..] {
+                let image = ColorImage::from_rgb(thumbnail.size, &thumbnail.rgb);
+                let texture = ui.ctx().load_texture(
+                    format!("thumbnail {}", thumbnail.index),
+                    image,
+                    TextureOptions::NEAREST,
+                );
+                self.thumbnails
+                    .push((thumbnail.index, thumbnail.position.is_some(), texture));
+            }
+        }
+
+        ScrollArea::horizontal()
+            .stick_to_right(true)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    for (index, found, texture) in &self.thumbnails {
+                        ui.vertical(|ui| {
+                            let response = ui.image((texture.id(), Vec2::splat(64.0)));
+                            if !found {
+                                ui.painter().rect_stroke(
+                                    response.rect,
+                                    0.0,
+                                    Stroke::new(2.0, Color32::RED),
+                                );
+                            }
+                            ui.label(index.to_string());
+                        });
+                    }
+                });
+            });
+    }
+
+    fn load_ghost(&mut self) {
+        self.ghost_status = match export::read_json(self.ghost_path.as_ref()) {
+            Ok(leds) => {
+                self.ghost = leds;
+                format!("Showing {} old positions", self.ghost.len())
+            }
+            Err(e) => format!("Failed to load: {e}"),
+        };
+    }
+
+    fn show_ghost_settings(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label(tr("Path"));
+            ui.text_edit_singleline(&mut self.ghost_path);
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button(tr("Load")).clicked() {
+                self.load_ghost();
+            }
+            if ui.button(tr("Clear")).clicked() {
+                self.ghost.clear();
+                self.ghost_status.clear();
+            }
+        });
+
+        ui.label(&self.ghost_status);
+
+        if let Some(offset) = overlay::ghost_offset(&self.ghost, &POINTS.read().unwrap()) {
+            ui.label(format!("Off by {offset:.1} px from the detections"))
+                .on_hover_text(
+                    "Median distance from each old position to the nearest detection, light \
+                     every LED for a fair comparison",
+                );
+        }
+    }
+
+    /// Flags the whole window when the number of detections is different from what's expected,
+    /// which nearly always means the thresholds or mask are off.
+    fn check_count(&mut self, ctx: &egui::Context) {
+        let count = POINTS.read().unwrap().len();
+        if !self.expect_count || count == self.expected_count {
+            self.count_mismatch = None;
+            return;
+        }
+
+        // Give LEDs a moment to switch, a scan is off by one for a frame on every step
+        let now = ctx.input(|i| i.time);
+        let (since, logged) = self.count_mismatch.get_or_insert((now, false));
+        if now - *since < COUNT_GRACE {
+            ctx.request_repaint();
+            return;
+        }
+
+        if !*logged {
+            warn!("Detecting {count} LEDs, expected {}", self.expected_count);
+            *logged = true;
+        }
+
+        let rect = ctx.screen_rect();
+        let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("count warning")));
+        painter.rect_stroke(rect.shrink(2.0), 0.0, Stroke::new(4.0, Color32::RED));
+        painter.text(
+            rect.center_top() + Vec2::new(0.0, 40.0),
+            Align2::CENTER_TOP,
+            format!("Detecting {count} LEDs, expected {}", self.expected_count),
+            FontId::proportional(24.0),
+            Color32::RED,
+        );
+    }
+
+    fn scan_mode(&self) -> ScanMode {
+        if self.use_scan_script {
+            ScanMode::Script(self.scan_script.clone())
+        } else if self.strobe_diff {
+            ScanMode::StrobeDiff
+        } else if self.interleave && segments::LAYOUT.read().unwrap().len() > 1 {
+            ScanMode::Interleaved
+        } else if self.color_coded {
+            ScanMode::ColorCoded
+        } else if self.scan_regions {
+            ScanMode::Regions
+        } else if self.coarse_to_fine {
+            ScanMode::CoarseToFine
+        } else if self.grouped {
+            ScanMode::Grouped
+        } else {
+            ScanMode::Sequential
+        }
+    }
+
+    fn show_settings(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing(tr("Source"), |ui| self.show_source(ui));
+
+        let mut settings = SETTINGS.write().unwrap();
+        let settings = &mut *settings;
+
+        ComboBox::from_label(tr("Detector"))
+            .selected_text(settings.detector.name())
+            .show_ui(ui, |ui| {
+                for kind in DetectorKind::ALL {
+                    ui.selectable_value(&mut settings.detector, kind, kind.name());
+                }
+            });
+
+        if settings.detector == DetectorKind::Template {
+            ui.horizontal(|ui| {
+                if ui
+                    .selectable_label(self.picking_template, "Capture template")
+                    .on_hover_text("Drag over a lit LED in view, bloom and all")
+                    .clicked()
+                {
+                    self.picking_template = !self.picking_template;
+                    self.picking_gray = false;
+                    self.picking_zone = None;
+                }
+                match &settings.template {
+                    Some(template) => {
+                        ui.label(format!("{}x{}", template.width, template.height()));
+                    }
+                    None => {
+                        ui.label("None yet");
+                    }
+                }
+            });
+            ui.add(
+                Slider::new(&mut settings.template_score, 0.3..=1.0)
+                    .text("Match")
+                    .fixed_decimals(2),
+            );
+        }
+
+        if settings.detector == DetectorKind::Neural {
+            ui.horizontal(|ui| {
+                ui.label("Model");
+                let mut path = self.model_path.take().unwrap_or_else(|| {
+                    settings
+                        .model
+                        .as_ref()
+                        .map_or_else(String::new, |path| path.display().to_string())
+                });
+                let response = ui
+                    .text_edit_singleline(&mut path)
+                    .on_hover_text("An ONNX file that turns RGB frames into a map of LED scores");
+                if response.has_focus() {
+                    self.model_path = Some(path);
+                } else if response.lost_focus() {
+                    settings.model = (!path.trim().is_empty()).then(|| PathBuf::from(path.trim()));
+                }
+            });
+            ui.add(
+                Slider::new(&mut settings.model_score, 0.05..=1.0)
+                    .text("Score")
+                    .fixed_decimals(2),
+            );
+        }
+
+        ui.horizontal(|ui| {
+            ComboBox::from_label(tr("Mode"))
+                .selected_text(settings.mode.name())
+                .show_ui(ui, |ui| {
+                    for mode in DetectionMode::ALL {
+                        ui.selectable_value(&mut settings.mode, mode, mode.name());
+                    }
+                });
+            if ui
+                .button("White")
+                .on_hover_text(
+                    "Set the bounds to bright, barely saturated blobs, for white LEDs and the \
+                     white channel of RGBW strips",
                 )
-                .expect("Failed to create decoder");
+                .clicked()
+            {
+                settings.target_white();
+            }
+        });
+
+        if settings.mode != DetectionMode::Lab {
+            ComboBox::from_label(tr("Threshold"))
+                .selected_text(settings.method.name())
+                .show_ui(ui, |ui| {
+                    for method in ThresholdMethod::ALL {
+                        ui.selectable_value(&mut settings.method, method, method.name());
+                    }
+                });
+
+            if settings.method == ThresholdMethod::Adaptive {
+                ui.add(
+                    DragValue::new(&mut settings.adaptive_block)
+                        .clamp_range(3..=255)
+                        .prefix("block "),
+                );
+                ui.add(
+                    DragValue::new(&mut settings.adaptive_offset)
+                        .clamp_range(0.0..=255.0)
+                        .speed(0.1)
+                        .prefix("offset "),
+                );
+            }
+        }
+
+        match settings.mode {
+            DetectionMode::Hsv => {
+                for (name, value, range) in [
+                    ("lower_h", &mut settings.lower_h, 0.0..=180.0),
+                    ("lower_s", &mut settings.lower_s, 0.0..=255.0),
+                    ("lower_v", &mut settings.lower_v, 0.0..=255.0),
+                    ("upper_h", &mut settings.upper_h, 0.0..=180.0),
+                    ("upper_s", &mut settings.upper_s, 0.0..=255.0),
+                    ("upper_v", &mut settings.upper_v, 0.0..=255.0),
+                ] {
+                    ui.add(
+                        DragValue::new(value)
+                            .clamp_range(range)
+                            .speed(0.1)
+                            .prefix(name),
+                    );
+                }
+            }
+            DetectionMode::Brightness if settings.method == ThresholdMethod::Fixed => {
+                ui.add(
+                    DragValue::new(&mut settings.brightness)
+                        .clamp_range(0.0..=255.0)
+                        .speed(0.1)
+                        .prefix("threshold"),
+                );
+            }
+            DetectionMode::Brightness => {}
+            DetectionMode::Lab => {
+                ui.horizontal(|ui| {
+                    ui.label("target");
+                    ui.color_edit_button_srgb(&mut settings.lab_target);
+                });
+                ui.add(
+                    DragValue::new(&mut settings.lab_tolerance)
+                        .clamp_range(1.0..=100.0)
+                        .speed(0.1)
+                        .prefix("delta-E "),
+                );
+            }
+        }
 
-                for frame in decoder.decode_raw_iter() {
-                    let frame = frame.expect("Failed to decode frame");
+        let mut interval = pipeline::INTERVAL.read().unwrap().as_millis() as u64;
+        if ui
+            .add(
+                DragValue::new(&mut interval)
+                    .clamp_range(10..=5000)
+                    .prefix("Detect every ")
+                    .suffix(" ms"),
+            )
+            .changed()
+        {
+            *pipeline::INTERVAL.write().unwrap() = Duration::from_millis(interval);
+        }
 
-                    *IMAGE.write().unwrap() = frame.data(0).to_vec();
-                    IMAGE_WIDTH.store(frame.width() as usize, Ordering::Relaxed);
+        ui.horizontal(|ui| {
+            let idle = !scan::RUNNING.load(Ordering::Relaxed);
+            let capture = ui
+                .add_enabled(idle, Button::new("Capture dark frame"))
+                .on_hover_text(match self.controller {
+                    Some(_) => "Turn every LED off and subtract what's left from now on",
+                    None => "Subtract the current frame from now on, turn the LEDs off first",
+                });
+            if capture.clicked() {
+                match scan::dark_frame() {
+                    Ok(job) => self.workers.extend(job),
+                    Err(e) => warn!("Failed to capture a dark frame: {e}"),
+                }
+            }
+
+            let mut dark = pipeline::DARK_FRAME.write().unwrap();
+            if dark.is_some() && ui.button(tr("Clear")).clicked() {
+                *dark = None;
+            }
+        });
 
-                    image.set(
-                        ColorImage::from_rgb(
-                            [frame.width() as usize, frame.height() as usize],
-                            frame.data(0),
-                        ),
-                        TextureOptions::LINEAR,
+        ui.collapsing(tr("Preprocessing"), |ui| {
+            ui.horizontal(|ui| {
+                ui.label(tr("Gains"));
+                for (gain, name) in settings.gains.iter_mut().zip(["R ", "G ", "B "]) {
+                    ui.add(
+                        DragValue::new(gain)
+                            .clamp_range(0.1..=8.0)
+                            .speed(0.01)
+                            .prefix(name),
                     );
                 }
+            })
+            .response
+            .on_hover_text("Multiply each channel before thresholding, for a camera's balance");
+            ui.horizontal(|ui| {
+                if ui
+                    .selectable_label(self.picking_gray, "Auto from gray region")
+                    .on_hover_text("Drag over something white or gray in view")
+                    .clicked()
+                {
+                    self.picking_gray = !self.picking_gray;
+                    self.picking_template = false;
+                    self.picking_zone = None;
+                }
+                if ui.button(tr("Reset")).clicked() {
+                    settings.gains = [1.0; 3];
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.add(
+                    DragValue::new(&mut settings.gamma)
+                        .clamp_range(0.1..=5.0)
+                        .speed(0.01)
+                        .prefix("Gamma "),
+                )
+                .on_hover_text("Above 1 brings up dim LEDs");
+                ui.add(
+                    DragValue::new(&mut settings.contrast)
+                        .clamp_range(0.1..=5.0)
+                        .speed(0.01)
+                        .prefix("Contrast "),
+                );
+                if ui.button(tr("Reset")).clicked() {
+                    settings.gamma = 1.0;
+                    settings.contrast = 1.0;
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ComboBox::from_label(tr("Denoise"))
+                    .selected_text(settings.denoise.name())
+                    .show_ui(ui, |ui| {
+                        for denoise in Denoise::ALL {
+                            ui.selectable_value(&mut settings.denoise, denoise, denoise.name());
+                        }
+                    });
+                ui.add_enabled(
+                    settings.denoise != Denoise::Off,
+                    DragValue::new(&mut settings.denoise_kernel)
+                        .clamp_range(1..=31)
+                        .suffix(" px"),
+                );
+            })
+            .response
+            .on_hover_text("Blur before thresholding, to steady blobs on noisy streams");
+        });
+
+        ui.add(
+            DragValue::new(&mut settings.merge_radius)
+                .clamp_range(0.0..=100.0)
+                .speed(0.1)
+                .prefix("Merge within ")
+                .suffix(" px"),
+        )
+        .on_hover_text("Treat blobs this close together as one LED, 0 to keep them all");
+        ui.add(
+            DragValue::new(&mut settings.blob_area)
+                .clamp_range(0.0..=10_000.0)
+                .prefix("LED size ")
+                .suffix(" px²"),
+        )
+        .on_hover_text("Split blobs much bigger than this into several LEDs, 0 to never split");
+
+        ui.collapsing(tr("Advanced detection"), |ui| {
+            ui.add(
+                Slider::new(&mut settings.min_area, 0.0..=1_000.0)
+                    .logarithmic(true)
+                    .text("Min area (px²)"),
+            )
+            .on_hover_text("Drop blobs smaller than this, 0 to keep them all");
+            ui.add(
+                Slider::new(&mut settings.max_area, 0.0..=100_000.0)
+                    .logarithmic(true)
+                    .text("Max area (px²)"),
+            )
+            .on_hover_text("Drop blobs bigger than this, 0 for no limit");
+
+            ComboBox::from_label("Contours")
+                .selected_text(settings.retrieval.name())
+                .show_ui(ui, |ui| {
+                    for retrieval in Retrieval::ALL {
+                        ui.selectable_value(&mut settings.retrieval, retrieval, retrieval.name());
+                    }
+                });
+            ComboBox::from_label("Approximation")
+                .selected_text(settings.approximation.name())
+                .show_ui(ui, |ui| {
+                    for method in Approximation::ALL {
+                        ui.selectable_value(&mut settings.approximation, method, method.name());
+                    }
+                });
+        });
+
+        let paused = pipeline::PAUSED.load(Ordering::Relaxed);
+        if ui
+            .button(if paused {
+                "Resume detection"
+            } else {
+                "Pause detection"
+            })
+            .clicked()
+        {
+            pipeline::PAUSED.store(!paused, Ordering::Relaxed);
+        }
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.expect_count, "Expect");
+            ui.add_enabled(
+                self.expect_count,
+                DragValue::new(&mut self.expected_count)
+                    .clamp_range(0..=10_000)
+                    .suffix(" LEDs"),
+            );
+        });
+
+        ui.checkbox(&mut self.show_stats, tr("Show stats"));
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.show_labels, tr("Label LEDs"));
+            ui.add_enabled(
+                self.show_labels,
+                DragValue::new(&mut self.label_size)
+                    .clamp_range(6.0..=48.0)
+                    .suffix(" pt"),
+            );
+        });
+        ui.checkbox(&mut self.color_by_index, tr("Color LEDs by index"))
+            .on_hover_text("Blue at the start of the strip to red at the end");
+        ui.collapsing(tr("Markers"), |ui| self.marker_style.show(ui));
+        ui.collapsing(tr("Compare with old map"), |ui| self.show_ghost_settings(ui));
+        self.inset.show_settings(ui);
+        self.inspector.show_settings(ui);
+        self.heatmap.show_settings(ui);
+
+        ui.horizontal(|ui| {
+            if ui
+                .button(tr("Projector mode"))
+                .on_hover_text("Only the video and overlay, full screen. Escape leaves it.")
+                .clicked()
+            {
+                self.set_fullscreen(ui.ctx(), true);
+            }
+
+            let mut pick = self.monitor.is_some();
+            ui.checkbox(&mut pick, "on monitor at");
+            let monitor = self.monitor.get_or_insert(Pos2::ZERO);
+            ui.add_enabled(pick, DragValue::new(&mut monitor.x).prefix("x "));
+            ui.add_enabled(pick, DragValue::new(&mut monitor.y).prefix("y "));
+            if !pick {
+                self.monitor = None;
+            }
+        });
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            let mut system = self.ui_scale.is_none();
+            ui.label(tr("UI scale"));
+            ui.checkbox(&mut system, tr("System"));
+            let scale = self.ui_scale.get_or_insert(ui.ctx().pixels_per_point());
+            ui.add_enabled(
+                !system,
+                DragValue::new(scale)
+                    .clamp_range(0.5..=4.0)
+                    .speed(0.01)
+                    .suffix("x"),
+            );
+            if system {
+                self.ui_scale = None;
             }
         });
+        ui.checkbox(&mut self.dock_settings, tr("Dock this panel"));
+
+        // Copied out, as the decoders read it every frame
+        let mut preview = *preview::SETTINGS.read().unwrap();
+        ui.horizontal(|ui| {
+            ui.label(tr("Preview"))
+                .on_hover_text("Update the video less often and show it smaller, for slow links");
+            ui.add(
+                DragValue::new(&mut preview.max_fps)
+                    .clamp_range(0.0..=60.0)
+                    .speed(0.1)
+                    .custom_formatter(|fps, _| match fps {
+                        0.0 => "every frame".to_owned(),
+                        fps => format!("{fps:.1} fps"),
+                    }),
+            );
+            ui.add(
+                DragValue::new(&mut preview.downscale)
+                    .clamp_range(1..=8)
+                    .prefix("1/"),
+            );
+        });
+        if preview != *preview::SETTINGS.read().unwrap() {
+            *preview::SETTINGS.write().unwrap() = preview;
+        }
+
+        // Copied out, as everything drawn here reads it through `tr`
+        let mut language = *i18n::LANGUAGE.read().unwrap();
+        ComboBox::from_label(tr("Language"))
+            .selected_text(language.name())
+            .show_ui(ui, |ui| {
+                for choice in Language::ALL {
+                    ui.selectable_value(&mut language, choice, choice.name());
+                }
+            });
+        if language != *i18n::LANGUAGE.read().unwrap() {
+            *i18n::LANGUAGE.write().unwrap() = language;
+        }
+    }
+
+    /// Picking a capture device, or going back to the source given on the command line.
+    fn show_source(&mut self, ui: &mut egui::Ui) {
+        const COMMAND_LINE: &str = "From the command line";
+
+        let devices = v4l2::DEVICES.read().unwrap().clone();
+        let name = |path: &PathBuf| match devices.iter().find(|device| &device.path == path) {
+            Some(device) => format!("{} ({})", device.name, path.display()),
+            None => path.display().to_string(),
+        };
+
+        let mut device = self.stream.device.clone();
+        ComboBox::from_label(tr("Camera"))
+            .selected_text(device.as_ref().map_or(COMMAND_LINE.to_owned(), name))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut device, None, COMMAND_LINE);
+                for available in &devices {
+                    let path = Some(available.path.clone());
+                    ui.selectable_value(&mut device, path, name(&available.path));
+                }
+            });
+        if devices.is_empty() {
+            ui.weak("No capture devices plugged in");
+        }
+        ui.weak("Detection settings are kept for each camera");
+
+        let current_size: Option<[usize; 2]> = self
+            .stream
+            .device_size
+            .as_deref()
+            .and_then(|size| size.try_into().ok());
+        let mut size = current_size;
+        let sizes = devices
+            .iter()
+            .find(|available| device.as_ref() == Some(&available.path))
+            .map(|available| &available.sizes[..])
+            .unwrap_or_default();
+        if !sizes.is_empty() {
+            let text = |size: Option<[usize; 2]>| match size {
+                Some([width, height]) => format!("{width}x{height}"),
+                None => "Largest".to_owned(),
+            };
+            ComboBox::from_label(tr("Size"))
+                .selected_text(text(size))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut size, None, text(None));
+                    for &option in sizes {
+                        ui.selectable_value(&mut size, Some(option), text(Some(option)));
+                    }
+                });
+        }
+
+        // Another device doesn't necessarily do the size picked for the last one
+        if device != self.stream.device {
+            size = None;
+        } else if size == current_size {
+            return;
+        }
+
+        self.stream.device = device;
+        self.stream.device_size = size.map(Vec::from);
+        self.switch_source();
+    }
+
+    /// Stops the decoder and starts another on `stream`, remembering the device for next time
+    /// and swapping in the detection settings last used with it.
+    fn switch_source(&mut self) {
+        pipeline::switch_source();
+        self.workers
+            .extend(self.stream.spawn_decoder(Some(self.image.clone())));
+
+        let profile = self.stream.profile_key();
+        let mut settings = SETTINGS.write().unwrap();
+        let old = std::mem::replace(&mut self.profile, profile);
+        self.profiles.insert(old.clone(), settings.clone());
+        if let Some(saved) = self.profiles.get(&self.profile) {
+            info!("Using the detection settings last used with {}", self.profile);
+            *settings = saved.clone();
+        }
+        drop(settings);
+
+        let mut scan_settings = scan::SETTINGS.write().unwrap();
+        self.scan_profiles
+            .insert(old.clone(), scan_settings.clone());
+        if let Some(saved) = self.scan_profiles.get(&self.profile) {
+            *scan_settings = saved.clone();
+        }
+        drop(scan_settings);
+
+        let mut lens = lens::LENS.write().unwrap();
+        remember_lens(&mut self.lenses, old, *lens);
+        *lens = self.lenses.get(&self.profile).copied();
+        drop(lens);
+
+        let Ok(dir) = std::env::current_dir() else {
+            return;
+        };
+        let devices = v4l2::DEVICES.read().unwrap();
+        let device = self
+            .stream
+            .device
+            .as_ref()
+            .and_then(|path| devices.iter().find(|device| &device.path == path));
+        match device {
+            Some(device) => {
+                let size = self.stream.device_size.as_deref();
+                self.devices.insert(dir, v4l2::Remembered {
+                    name: device.name.clone(),
+                    path: device.path.clone(),
+                    size: size.and_then(|size| size.try_into().ok()),
+                });
+            }
+            None => {
+                self.devices.remove(&dir);
+            }
+        }
+    }
+
+    /// Opens a file dropped onto the window by its extension: videos and images become the
+    /// source, maps are shown over the video to compare with, and Rhai files become the scan
+    /// script.
+    fn open_dropped(&mut self, path: &Path) {
+        if path.join(recording::STEPS).is_file() {
+            self.open_recording(path);
+            return;
+        }
+
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+
+        match extension.as_str() {
+            "json" => {
+                self.ghost_path = path.display().to_string();
+                self.load_ghost();
+                info!("{}: {}", path.display(), self.ghost_status);
+            }
+            "rhai" => match std::fs::read_to_string(path) {
+                Ok(script) => {
+                    info!("Using {} as the scan script", path.display());
+                    self.scan_script = script;
+                    self.use_scan_script = true;
+                }
+                Err(e) => toasts::error(format!("Failed to read {}: {e}", path.display()), None),
+            },
+            extension if DROPPED_MEDIA.contains(&extension) => {
+                let url = std::fs::canonicalize(path)
+                    .ok()
+                    .and_then(|path| video_rs::Url::from_file_path(path).ok());
+                let Some(url) = url else {
+                    toasts::error(format!("Can't open {}", path.display()), None);
+                    return;
+                };
+
+                info!("Reading frames from {}", path.display());
+                self.stream.url = url;
+                self.stream.device = None;
+                self.stream.rpicam = None;
+                self.stream.simulate = false;
+                #[cfg(feature = "realsense")]
+                {
+                    self.stream.realsense = false;
+                }
+                self.switch_source();
+            }
+            _ => toasts::error(format!("Don't know how to open {}", path.display()), None),
+        }
+    }
+
+    fn open_recording(&mut self, directory: &Path) {
+        match playback::Playback::open(directory) {
+            Ok(playback) => {
+                info!("Playing back the recording in {}", directory.display());
+                self.playback = Some(playback);
+            }
+            Err(e) => toasts::error(format!("{e:#}"), None),
+        }
+    }
+
+    /// Maps the captured LEDs, the ones clicked in order first and the rest after as found.
+    fn apply_hand_order(&self) {
+        let rest = (0..self.captured.len()).filter(|index| !self.clicked.contains(index));
+        *scan::MAP.write().unwrap() = self
+            .clicked
+            .iter()
+            .copied()
+            .chain(rest)
+            .map(|index| Some(self.captured[index]))
+            .collect();
+    }
+
+    /// Orders the captured LED nearest `pos` next, if one that isn't ordered yet is within
+    /// `radius`.
+    fn click_captured(&mut self, pos: Pos2, radius: f32) {
+        let nearest = (0..self.captured.len())
+            .filter(|index| !self.clicked.contains(index))
+            .map(|index| (index, self.captured[index].distance(pos)))
+            .filter(|&(_, distance)| distance <= radius)
+            .min_by(|a, b| a.1.total_cmp(&b.1));
+        let Some((index, _)) = nearest else {
+            return;
+        };
+
+        self.clicked.push(index);
+        self.apply_hand_order();
+        if self.clicked.len() == self.captured.len() {
+            info!("Ordered all {} captured LEDs by hand", self.captured.len());
+            self.ordering_by_hand = false;
+        }
+    }
+
+    fn save_snapshot(&mut self) {
+        let leds = led_positions();
+        let overlay = self.snapshot_overlay.then_some(&leds[..]);
+        let path = PathBuf::from(&self.export_path).with_extension("png");
+        self.export_status = match snapshot::save(&path, overlay) {
+            Ok(()) => format!("Saved {}", path.display()),
+            Err(e) => format!("Failed to save {}: {e}", path.display()),
+        };
+        info!("{}", self.export_status);
+    }
+
+    /// Puts the current detections, or the map as it would be exported, on the clipboard.
+    fn copy_leds(&mut self, ctx: &egui::Context, detections: bool, format: ExportFormat) {
+        let leds = if detections {
+            Ok(detected_leds())
+        } else {
+            export_leds(self.snap_to_grid, &self.transform)
+        };
+
+        self.export_status = match leds.and_then(|leds| Ok((leds.len(), format.to_text(&leds)?))) {
+            Ok((count, text)) => {
+                ctx.output_mut(|output| output.copied_text = text);
+                format!("Copied {count} LEDs as {}", format.name())
+            }
+            Err(e) => format!("Failed to copy: {e}"),
+        };
+    }
+
+    /// Connects to every segment, replacing the controller there was.
+    fn connect(&mut self) {
+        match segments::connect(&self.segments) {
+            Ok(controller) => {
+                info!("Connected {} segments", self.segments.len());
+                let controller: SharedController = Arc::new(Mutex::new(controller));
+                *scan::CONTROLLER.write().unwrap() = Some(controller.clone());
+                self.controller = Some(controller);
+                self.controller_status = "Connected".to_owned();
+            }
+            Err(e) => {
+                warn!("{e:#}");
+                self.controller_status = format!("{e:#}");
+            }
+        }
+    }
+
+    /// Takes the pixel orders a detection found into the segments, reconnecting if any changed.
+    fn apply_pixel_orders(&mut self, orders: &[Option<PixelOrder>]) {
+        let mut changed = Vec::new();
+        for (segment, order) in self.segments.iter_mut().zip(orders) {
+            if let Some(order) = order.filter(|&order| order != segment.config.pixel_order) {
+                segment.config.pixel_order = order;
+                changed.push(format!("{} to {}", segment.name, order.name()));
+            }
+        }
+
+        let unknown = orders.iter().filter(|order| order.is_none()).count();
+        if !changed.is_empty() {
+            self.connect();
+        }
+        self.controller_status = match (changed.is_empty(), unknown) {
+            (true, 0) => "Pixel order already right".to_owned(),
+            (false, 0) => format!("Pixel order set, {}", changed.join(", ")),
+            (_, unknown) => format!(
+                "Couldn't tell the pixel order of {unknown} segments, see the log{}",
+                changed
+                    .iter()
+                    .map(|changed| format!(", set {changed}"))
+                    .collect::<String>()
+            ),
+        };
+    }
+
+    fn set_fullscreen(&mut self, ctx: &egui::Context, fullscreen: bool) {
+        if let Some(position) = self.monitor.filter(|_| fullscreen) {
+            ctx.send_viewport_cmd(ViewportCommand::OuterPosition(position));
+        }
+        ctx.send_viewport_cmd(ViewportCommand::Fullscreen(fullscreen));
+        self.fullscreen = fullscreen;
+    }
+
+    /// The stream, detection and controller at a glance, along the bottom of the window.
+    fn show_status_bar(&mut self, ctx: &egui::Context) {
+        self.rates.update();
 
-        thread::spawn({
-            move || {
-                loop {
-                    thread::sleep(Duration::from_millis(100));
+        TopBottomPanel::bottom("status").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                let frozen = freeze::frozen();
+                let (color, state) = match stats::frame_time().map(|time| time.elapsed()) {
+                    None => (Color32::GRAY, "Waiting for"),
+                    Some(age) if age > STREAM_STALLED => (Color32::RED, "Stalled"),
+                    Some(_) if frozen.is_some() => (Color32::RED, "Frozen"),
+                    Some(_) => (Color32::GREEN, "Receiving"),
+                };
+                ui.colored_label(color, "⏺");
+                let label = ui.label(format!("{state} {}", self.profile));
+                if let Some((cause, since)) = frozen {
+                    label.on_hover_text(format!(
+                        "Frames keep coming, but {} {} seconds ago. Scanning now would put \
+                         every LED in the same spot.",
+                        cause.describe(),
+                        since.elapsed().as_secs()
+                    ));
+                }
+                ui.separator();
 
-                    let image_data = IMAGE.read().unwrap().clone();
+                ui.label(format!(
+                    "{:.1} fps decoded, {:.1} detected",
+                    self.rates.decode_fps, self.rates.detection_fps
+                ));
+                ui.separator();
 
-                    let image = {
-                        let width = IMAGE_WIDTH.load(Ordering::Relaxed);
+                let count = POINTS.read().unwrap().len();
+                if pipeline::PAUSED.load(Ordering::Relaxed) {
+                    ui.colored_label(Color32::YELLOW, "Detection paused");
+                } else if self.expect_count {
+                    let color = match count == self.expected_count {
+                        true => ui.visuals().text_color(),
+                        false => Color32::RED,
+                    };
+                    ui.colored_label(color, format!("{count} of {} LEDs", self.expected_count));
+                } else {
+                    ui.label(format!("{count} LEDs"));
+                }
+                ui.separator();
 
-                        if width == 0 {
-                            continue;
+                match &self.controller {
+                    Some(_) => {
+                        let mut kinds = Vec::new();
+                        for segment in &self.segments {
+                            let kind = segment.config.kind.name();
+                            if !kinds.contains(&kind) {
+                                kinds.push(kind);
+                            }
                         }
+                        let leds = self
+                            .segments
+                            .iter()
+                            .map(|s| s.config.led_count)
+                            .sum::<usize>();
+                        ui.label(format!("{} with {leds} LEDs", kinds.join(", ")));
+                    }
+                    None => {
+                        ui.colored_label(Color32::GRAY, "No controller");
+                    }
+                }
+                let errors = stats::CONTROLLER_ERRORS.load(Ordering::Relaxed);
+                if errors > 0 {
+                    ui.colored_label(Color32::RED, format!("{errors} failed sends"));
+                }
+            });
+        });
 
-                        unsafe {
-                            Mat::new_rows_cols_with_data(
-                                (image_data.len() / width / 3) as i32,
-                                width as i32,
-                                CV_8UC3,
-                                image_data.as_ptr() as *mut _,
-                                Mat_AUTO_STEP,
-                            )
-                            .unwrap()
+        // Otherwise a stream that stops would stop the repaints that would show it
+        ctx.request_repaint_after(Duration::from_secs(1));
+    }
+
+    fn show_log(&self, ctx: &egui::Context) {
+        TopBottomPanel::bottom("log").show(ctx, |ui| {
+            CollapsingHeader::new(tr("Log")).show(ui, |ui| {
+                ScrollArea::vertical()
+                    .max_height(200.0)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for line in logging::LOG.lock().unwrap().iter() {
+                            let color = match line.level {
+                                Level::ERROR => Color32::RED,
+                                Level::WARN => Color32::YELLOW,
+                                _ => ui.visuals().text_color(),
+                            };
+
+                            ui.colored_label(
+                                color,
+                                format!(
+                                    "[{:8.3}] {:>5} {}: {}",
+                                    line.time.as_secs_f32(),
+                                    line.level,
+                                    line.target,
+                                    line.message
+                                ),
+                            );
                         }
-                    };
+                    });
+            });
+        });
+    }
 
-                    let mut hsv_image = Mat::default();
-                    cvt_color(&image, &mut hsv_image, COLOR_RGB2HSV, 0).unwrap();
-                    drop((image, image_data));
-
-                    let settings = unsafe { &SETTINGS };
-                    let lower_green =
-                        Scalar::new(settings.lower_h, settings.lower_s, settings.lower_v, 0.0);
-                    let upper_green =
-                        Scalar::new(settings.upper_h, settings.upper_s, settings.upper_v, 0.0);
-
-                    // Threshold the HSV image to get only green colors
-                    let mut mask = Mat::default();
-                    in_range(&hsv_image, &lower_green, &upper_green, &mut mask).unwrap();
-
-                    // Find contours
-                    let mut contours = Vector::<Vector<Point>>::new();
-                    find_contours(
-                        &mask,
-                        &mut contours,
-                        RETR_EXTERNAL,
-                        CHAIN_APPROX_SIMPLE,
-                        Default::default(),
-                    )
-                    .unwrap();
+    /// Offers to restore the session a crash left behind.
+    fn show_recovery(&mut self, ctx: &egui::Context) {
+        let Some(session) = &self.recovery else {
+            return;
+        };
 
-                    *POINTS.write().unwrap() = contours
-                        .iter()
-                        .map(|contour| {
-                            let moments = moments(&contour, false).unwrap();
+        let mut choice = None;
+        Window::new(tr("Recover session"))
+            .id(Id::new("Recover session"))
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, Vec2::ZERO)
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "The last session didn't close cleanly. Its autosave from {} has {} of {} \
+                     LEDs mapped.",
+                    ptz::timestamp(session.saved),
+                    session.map.iter().flatten().count(),
+                    session.map.len()
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button(tr("Restore")).clicked() {
+                        choice = Some(true);
+                    }
+                    if ui.button(tr("Discard")).clicked() {
+                        choice = Some(false);
+                    }
+                });
+            });
+
+        match choice {
+            Some(true) => {
+                if let Some(session) = self.recovery.take() {
+                    info!("Restored the session saved at {}", ptz::timestamp(session.saved));
+                    self.segments = session.restore();
+                }
+            }
+            Some(false) => {
+                self.recovery = None;
+                autosave::discard();
+            }
+            None => {}
+        }
+    }
 
-                            // // Calculate area
-                            // let area = contour_area(&contour, false).unwrap();
+    fn show_toasts(&mut self, ctx: &egui::Context) {
+        let mut retry = None;
 
-                            // Calculate bounding rectangle
-                            let rect = bounding_rect(&contour).unwrap();
-                            let size = rect.size();
+        Area::new("toasts")
+            .anchor(Align2::RIGHT_TOP, [-10.0, 10.0])
+            .order(Order::Foreground)
+            .show(ctx, |ui| {
+                TOASTS.lock().unwrap().retain(|toast| {
+                    let mut keep = true;
 
-                            Rect::from_center_size(
-                                Pos2::new(
-                                    (moments.m10 / moments.m00) as f32,
-                                    (moments.m01 / moments.m00) as f32,
-                                ),
-                                Vec2::new(size.width as f32, size.height as f32),
-                            )
-                        })
-                        .filter(|rect| rect.is_finite())
-                        .collect::<Vec<_>>();
+                    Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.set_max_width(300.0);
+                        ui.colored_label(Color32::RED, &toast.message);
+
+                        ui.horizontal(|ui| {
+                            if let Some(action) = toast.retry {
+                                if ui.button(tr("Retry")).clicked() {
+                                    retry = Some(action);
+                                    keep = false;
+                                }
+                            }
+                            if ui.button(tr("Dismiss")).clicked() {
+                                keep = false;
+                            }
+                        });
+                    });
+
+                    keep
+                });
+            });
+
+        match retry {
+            Some(Retry::Stream) => {
+                let decoder = self.stream.spawn_decoder(Some(self.image.clone()));
+                self.workers.extend(decoder);
+            }
+            Some(Retry::Scan) => send(scan::Command::Rescan),
+            None => {}
+        }
+    }
+}
+
+/// Sets the gains so `region` of the latest frame comes out gray.
+fn white_balance(region: Rect) {
+    let Some((frame, width)) = pipeline::latest_rgb() else {
+        warn!("No frame to white balance on yet");
+        return;
+    };
+
+    match led_detect::preprocess::white_balance(&frame, width, region) {
+        Some(gains) => {
+            info!("White balanced to gains {gains:.2?}");
+            SETTINGS.write().unwrap().gains = gains;
+        }
+        None => warn!("Nothing to white balance on in that region"),
+    }
+}
+
+/// Makes `region` of the latest frame the template for `DetectorKind::Template`.
+fn capture_template(region: Rect) {
+    let Some((frame, width)) = pipeline::latest_rgb() else {
+        warn!("No frame to capture a template from yet");
+        return;
+    };
+
+    match led_detect::template::Template::capture(&frame, width, region) {
+        Some(template) => {
+            info!("Captured a {}x{} template", template.width, template.height());
+            SETTINGS.write().unwrap().template = Some(template);
+        }
+        None => warn!(
+            "Nothing to use as a template there, it takes a lit LED at most {} pixels across",
+            led_detect::template::MAX_SIZE
+        ),
+    }
+}
+
+/// Timing and colors of the scans, kept for each camera like the detection settings.
+fn show_scan_settings(ui: &mut egui::Ui) {
+    let mut settings = scan::SETTINGS.write().unwrap();
+
+    let mut settle = settings.settle.as_millis() as u64;
+    ui.add(
+        DragValue::new(&mut settle)
+            .clamp_range(0..=5000)
+            .prefix("Settle time: ")
+            .suffix(" ms"),
+    )
+    .on_hover_text("How long to wait after lighting an LED, unless the latency was measured");
+    settings.settle = Duration::from_millis(settle);
+
+    let mut on_time = settings.on_time.as_millis() as u64;
+    ui.add(
+        DragValue::new(&mut on_time)
+            .clamp_range(0..=5000)
+            .prefix("On time: ")
+            .suffix(" ms"),
+    )
+    .on_hover_text("How long each LED stays lit at least, for long exposures");
+    settings.on_time = Duration::from_millis(on_time);
+
+    ui.horizontal(|ui| {
+        ui.add_enabled_ui(!settings.white, |ui| {
+            ui.label(tr("Color"));
+            ui.color_edit_button_srgb(&mut settings.color);
+        });
+        ui.checkbox(&mut settings.white, tr("White channel"))
+            .on_hover_text(
+                "Light only the white channel of RGBW and white-only strips instead of the color. \
+             Strips without one show gray.",
+            );
+    });
+    ui.add(Slider::new(&mut settings.brightness, 0.01..=1.0).text("Brightness"))
+        .on_hover_text("Of everything the scans light, lower it when LEDs bloom into each other");
+
+    ui.horizontal(|ui| {
+        let mut second = settings.second_brightness.is_some();
+        if ui
+            .checkbox(&mut second, tr("Second capture"))
+            .on_hover_text(
+                "Capture each LED of a sequential scan dimmed too, to place those near the camera \
+                 that bloom at full brightness while still finding those far away",
+            )
+            .changed()
+        {
+            settings.second_brightness = second.then_some(0.2);
+        }
+        if let Some(share) = &mut settings.second_brightness {
+            ui.add(Slider::new(share, 0.01..=1.0).text("of the color"));
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ComboBox::from_label(tr("Order"))
+            .selected_text(settings.order.name())
+            .show_ui(ui, |ui| {
+                for order in ScanOrder::FIXED {
+                    let name = order.name();
+                    ui.selectable_value(&mut settings.order, order, name);
                 }
+                let custom = matches!(settings.order, ScanOrder::Custom(_));
+                if ui.selectable_label(custom, "Custom list").clicked() && !custom {
+                    settings.order = ScanOrder::Custom(String::new());
+                }
+            })
+            .response
+            .on_hover_text(
+                "Which LEDs the scans that light one at a time go through, in which order. Random \
+                 spreads drift in the camera or lighting over the whole map",
+            );
+    });
+    if let ScanOrder::Custom(list) = &mut settings.order {
+        ui.add(TextEdit::singleline(list).hint_text("0-49, 100, 199-150"));
+        if let Err(e) = scan::parse_list(list) {
+            ui.colored_label(Color32::LIGHT_RED, format!("{e:#}"));
+        }
+    }
+
+    ui.horizontal(|ui| {
+        ui.add(
+            DragValue::new(&mut settings.group_size)
+                .clamp_range(2..=100)
+                .prefix("Group size: "),
+        )
+        .on_hover_text("How many LEDs a grouped scan lights at once at most");
+        ui.add(
+            DragValue::new(&mut settings.group_separation)
+                .clamp_range(10.0..=1000.0)
+                .prefix("apart: ")
+                .suffix(" px"),
+        )
+        .on_hover_text(
+            "How far apart the LEDs a grouped scan lights together have to be, which lets each \
+             move half of it since the prior map or last scan",
+        );
+    });
+
+    ComboBox::from_label(tr("If the camera moves"))
+        .selected_text(tr(settings.drift.name()))
+        .show_ui(ui, |ui| {
+            for drift in DriftAction::ALL {
+                ui.selectable_value(&mut settings.drift, drift, tr(drift.name()));
+            }
+        })
+        .response
+        .on_hover_text(
+            "What to do when the camera gets bumped during a scan, going by the scene around \
+             the LEDs. Stopping keeps the scan resumable once the camera is back",
+        );
+
+    if *settings != ScanSettings::DEFAULT && ui.button(tr("Reset")).clicked() {
+        *settings = ScanSettings::DEFAULT;
+    }
+}
+
+/// Editor for where a sACN segment's LEDs are in the DMX universes.
+fn show_addressing(ui: &mut egui::Ui, addressing: &mut Vec<DmxRange>) {
+    CollapsingHeader::new(tr("Addressing")).show(ui, |ui| {
+        if addressing.is_empty() {
+            ui.label("Packed from universe 1, channel 1, in RGB");
+        }
+
+        let mut remove = None;
+        egui::Grid::new("addressing").show(ui, |ui| {
+            if !addressing.is_empty() {
+                ui.label("From LED");
+                ui.label("Universe");
+                ui.label("Channel");
+                ui.label(tr("Order"));
+                ui.end_row();
+            }
+
+            for (i, range) in addressing.iter_mut().enumerate() {
+                ui.add(DragValue::new(&mut range.first_led));
+                ui.add(DragValue::new(&mut range.universe).clamp_range(1..=63_999));
+                ui.add(DragValue::new(&mut range.channel).clamp_range(1..=512));
+                ComboBox::from_id_source(("order", i))
+                    .selected_text(range.order.name())
+                    .show_ui(ui, |ui| {
+                        for order in ChannelOrder::ALL {
+                            ui.selectable_value(&mut range.order, order, order.name());
+                        }
+                    });
+                if ui.button(tr("Remove")).clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
             }
         });
 
-        Self { image }
+        if let Some(i) = remove {
+            addressing.remove(i);
+        }
+
+        if ui.button("Add range").clicked() {
+            // Carry on in the universe after the last range, which is the usual next fixture
+            let range = match addressing.last() {
+                Some(last) => DmxRange {
+                    first_led: last.first_led + 1,
+                    universe: last.universe + 1,
+                    ..last.clone()
+                },
+                None => DmxRange::default(),
+            };
+            addressing.push(range);
+        }
+    });
+}
+
+/// Sends `command` to the scan, logging why when it can't be carried out right now.
+fn send(command: scan::Command) {
+    if let Err(e) = scan::command(command) {
+        warn!("{e}");
+    }
+}
+
+/// A located LED, in camera space: x right, y down, z away from the camera.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Led {
+    /// Which segment of the project the LED is on, see `segments`. Maps from before segments
+    /// existed are all one segment.
+    #[serde(default)]
+    segment: usize,
+    /// Index within the segment.
+    index: usize,
+    position: [f32; 3],
+}
+
+/// How long after sending colors the LEDs show them in the video, and whether that was measured.
+fn video_latency() -> (Duration, bool) {
+    match *scan::MEASURED_LATENCY.read().unwrap() {
+        Some(measured) => (measured, true),
+        None => (scan::SETTINGS.read().unwrap().settle, false),
+    }
+}
+
+/// Current LED positions as shown in the 3D preview and written by the exporters: the scanned map
+/// if there is one, the live detections otherwise. There is no reconstruction yet, so every LED
+/// sits on the z=0 plane.
+fn led_positions() -> Vec<Led> {
+    let leds = scan::leds();
+
+    if !leds.is_empty() {
+        leds
+    } else {
+        detected_leds()
+    }
+}
+
+/// What the exporters get: `led_positions`, placed in 3D with a stereo pair or depth camera or optionally
+/// snapped to a grid, then transformed.
+fn export_leds(snap_to_grid: bool, transform: &Transform) -> anyhow::Result<Vec<Led>> {
+    let mut leds = depth::to_3d(&stereo::to_3d(&led_positions()));
+    if snap_to_grid {
+        leds = grid::fit(&leds)?.leds;
+    }
+    Ok(transform.apply(&leds))
+}
+
+/// The camera's angle of view, entered or from its ONVIF lens description.
+fn show_lens(ui: &mut egui::Ui) {
+    let mut lens = lens::LENS.write().unwrap();
+
+    ui.horizontal(|ui| {
+        let mut known = lens.is_some();
+        if ui
+            .checkbox(&mut known, tr("Angle of view"))
+            .on_hover_text(
+                "The camera's horizontal angle of view, standing in for a calibration for the \
+                 stereo pair and for exporting at a distance",
+            )
+            .changed()
+        {
+            *lens = known.then_some(Lens { fov: 60.0 });
+        }
+        if let Some(lens) = lens.as_mut() {
+            ui.add(
+                DragValue::new(&mut lens.fov)
+                    .speed(0.1)
+                    .clamp_range(1.0..=170.0)
+                    .suffix("°"),
+            );
+        }
+    });
+
+    if let Some(lens) = lens.as_mut() {
+        ui.horizontal(|ui| {
+            ui.label(tr("Focal length"));
+            ui.add(
+                DragValue::from_get_set(|value| {
+                    if let Some(value) = value {
+                        *lens = Lens::from_focal_length(value as f32, lens::FULL_FRAME_WIDTH);
+                    }
+                    lens.equivalent_focal_length() as f64
+                })
+                .speed(0.1)
+                .clamp_range(1.0..=2000.0)
+                .suffix(" mm"),
+            )
+            .on_hover_text("As a 35 mm equivalent");
+        });
+        if let Some((focal_length, _)) = lens::intrinsics() {
+            ui.label(format!("{focal_length:.0} pixels at the current resolution"));
+        }
+    }
+
+    let camera = ptz::CAMERA.read().unwrap().clone();
+    if ui
+        .add_enabled(camera.is_some(), Button::new("From camera"))
+        .on_hover_text("Take it from the lens description of the PTZ camera, which few give")
+        .on_disabled_hover_text("Connect to the camera under PTZ first")
+        .clicked()
+    {
+        match camera.and_then(|camera| camera.lens()) {
+            Some(found) => {
+                info!("The camera gives an angle of view of {:.1}°", found.fov);
+                *lens = Some(found);
+            }
+            None => toasts::error("The camera doesn't describe its lens", None),
+        }
     }
 }
 
+/// Keeps `lens` as the angle of view of `profile`, or forgets it.
+fn remember_lens(lenses: &mut HashMap<String, Lens>, profile: String, lens: Option<Lens>) {
+    match lens {
+        Some(lens) => lenses.insert(profile, lens),
+        None => lenses.remove(&profile),
+    };
+}
+
+/// The live detections, numbered in whatever order they were found.
+fn detected_leds() -> Vec<Led> {
+    POINTS
+        .read()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .map(|(index, rect)| Led {
+            segment: 0,
+            index,
+            position: [rect.center().x, rect.center().y, 0.0],
+        })
+        .collect()
+}
+
 impl eframe::App for CalibratorApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.keymap.save(storage);
+        eframe::set_value(storage, UI_SCALE_KEY, &self.ui_scale);
+        eframe::set_value(storage, LANGUAGE_KEY, &*i18n::LANGUAGE.read().unwrap());
+        eframe::set_value(storage, DOCK_SETTINGS_KEY, &self.dock_settings);
+        eframe::set_value(storage, MARKER_STYLE_KEY, &self.marker_style);
+        eframe::set_value(storage, DEVICES_KEY, &self.devices);
+        let settings = SETTINGS.read().unwrap().clone();
+        self.profiles.insert(self.profile.clone(), settings);
+        eframe::set_value(storage, PROFILES_KEY, &self.profiles);
+        let scan_settings = scan::SETTINGS.read().unwrap().clone();
+        self.scan_profiles
+            .insert(self.profile.clone(), scan_settings);
+        eframe::set_value(storage, SCAN_PROFILES_KEY, &self.scan_profiles);
+        let lens = *lens::LENS.read().unwrap();
+        remember_lens(&mut self.lenses, self.profile.clone(), lens);
+        eframe::set_value(storage, LENSES_KEY, &self.lenses);
+        self.projects.sync(&self.export_path);
+        eframe::set_value(storage, PROJECTS_KEY, &self.projects);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        let scanning = scan::RUNNING.load(Ordering::SeqCst);
+
+        scan::stop();
+        pipeline::shutdown();
+        // Unless the last crash's session is still waiting to be restored
+        if self.recovery.is_none() {
+            autosave::discard();
+        }
+
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+        if !scan::join_until(deadline) {
+            warn!("The scan thread didn't stop within {SHUTDOWN_TIMEOUT:?}");
+        }
+        for handle in self.workers.drain(..) {
+            if !pipeline::join_until(handle, deadline) {
+                warn!("A worker thread didn't stop within {SHUTDOWN_TIMEOUT:?}");
+            }
+        }
+
+        // Keep what an interrupted scan found so far
+        if scanning {
+            let path = PathBuf::from(&self.export_path).with_extension("partial.json");
+            match ExportFormat::Json.write(&path, &scan::leds()) {
+                Ok(()) => info!("Saved the interrupted scan to {}", path.display()),
+                Err(e) => error!("Failed to save the interrupted scan to {}: {e}", path.display()),
+            }
+        }
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        ctx.set_pixels_per_point(1.);
+        // Follows the system unless set, which also covers moving to a monitor with another scale
+        let scale = self
+            .ui_scale
+            .or(ctx.native_pixels_per_point())
+            .unwrap_or(1.0);
+        if ctx.pixels_per_point() != scale {
+            ctx.set_pixels_per_point(scale);
+        }
+
+        // A scan that just stopped may have left a journal behind, or cleaned one up
+        let scanning = scan::RUNNING.load(Ordering::Relaxed);
+        if self.was_scanning && !scanning {
+            self.interrupted = interrupted_scan();
+        }
+        // Whoever started it, the LEDs are the scan's now
+        if !self.was_scanning && scanning {
+            self.verify_started = None;
+        }
+        self.was_scanning = scanning;
+
+        if self.recovery.is_none() {
+            self.autosave.tick(&self.segments);
+        }
+
+        if let Some(orders) = pixel_order::DETECTED.lock().unwrap().take() {
+            self.apply_pixel_orders(&orders);
+        }
+
+        for action in self.keymap.pressed(ctx, false) {
+            match action {
+                Action::StartScan => send(scan::Command::Start(self.scan_mode())),
+                Action::StopScan => send(scan::Command::Stop),
+                Action::PauseDetection => {
+                    pipeline::PAUSED.fetch_xor(true, Ordering::Relaxed);
+                }
+                Action::Snapshot => self.save_snapshot(),
+                Action::Fullscreen => self.set_fullscreen(ctx, !self.fullscreen),
+                Action::ToggleStats => self.show_stats = !self.show_stats,
+                Action::CopyMap => self.copy_leds(ctx, false, ExportFormat::Json),
+                Action::ToggleMask => self.inset.toggle_mask(),
+                Action::Undo => self.review.undo(),
+                // The review window takes these itself
+                Action::NextLed
+                | Action::PrevLed
+                | Action::NextRow
+                | Action::PrevRow
+                | Action::AcceptLed
+                | Action::RejectLed => {}
+            }
+        }
+        for file in ctx.input(|i| i.raw.dropped_files.clone()) {
+            if let Some(path) = file.path {
+                self.open_dropped(&path);
+            }
+        }
+        if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            let painter = ctx.layer_painter(LayerId::new(Order::Foreground, Id::new("drop")));
+            let screen = ctx.screen_rect();
+            painter.rect_filled(screen, 0.0, Color32::from_black_alpha(160));
+            painter.text(
+                screen.center(),
+                Align2::CENTER_CENTER,
+                "Drop a video, image, map or scan script to open it",
+                FontId::proportional(24.0),
+                Color32::WHITE,
+            );
+        }
+
+        if self.fullscreen && ctx.input(|i| i.key_pressed(Key::Escape)) {
+            self.set_fullscreen(ctx, false);
+        }
+
+        if !self.fullscreen {
+            let switched = TopBottomPanel::top("projects")
+                .show(ctx, |ui| {
+                    self.projects
+                        .show(ui, &mut self.segments, &mut self.export_path)
+                })
+                .inner;
+            // The other project's segments, on the same hardware or not
+            if switched && self.controller.is_some() {
+                self.connect();
+            }
+
+            self.show_status_bar(ctx);
+            self.show_log(ctx);
+            self.show_toasts(ctx);
+            self.show_recovery(ctx);
+
+            if self.show_stats {
+                self.stats.show(ctx);
+            }
+
+            if self.dock_settings {
+                SidePanel::right("settings").show(ctx, |ui| {
+                    ScrollArea::vertical().show(ui, |ui| self.show_settings(ui));
+                });
+            }
+        }
+
+        // What the panels leave
+        let video_rect = ctx.available_rect();
+        // In frame pixels even when the preview is smaller, so the overlay lines up
+        let frame = pipeline::frame_size().map_or(self.image.size_vec2(), |[width, height]| {
+            Vec2::new(width as f32, height as f32)
+        });
+        let view = ViewTransform::fit(frame, video_rect);
 
         Area::new("video feed")
-            .fixed_pos(Pos2::ZERO)
+            .fixed_pos(video_rect.min)
             .show(ctx, |ui| {
-                Image::new(&self.image)
-                    .fit_to_exact_size(ui.available_size())
-                    .maintain_aspect_ratio(true)
-                    .paint_at(ui, Rect::from_min_size(Pos2::ZERO, ui.available_size()));
+                ui.painter().rect_filled(video_rect, 0.0, Color32::BLACK);
+                Image::new(&self.image).paint_at(ui, view.rect);
+                self.heatmap.show(ui, &view);
+
+                if let Some(started) = self.verify_started {
+                    let t = (ui.input(|i| i.time) - started) as f32;
+                    let leds = led_positions();
+                    let centers = leds
+                        .iter()
+                        .map(|led| Pos2::new(led.position[0], led.position[1]))
+                        .collect::<Vec<_>>();
+                    let colors = self.verify_pattern.render(&centers, t);
+                    // The video shows the LEDs as they were, so the overlay lags behind to match
+                    let shown = if self.verify_sync {
+                        let latency = video_latency().0.as_secs_f32();
+                        self.verify_pattern.render(&centers, t - latency)
+                    } else {
+                        colors.clone()
+                    };
+
+                    for (center, color) in centers.iter().zip(&shown) {
+                        ui.painter()
+                            .circle_filled(view.to_screen(*center), 4., *color);
+                    }
+
+                    // The scan thread holds the controller while it runs
+                    if let Some(mut controller) =
+                        self.controller.as_ref().and_then(|c| c.try_lock().ok())
+                    {
+                        let layout = segments::LAYOUT.read().unwrap();
+                        controller.set_all(Color32::BLACK);
+                        for (led, color) in leds.iter().zip(colors) {
+                            controller.set_pixel(layout.global(led.segment, led.index), color);
+                        }
+                        if let Err(e) = controller.flush() {
+                            let status = format!("Failed to send: {e}");
+                            if self.controller_status != status {
+                                warn!("Failed to send verify pattern: {e}");
+                                self.controller_status = status;
+                            }
+                        }
+                    }
+                }
+
+                if !self.ghost.is_empty() {
+                    let (ghost, leds) = (view.leds_to_screen(&self.ghost), scan::leds());
+                    overlay::ghost(ui.painter(), &ghost, &view.leds_to_screen(&leds));
+                }
 
                 for point in POINTS.read().unwrap().iter() {
+                    self.marker_style
+                        .detection(ui.painter(), view.rect_to_screen(*point));
+                }
+
+                // Only once a scan has put the LEDs in order
+                if self.show_labels || self.color_by_index {
+                    let leds = view.leds_to_screen(&scan::leds());
+                    if self.color_by_index {
+                        overlay::index_markers(ui.painter(), &leds, &self.marker_style);
+                    }
+                    if self.show_labels {
+                        overlay::labels(ui.painter(), &leds, self.label_size);
+                    }
+                }
+
+                for outlier in outliers::find() {
+                    ui.painter().circle_stroke(
+                        view.to_screen(outlier.position),
+                        12.,
+                        Stroke::new(2., outliers::COLOR),
+                    );
+                }
+
+                for source in ignored::IGNORED.read().unwrap().iter() {
+                    ui.painter().circle_stroke(
+                        view.to_screen(source.position),
+                        source.radius * view.scale,
+                        Stroke::new(1., Color32::GRAY),
+                    );
+                }
+
+                if !self.fullscreen {
+                    self.inset.show(ui, &view, &self.image);
+                    self.inspector.show(ui, &view);
+                }
+
+                for (i, segment) in self.segments.iter().enumerate() {
+                    let Some(zone) = segment.zone else {
+                        continue;
+                    };
+                    let color = segments::PALETTE[i % segments::PALETTE.len()];
+                    let rect = view.rect_to_screen(zone.rect);
                     ui.painter()
-                        .rect_stroke(*point, 0., Stroke::new(1., Color32::RED))
+                        .rect_stroke(rect, 0.0, Stroke::new(1., color.gamma_multiply(0.6)));
+                    ui.painter().text(
+                        rect.left_top() + Vec2::splat(2.0),
+                        Align2::LEFT_TOP,
+                        &segment.name,
+                        FontId::proportional(12.0),
+                        color,
+                    );
+                }
+
+                // Regions and hints are kept in frame pixels, like the detections
+                if self.picking_gray || self.picking_template || self.picking_zone.is_some() {
+                    let response = ui.interact(view.rect, Id::new("region"), Sense::drag());
+                    let start = ui.input(|i| i.pointer.press_origin());
+                    if let Some((start, end)) = start.zip(response.interact_pointer_pos()) {
+                        let region = Rect::from_two_pos(view.to_frame(start), view.to_frame(end));
+                        self.region = Some(region);
+                    }
+                    if let Some(region) = self.region {
+                        ui.painter().rect_stroke(
+                            view.rect_to_screen(region),
+                            0.0,
+                            Stroke::new(1., Color32::WHITE),
+                        );
+                        if response.drag_released() {
+                            if let Some(segment) = self.picking_zone.take() {
+                                let depth = self.segments[segment].zone.map_or(0, |z| z.depth);
+                                self.segments[segment].zone = Some(Zone { rect: region, depth });
+                                zones::set(&self.segments);
+                            } else if self.picking_gray {
+                                white_balance(region);
+                            } else {
+                                capture_template(region);
+                            }
+                            self.picking_gray = false;
+                            self.picking_template = false;
+                            self.region = None;
+                        }
+                    }
+                }
+
+                if self.ordering_by_hand {
+                    let response = ui.interact(view.rect, Id::new("hand order"), Sense::click());
+                    if let Some(pos) = response
+                        .interact_pointer_pos()
+                        .filter(|_| response.clicked())
+                        .map(|pos| view.to_frame(pos))
+                    {
+                        self.click_captured(pos, HAND_PICK_RADIUS / view.scale);
+                    }
+                }
+
+                if let Some(hint) = self.picking {
+                    let response = ui.interact(view.rect, Id::new("pick hint"), Sense::click());
+                    if let Some(pos) = response
+                        .interact_pointer_pos()
+                        .filter(|_| response.clicked())
+                        .map(|pos| view.to_frame(pos))
+                    {
+                        match hint {
+                            Hint::Start => self.order_start = Some(pos),
+                            Hint::End => self.order_end = Some(pos),
+                        }
+                        self.picking = None;
+                    }
+                }
+
+                ui.painter().add(egui::Shape::line(
+                    self.order_path
+                        .iter()
+                        .map(|&pos| view.to_screen(pos))
+                        .collect(),
+                    Stroke::new(1., Color32::YELLOW),
+                ));
+                for (hint, color) in
+                    [(self.order_start, Color32::GREEN), (self.order_end, Color32::RED)]
+                {
+                    if let Some(pos) = hint {
+                        ui.painter()
+                            .circle_stroke(view.to_screen(pos), 8., Stroke::new(2., color));
+                    }
                 }
             });
 
-        Window::new("Settings")
-            .default_size([200.0, 200.0])
+        self.check_count(ctx);
+
+        if self.fullscreen {
+            ctx.request_repaint();
+            return;
+        }
+
+        if !self.dock_settings {
+            Window::new(tr("Settings"))
+                .id(Id::new("Settings"))
+                .default_size([200.0, 200.0])
+                .show(ctx, |ui| self.show_settings(ui));
+        }
+
+        Window::new(tr("Map diff"))
+            .id(Id::new("Map diff"))
+            .default_size([400.0, 400.0])
+            .default_open(false)
+            .show(ctx, |ui| self.map_diff.show(ui));
+
+        Window::new(tr("PTZ camera"))
+            .id(Id::new("PTZ camera"))
+            .default_open(false)
+            .show(ctx, |ui| self.ptz.show(ui));
+
+        Window::new(tr("3D preview"))
+            .id(Id::new("3D preview"))
+            .default_size([300.0, 300.0])
+            .default_open(false)
             .show(ctx, |ui| {
-                let settings = unsafe { &mut SETTINGS };
+                self.viewport
+                    .show(ui, &depth::to_3d(&stereo::to_3d(&led_positions())));
+            });
+
+        Window::new(tr("Controller"))
+            .id(Id::new("Controller"))
+            .default_open(false)
+            .show(ctx, |ui| {
+                let mut remove = None;
+                let removable = self.segments.len() > 1;
+                let mut zones_changed = false;
+
+                for (i, segment) in self.segments.iter_mut().enumerate() {
+                    let config = &mut segment.config;
+
+                    ui.push_id(i, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Segment"));
+                            ui.text_edit_singleline(&mut segment.name);
+                            if removable && ui.button(tr("Remove")).clicked() {
+                                remove = Some(i);
+                            }
+                        });
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Zone"));
+                            let picking = self.picking_zone == Some(i);
+                            if ui
+                                .selectable_label(picking, "Draw")
+                                .on_hover_text(
+                                    "Drag out where this segment's LEDs are in the video, so its \
+                                     scan leaves out everything else and the other segments' \
+                                     scans leave this out",
+                                )
+                                .clicked()
+                            {
+                                self.picking_zone = (!picking).then_some(i);
+                                self.picking_gray = false;
+                                self.picking_template = false;
+                            }
+                            let mut clear = false;
+                            if let Some(zone) = &mut segment.zone {
+                                zones_changed |= ui
+                                    .add(DragValue::new(&mut zone.depth).prefix("Depth: "))
+                                    .on_hover_text("Lower is in front where zones overlap")
+                                    .changed();
+                                clear = ui.button(tr("Clear")).clicked();
+                            }
+                            if clear {
+                                segment.zone = None;
+                                zones_changed = true;
+                            }
+                        });
+
+                        ComboBox::from_label(tr("Type"))
+                            .selected_text(config.kind.name())
+                            .show_ui(ui, |ui| {
+                                for kind in ControllerKind::ALL {
+                                    ui.selectable_value(&mut config.kind, kind, kind.name());
+                                }
+                            });
+
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Address"));
+                            ui.add(
+                                TextEdit::singleline(&mut config.address)
+                                    .hint_text(config.kind.address_hint()),
+                            );
+                            let found = discovery::FOUND.read().unwrap();
+                            if !found.is_empty() {
+                                ui.menu_button(tr("Found"), |ui| {
+                                    for found in found.iter() {
+                                        let leds = match found.led_count {
+                                            Some(count) => format!(", {count} LEDs"),
+                                            None => String::new(),
+                                        };
+                                        let text = format!(
+                                            "{} at {} ({}{leds})",
+                                            found.name,
+                                            found.address,
+                                            found.kind.name()
+                                        );
+                                        if ui.button(text).clicked() {
+                                            config.kind = found.kind;
+                                            config.address = found.address.to_string();
+                                            if let Some(count) = found.led_count {
+                                                config.led_count = count;
+                                            }
+                                            ui.close_menu();
+                                        }
+                                    }
+                                });
+                            }
+                        });
+
+                        ui.add(
+                            DragValue::new(&mut config.led_count)
+                                .clamp_range(1..=10_000)
+                                .prefix("LEDs: "),
+                        );
+
+                        ComboBox::from_label(tr("Pixel order"))
+                            .selected_text(config.pixel_order.name())
+                            .show_ui(ui, |ui| {
+                                for order in PixelOrder::ALL {
+                                    ui.selectable_value(
+                                        &mut config.pixel_order,
+                                        order,
+                                        order.name(),
+                                    );
+                                }
+                            });
+
+                        if config.kind != ControllerKind::Sacn || config.addressing.is_empty() {
+                            ComboBox::from_label(tr("Channels"))
+                                .selected_text(config.channels.name())
+                                .show_ui(ui, |ui| {
+                                    for channels in Channels::ALL {
+                                        let name = channels.name();
+                                        ui.selectable_value(&mut config.channels, channels, name);
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "What each LED has, RGBW strips take a white channel after \
+                                     the colors",
+                                );
+                        }
+
+                        if config.kind == ControllerKind::Sacn {
+                            show_addressing(ui, &mut config.addressing);
+                        }
+                    });
+
+                    ui.separator();
+                }
+
+                if let Some(i) = remove {
+                    self.segments.remove(i);
+                    self.picking_zone = None;
+                    zones_changed = true;
+                }
+                if zones_changed {
+                    zones::set(&self.segments);
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button(tr("Add segment")).clicked() {
+                        let config = self.segments.last().map(|s| s.config.clone()).unwrap();
+                        self.segments.push(Segment {
+                            name: format!("strip {}", self.segments.len() + 1),
+                            config,
+                            zone: None,
+                        });
+                    }
+
+                    if ui.button(tr("Connect")).clicked() {
+                        self.connect();
+                    }
+
+                    if discovery::SEARCHING.load(Ordering::Relaxed) {
+                        ui.spinner();
+                    } else if ui
+                        .button(tr("Find controllers"))
+                        .on_hover_text(
+                            "Look for WLED and ESPixelStick controllers on the network, to pick \
+                             from next to each segment's address",
+                        )
+                        .clicked()
+                    {
+                        self.workers.push(discovery::spawn_search());
+                    }
+
+                    let idle = !scan::RUNNING.load(Ordering::Relaxed);
+                    if let Some(controller) = self.controller.clone().filter(|_| idle) {
+                        if ui
+                            .button(tr("Detect pixel order"))
+                            .on_hover_text(
+                                "Light each segment red and green and set its pixel order from \
+                                 what the camera sees, with the detection passing colored light",
+                            )
+                            .clicked()
+                        {
+                            self.workers.extend(scan::start_pixel_order_detection(
+                                controller,
+                                self.segments.clone(),
+                            ));
+                        }
+                    }
+                });
+
+                ui.label(&self.controller_status);
+
+                ui.separator();
+
+                if scan::RUNNING.load(Ordering::Relaxed) {
+                    let current = scan::CURRENT.load(Ordering::Relaxed);
+                    let total = scan::STEPS.load(Ordering::Relaxed);
+                    let eta = match scan::eta() {
+                        Some(eta) => {
+                            format!(", {}:{:02} left", eta.as_secs() / 60, eta.as_secs() % 60)
+                        }
+                        None => String::new(),
+                    };
 
-                for (name, value, range) in [
-                    ("lower_h", &mut settings.lower_h, 0.0..=180.0),
-                    ("lower_s", &mut settings.lower_s, 0.0..=255.0),
-                    ("lower_v", &mut settings.lower_v, 0.0..=255.0),
-                    ("upper_h", &mut settings.upper_h, 0.0..=180.0),
-                    ("upper_s", &mut settings.upper_s, 0.0..=255.0),
-                    ("upper_v", &mut settings.upper_v, 0.0..=255.0),
-                ] {
                     ui.add(
-                        DragValue::new(value)
-                            .clamp_range(range)
-                            .speed(0.1)
-                            .prefix(name),
+                        ProgressBar::new(current as f32 / total.max(1) as f32)
+                            .text(format!("LED {} of {total}{eta}", current + 1)),
+                    );
+                    if ui.button(tr("Stop scan")).clicked() {
+                        send(scan::Command::Stop);
+                    }
+                } else if let Some(controller) = &self.controller {
+                    ui.checkbox(&mut self.use_scan_script, tr("Use scan script"));
+                    let mut check_colors = issues::CHECK_COLORS.load(Ordering::Relaxed);
+                    if ui
+                        .checkbox(&mut check_colors, "Check colors after scanning")
+                        .on_hover_text(
+                            "Light every LED red, green and blue for the hardware report",
+                        )
+                        .changed()
+                    {
+                        issues::CHECK_COLORS.store(check_colors, Ordering::Relaxed);
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("When a scan ends or gets stuck");
+                        for (flag, label) in
+                            [(&alerts::SOUND, "Play a sound"), (&alerts::DESKTOP, "Notify")]
+                        {
+                            let mut on = flag.load(Ordering::Relaxed);
+                            if ui.checkbox(&mut on, label).changed() {
+                                flag.store(on, Ordering::Relaxed);
+                            }
+                        }
+                    });
+                    let mut lock = awb::LOCK.load(Ordering::Relaxed);
+                    if ui
+                        .checkbox(&mut lock, "Lock white balance")
+                        .on_hover_text(
+                            "Turn the camera's auto white balance off while scanning, so it \
+                             doesn't shift the colors halfway. Works for V4L2 devices and the \
+                             ONVIF camera connected in the PTZ window",
+                        )
+                        .changed()
+                    {
+                        awb::LOCK.store(lock, Ordering::Relaxed);
+                    }
+                    let mut resolve = reflections::RESOLVE.load(Ordering::Relaxed);
+                    if ui
+                        .checkbox(&mut resolve, "Blink to tell reflections apart")
+                        .on_hover_text(
+                            "When more than one blob lights up with an LED in a sequential scan, \
+                             blink it and keep the blob that follows it the most",
+                        )
+                        .changed()
+                    {
+                        reflections::RESOLVE.store(resolve, Ordering::Relaxed);
+                    }
+                    ui.add_enabled(
+                        !self.use_scan_script && !self.interleave && !self.color_coded,
+                        Checkbox::new(&mut self.strobe_diff, "Strobe diff"),
+                    )
+                    .on_hover_text("Detect on the difference between the LED off and on");
+                    ui.add_enabled(
+                        !self.use_scan_script
+                            && !self.strobe_diff
+                            && !self.color_coded
+                            && segments::LAYOUT.read().unwrap().len() > 1,
+                        Checkbox::new(&mut self.interleave, "Interleave segments"),
+                    )
+                    .on_hover_text(
+                        "Scan all segments at once, each in its own color, or as many at a time \
+                         as there are colors. Needs a detection mode that lets colored LEDs \
+                         through.",
+                    );
+                    ui.add_enabled(
+                        !self.use_scan_script && !self.strobe_diff && !self.interleave,
+                        Checkbox::new(&mut self.color_coded, "Color-coded"),
+                    )
+                    .on_hover_text(
+                        "Light all LEDs at once in a sequence of colors that spells out their \
+                         index. Needs a still camera and a detection mode that lets colored LEDs \
+                         through.",
+                    );
+                    ui.add_enabled(
+                        !self.use_scan_script
+                            && !self.strobe_diff
+                            && !self.interleave
+                            && !self.color_coded
+                            && ptz::CAMERA.read().unwrap().is_some(),
+                        Checkbox::new(&mut self.scan_regions, "Scan regions"),
+                    )
+                    .on_hover_text(
+                        "Point the PTZ camera at each of its regions in turn and stitch the maps \
+                         together",
+                    );
+                    ui.add_enabled(
+                        !self.use_scan_script
+                            && !self.strobe_diff
+                            && !self.interleave
+                            && !self.color_coded
+                            && !self.scan_regions,
+                        Checkbox::new(&mut self.coarse_to_fine, "Coarse to fine"),
+                    )
+                    .on_hover_text(
+                        "Scan at half resolution first, then light each LED found again and place \
+                         it precisely from a full resolution crop. Takes twice as long, for \
+                         distant LEDs only a few pixels across.",
+                    );
+                    ui.add_enabled(
+                        !self.use_scan_script
+                            && !self.strobe_diff
+                            && !self.interleave
+                            && !self.color_coded
+                            && !self.scan_regions
+                            && !self.coarse_to_fine,
+                        Checkbox::new(&mut self.grouped, "Grouped"),
+                    )
+                    .on_hover_text(
+                        "Light several LEDs far enough apart at once, going by the prior map or \
+                         the last scan, and give each the blob near where it was. LEDs that \
+                         aren't clear are lit again on their own.",
+                    );
+
+                    ui.horizontal(|ui| {
+                        if ui.button(tr("Measure latency")).clicked() {
+                            self.workers.extend(scan::start_latency_measurement(
+                                controller.clone(),
+                                self.latency_led,
+                            ));
+                        }
+                        ui.add(DragValue::new(&mut self.latency_led).prefix("using LED "));
+                    });
+                    match *scan::MEASURED_LATENCY.read().unwrap() {
+                        Some(latency) => ui.label(format!(
+                            "Latency {} ms, {} ms per step",
+                            latency.as_millis(),
+                            scan::step_time(Duration::ZERO).as_millis()
+                        )),
+                        None => ui.label(tr("Latency not measured, using a guess")),
+                    };
+
+                    ui.collapsing(tr("Scan settings"), show_scan_settings);
+                    ui.collapsing(tr("Prior map"), |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label(tr("Path"));
+                            ui.text_edit_singleline(&mut self.prior_path);
+                        });
+                        ui.add(
+                            DragValue::new(&mut self.prior_radius)
+                                .clamp_range(1.0..=500.0)
+                                .prefix("Radius: ")
+                                .suffix(" px"),
+                        );
+
+                        ui.horizontal(|ui| {
+                            if ui.button(tr("Load")).clicked() {
+                                self.prior_status =
+                                    match export::read_json(self.prior_path.as_ref()) {
+                                        Ok(leds) => {
+                                            let priors = Priors::new(&leds, self.prior_radius);
+                                            *scan::PRIORS.write().unwrap() = Some(priors);
+                                            format!("Using {} old positions", leds.len())
+                                        }
+                                        Err(e) => format!("Failed to load: {e}"),
+                                    };
+                            }
+                            if ui.button(tr("Clear")).clicked() {
+                                *scan::PRIORS.write().unwrap() = None;
+                                self.prior_status.clear();
+                            }
+                        });
+
+                        // Radius changes apply to loaded priors right away
+                        if let Some(priors) = scan::PRIORS.write().unwrap().as_mut() {
+                            priors.radius = self.prior_radius;
+                        }
+
+                        ui.label(&self.prior_status);
+                    });
+
+                    if ui.button(tr("Start scan")).clicked() {
+                        send(scan::Command::Start(self.scan_mode()));
+                    }
+
+                    if let Some((captured, count)) = self.interrupted {
+                        if ui
+                            .button(format!(
+                                "Resume interrupted scan ({captured} of {count} LEDs done)"
+                            ))
+                            .on_hover_text(format!("Picks up from {}", journal::PATH))
+                            .clicked()
+                        {
+                            send(scan::Command::Resume);
+                        }
+                    }
+                }
+            });
+
+        Window::new(tr("Free-run order"))
+            .id(Id::new("Free-run order"))
+            .default_open(false)
+            .show(ctx, |ui| {
+                ui.label("Without a controller: light every LED, then order the detections.");
+
+                ui.horizontal(|ui| {
+                    for (hint, name) in [(Hint::Start, "Pick start"), (Hint::End, "Pick end")] {
+                        if ui
+                            .selectable_label(self.picking == Some(hint), name)
+                            .clicked()
+                        {
+                            self.picking = Some(hint);
+                            self.ordering_by_hand = false;
+                        }
+                    }
+                    if ui.button(tr("Clear")).clicked() {
+                        self.order_start = None;
+                        self.order_end = None;
+                        self.order_path.clear();
+                    }
+                });
+
+                let scanning = scan::RUNNING.load(Ordering::Relaxed);
+                if ui
+                    .add_enabled(!scanning, Button::new("Order detections"))
+                    .clicked()
+                {
+                    let points = POINTS
+                        .read()
+                        .unwrap()
+                        .iter()
+                        .map(|rect| rect.center())
+                        .collect::<Vec<_>>();
+                    self.order_path = ordering::order(&points, self.order_start, self.order_end);
+                    self.captured.clear();
+                    self.clicked.clear();
+                    self.ordering_by_hand = false;
+
+                    scan::reset(self.order_path.len());
+                    *scan::MAP.write().unwrap() =
+                        self.order_path.iter().copied().map(Some).collect();
+                    info!("Ordered {} detections into a path", self.order_path.len());
+                }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!scanning, Button::new("Capture unordered"))
+                        .on_hover_text("Map every detection as it is, numbered as found")
+                        .clicked()
+                    {
+                        self.captured = POINTS
+                            .read()
+                            .unwrap()
+                            .iter()
+                            .map(|rect| rect.center())
+                            .collect();
+                        self.clicked.clear();
+                        self.order_path.clear();
+                        scan::reset(self.captured.len());
+                        self.apply_hand_order();
+                        info!("Captured {} detections", self.captured.len());
+                    }
+
+                    if !self.captured.is_empty() {
+                        if ui
+                            .selectable_label(self.ordering_by_hand, "Order by hand")
+                            .on_hover_text("Click the LEDs on the video from the first to the last")
+                            .clicked()
+                        {
+                            self.ordering_by_hand = !self.ordering_by_hand;
+                            self.picking = None;
+                        }
+                        if ui
+                            .add_enabled(!self.clicked.is_empty(), Button::new("Undo click"))
+                            .clicked()
+                        {
+                            self.clicked.pop();
+                            self.apply_hand_order();
+                        }
+                    }
+                });
+                if !self.captured.is_empty() {
+                    ui.label(format!(
+                        "{} of {} captured LEDs ordered",
+                        self.clicked.len(),
+                        self.captured.len()
+                    ));
+                }
+            });
+
+        Window::new(tr("Ignored sources"))
+            .id(Id::new("Ignored sources"))
+            .default_open(false)
+            .show(ctx, |ui| {
+                let mut learn = ignored::LEARN_BEFORE_SCAN.load(Ordering::Relaxed);
+                if ui.checkbox(&mut learn, "Learn before each scan").changed() {
+                    ignored::LEARN_BEFORE_SCAN.store(learn, Ordering::Relaxed);
+                }
+
+                ui.horizontal(|ui| {
+                    let idle = !scan::RUNNING.load(Ordering::Relaxed);
+                    if let Some(controller) = self.controller.as_ref().filter(|_| idle) {
+                        if ui
+                            .button("Learn now")
+                            .on_hover_text("Turn every LED off and record what stays lit")
+                            .clicked()
+                        {
+                            self.workers
+                                .extend(scan::start_baseline(controller.clone()));
+                        }
+                    }
+                    if ui.button(tr("Clear")).clicked() {
+                        ignored::IGNORED.write().unwrap().clear();
+                    }
+                });
+
+                let mut sources = ignored::IGNORED.write().unwrap();
+                if sources.is_empty() {
+                    ui.label("Nothing ignored");
+                }
+
+                let mut remove = None;
+                for (i, source) in sources.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "({:.0}, {:.0}), r {:.0} px, lit {:.0}% of the time",
+                            source.position.x,
+                            source.position.y,
+                            source.radius,
+                            source.presence * 100.0
+                        ));
+                        if ui.small_button(tr("Remove")).clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    sources.remove(i);
+                }
+            });
+
+        Window::new(tr("Scan gallery"))
+            .id(Id::new("Scan gallery"))
+            .default_open(false)
+            .show(ctx, |ui| self.show_gallery(ui));
+
+        Window::new(tr("Review"))
+            .id(Id::new("Review"))
+            .default_open(false)
+            .show(ctx, |ui| self.review.show(ui, &mut self.keymap));
+
+        if let Some(playback) = &mut self.playback {
+            let mut open = true;
+            Window::new(format!("Recording {}", playback.directory().display()))
+                .id(egui::Id::new("recording"))
+                .open(&mut open)
+                .show(ctx, |ui| playback.show(ui));
+            if !open {
+                self.playback = None;
+            }
+        }
+
+        Window::new(tr("Scan script"))
+            .id(Id::new("Scan script"))
+            .default_open(false)
+            .show(ctx, |ui| {
+                ScrollArea::vertical().show(ui, |ui| {
+                    ui.add(
+                        TextEdit::multiline(&mut self.scan_script)
+                            .code_editor()
+                            .desired_width(f32::INFINITY),
+                    );
+                });
+            });
+
+        Window::new(tr("Export"))
+            .id(Id::new("Export"))
+            .default_open(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(tr("Path"));
+                    ui.text_edit_singleline(&mut self.export_path);
+                });
+
+                let outliers = outliers::find();
+                if !outliers.is_empty() {
+                    let indices = outliers
+                        .iter()
+                        .map(|outlier| outlier.index.to_string())
+                        .collect::<Vec<_>>();
+                    ui.colored_label(
+                        outliers::COLOR,
+                        format!("Out of place, likely reflections: {}", indices.join(", ")),
+                    )
+                    .on_hover_text(
+                        "Ringed in the video, check them in the review before exporting",
+                    );
+                }
+
+                ui.collapsing(tr("Lens"), show_lens);
+
+                ui.collapsing(tr("Transform"), |ui| {
+                    let transform = &mut self.transform;
+
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut transform.flip_x, tr("Flip X"));
+                        ui.checkbox(&mut transform.flip_y, tr("Flip Y"));
+                    });
+
+                    ui.horizontal(|ui| {
+                        let mut enabled = transform.distance.is_some();
+                        if ui
+                        .checkbox(&mut enabled, tr("At distance"))
+                        .on_hover_text(
+                            "Put the LEDs without a depth this far in front of the camera, going \
+                             by the angle of view under Lens, to export a flat installation in \
+                             meters",
+                        )
+                        .changed()
+                    {
+                        transform.distance = enabled.then_some(1.0);
+                    }
+                        if let Some(distance) = &mut transform.distance {
+                            ui.add(
+                                DragValue::new(distance)
+                                    .speed(0.01)
+                                    .clamp_range(0.01..=1000.0)
+                                    .suffix(" m"),
+                            );
+                        }
+                    });
+
+                    ComboBox::from_label(tr("Rotate"))
+                        .selected_text(transform.rotate.name())
+                        .show_ui(ui, |ui| {
+                            for rotation in Rotation::ALL {
+                                ui.selectable_value(
+                                    &mut transform.rotate,
+                                    rotation,
+                                    rotation.name(),
+                                );
+                            }
+                        });
+
+                    for (label, value, default) in [
+                        ("Fit to", &mut transform.fit, 1.0),
+                        ("Translate", &mut transform.translate, 0.0),
+                    ] {
+                        ui.horizontal(|ui| {
+                            let mut enabled = value.is_some();
+                            if ui.checkbox(&mut enabled, label).changed() {
+                                *value = enabled.then(|| vec![default; 2]);
+                            }
+                            if let Some(value) = value {
+                                ui.add(DragValue::new(&mut value[0]).speed(0.1));
+                                ui.add(DragValue::new(&mut value[1]).speed(0.1));
+                            }
+                        });
+                    }
+
+                    if ui.button(tr("Reset")).clicked() {
+                        *transform = Transform::default();
+                    }
+                });
+
+                ui.collapsing(tr("Grid"), |ui| {
+                    ui.checkbox(&mut self.snap_to_grid, tr("Snap to grid"))
+                        .on_hover_text(
+                            "Export integer row and column coordinates, for LED matrices",
+                        );
+                    if ui.button(tr("Fit")).clicked() {
+                        self.export_status = match grid::fit(&led_positions()) {
+                            Ok(fit) => fit.summary(),
+                            Err(e) => format!("Failed to fit a grid: {e}"),
+                        };
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    for format in ExportFormat::ALL {
+                        if ui.button(format.name()).clicked() {
+                            let path =
+                                PathBuf::from(&self.export_path).with_extension(format.extension());
+                            self.export_status =
+                                match export_leds(self.snap_to_grid, &self.transform)
+                                    .and_then(|leds| format.write(&path, &leds))
+                                {
+                                    Ok(()) => format!("Saved {}", path.display()),
+                                    Err(e) => format!("Failed to save {}: {e}", path.display()),
+                                };
+                        }
+                    }
+                });
+
+                let plugins = exporters::all();
+                if !plugins.is_empty() {
+                    ui.horizontal(|ui| {
+                        for exporter in plugins {
+                            let button = ui
+                                .button(exporter.name())
+                                .on_hover_text(format!("From the {} directory", exporters::DIR));
+                            if button.clicked() {
+                                let path = PathBuf::from(&self.export_path)
+                                    .with_extension(exporter.extension());
+                                self.export_status =
+                                    match export_leds(self.snap_to_grid, &self.transform)
+                                        .and_then(|leds| exporters::write(&*exporter, &path, &leds))
+                                    {
+                                        Ok(()) => format!("Saved {}", path.display()),
+                                        Err(e) => {
+                                            format!("Failed to save {}: {e:#}", path.display())
+                                        }
+                                    };
+                            }
+                        }
+                    });
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label(tr("Copy"));
+                    for (label, detections) in [("map", false), ("detections", true)] {
+                        for format in [ExportFormat::Json, ExportFormat::Csv] {
+                            if ui.button(format!("{label} as {}", format.name())).clicked() {
+                                self.copy_leds(ui.ctx(), detections, format);
+                            }
+                        }
+                    }
+                });
+
+                ui.separator();
+
+                ui.collapsing("LedFx", |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Device");
+                        ui.text_edit_singleline(&mut self.ledfx_device);
+                    });
+                    ui.add(
+                        DragValue::new(&mut self.ledfx_columns)
+                            .clamp_range(1..=1024)
+                            .prefix("Columns: "),
                     );
+                    ui.horizontal(|ui| {
+                        ui.label("URL");
+                        ui.text_edit_singleline(&mut self.ledfx_url);
+                    });
+
+                    let layout = || {
+                        let leds = export_leds(self.snap_to_grid, &self.transform)?;
+                        anyhow::Ok(LedfxLayout::new(&leds, &self.ledfx_device, self.ledfx_columns))
+                    };
+                    ui.horizontal(|ui| {
+                        if ui.button("Save JSON").clicked() {
+                            let path =
+                                PathBuf::from(&self.export_path).with_extension("ledfx.json");
+                            self.export_status = match layout().and_then(|layout| {
+                                layout.save(&path, "Calibrated layout")?;
+                                Ok(layout)
+                            }) {
+                                Ok(layout) => {
+                                    format!("Saved {}{}", path.display(), layout.dropped_note())
+                                }
+                                Err(e) => format!("Failed to save {}: {e}", path.display()),
+                            };
+                        }
+
+                        if ledfx::PUSHING.load(Ordering::Relaxed) {
+                            ui.spinner();
+                        } else if ui.button("Push to LedFx").clicked() {
+                            match layout() {
+                                Ok(layout) => self.workers.push(layout.spawn_push(
+                                    self.ledfx_url.clone(),
+                                    "Calibrated layout".to_owned(),
+                                )),
+                                Err(e) => {
+                                    self.export_status = format!("Failed to push to LedFx: {e}")
+                                }
+                            }
+                        }
+                        if let Some(status) = ledfx::PUSHED.lock().unwrap().take() {
+                            self.export_status = status;
+                        }
+                    });
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    if ui.button(tr("Save snapshot")).clicked() {
+                        self.save_snapshot();
+                    }
+                    ui.checkbox(&mut self.snapshot_overlay, "With detections");
+                });
+
+                if ui
+                    .button("Save timelapse")
+                    .on_hover_text("Every frame of the last scan blended into one long exposure")
+                    .clicked()
+                {
+                    let path = PathBuf::from(format!("{}-timelapse.png", self.export_path));
+                    self.export_status = match timelapse::save(&path) {
+                        Ok(()) => format!("Saved {}", path.display()),
+                        Err(e) => format!("Failed to save {}: {e}", path.display()),
+                    };
+                }
+
+                if ui
+                    .button("Save hardware report")
+                    .on_hover_text("Dead and dim LEDs from the last scan")
+                    .clicked()
+                {
+                    let path = PathBuf::from(format!("{}-issues.txt", self.export_path));
+                    let report = issues::report();
+                    self.export_status = match report.save(&path) {
+                        Ok(()) => format!("Saved {}, {}", path.display(), report.summary()),
+                        Err(e) => format!("{e:#}"),
+                    };
+                }
+
+                if ui
+                .button(tr("Save report"))
+                .on_hover_text(
+                    "The layout, every LED, what went wrong and the scan settings, with pictures, \
+                     as one HTML file",
+                )
+                .clicked()
+            {
+                let path = PathBuf::from(format!("{}-report.html", self.export_path));
+                self.export_status = match report::save(&path) {
+                    Ok(()) => format!("Saved {}", path.display()),
+                    Err(e) => format!("{e:#}"),
+                };
+            }
+
+                if ui
+                    .button("Save brightness map")
+                    .on_hover_text("Peak brightness of every LED from the last scan, as CSV")
+                    .clicked()
+                {
+                    let path = PathBuf::from(format!("{}-brightness.csv", self.export_path));
+                    self.export_status = match issues::save_brightness(&path) {
+                        Ok(()) => match issues::report().uniformity {
+                            Some(uniformity) => format!(
+                                "Saved {}, peak brightness {}",
+                                path.display(),
+                                uniformity.summary()
+                            ),
+                            None => format!("Saved {}", path.display()),
+                        },
+                        Err(e) => format!("{e:#}"),
+                    };
+                }
+
+                let directory = PathBuf::from(format!("{}-frames", self.export_path));
+                let mut recording = recording::DIRECTORY.read().unwrap().is_some();
+                if ui
+                    .checkbox(&mut recording, "Record scans")
+                    .on_hover_text(format!(
+                        "Save an annotated frame for every LED captured to {}",
+                        directory.display()
+                    ))
+                    .changed()
+                {
+                    *recording::DIRECTORY.write().unwrap() = recording.then_some(directory.clone());
+                }
+                let recorded = directory.join(recording::STEPS).is_file();
+                if ui
+                    .add_enabled(recorded, egui::Button::new("Open recording"))
+                    .on_hover_text("Scrub through the recorded scan step by step")
+                    .clicked()
+                {
+                    self.open_recording(&directory);
+                }
+
+                ui.label(&self.export_status);
+            });
+
+        Window::new(tr("Keys"))
+            .id(Id::new("Keys"))
+            .default_open(false)
+            .show(ctx, |ui| self.keymap.show(ui));
+
+        Window::new(tr("Verify"))
+            .id(Id::new("Verify"))
+            .default_open(false)
+            .show(ctx, |ui| {
+                ComboBox::from_label("Pattern")
+                    .selected_text(self.verify_pattern.name())
+                    .show_ui(ui, |ui| {
+                        for pattern in Pattern::ALL {
+                            ui.selectable_value(&mut self.verify_pattern, pattern, pattern.name());
+                        }
+                    });
+
+                let mut playing = self.verify_started.is_some();
+                if ui.checkbox(&mut playing, "Play").changed() {
+                    self.verify_started = playing.then(|| ui.input(|i| i.time));
                 }
+
+                let (latency, measured) = video_latency();
+                ui.checkbox(&mut self.verify_sync, "Sync with the video")
+                    .on_hover_text(format!(
+                    "Delay the pattern drawn over the video by the {} ms the LEDs take to show up \
+                     in it, {}",
+                    latency.as_millis(),
+                    if measured {
+                        "as measured"
+                    } else {
+                        "going by the settle time until the latency is measured"
+                    }
+                ));
             });
 
         ctx.request_repaint();
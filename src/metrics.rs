@@ -0,0 +1,112 @@
+//! The pipeline and scan counters in the Prometheus text format, served on `/metrics` by `web`,
+//! for keeping an eye on long headless calibrations.
+//!
+//! Frame rates are left to the scraper, as `rate()` over the frame counters.
+
+use std::{fmt::Write, sync::atomic::Ordering};
+
+use crate::{freeze, pipeline, scan, stats};
+
+const PREFIX: &str = "led_calibrator";
+
+/// Every metric, in the text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+    let (lag, latency) = stats::timing();
+
+    let counters = [
+        ("frames_decoded_total", "Frames decoded from the stream.", &stats::DECODED),
+        ("frames_detected_total", "Frames detection ran on.", &stats::DETECTED),
+        (
+            "frames_dropped_total",
+            "Frames missing from the stream, going by gaps in the timestamps.",
+            &stats::DROPPED,
+        ),
+        (
+            "frames_repeated_total",
+            "Frames that repeated the one before, by timestamp or picture.",
+            &freeze::REPEATED,
+        ),
+        (
+            "controller_errors_total",
+            "Flushes to the controller that failed.",
+            &stats::CONTROLLER_ERRORS,
+        ),
+    ];
+    for (name, help, counter) in counters {
+        metric(&mut out, name, "counter", help, counter.load(Ordering::Relaxed) as f64);
+    }
+
+    let map = scan::MAP.read().unwrap();
+    let gauges = [
+        ("stream_lag_seconds", "How far behind its best the stream is running.", lag),
+        (
+            "detection_latency_seconds",
+            "Time from a frame arriving to its detections being published.",
+            latency.as_secs_f64(),
+        ),
+        (
+            "detections",
+            "Blobs in the latest detection pass.",
+            pipeline::POINTS.read().unwrap().len() as f64,
+        ),
+        (
+            "scan_running",
+            "Whether a scan or other controller job is running.",
+            scan::RUNNING.load(Ordering::Relaxed) as u8 as f64,
+        ),
+        (
+            "scan_step",
+            "Step the scan is at, counting from 0.",
+            scan::CURRENT.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "scan_steps",
+            "Steps in the current or last scan.",
+            scan::STEPS.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "scan_leds_found",
+            "LEDs the current or last scan found.",
+            map.iter().flatten().count() as f64,
+        ),
+        ("scan_leds", "LEDs in the current or last scan.", map.len() as f64),
+    ];
+    drop(map);
+    for (name, help, value) in gauges {
+        metric(&mut out, name, "gauge", help, value);
+    }
+    if let Some(eta) = scan::eta() {
+        metric(
+            &mut out,
+            "scan_eta_seconds",
+            "gauge",
+            "Estimated time left in the scan.",
+            eta.as_secs_f64(),
+        );
+    }
+
+    // One series per state, the current one at 1
+    let state = scan::STATE.read().unwrap().clone();
+    let _ = writeln!(out, "# HELP {PREFIX}_scan_state Where the last scan is at.");
+    let _ = writeln!(out, "# TYPE {PREFIX}_scan_state gauge");
+    for (name, current) in [
+        ("idle", state == scan::State::Idle),
+        ("running", state == scan::State::Running),
+        ("stopping", state == scan::State::Stopping),
+        ("finished", state == scan::State::Finished),
+        ("stopped", state == scan::State::Stopped),
+        ("aborted", state == scan::State::Aborted),
+        ("failed", matches!(state, scan::State::Failed(_))),
+    ] {
+        let _ = writeln!(out, "{PREFIX}_scan_state{{state=\"{name}\"}} {}", current as u8);
+    }
+
+    out
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+    let _ = writeln!(out, "# TYPE {PREFIX}_{name} {kind}");
+    let _ = writeln!(out, "{PREFIX}_{name} {value}");
+}
@@ -0,0 +1,122 @@
+//! Guessing the index order of a strip from its LEDs all lit at once, for when there's no
+//! controller to light them one by one. The LEDs are chained into the shortest path that's easy to
+//! find, which matches the wiring for strips that don't cross or double back on themselves.
+
+use eframe::epaint::Pos2;
+
+/// How many rounds of 2-opt to run at most. Each round fixes every crossing it finds, and
+/// strips are usually untangled after a handful.
+const MAX_ROUNDS: usize = 50;
+
+/// Orders `points` into a path. It starts at the point nearest `start` and ends at the one
+/// nearest `end` when those are given, otherwise at whichever end looks most like one.
+pub fn order(points: &[Pos2], start: Option<Pos2>, end: Option<Pos2>) -> Vec<Pos2> {
+    if points.len() < 2 {
+        return points.to_vec();
+    }
+
+    let mut pool = points.to_vec();
+
+    // Without a hint, the point furthest from the middle is probably the start of the strip
+    let centroid = pool.iter().fold(Pos2::ZERO, |sum, p| sum + p.to_vec2()) / pool.len() as f32;
+    let first = nearest(&pool, start.unwrap_or(centroid), start.is_none());
+    let mut path = vec![pool.swap_remove(first)];
+
+    let last = end.map(|end| pool.swap_remove(nearest(&pool, end, false)));
+
+    // Greedy nearest neighbour chain
+    while !pool.is_empty() {
+        let next = nearest(&pool, *path.last().unwrap(), false);
+        path.push(pool.swap_remove(next));
+    }
+    path.extend(last);
+
+    untangle(&mut path, end.is_some());
+    path
+}
+
+/// Index of the point nearest `to`, or furthest with `furthest`.
+fn nearest(points: &[Pos2], to: Pos2, furthest: bool) -> usize {
+    let distances = points.iter().map(|p| p.distance_sq(to)).enumerate();
+    let index = if furthest {
+        distances.max_by(|a, b| a.1.total_cmp(&b.1))
+    } else {
+        distances.min_by(|a, b| a.1.total_cmp(&b.1))
+    };
+    index.map_or(0, |(index, _)| index)
+}
+
+/// 2-opt: reverses stretches of the path wherever that makes it shorter, which removes the
+/// crossings the greedy chain leaves behind. The first point stays put, and so does the last
+/// with `fixed_end`.
+fn untangle(path: &mut [Pos2], fixed_end: bool) {
+    let n = path.len();
+    let last = if fixed_end { n.saturating_sub(1) } else { n };
+
+    for _ in 0..MAX_ROUNDS {
+        let mut improved = false;
+
+        for i in 1..last {
+            for j in i + 1..last {
+                // Reversing i..=j replaces the edges (i-1, i) and (j, j+1) with (i-1, j) and
+                // (i, j+1). Past the end of the path there's no second edge.
+                let before = path[i - 1].distance(path[i])
+                    + path.get(j + 1).map_or(0.0, |next| path[j].distance(*next));
+                let after = path[i - 1].distance(path[j])
+                    + path.get(j + 1).map_or(0.0, |next| path[i].distance(*next));
+
+                if after + 1e-3 < before {
+                    path[i..=j].reverse();
+                    improved = true;
+                }
+            }
+        }
+
+        if !improved {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eframe::epaint::Vec2;
+
+    use super::*;
+
+    /// A strip run along three sides of a rectangle, 10 apart, and the same LEDs shuffled.
+    fn strip() -> (Vec<Pos2>, Vec<Pos2>) {
+        let bottom = (0..=10).map(|i| Pos2::new(i as f32 * 10.0, 0.0));
+        let side = (1..=5).map(|i| Pos2::new(100.0, i as f32 * 10.0));
+        let top = (0..10).rev().map(|i| Pos2::new(i as f32 * 10.0, 50.0));
+        let strip = bottom.chain(side).chain(top).collect::<Vec<_>>();
+
+        // 7 and the 26 LEDs share no factor, so this visits all of them
+        let shuffled = (0..strip.len())
+            .map(|i| strip[i * 7 % strip.len()])
+            .collect();
+        (strip, shuffled)
+    }
+
+    #[test]
+    fn orders_along_the_strip() {
+        let (strip, shuffled) = strip();
+        let mut path = order(&shuffled, None, None);
+        if path[0] != strip[0] {
+            path.reverse();
+        }
+        assert_eq!(path, strip);
+    }
+
+    #[test]
+    fn hints_pick_the_direction() {
+        let (strip, shuffled) = strip();
+        let reversed = strip.iter().rev().copied().collect::<Vec<_>>();
+        let near = |p: Pos2| Some(p + Vec2::splat(2.0));
+
+        assert_eq!(order(&shuffled, near(strip[0]), None), strip);
+        assert_eq!(order(&shuffled, near(*strip.last().unwrap()), None), reversed);
+        assert_eq!(order(&shuffled, None, near(strip[0])), reversed);
+        assert_eq!(order(&shuffled, near(strip[0]), near(*strip.last().unwrap())), strip);
+    }
+}
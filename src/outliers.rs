@@ -0,0 +1,157 @@
+//! LEDs found far from where the LEDs either side of them put them, which is what a reflection or
+//! a stray light picked up instead of the LED looks like in the map. Strips run smoothly from one
+//! LED to the next, so each LED should sit about where its neighbors along the index say.
+
+use eframe::epaint::{Color32, Pos2};
+
+use crate::{
+    scan,
+    segments::{self, Layout},
+};
+
+/// Further than this many LED spacings from where its neighbors put it, an LED is out of place.
+const MAX_OFF: f32 = 4.0;
+
+/// What out of place LEDs are marked with, anywhere they're shown.
+pub const COLOR: Color32 = Color32::from_rgb(255, 140, 0);
+
+/// At most this share of a segment is flagged, past that the map is more wrong than any few LEDs.
+const MAX_SHARE: f32 = 0.25;
+
+pub struct Outlier {
+    pub index: usize,
+    pub position: Pos2,
+    /// How far it is from where its neighbors put it, in LED spacings.
+    pub off: f32,
+}
+
+/// The out of place LEDs in the current map, by index.
+pub fn find() -> Vec<Outlier> {
+    let map = scan::MAP.read().unwrap().clone();
+    let layout = segments::LAYOUT.read().unwrap();
+    outliers(&map, &layout)
+}
+
+/// Goes segment by segment, since the end of one strip says nothing about the start of the next.
+fn outliers(map: &[Option<Pos2>], layout: &Layout) -> Vec<Outlier> {
+    let mut found = Vec::new();
+    for segment in 0..layout.len() {
+        let offset = layout.offset(segment);
+        let count = layout.count(segment).unwrap_or(map.len());
+        let leds = (offset..(offset + count).min(map.len()))
+            .filter_map(|index| Some((index, map[index]?)))
+            .collect::<Vec<_>>();
+        found.extend(in_segment(leds));
+    }
+    found.sort_by_key(|outlier| outlier.index);
+    found
+}
+
+/// Takes out the LED furthest off until the rest line up, so one misdetection doesn't get its
+/// neighbors flagged along with it.
+fn in_segment(mut leds: Vec<(usize, Pos2)>) -> Vec<Outlier> {
+    let Some(spacing) = spacing(&leds) else {
+        return Vec::new();
+    };
+
+    let mut found = Vec::new();
+    let most = (leds.len() as f32 * MAX_SHARE) as usize;
+    while found.len() < most && leds.len() > 3 {
+        let Some((worst, off)) = (0..leds.len())
+            .filter_map(|k| Some((k, off(&leds, k)? / spacing)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .filter(|&(_, off)| off > MAX_OFF)
+        else {
+            break;
+        };
+        let (index, position) = leds.remove(worst);
+        found.push(Outlier { index, position, off });
+    }
+    found
+}
+
+/// The median distance between LEDs next to each other, `None` with too few to tell.
+fn spacing(leds: &[(usize, Pos2)]) -> Option<f32> {
+    let mut steps = leds
+        .windows(2)
+        .map(|pair| pair[0].1.distance(pair[1].1) / (pair[1].0 - pair[0].0) as f32)
+        .collect::<Vec<_>>();
+    if steps.len() < 3 {
+        return None;
+    }
+
+    let middle = steps.len() / 2;
+    let spacing = *steps.select_nth_unstable_by(middle, f32::total_cmp).1;
+    (spacing > 0.0).then_some(spacing)
+}
+
+/// How far `leds[k]` is from where the LEDs around it put it, in pixels, per LED of index between
+/// it and the nearest of them so gaps left by LEDs that weren't found get more room. Interpolated
+/// between the LEDs either side, or carried on from the two before or after at the ends.
+fn off(leds: &[(usize, Pos2)], k: usize) -> Option<f32> {
+    let (index, position) = leds[k];
+    let (a, b) = match (k.checked_sub(1), leds.get(k + 1)) {
+        (Some(before), Some(&after)) => (leds[before], after),
+        (None, Some(&after)) => (*leds.get(k + 2)?, after),
+        (Some(before), None) => (leds[before.checked_sub(1)?], leds[before]),
+        (None, None) => return None,
+    };
+
+    let t = (index as f32 - a.0 as f32) / (b.0 as f32 - a.0 as f32);
+    let expected = a.1 + (b.1 - a.1) * t;
+    let gap = a.0.abs_diff(index).min(b.0.abs_diff(index));
+    Some(position.distance(expected) / gap as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use eframe::epaint::vec2;
+
+    use super::*;
+
+    /// A gentle arc of LEDs about 10 apart.
+    fn arc(count: usize) -> Vec<Option<Pos2>> {
+        (0..count)
+            .map(|i| {
+                let angle = i as f32 * 0.05;
+                Some(Pos2::new(200.0 * angle.sin(), 200.0 * (1.0 - angle.cos())))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn flags_a_reflection() {
+        let mut map = arc(30);
+        assert!(outliers(&map, &Layout::default()).is_empty());
+
+        // A reflection of LED 12 picked up on the other side of the room
+        map[12] = Some(map[12].unwrap() + vec2(80.0, -60.0));
+        // and LED 20 not found at all, which isn't an outlier
+        map[20] = None;
+        let found = outliers(&map, &Layout::default());
+        assert_eq!(found.iter().map(|o| o.index).collect::<Vec<_>>(), [12]);
+        assert!(found[0].off > MAX_OFF);
+    }
+
+    #[test]
+    fn short_maps_dont_panic() {
+        // With the last LED way off, as the ends have the fewest neighbors to go by
+        let map = |count| {
+            let mut map = arc(count);
+            if let Some(last) = map.last_mut() {
+                *last = Some(Pos2::new(500.0, 500.0));
+            }
+            map
+        };
+
+        // Too few steps between LEDs to tell their spacing
+        for count in 0..4 {
+            assert!(outliers(&map(count), &Layout::default()).is_empty(), "{count} LEDs");
+        }
+        for count in 4..6 {
+            let found = outliers(&map(count), &Layout::default());
+            assert!(found.iter().all(|o| o.index == count - 1), "{count} LEDs");
+        }
+        assert!(outliers(&[None; 8], &Layout::default()).is_empty());
+    }
+}
@@ -0,0 +1,280 @@
+//! What gets drawn over the video: the detection markers, the scanned LEDs' indices, and an old
+//! map to compare against. Everything is in frame pixels until `ViewTransform` puts it on screen.
+
+use eframe::{
+    egui::{self, ComboBox, DragValue, Painter},
+    epaint::{Color32, FontId, Hsva, Pos2, Rect, Rgba, Stroke, Vec2},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{segments, Led};
+
+/// The Okabe-Ito palette, which stays distinguishable with every common form of color blindness.
+pub const COLORBLIND_SAFE: [Color32; 8] = [
+    Color32::from_rgb(230, 159, 0),
+    Color32::from_rgb(86, 180, 233),
+    Color32::from_rgb(0, 158, 115),
+    Color32::from_rgb(240, 228, 66),
+    Color32::from_rgb(0, 114, 178),
+    Color32::from_rgb(213, 94, 0),
+    Color32::from_rgb(204, 121, 167),
+    Color32::WHITE,
+];
+
+/// Stops of viridis, a gradient that reads the same with color blindness and in grayscale.
+const VIRIDIS: [Color32; 5] = [
+    Color32::from_rgb(68, 1, 84),
+    Color32::from_rgb(59, 82, 139),
+    Color32::from_rgb(33, 145, 140),
+    Color32::from_rgb(94, 201, 98),
+    Color32::from_rgb(253, 231, 37),
+];
+
+/// Size of the markers on scanned LEDs, which have no blob to size them by.
+const LED_MARKER_SIZE: f32 = 10.0;
+
+/// Where the video is painted: the frame scaled to fit the space it's given and centered, with
+/// bars on the sides that don't match its aspect ratio.
+#[derive(Clone, Copy)]
+pub struct ViewTransform {
+    /// Where the frame ends up on screen.
+    pub rect: Rect,
+    /// Screen points per frame pixel.
+    pub scale: f32,
+}
+
+impl ViewTransform {
+    /// A `frame` sized frame fit into `available`.
+    pub fn fit(frame: Vec2, available: Rect) -> Self {
+        if frame.x <= 0.0 || frame.y <= 0.0 {
+            return Self { rect: available, scale: 1.0 };
+        }
+        let scale = (available.width() / frame.x).min(available.height() / frame.y);
+        Self {
+            rect: Rect::from_center_size(available.center(), frame * scale),
+            scale,
+        }
+    }
+
+    pub fn to_screen(self, pos: Pos2) -> Pos2 {
+        self.rect.min + pos.to_vec2() * self.scale
+    }
+
+    pub fn to_frame(self, pos: Pos2) -> Pos2 {
+        ((pos - self.rect.min) / self.scale).to_pos2()
+    }
+
+    pub fn rect_to_screen(self, rect: Rect) -> Rect {
+        Rect::from_min_max(self.to_screen(rect.min), self.to_screen(rect.max))
+    }
+
+    /// `leds` moved to where they are on screen, for the functions here that draw them.
+    pub fn leds_to_screen(self, leds: &[Led]) -> Vec<Led> {
+        leds.iter()
+            .map(|led| {
+                let [x, y, z] = led.position;
+                let pos = self.to_screen(Pos2::new(x, y));
+                Led { position: [pos.x, pos.y, z], ..*led }
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Shape {
+    Box,
+    Crosshair,
+    Circle,
+}
+
+impl Shape {
+    pub const ALL: [Self; 3] = [Self::Box, Self::Crosshair, Self::Circle];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Box => "Box",
+            Self::Crosshair => "Crosshair",
+            Self::Circle => "Circle",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct MarkerStyle {
+    pub shape: Shape,
+    pub width: f32,
+    /// Color of the detection markers.
+    pub color: Color32,
+    /// Use viridis instead of blue to red for coloring by index.
+    pub colorblind_gradient: bool,
+}
+
+impl Default for MarkerStyle {
+    fn default() -> Self {
+        Self {
+            shape: Shape::Box,
+            width: 1.0,
+            color: Color32::RED,
+            colorblind_gradient: false,
+        }
+    }
+}
+
+impl MarkerStyle {
+    /// Marks the detection in `rect`.
+    pub fn detection(&self, painter: &Painter, rect: Rect) {
+        self.draw(painter, rect, self.color);
+    }
+
+    fn draw(&self, painter: &Painter, rect: Rect, color: Color32) {
+        let stroke = Stroke::new(self.width, color);
+
+        match self.shape {
+            Shape::Box => painter.rect_stroke(rect, 0.0, stroke),
+            Shape::Crosshair => {
+                let center = rect.center();
+                painter.hline(rect.x_range(), center.y, stroke);
+                painter.vline(center.x, rect.y_range(), stroke);
+            }
+            Shape::Circle => {
+                painter.circle_stroke(rect.center(), rect.size().max_elem() / 2.0, stroke)
+            }
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        ComboBox::from_label("Shape")
+            .selected_text(self.shape.name())
+            .show_ui(ui, |ui| {
+                for shape in Shape::ALL {
+                    ui.selectable_value(&mut self.shape, shape, shape.name());
+                }
+            });
+        ui.add(
+            DragValue::new(&mut self.width)
+                .clamp_range(0.5..=10.0)
+                .speed(0.1)
+                .prefix("Width "),
+        );
+
+        ui.horizontal(|ui| {
+            ui.color_edit_button_srgba(&mut self.color);
+            for color in COLORBLIND_SAFE {
+                let (rect, response) =
+                    ui.allocate_exact_size(Vec2::splat(16.0), egui::Sense::click());
+                ui.painter().rect_filled(rect, 2.0, color);
+                if response.on_hover_text("Colorblind-safe").clicked() {
+                    self.color = color;
+                }
+            }
+        });
+
+        ui.checkbox(&mut self.colorblind_gradient, "Colorblind-safe index colors");
+    }
+
+    /// Color for somewhere along the strip, from 0 at the start to 1 at the end.
+    pub fn gradient(&self, t: f32) -> Color32 {
+        if self.colorblind_gradient {
+            viridis(t)
+        } else {
+            gradient(t)
+        }
+    }
+}
+
+/// Where a label goes relative to its LED.
+const LABEL_OFFSET: Vec2 = Vec2::new(6.0, -16.0);
+
+/// Writes each LED's index next to it. Labels that would overlap one already drawn are left out,
+/// going from the lowest index up, so dense maps stay readable.
+pub fn labels(painter: &Painter, leds: &[Led], font_size: f32) {
+    let mut taken: Vec<Rect> = Vec::with_capacity(leds.len());
+
+    for led in leds {
+        let galley = painter.layout_no_wrap(
+            led.index.to_string(),
+            FontId::monospace(font_size),
+            Color32::YELLOW,
+        );
+        let pos = Pos2::new(led.position[0], led.position[1]) + LABEL_OFFSET;
+        let rect = Rect::from_min_size(pos, galley.size()).expand(1.0);
+
+        if taken.iter().any(|other| other.intersects(rect)) {
+            continue;
+        }
+        taken.push(rect);
+
+        painter.rect_filled(rect, 2.0, Color32::from_black_alpha(160));
+        painter.galley(pos, galley);
+    }
+}
+
+/// Color for somewhere along the strip, from blue at the start through green to red at the end.
+/// Going around the hue circle rather than straight from blue to red keeps neighbouring stretches
+/// apart.
+pub fn gradient(t: f32) -> Color32 {
+    Hsva::new((1.0 - t.clamp(0.0, 1.0)) * 2.0 / 3.0, 1.0, 1.0, 1.0).into()
+}
+
+fn viridis(t: f32) -> Color32 {
+    let t = t.clamp(0.0, 1.0) * (VIRIDIS.len() - 1) as f32;
+    let i = (t as usize).min(VIRIDIS.len() - 2);
+    let (a, b) = (Rgba::from(VIRIDIS[i]), Rgba::from(VIRIDIS[i + 1]));
+    (a * (1.0 - (t - i as f32)) + b * (t - i as f32)).into()
+}
+
+/// Draws `old`, a map loaded from a file, faintly under the live view, with a line from each LED
+/// to where `current` has it when the LED was scanned again, so a shifted camera or installation
+/// shows up before rescanning.
+pub fn ghost(painter: &Painter, old: &[Led], current: &[Led]) {
+    let color = Color32::from_white_alpha(90);
+    let stroke = Stroke::new(1.0, Color32::from_rgba_unmultiplied(255, 160, 0, 160));
+
+    for led in old {
+        let center = Pos2::new(led.position[0], led.position[1]);
+        painter.circle_stroke(center, LED_MARKER_SIZE / 2.0, Stroke::new(1.5, color));
+
+        let now = current
+            .iter()
+            .find(|now| now.segment == led.segment && now.index == led.index);
+        if let Some(now) = now {
+            painter.line_segment([center, Pos2::new(now.position[0], now.position[1])], stroke);
+        }
+    }
+}
+
+/// Median distance from each LED of `old` to the nearest of `points`, for a rough idea of how far
+/// things moved with every LED lit. `None` without both.
+pub fn ghost_offset(old: &[Led], points: &[Rect]) -> Option<f32> {
+    let mut distances = old
+        .iter()
+        .filter_map(|led| {
+            let center = Pos2::new(led.position[0], led.position[1]);
+            points
+                .iter()
+                .map(|point| point.center().distance(center))
+                .min_by(f32::total_cmp)
+        })
+        .collect::<Vec<_>>();
+    if distances.is_empty() {
+        return None;
+    }
+
+    let middle = distances.len() / 2;
+    Some(*distances.select_nth_unstable_by(middle, f32::total_cmp).1)
+}
+
+/// Marks each LED in a color along `style`'s gradient by its index over every segment, so LEDs out
+/// of order or segments swapped show up as jumps in color.
+pub fn index_markers(painter: &Painter, leds: &[Led], style: &MarkerStyle) {
+    let layout = segments::LAYOUT.read().unwrap();
+    let global = |led: &Led| layout.global(led.segment, led.index);
+    let last = leds.iter().map(global).max().unwrap_or(0).max(1);
+
+    for led in leds {
+        let color = style.gradient(global(led) as f32 / last as f32);
+        let center = Pos2::new(led.position[0], led.position[1]);
+        let rect = Rect::from_center_size(center, Vec2::splat(LED_MARKER_SIZE));
+        style.draw(painter, rect, color);
+    }
+}
@@ -0,0 +1,58 @@
+use eframe::epaint::{Color32, Hsva, Pos2, Rect, Vec2};
+
+/// Spatial test patterns for checking a calibrated map against the physical LEDs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    SweepX,
+    ExpandingCircle,
+    Rainbow,
+}
+
+impl Pattern {
+    pub const ALL: [Self; 3] = [Self::SweepX, Self::ExpandingCircle, Self::Rainbow];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::SweepX => "Sweep along X",
+            Self::ExpandingCircle => "Expanding circle",
+            Self::Rainbow => "Rainbow by position",
+        }
+    }
+
+    /// Color of an LED at `pos` (normalized to 0..1 over the layout's bounding box), `t` seconds
+    /// after the pattern started.
+    pub fn color(self, pos: Pos2, t: f32) -> Color32 {
+        const PERIOD: f32 = 2.0;
+        let phase = (t / PERIOD).fract();
+
+        match self {
+            Self::SweepX => {
+                if (pos.x - phase).abs() < 0.05 {
+                    Color32::WHITE
+                } else {
+                    Color32::BLACK
+                }
+            }
+            Self::ExpandingCircle => {
+                let radius = phase * 0.75;
+                if (pos.distance(Pos2::new(0.5, 0.5)) - radius).abs() < 0.04 {
+                    Color32::WHITE
+                } else {
+                    Color32::BLACK
+                }
+            }
+            Self::Rainbow => Hsva::new((pos.x + t * 0.25).fract(), 1.0, 1.0, 1.0).into(),
+        }
+    }
+
+    /// Colors for every LED in `points`, in the same order.
+    pub fn render(self, points: &[Pos2], t: f32) -> Vec<Color32> {
+        let bounds = Rect::from_points(points);
+        let size = bounds.size().max(Vec2::splat(f32::EPSILON));
+
+        points
+            .iter()
+            .map(|point| self.color(((*point - bounds.min) / size).to_pos2(), t))
+            .collect()
+    }
+}
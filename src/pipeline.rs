@@ -0,0 +1,566 @@
+//! The decoder and detection threads, shared by the GUI and the headless subcommands.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use eframe::epaint::{Rect, TextureHandle};
+use led_detect::{subtract_dark, Mask, Yuv420};
+pub use led_detect::{
+    Approximation, Denoise, DetectionMode, DetectorKind, Retrieval, Settings, ThresholdMethod,
+};
+use tracing::{error, info, warn};
+use video_rs::{
+    ffmpeg::{
+        codec::context::Context as CodecContext,
+        format::Pixel,
+        frame::Video,
+        software::scaling::{Context as Scaler, Flags},
+    },
+    Decoder, Locator, Reader, Url,
+};
+
+use crate::{
+    frames::FrameBuffer,
+    freeze, preview, rpicam, stats,
+    toasts::{self, Retry},
+    v4l2, yuv,
+};
+
+/// The latest frame as packed RGB.
+pub static IMAGE: FrameBuffer = FrameBuffer::new();
+pub static IMAGE_WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// The latest frame as decoded, when the decoder was started with `yuv`. Detection prefers this
+/// over `IMAGE`. Shared so detection can keep working on a frame while the decoder moves on.
+pub static YUV_FRAME: RwLock<Option<Arc<Yuv420>>> = RwLock::new(None);
+
+pub static POINTS: RwLock<Vec<Rect>> = RwLock::new(Vec::new());
+
+/// A frame of the installation with every LED off, as packed RGB with its width. While set it's
+/// subtracted from every frame before thresholding, which takes out light that's always there.
+pub static DARK_FRAME: RwLock<Option<Arc<DarkFrame>>> = RwLock::new(None);
+
+/// The pixels and width of `DARK_FRAME`.
+pub type DarkFrame = (Vec<u8>, usize);
+
+/// Set once to stop the decoder and detection threads for good.
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+/// Bumped when switching to another source, which stops the decoder of the one before.
+static SOURCE: AtomicUsize = AtomicUsize::new(0);
+
+pub static SETTINGS: RwLock<Settings> = RwLock::new(Settings::DEFAULT);
+
+/// How long the detection thread sleeps between passes.
+pub static INTERVAL: RwLock<Duration> = RwLock::new(Duration::from_millis(100));
+
+/// Stops detection from updating `POINTS`, keeping the last detections on screen.
+pub static PAUSED: AtomicBool = AtomicBool::new(false);
+
+/// How RTSP streams are carried. Other kinds of streams ignore this.
+#[derive(Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Transport {
+    /// Everything over the one connection, which gets through NAT and firewalls.
+    #[default]
+    Tcp,
+    /// Video over separate UDP ports. Less latency, but frames get lost on busy networks.
+    Udp,
+}
+
+/// Time ffmpeg waits on a stalled stream before giving up, in microseconds. Can't be too low,
+/// since connecting to an RTSP camera sometimes takes a while.
+const STREAM_TIMEOUT_US: &str = "16000000";
+
+pub fn options(transport: Transport) -> video_rs::Options<'static> {
+    let transport = match transport {
+        Transport::Tcp => "tcp",
+        Transport::Udp => "udp",
+    };
+
+    video_rs::Options::new_from_hashmap(&HashMap::from(
+        [
+            ("rtsp_transport", transport),
+            ("rw_timeout", STREAM_TIMEOUT_US),
+            ("stimeout", STREAM_TIMEOUT_US),
+        ]
+        .map(|(key, value)| (key.to_owned(), value.to_owned())),
+    ))
+}
+
+/// Where ffmpeg should open `url`. Files go by path, as ffmpeg doesn't undo the percent-encoding
+/// of `file:` URLs.
+pub fn locator(url: &Url) -> Locator {
+    match url.to_file_path() {
+        Ok(path) if url.scheme() == "file" => Locator::Path(path),
+        _ => Locator::Url(url.clone()),
+    }
+}
+
+/// Holds back frames of a file to when they're due, which would otherwise be decoded as fast as
+/// they can be. Streams come in at their own pace.
+fn pace(url: &Url, started: Instant, seconds: Option<f64>) {
+    if url.scheme() != "file" {
+        return;
+    }
+    let Some(due) = seconds.and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()) else {
+        return;
+    };
+    if let Some(wait) = due.checked_sub(started.elapsed()) {
+        thread::sleep(wait);
+    }
+}
+
+/// `url` with the password blanked out, for logging.
+pub fn redacted(url: &Url) -> Url {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        let _ = url.set_password(Some("***"));
+    }
+    url
+}
+
+/// Decodes `url` into `IMAGE`, and into `texture` when running with a window. Credentials go in
+/// the URL, and ffmpeg answers both basic and digest challenges with them.
+///
+/// With `yuv` the frames are kept as decoded in `YUV_FRAME` instead, and only converted to RGB
+/// when there's a texture to show them in.
+pub fn spawn_decoder(
+    url: Url,
+    transport: Transport,
+    yuv: bool,
+    texture: Option<TextureHandle>,
+) -> JoinHandle<()> {
+    let source = source();
+    thread::spawn({
+        let mut texture = texture;
+        move || {
+            let shown = redacted(&url);
+
+            if yuv {
+                if let Err(e) = decode_yuv(&url, transport, source, texture) {
+                    error!("YUV decoder for {shown} stopped: {e}");
+                    toasts::error(format!("Video stream stopped: {e}"), Some(Retry::Stream));
+                }
+                return;
+            }
+
+            let opts = options(transport);
+            let mut decoder = match Decoder::new_with_options(&locator(&url), &opts) {
+                Ok(decoder) => decoder,
+                Err(e) => {
+                    error!("Failed to open {shown}: {e}");
+                    toasts::error(format!("Failed to open {shown}: {e}"), Some(Retry::Stream));
+                    return;
+                }
+            };
+
+            info!("Opened {shown}");
+
+            let time_base = stats::seconds(decoder.time_base());
+            let frame_rate = decoder.frame_rate() as f64;
+            let started = Instant::now();
+
+            for frame in decoder.decode_raw_iter() {
+                if source_stopped(source) {
+                    info!("Closing {shown}");
+                    return;
+                }
+
+                let frame = match frame {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        error!("Failed to decode frame, stopping: {e}");
+                        toasts::error(format!("Video stream stopped: {e}"), Some(Retry::Stream));
+                        return;
+                    }
+                };
+
+                let (width, height) = (frame.width() as usize, frame.height() as usize);
+                let seconds = frame.pts().map(|pts| pts as f64 * time_base);
+                pace(&url, started, seconds);
+                stats::frame_decoded(seconds, frame_rate);
+
+                if IMAGE_WIDTH.load(Ordering::Relaxed) != width {
+                    info!("Receiving {width}x{height} frames");
+                }
+
+                IMAGE.publish(|data| {
+                    packed_rgb(frame.data(0), frame.stride(0), width, height, data);
+
+                    if let Some(texture) = &mut texture {
+                        preview::show(texture, [width, height], data);
+                    }
+                });
+                IMAGE_WIDTH.store(width, Ordering::Relaxed);
+            }
+        }
+    })
+}
+
+/// Reads frames from a Raspberry Pi camera module like `spawn_decoder` with `yuv`.
+pub fn spawn_rpicam(camera: rpicam::Camera, texture: Option<TextureHandle>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = rpicam::decode(&camera, texture) {
+            error!("Raspberry Pi camera stopped: {e:#}");
+            toasts::error(format!("Camera stopped: {e:#}"), Some(Retry::Stream));
+        }
+    })
+}
+
+/// Reads frames from a local capture device, see `v4l2::decode`.
+pub fn spawn_v4l2(
+    path: PathBuf,
+    size: Option<[usize; 2]>,
+    texture: Option<TextureHandle>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = v4l2::decode(&path, size, texture) {
+            error!("{} stopped: {e:#}", path.display());
+            toasts::error(format!("Camera stopped: {e:#}"), Some(Retry::Stream));
+        }
+    })
+}
+
+/// Reads color and depth frames from a RealSense camera, see `realsense::decode`.
+#[cfg(feature = "realsense")]
+pub fn spawn_realsense(texture: Option<TextureHandle>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        if let Err(e) = crate::realsense::decode(texture) {
+            error!("RealSense camera stopped: {e:#}");
+            toasts::error(format!("Camera stopped: {e:#}"), Some(Retry::Stream));
+        }
+    })
+}
+
+/// Decodes without video-rs' conversion to RGB, which it always does.
+fn decode_yuv(
+    url: &Url,
+    transport: Transport,
+    source: usize,
+    mut texture: Option<TextureHandle>,
+) -> anyhow::Result<()> {
+    let shown = redacted(url);
+    let mut reader = Reader::new_with_options(&locator(url), &options(transport))
+        .map_err(|e| anyhow::anyhow!("Failed to open {shown}: {e}"))?;
+    let stream_index = reader
+        .best_video_stream_index()
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+    let stream = reader
+        .input
+        .stream(stream_index)
+        .ok_or_else(|| anyhow::anyhow!("No video stream in {shown}"))?;
+    let parameters = stream.parameters();
+    let time_base = stats::seconds(stream.time_base());
+    let frame_rate = stats::seconds(stream.avg_frame_rate());
+    let mut decoder = CodecContext::from_parameters(parameters)?
+        .decoder()
+        .video()?;
+
+    info!("Opened {shown} without RGB conversion");
+
+    let mut frame = Video::empty();
+    let mut rgb = Video::empty();
+    let mut scaler = None;
+    let started = Instant::now();
+
+    for (stream, packet) in reader.input.packets() {
+        if source_stopped(source) {
+            info!("Closing {shown}");
+            return Ok(());
+        }
+
+        if stream.index() != stream_index {
+            continue;
+        }
+
+        decoder.send_packet(&packet)?;
+
+        while decoder.receive_frame(&mut frame).is_ok() {
+            let yuv = yuv::from_frame(&frame).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "The stream is {:?}, the YUV path only handles planar 4:2:0",
+                    frame.format()
+                )
+            })?;
+            let (width, height) = (yuv.width, yuv.height);
+            let seconds = frame.pts().map(|pts| pts as f64 * time_base);
+            pace(url, started, seconds);
+            stats::frame_decoded(seconds, frame_rate);
+
+            if IMAGE_WIDTH.load(Ordering::Relaxed) != width {
+                info!("Receiving {width}x{height} frames");
+            }
+
+            // Only the preview needs RGB, and it doesn't need to be sharp
+            if let Some(texture) = texture.as_mut().filter(|_| preview::due()) {
+                let scaler = match &mut scaler {
+                    Some(scaler) => scaler,
+                    None => scaler.insert(Scaler::get(
+                        frame.format(),
+                        frame.width(),
+                        frame.height(),
+                        Pixel::RGB24,
+                        frame.width(),
+                        frame.height(),
+                        Flags::FAST_BILINEAR,
+                    )?),
+                };
+                scaler.run(&frame, &mut rgb)?;
+
+                IMAGE.publish(|data| {
+                    packed_rgb(rgb.data(0), rgb.stride(0), width, height, data);
+                    preview::show(texture, [width, height], data);
+                });
+            }
+
+            *YUV_FRAME.write().unwrap() = Some(Arc::new(yuv));
+            IMAGE_WIDTH.store(width, Ordering::Relaxed);
+        }
+    }
+
+    anyhow::bail!("End of stream")
+}
+
+/// Copies an RGB24 plane into `out`, tightly packed. Decoders are free to pad every row out to
+/// `stride` bytes for alignment, which nothing downstream expects.
+pub fn packed_rgb(data: &[u8], stride: usize, width: usize, height: usize, out: &mut Vec<u8>) {
+    let row = width * 3;
+    if stride == row {
+        out.extend_from_slice(&data[..row * height]);
+        return;
+    }
+
+    for line in data.chunks(stride).take(height) {
+        out.extend_from_slice(&line[..row]);
+    }
+}
+
+/// Periodically runs the detector picked in `SETTINGS` on the latest frame and publishes what it
+/// finds to `POINTS`.
+pub fn spawn_detection() -> JoinHandle<()> {
+    thread::spawn({
+        move || {
+            // Only log a failure once, instead of every pass while it keeps failing
+            let mut last_error = None;
+            let mut kind = SETTINGS.read().unwrap().detector;
+            let mut detector = kind.detector();
+
+            while !SHUTDOWN.load(Ordering::Relaxed) {
+                let interval = *INTERVAL.read().unwrap();
+                thread::sleep(interval);
+
+                if PAUSED.load(Ordering::Relaxed) {
+                    continue;
+                }
+
+                let width = IMAGE_WIDTH.load(Ordering::Relaxed);
+                let image = IMAGE.latest().unwrap_or_default();
+
+                if width == 0 {
+                    continue;
+                }
+
+                let settings = SETTINGS.read().unwrap().clone();
+                if settings.detector != kind {
+                    info!("Detecting with {}", settings.detector.name());
+                    kind = settings.detector;
+                    detector = kind.detector();
+                }
+
+                let frame_time = stats::frame_time();
+                // Not holding the locks while detecting, as the decoder needs them every frame
+                let dark = DARK_FRAME.read().unwrap().clone();
+                let yuv = YUV_FRAME.read().unwrap().clone();
+                if let Some(arrived) = frame_time {
+                    freeze::picture(yuv.as_ref().map_or(&image[..], |yuv| &yuv.y), arrived);
+                }
+                let points = match (yuv.as_ref(), dark.as_ref()) {
+                    // Subtracting only makes sense in RGB, so that needs the full conversion
+                    (Some(yuv), Some(dark)) => detector.detect_rgb(
+                        &subtract_dark(yuv.to_rgb(), yuv.width, dark),
+                        yuv.width,
+                        &settings,
+                    ),
+                    (Some(yuv), None) => detector.detect_yuv(yuv, &settings),
+                    (None, Some(dark)) => detector.detect_rgb(
+                        &subtract_dark(image.to_vec(), width, dark),
+                        width,
+                        &settings,
+                    ),
+                    (None, None) => detector.detect_rgb(&image, width, &settings),
+                };
+                freeze::check();
+
+                match points {
+                    Ok(points) => {
+                        *POINTS.write().unwrap() = points;
+                        stats::detection_done(frame_time);
+                        last_error = None;
+                    }
+                    Err(e) => {
+                        let e = e.to_string();
+                        if last_error.as_ref() != Some(&e) {
+                            warn!("Detection failed: {e}");
+                            toasts::error(format!("Detection failed: {e}"), None);
+                            last_error = Some(e);
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// The latest frame as packed RGB with its width, converting from YUV when there's no RGB copy.
+pub fn latest_rgb() -> Option<(Arc<Vec<u8>>, usize)> {
+    let yuv = YUV_FRAME.read().unwrap().clone();
+    if let Some(yuv) = yuv {
+        return Some((Arc::new(yuv.to_rgb()), yuv.width));
+    }
+
+    let width = IMAGE_WIDTH.load(Ordering::Relaxed);
+    let image = IMAGE.latest()?;
+    (width > 0 && !image.is_empty()).then_some((image, width))
+}
+
+/// Width and height of the latest frame, without converting it.
+pub fn frame_size() -> Option<[usize; 2]> {
+    if let Some(yuv) = YUV_FRAME.read().unwrap().as_ref() {
+        return Some([yuv.width, yuv.height]);
+    }
+
+    let width = IMAGE_WIDTH.load(Ordering::Relaxed);
+    let image = IMAGE.latest()?;
+    (width > 0 && !image.is_empty()).then(|| [width, image.len() / 3 / width])
+}
+
+/// The mask detection finds the blobs in for the latest frame, as the detection thread would
+/// threshold it.
+pub fn latest_mask() -> anyhow::Result<Option<Mask>> {
+    let settings = SETTINGS.read().unwrap().clone();
+    let dark = DARK_FRAME.read().unwrap().clone();
+    let yuv = YUV_FRAME.read().unwrap().clone();
+    if let (Some(yuv), None) = (yuv, dark.as_ref()) {
+        return Ok(Some(led_detect::mask_yuv(&yuv, &settings)?));
+    }
+
+    let Some((frame, width)) = latest_rgb() else {
+        return Ok(None);
+    };
+    let mask = match dark.as_ref() {
+        Some(dark) => {
+            led_detect::mask_rgb(&subtract_dark(frame.to_vec(), width, dark), width, &settings)
+        }
+        None => led_detect::mask_rgb(&frame, width, &settings),
+    };
+    Ok(Some(mask?))
+}
+
+/// Records the latest frame as `DARK_FRAME`. The LEDs should all be off by now.
+pub fn capture_dark_frame() -> anyhow::Result<()> {
+    let (frame, width) =
+        latest_rgb().ok_or_else(|| anyhow::anyhow!("No frame has been received yet"))?;
+    *DARK_FRAME.write().unwrap() = Some(Arc::new((Arc::unwrap_or_clone(frame), width)));
+    info!("Captured a dark frame");
+    Ok(())
+}
+
+/// Runs detection on `frame` like the detection thread would, dark frame and all.
+pub fn detect_frame(frame: &[u8], width: usize) -> led_detect::Result<Vec<Rect>> {
+    let settings = SETTINGS.read().unwrap().clone();
+    match DARK_FRAME.read().unwrap().as_ref() {
+        Some(dark) => {
+            led_detect::detect_rgb(&subtract_dark(frame.to_vec(), width, dark), width, &settings)
+        }
+        None => led_detect::detect_rgb(frame, width, &settings),
+    }
+}
+
+/// Whether frames come with capture times, see `frame_after`. YUV frames skip `IMAGE` and so
+/// don't.
+pub fn frames_stamped() -> bool {
+    YUV_FRAME.read().unwrap().is_none() && IMAGE.latest().is_some()
+}
+
+/// Waits for the first frame captured at or after `instant` and returns it with its width and
+/// how long after `instant` it was captured. `None` if there's none before `timeout`.
+pub fn frame_after(instant: Instant, timeout: Duration) -> Option<(Arc<Vec<u8>>, usize, Duration)> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline && !shutting_down() {
+        if let Some((frame, captured)) = IMAGE.latest_stamped() {
+            let width = IMAGE_WIDTH.load(Ordering::Relaxed);
+            if captured >= instant && width > 0 {
+                return Some((frame, width, captured - instant));
+            }
+        }
+        thread::sleep(Duration::from_millis(2));
+    }
+    None
+}
+
+/// Runs detection with the current settings on a frame other than the live one.
+pub fn detect_rgb(image_data: &[u8], width: usize) -> led_detect::Result<Vec<Rect>> {
+    led_detect::detect_rgb(image_data, width, &SETTINGS.read().unwrap())
+}
+
+/// Blocks until the decoder has produced its first frame.
+pub fn wait_for_first_frame(timeout: Duration) -> anyhow::Result<()> {
+    let start = Instant::now();
+    while IMAGE_WIDTH.load(Ordering::Relaxed) == 0 {
+        if start.elapsed() > timeout {
+            anyhow::bail!("No frames received from the stream within {timeout:?}");
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    Ok(())
+}
+
+/// Asks the decoder and detection threads to stop after their current frame.
+pub fn shutdown() {
+    SHUTDOWN.store(true, Ordering::Relaxed);
+}
+
+/// Whether `shutdown` has been called, for other threads that should stop along with these.
+pub fn shutting_down() -> bool {
+    SHUTDOWN.load(Ordering::Relaxed)
+}
+
+/// Stops the decoder of the current source, for starting one on another.
+pub fn switch_source() {
+    SOURCE.fetch_add(1, Ordering::Relaxed);
+    freeze::reset();
+}
+
+/// The current source, for a decoder to check with `source_stopped` as it goes.
+pub fn source() -> usize {
+    SOURCE.load(Ordering::Relaxed)
+}
+
+/// Whether a decoder started on `source` should stop, because of a switch or `shutdown`.
+pub fn source_stopped(source: usize) -> bool {
+    shutting_down() || SOURCE.load(Ordering::Relaxed) != source
+}
+
+/// Joins `handle` unless it is still running at `deadline`, in which case it's left to die with
+/// the process. Returns whether the thread finished.
+pub fn join_until(handle: JoinHandle<()>, deadline: Instant) -> bool {
+    while !handle.is_finished() {
+        if Instant::now() > deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    if handle.join().is_err() {
+        warn!("Worker thread panicked");
+    }
+    true
+}
@@ -0,0 +1,97 @@
+//! Working out the pixel order of each segment by lighting it red and then green and looking at
+//! which color the camera sees. Scans map fine with the wrong order, as they only look for light,
+//! so it tends to go unnoticed until everything played on the map comes out green-shifted.
+
+use std::{sync::Mutex, thread};
+
+use anyhow::Context;
+use eframe::epaint::Color32;
+use tracing::{info, warn};
+
+use crate::{
+    controller::PixelOrder,
+    ignored, pipeline,
+    scan::{self, SharedController},
+    segments::Segment,
+};
+
+/// The last detection's order for each segment, `None` where it couldn't tell, waiting for the
+/// window to take it into the segments and reconnect.
+pub static DETECTED: Mutex<Option<Vec<Option<PixelOrder>>>> = Mutex::new(None);
+
+/// Lights every LED of each of `segments` in turn, which `controller` is connected to, and works
+/// out the order each is really wired in from what its current order shows. All of a segment at
+/// once rather than a single LED, so it doesn't matter which of them are in view.
+pub fn detect(
+    controller: &SharedController,
+    segments: &[Segment],
+) -> anyhow::Result<Vec<Option<PixelOrder>>> {
+    let mut controller = controller.lock().unwrap();
+    let step = scan::step_time(controller.latency_hint());
+    let settings = scan::SETTINGS.read().unwrap().clone();
+
+    let mut orders = Vec::new();
+    let mut offset = 0;
+    for segment in segments {
+        let leds = offset..offset + segment.config.led_count;
+        offset = leds.end;
+
+        let mut shown = [None; 2];
+        for (shown, color) in shown.iter_mut().zip([Color32::RED, Color32::GREEN]) {
+            controller.set_all(Color32::BLACK);
+            for index in leds.clone() {
+                controller.set_pixel(index, settings.dim(color));
+            }
+            controller.flush()?;
+            thread::sleep(step);
+
+            let (frame, width) = pipeline::latest_rgb().context("No frames from the stream")?;
+            let points = ignored::filter(&pipeline::detect_frame(&frame, width)?);
+            let total = points
+                .iter()
+                .map(|rect| scan::mean_color(&frame, width, rect))
+                .fold([0.0; 3], |[r, g, b], [dr, dg, db]| [r + dr, g + dg, b + db]);
+            *shown = strongest(total);
+        }
+
+        let current = segment.config.pixel_order;
+        let order = match shown {
+            [Some(red), Some(green)] if red != green => {
+                current.corrected([red, green, 3 - red - green])
+            }
+            _ => None,
+        };
+        match order {
+            Some(order) if order == current => {
+                info!("Segment {:?} is wired {}, as set", segment.name, order.name())
+            }
+            Some(order) => info!(
+                "Segment {:?} is wired {}, not {}",
+                segment.name,
+                order.name(),
+                current.name()
+            ),
+            None => warn!(
+                "Couldn't tell the pixel order of segment {:?}, red and green showed as {shown:?}",
+                segment.name
+            ),
+        }
+        orders.push(order);
+    }
+
+    controller.set_all(Color32::BLACK);
+    controller.flush()?;
+    Ok(orders)
+}
+
+/// The channel well above the other two in `rgb`, `None` if there's none or nothing was lit.
+fn strongest(rgb: [f32; 3]) -> Option<usize> {
+    let (strongest, max) = rgb
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let others = rgb.iter().sum::<f32>() - max;
+
+    (max > others).then_some(strongest)
+}
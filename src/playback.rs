@@ -0,0 +1,202 @@
+//! Going back over a recorded scan, with a timeline of its steps to scrub through. Each step shows
+//! the frame captured for it and where its LED was found, so a single LED that went wrong can be
+//! looked at without running the scan again.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use eframe::{
+    egui::{self, Key, Sense, TextureOptions},
+    epaint::{Color32, ColorImage, Pos2, Rect, Stroke, TextureHandle, Vec2},
+};
+
+use crate::{
+    recording::{Step, STEPS},
+    toasts,
+};
+
+const TIMELINE_HEIGHT: f32 = 24.0;
+/// Widest the frame is shown, as it's usually a lot bigger than the window, and the timeline above.
+const FRAME_WIDTH: f32 = 640.0;
+
+pub struct Playback {
+    directory: PathBuf,
+    steps: Vec<Step>,
+    current: usize,
+    /// The frame of `current`, with its size, `None` until loaded or if it couldn't be.
+    frame: Option<(usize, TextureHandle, [usize; 2])>,
+    /// LED to jump to the first step of.
+    find: usize,
+}
+
+impl Playback {
+    /// Opens the recording written to `directory`.
+    pub fn open(directory: &Path) -> anyhow::Result<Self> {
+        let path = directory.join(STEPS);
+        let steps = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<Step>, _>>()
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        anyhow::ensure!(!steps.is_empty(), "{} has no steps", path.display());
+
+        Ok(Self {
+            directory: directory.to_owned(),
+            steps,
+            current: 0,
+            frame: None,
+            find: 0,
+        })
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let found = self.steps.iter().filter(|s| s.position.is_some()).count();
+        ui.label(format!("{} steps, {found} of them found their LED", self.steps.len()));
+
+        self.timeline(ui);
+
+        ui.horizontal(|ui| {
+            if ui.button("<").on_hover_text("Left arrow").clicked() {
+                self.current = self.current.saturating_sub(1);
+            }
+            if ui.button(">").on_hover_text("Right arrow").clicked() {
+                self.current = (self.current + 1).min(self.steps.len() - 1);
+            }
+
+            ui.separator();
+            ui.label("LED");
+            ui.add(egui::DragValue::new(&mut self.find));
+            if ui.button("Find").clicked() {
+                match self.steps.iter().position(|s| s.index == self.find) {
+                    Some(step) => self.current = step,
+                    None => {
+                        toasts::error(format!("LED {} isn't in this recording", self.find), None)
+                    }
+                }
+            }
+        });
+
+        let step = &self.steps[self.current];
+        ui.label(format!(
+            "Step {} of {}, LED {} at {:.1} s: {}",
+            self.current + 1,
+            self.steps.len(),
+            step.index,
+            step.millis as f32 / 1000.0,
+            match step.position {
+                Some([x, y]) => format!("found at {x:.1}, {y:.1}"),
+                None => "not found".to_owned(),
+            }
+        ));
+
+        self.draw_frame(ui);
+    }
+
+    /// The steps laid out by when they were captured, green where the LED was found and red where
+    /// it wasn't. Clicking or dragging picks a step, and the arrow keys move step by step.
+    fn timeline(&mut self, ui: &mut egui::Ui) {
+        let (response, painter) =
+            ui.allocate_painter(Vec2::new(FRAME_WIDTH, TIMELINE_HEIGHT), Sense::click_and_drag());
+        let rect = response.rect;
+        painter.rect_filled(rect, 2.0, Color32::from_gray(30));
+
+        let end = self.steps.last().map_or(0, |s| s.millis).max(1) as f32;
+        let x = |step: &Step| rect.left() + rect.width() * step.millis as f32 / end;
+        for step in &self.steps {
+            let color = match step.position {
+                Some(_) => Color32::GREEN,
+                None => Color32::RED,
+            };
+            let x = x(step);
+            painter.line_segment(
+                [Pos2::new(x, rect.top() + 6.0), Pos2::new(x, rect.bottom() - 6.0)],
+                Stroke::new(1.0, color),
+            );
+        }
+        let x_current = x(&self.steps[self.current]);
+        painter.line_segment(
+            [Pos2::new(x_current, rect.top()), Pos2::new(x_current, rect.bottom())],
+            Stroke::new(2.0, Color32::WHITE),
+        );
+
+        if let Some(pointer) = response.interact_pointer_pos() {
+            let millis = (pointer.x - rect.left()) / rect.width() * end;
+            self.current = nearest(&self.steps, millis);
+        }
+
+        if response.hovered() && !ui.ctx().wants_keyboard_input() {
+            ui.input(|input| {
+                if input.key_pressed(Key::ArrowLeft) {
+                    self.current = self.current.saturating_sub(1);
+                }
+                if input.key_pressed(Key::ArrowRight) {
+                    self.current = (self.current + 1).min(self.steps.len() - 1);
+                }
+            });
+        }
+    }
+
+    /// The current step's frame with a cross where its LED was found, loading it if it changed.
+    fn draw_frame(&mut self, ui: &mut egui::Ui) {
+        let step = &self.steps[self.current];
+        if self.frame.as_ref().map(|(frame, ..)| *frame) != Some(step.frame) {
+            let path = self.directory.join(format!("frame-{:05}.png", step.frame));
+            self.frame = match image::open(&path) {
+                Ok(image) => {
+                    let image = image.to_rgb8();
+                    let size = [image.width() as usize, image.height() as usize];
+                    let texture = ui.ctx().load_texture(
+                        "recorded frame",
+                        ColorImage::from_rgb(size, image.as_raw()),
+                        TextureOptions::LINEAR,
+                    );
+                    Some((step.frame, texture, size))
+                }
+                Err(e) => {
+                    ui.label(format!("Can't open {}: {e}", path.display()));
+                    None
+                }
+            };
+        }
+        let Some((_, texture, size)) = &self.frame else {
+            return;
+        };
+
+        let scale = (FRAME_WIDTH / size[0] as f32).min(1.0);
+        let (response, painter) =
+            ui.allocate_painter(Vec2::new(size[0] as f32, size[1] as f32) * scale, Sense::hover());
+        let rect = response.rect;
+        painter.image(
+            texture.id(),
+            rect,
+            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+            Color32::WHITE,
+        );
+        if let Some([x, y]) = step.position {
+            let pos = rect.min + Vec2::new(x, y) * scale;
+            let stroke = Stroke::new(1.5, Color32::YELLOW);
+            painter.circle_stroke(pos, 10.0, stroke);
+            painter.line_segment([pos - Vec2::X * 14.0, pos + Vec2::X * 14.0], stroke);
+            painter.line_segment([pos - Vec2::Y * 14.0, pos + Vec2::Y * 14.0], stroke);
+        }
+    }
+}
+
+/// The step captured closest to `millis` into the scan.
+fn nearest(steps: &[Step], millis: f32) -> usize {
+    (0..steps.len())
+        .min_by(|&a, &b| {
+            let off = |i: usize| (steps[i].millis as f32 - millis).abs();
+            off(a).total_cmp(&off(b))
+        })
+        .unwrap_or(0)
+}
@@ -0,0 +1,115 @@
+//! Keeping the video in the window light, for weak Wi-Fi on site and slow laptops. The window can
+//! show a camera's substream while the main stream goes on feeding detection and scans at full
+//! resolution, and whichever it shows can be updated less often and at a fraction of its
+//! resolution.
+//!
+//! Everything drawn over the video stays in frame pixels of the main stream, so the substream
+//! has to show the same view, only smaller.
+
+use std::{
+    sync::{Mutex, RwLock},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use eframe::{
+    egui::TextureOptions,
+    epaint::{Color32, ColorImage, TextureHandle},
+};
+use tracing::{error, info};
+use video_rs::{Decoder, Url};
+
+use crate::{
+    pipeline::{self, Transport},
+    toasts::{self, Retry},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+pub struct Preview {
+    /// Most updates of the video in the window a second, 0 for every frame.
+    pub max_fps: f32,
+    /// Frame pixels per preview pixel in each direction, 1 for full resolution.
+    pub downscale: usize,
+}
+
+pub static SETTINGS: RwLock<Preview> = RwLock::new(Preview { max_fps: 0.0, downscale: 1 });
+
+/// When the video in the window was last updated.
+static LAST: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Whether it's time to update the video in the window again.
+pub fn due() -> bool {
+    let max_fps = SETTINGS.read().unwrap().max_fps;
+    let last = *LAST.lock().unwrap();
+    max_fps <= 0.0
+        || last.is_none_or(|last| last.elapsed() >= Duration::from_secs_f32(1.0 / max_fps))
+}
+
+/// Puts the `width` by `height` packed RGB `frame` in `texture` when it's `due`, at the preview's
+/// resolution.
+pub fn show(texture: &mut TextureHandle, [width, height]: [usize; 2], frame: &[u8]) {
+    if !due() {
+        return;
+    }
+    *LAST.lock().unwrap() = Some(Instant::now());
+
+    let downscale = SETTINGS.read().unwrap().downscale.max(1);
+    let image = match downscale {
+        1 => ColorImage::from_rgb([width, height], frame),
+        step => ColorImage {
+            size: [width.div_ceil(step), height.div_ceil(step)],
+            pixels: (0..height)
+                .step_by(step)
+                .flat_map(|y| (0..width).step_by(step).map(move |x| (y * width + x) * 3))
+                .map(|i| Color32::from_rgb(frame[i], frame[i + 1], frame[i + 2]))
+                .collect(),
+        },
+    };
+    texture.set(image, TextureOptions::LINEAR);
+}
+
+/// Decodes `url` into `texture` alone, until the main stream's decoder stops.
+pub fn spawn_substream(
+    url: Url,
+    transport: Transport,
+    mut texture: TextureHandle,
+) -> JoinHandle<()> {
+    let source = pipeline::source();
+    thread::spawn(move || {
+        let shown = pipeline::redacted(&url);
+        if let Err(e) = run(&url, transport, source, &mut texture) {
+            error!("Preview stream {shown} stopped: {e:#}");
+            toasts::error(format!("Preview stream stopped: {e:#}"), Some(Retry::Stream));
+        }
+    })
+}
+
+fn run(
+    url: &Url,
+    transport: Transport,
+    source: usize,
+    texture: &mut TextureHandle,
+) -> anyhow::Result<()> {
+    let mut decoder =
+        Decoder::new_with_options(&pipeline::locator(url), &pipeline::options(transport))
+            .map_err(|e| anyhow::anyhow!("Failed to open it: {e}"))?;
+    info!("Showing {} in the window", pipeline::redacted(url));
+
+    let mut rgb = Vec::new();
+    for frame in decoder.decode_raw_iter() {
+        if pipeline::source_stopped(source) {
+            return Ok(());
+        }
+        let frame = frame.map_err(|e| anyhow::anyhow!("{e}"))?;
+        if !due() {
+            continue;
+        }
+
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+        rgb.clear();
+        pipeline::packed_rgb(frame.data(0), frame.stride(0), width, height, &mut rgb);
+        show(texture, [width, height], &rgb);
+    }
+
+    anyhow::bail!("End of stream")
+}
@@ -0,0 +1,133 @@
+//! Several projects open at once, like the props at one venue, each with its own detection and
+//! scan settings, map, ignored sources, segments, angle of view and export path, in tabs along
+//! the top. They share the camera and everything else about the window, the angle of view being
+//! per project for zoom lenses.
+//!
+//! Only the shown project lives in the globals everything works on. Switching puts it away as an
+//! autosave `Session` and restores the other one, so a project is exactly what a crash recovery
+//! brings back.
+
+use std::sync::atomic::Ordering;
+
+use eframe::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{autosave::Session, scan, segments::Segment};
+
+#[derive(Serialize, Deserialize)]
+struct Project {
+    name: String,
+    export_path: String,
+    /// `None` for the shown project, whose state is in the globals.
+    session: Option<Session>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Projects {
+    projects: Vec<Project>,
+    shown: usize,
+}
+
+impl Projects {
+    pub fn new(export_path: &str) -> Self {
+        Self {
+            projects: vec![Project {
+                name: "Project 1".to_owned(),
+                export_path: export_path.to_owned(),
+                session: None,
+            }],
+            shown: 0,
+        }
+    }
+
+    /// The project tabs, switching `segments` and `export_path` along with the globals. Returns
+    /// whether another project is shown now, for the caller to connect its segments.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        segments: &mut Vec<Segment>,
+        export_path: &mut String,
+    ) -> bool {
+        let mut switch = None;
+        let mut close = None;
+        let closable = self.projects.len() > 1;
+
+        // The scan writes to the shown project's map as it goes
+        let idle = !scan::RUNNING.load(Ordering::Relaxed);
+        ui.add_enabled_ui(idle, |ui| {
+            ui.horizontal(|ui| {
+                for (i, project) in self.projects.iter_mut().enumerate() {
+                    let response = ui.selectable_label(i == self.shown, &project.name);
+                    if response.clicked() && i != self.shown {
+                        switch = Some(i);
+                    }
+                    response.context_menu(|ui| {
+                        ui.text_edit_singleline(&mut project.name);
+                        if closable && ui.button("Close").clicked() {
+                            close = Some(i);
+                            ui.close_menu();
+                        }
+                    });
+                }
+
+                if ui
+                    .button("+")
+                    .on_hover_text("New project with the current settings and segments")
+                    .clicked()
+                {
+                    self.projects.push(Project {
+                        name: format!("Project {}", self.projects.len() + 1),
+                        export_path: export_path.clone(),
+                        session: Some(Session::fresh(segments)),
+                    });
+                    switch = Some(self.projects.len() - 1);
+                }
+            });
+        });
+
+        if let Some(i) = close {
+            // Closing the shown project shows a neighbour first
+            let shown = i == self.shown;
+            if shown {
+                self.switch(if i == 0 { 1 } else { i - 1 }, segments, export_path);
+            }
+            self.projects.remove(i);
+            if self.shown > i {
+                self.shown -= 1;
+            }
+            return shown;
+        }
+
+        match switch {
+            Some(i) => {
+                self.switch(i, segments, export_path);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn switch(&mut self, to: usize, segments: &mut Vec<Segment>, export_path: &mut String) {
+        let shown = &mut self.projects[self.shown];
+        shown.session = Some(Session::current(segments));
+        shown.export_path = std::mem::take(export_path);
+
+        let next = &mut self.projects[to];
+        *export_path = next.export_path.clone();
+        if let Some(session) = next.session.take() {
+            *segments = session.restore();
+        }
+        self.shown = to;
+    }
+
+    /// Brings the shown project's export path up to date for saving. The rest of its state is
+    /// saved like when there's a single project, if at all.
+    pub fn sync(&mut self, export_path: &str) {
+        self.projects[self.shown].export_path = export_path.to_owned();
+    }
+
+    /// The export path of the project shown after a restart.
+    pub fn export_path(&self) -> &str {
+        &self.projects[self.shown].export_path
+    }
+}
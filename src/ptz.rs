@@ -0,0 +1,572 @@
+//! Pan, tilt and zoom for PTZ cameras over ONVIF, for installations too big to see well from one
+//! camera position. See `regions` for scanning them a region at a time.
+//!
+//! Only the handful of SOAP calls needed here are spoken, and the answers are picked apart by
+//! element name instead of with a full XML parser. Positions are in ONVIF's generic spaces: pan
+//! and tilt from -1 to 1, zoom from 0 to 1.
+
+use std::{
+    fmt, fs,
+    path::Path,
+    sync::{Arc, RwLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use eframe::egui::{self, Sense, Slider};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tracing::info;
+
+use crate::{lens::Lens, toasts};
+
+/// The camera connected to, for the regions scan.
+pub static CAMERA: RwLock<Option<Arc<Ptz>>> = RwLock::new(None);
+
+/// Where the regions scan points the camera, in order. Each region has to overlap one before it.
+pub static REGIONS: RwLock<Vec<Position>> = RwLock::new(Vec::new());
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest a move is waited on, for cameras that never report being done.
+const MOVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+const IMAGING: &str = "http://www.onvif.org/ver20/imaging/wsdl";
+
+#[derive(Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Position {
+    pub pan: f32,
+    pub tilt: f32,
+    pub zoom: f32,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.3},{:.3},{:.3}", self.pan, self.tilt, self.zoom)
+    }
+}
+
+pub struct Ptz {
+    agent: ureq::Agent,
+    ptz_url: String,
+    username: String,
+    password: String,
+    /// Media profile the moves are for, the first the camera lists.
+    profile: String,
+    /// URL of the imaging service and the video source of the profile, for cameras with one.
+    imaging: Option<(String, String)>,
+    /// The angle of view of the profile's video source, for cameras that describe their lens.
+    lens: Option<Lens>,
+}
+
+impl Ptz {
+    /// Connects to the camera at `address`, a host or the full device service URL, and finds its
+    /// PTZ service and media profile.
+    pub fn connect(address: &str, username: &str, password: &str) -> anyhow::Result<Self> {
+        let device_url = if address.contains("://") {
+            address.to_owned()
+        } else {
+            format!("http://{address}/onvif/device_service")
+        };
+
+        let mut ptz = Self {
+            agent: ureq::AgentBuilder::new().timeout(TIMEOUT).build(),
+            ptz_url: String::new(),
+            username: username.to_owned(),
+            password: password.to_owned(),
+            profile: String::new(),
+            imaging: None,
+            lens: None,
+        };
+
+        let capabilities = ptz.call(
+            &device_url,
+            "http://www.onvif.org/ver10/device/wsdl",
+            "<GetCapabilities><Category>All</Category></GetCapabilities>",
+        )?;
+        let media_url = section(&capabilities, "Media")
+            .and_then(|media| text(media, "XAddr"))
+            .context("The camera has no media service")?
+            .to_owned();
+        ptz.ptz_url = section(&capabilities, "PTZ")
+            .and_then(|ptz| text(ptz, "XAddr"))
+            .context("The camera has no PTZ service")?
+            .to_owned();
+
+        let profiles =
+            ptz.call(&media_url, "http://www.onvif.org/ver10/media/wsdl", "<GetProfiles/>")?;
+        ptz.profile = attribute(&profiles, "Profiles", "token")
+            .context("The camera has no media profiles")?
+            .to_owned();
+        let imaging_url =
+            section(&capabilities, "Imaging").and_then(|imaging| text(imaging, "XAddr"));
+        let configuration = section(&profiles, "VideoSourceConfiguration");
+        let source = configuration.and_then(|configuration| text(configuration, "SourceToken"));
+        ptz.imaging = imaging_url
+            .zip(source)
+            .map(|(url, source)| (url.to_owned(), source.to_owned()));
+        ptz.lens = configuration
+            .and_then(|configuration| section(configuration, "LensDescription"))
+            .and_then(angle_of_view);
+
+        info!("Connected to the PTZ camera at {device_url}, profile {}", ptz.profile);
+        Ok(ptz)
+    }
+
+    /// Starts moving at these speeds, from -1 to 1, until `stop`.
+    pub fn continuous_move(&self, pan: f32, tilt: f32, zoom: f32) -> anyhow::Result<()> {
+        self.ptz(&format!(
+            "<ContinuousMove><ProfileToken>{}</ProfileToken><Velocity>\
+             <tt:PanTilt x=\"{pan}\" y=\"{tilt}\"/><tt:Zoom x=\"{zoom}\"/>\
+             </Velocity></ContinuousMove>",
+            self.profile
+        ))
+        .map(drop)
+    }
+
+    pub fn stop(&self) -> anyhow::Result<()> {
+        self.ptz(&format!(
+            "<Stop><ProfileToken>{}</ProfileToken><PanTilt>true</PanTilt><Zoom>true</Zoom></Stop>",
+            self.profile
+        ))
+        .map(drop)
+    }
+
+    /// Moves to `position` and waits until the camera is still there.
+    pub fn go_to(&self, position: Position) -> anyhow::Result<()> {
+        let Position { pan, tilt, zoom } = position;
+        self.ptz(&format!(
+            "<AbsoluteMove><ProfileToken>{}</ProfileToken><Position>\
+             <tt:PanTilt x=\"{pan}\" y=\"{tilt}\"/><tt:Zoom x=\"{zoom}\"/>\
+             </Position></AbsoluteMove>",
+            self.profile
+        ))?;
+
+        let start = Instant::now();
+        while start.elapsed() < MOVE_TIMEOUT {
+            thread::sleep(Duration::from_millis(250));
+            let status = self.status()?;
+            let moving = section(&status, "MoveStatus");
+            // Cameras that don't report it get a few seconds
+            match moving.and_then(|moving| text(moving, "PanTilt").zip(text(moving, "Zoom"))) {
+                Some(("IDLE", "IDLE")) => return Ok(()),
+                Some(_) => {}
+                None if start.elapsed() > Duration::from_secs(3) => return Ok(()),
+                None => {}
+            }
+        }
+        anyhow::bail!("The camera didn't get to {position} in time")
+    }
+
+    /// Where the camera is pointed now.
+    pub fn position(&self) -> anyhow::Result<Position> {
+        let status = self.status()?;
+        let position = section(&status, "Position").context("The camera didn't say where it is")?;
+        let value = |element, attr| -> anyhow::Result<f32> {
+            attribute(position, element, attr)
+                .and_then(|value| value.parse().ok())
+                .with_context(|| format!("The camera didn't report its {element} {attr}"))
+        };
+        Ok(Position {
+            pan: value("PanTilt", "x")?,
+            tilt: value("PanTilt", "y")?,
+            zoom: value("Zoom", "x")?,
+        })
+    }
+
+    /// The angle of view the camera describes its lens with, `None` for most, which don't.
+    pub fn lens(&self) -> Option<Lens> {
+        self.lens
+    }
+
+    /// Whether the camera balances white by itself, `None` if it can't say.
+    pub fn auto_white_balance(&self) -> anyhow::Result<Option<bool>> {
+        let Some(settings) = self.imaging_settings()? else {
+            return Ok(None);
+        };
+        let mode = section(&settings, "WhiteBalance").and_then(|balance| text(balance, "Mode"));
+        Ok(mode.map(|mode| mode == "AUTO"))
+    }
+
+    /// Turns automatic white balance on or off, off keeping the gains it's at.
+    pub fn set_auto_white_balance(&self, auto: bool) -> anyhow::Result<()> {
+        let (url, source) = self
+            .imaging
+            .as_ref()
+            .context("The camera has no imaging service")?;
+
+        let balance = if auto {
+            "<tt:Mode>AUTO</tt:Mode>".to_owned()
+        } else {
+            // Some cameras want the gains along with manual mode
+            let settings = self.imaging_settings()?.unwrap_or_default();
+            let current = section(&settings, "WhiteBalance").unwrap_or_default();
+            let gain = |name| {
+                text(current, name)
+                    .map_or(String::new(), |gain| format!("<tt:{name}>{gain}</tt:{name}>"))
+            };
+            format!("<tt:Mode>MANUAL</tt:Mode>{}{}", gain("CrGain"), gain("CbGain"))
+        };
+        self.call(
+            url,
+            IMAGING,
+            &format!(
+                "<SetImagingSettings><VideoSourceToken>{source}</VideoSourceToken>\
+                 <ImagingSettings><tt:WhiteBalance>{balance}</tt:WhiteBalance></ImagingSettings>\
+                 <ForcePersistence>false</ForcePersistence></SetImagingSettings>"
+            ),
+        )
+        .map(drop)
+    }
+
+    /// What the imaging service answers for the profile's video source, `None` without one.
+    fn imaging_settings(&self) -> anyhow::Result<Option<String>> {
+        let Some((url, source)) = &self.imaging else {
+            return Ok(None);
+        };
+        let body = format!(
+            "<GetImagingSettings><VideoSourceToken>{source}</VideoSourceToken></GetImagingSettings>"
+        );
+        self.call(url, IMAGING, &body).map(Some)
+    }
+
+    fn status(&self) -> anyhow::Result<String> {
+        self.ptz(&format!("<GetStatus><ProfileToken>{}</ProfileToken></GetStatus>", self.profile))
+    }
+
+    fn ptz(&self, body: &str) -> anyhow::Result<String> {
+        self.call(&self.ptz_url, "http://www.onvif.org/ver20/ptz/wsdl", body)
+    }
+
+    /// Sends `body` in the `namespace` of a service, with a WS-Security header if there's a
+    /// username, and returns the answer.
+    fn call(&self, url: &str, namespace: &str, body: &str) -> anyhow::Result<String> {
+        let security = if self.username.is_empty() {
+            String::new()
+        } else {
+            self.security()
+        };
+        // The body's elements default to the service's namespace
+        let mut body = body.to_owned();
+        let name_end = body.find(['>', '/', ' ']).unwrap_or(body.len());
+        body.insert_str(name_end, &format!(" xmlns=\"{namespace}\""));
+        let envelope = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <s:Envelope xmlns:s=\"http://www.w3.org/2003/05/soap-envelope\" \
+             xmlns:tt=\"http://www.onvif.org/ver10/schema\">\
+             <s:Header>{security}</s:Header><s:Body>{body}</s:Body></s:Envelope>"
+        );
+
+        let response = self
+            .agent
+            .post(url)
+            .set("Content-Type", "application/soap+xml; charset=utf-8")
+            .send_string(&envelope);
+        match response {
+            Ok(response) => Ok(response.into_string()?),
+            Err(ureq::Error::Status(status, response)) => {
+                let answer = response.into_string().unwrap_or_default();
+                let reason = text(&answer, "Text").unwrap_or("no reason given");
+                anyhow::bail!("The camera refused with {status}: {reason}")
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to reach {url}")),
+        }
+    }
+
+    /// A UsernameToken with the password digested, as ONVIF requires.
+    fn security(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let nonce = Sha1::digest(format!("{}{}", now.as_nanos(), self.ptz_url).as_bytes());
+        let created = timestamp(now.as_secs());
+
+        let mut digest = Sha1::new();
+        digest.update(nonce);
+        digest.update(created.as_bytes());
+        digest.update(self.password.as_bytes());
+
+        format!(
+            "<Security s:mustUnderstand=\"1\" xmlns=\"http://docs.oasis-open.org/wss/2004/01/\
+             oasis-200401-wss-wssecurity-secext-1.0.xsd\"><UsernameToken>\
+             <Username>{}</Username>\
+             <Password Type=\"http://docs.oasis-open.org/wss/2004/01/\
+             oasis-200401-wss-username-token-profile-1.0#PasswordDigest\">{}</Password>\
+             <Nonce EncodingType=\"http://docs.oasis-open.org/wss/2004/01/\
+             oasis-200401-wss-soap-message-security-1.0#Base64Binary\">{}</Nonce>\
+             <Created xmlns=\"http://docs.oasis-open.org/wss/2004/01/\
+             oasis-200401-wss-wssecurity-utility-1.0.xsd\">{created}</Created>\
+             </UsernameToken></Security>",
+            self.username,
+            BASE64.encode(digest.finalize()),
+            BASE64.encode(nonce),
+        )
+    }
+}
+
+/// A camera and the regions to scan with it, saved to scan the same venue again later.
+#[derive(Serialize, Deserialize)]
+pub struct Setup {
+    pub address: String,
+    pub username: String,
+    pub password: String,
+    pub regions: Vec<Position>,
+}
+
+impl Setup {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Runs `action` on the connected camera off the UI thread, as cameras can take a while to
+/// answer. Failures end up as toasts.
+fn spawn(action: impl FnOnce(&Ptz) -> anyhow::Result<()> + Send + 'static) {
+    let Some(camera) = CAMERA.read().unwrap().clone() else {
+        return;
+    };
+    thread::spawn(move || {
+        if let Err(e) = action(&camera) {
+            toasts::error(format!("PTZ camera: {e:#}"), None);
+        }
+    });
+}
+
+/// The PTZ window's state.
+pub struct Panel {
+    address: String,
+    username: String,
+    password: String,
+    status: String,
+    /// How fast the direction buttons move the camera, from 0 to 1.
+    speed: f32,
+    /// Where the camera and regions are saved, for `scan --regions`.
+    path: String,
+}
+
+impl Default for Panel {
+    fn default() -> Self {
+        Self {
+            address: String::new(),
+            username: "admin".to_owned(),
+            password: String::new(),
+            status: String::new(),
+            speed: 0.5,
+            path: "regions.json".to_owned(),
+        }
+    }
+}
+
+impl Panel {
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        egui::Grid::new("ptz login").num_columns(2).show(ui, |ui| {
+            ui.label("Address");
+            ui.text_edit_singleline(&mut self.address)
+                .on_hover_text("Host of the camera, or the full URL of its ONVIF device service");
+            ui.end_row();
+            ui.label("Username");
+            ui.text_edit_singleline(&mut self.username);
+            ui.end_row();
+            ui.label("Password");
+            ui.add(egui::TextEdit::singleline(&mut self.password).password(true));
+            ui.end_row();
+        });
+
+        if ui.button("Connect").clicked() {
+            *CAMERA.write().unwrap() = None;
+            self.status = match Ptz::connect(&self.address, &self.username, &self.password) {
+                Ok(camera) => {
+                    *CAMERA.write().unwrap() = Some(Arc::new(camera));
+                    "Connected".to_owned()
+                }
+                Err(e) => format!("{e:#}"),
+            };
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.path);
+            if ui.button("Save").clicked() {
+                let setup = Setup {
+                    address: self.address.clone(),
+                    username: self.username.clone(),
+                    password: self.password.clone(),
+                    regions: REGIONS.read().unwrap().clone(),
+                };
+                self.status = match setup.save(self.path.as_ref()) {
+                    Ok(()) => format!("Saved {}", self.path),
+                    Err(e) => format!("{e:#}"),
+                };
+            }
+            if ui.button("Load").clicked() {
+                self.status = match Setup::load(self.path.as_ref()) {
+                    Ok(setup) => {
+                        self.address = setup.address;
+                        self.username = setup.username;
+                        self.password = setup.password;
+                        *REGIONS.write().unwrap() = setup.regions;
+                        "Loaded, connect to use the regions".to_owned()
+                    }
+                    Err(e) => format!("{e:#}"),
+                };
+            }
+        });
+        ui.label(&self.status);
+
+        if CAMERA.read().unwrap().is_none() {
+            return;
+        }
+
+        ui.separator();
+        ui.add(Slider::new(&mut self.speed, 0.05..=1.0).text("Speed"));
+        let speed = self.speed;
+        // Moving for as long as a button is held
+        let button = |ui: &mut egui::Ui, label: &str, pan: f32, tilt: f32, zoom: f32| {
+            let response = ui.button(label).interact(Sense::drag());
+            if response.drag_started() {
+                spawn(move |camera| {
+                    camera.continuous_move(pan * speed, tilt * speed, zoom * speed)
+                });
+            }
+            if response.drag_released() {
+                spawn(Ptz::stop);
+            }
+        };
+        egui::Grid::new("ptz pad").show(ui, |ui| {
+            ui.label("");
+            button(ui, "⏶", 0.0, 1.0, 0.0);
+            ui.label("");
+            button(ui, "Zoom in", 0.0, 0.0, 1.0);
+            ui.end_row();
+            button(ui, "⏴", -1.0, 0.0, 0.0);
+            ui.label("");
+            button(ui, "⏵", 1.0, 0.0, 0.0);
+            ui.end_row();
+            ui.label("");
+            button(ui, "⏷", 0.0, -1.0, 0.0);
+            ui.label("");
+            button(ui, "Zoom out", 0.0, 0.0, -1.0);
+            ui.end_row();
+        });
+
+        ui.separator();
+        ui.label("Regions, scanned in order, each overlapping one before it");
+        let mut regions = REGIONS.write().unwrap();
+        let mut remove = None;
+        for (number, &region) in regions.iter().enumerate() {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}: {region}", number + 1));
+                if ui.small_button("Go").clicked() {
+                    spawn(move |camera| camera.go_to(region));
+                }
+                if ui.small_button("✖").clicked() {
+                    remove = Some(number);
+                }
+            });
+        }
+        if let Some(number) = remove {
+            regions.remove(number);
+        }
+
+        if ui.button("Add current position").clicked() {
+            let camera = CAMERA.read().unwrap().clone();
+            match camera.map(|camera| camera.position()) {
+                Some(Ok(position)) => regions.push(position),
+                Some(Err(e)) => self.status = format!("{e:#}"),
+                None => {}
+            }
+        }
+    }
+}
+
+/// `secs` since the epoch as an ISO 8601 UTC time.
+pub fn timestamp(secs: u64) -> String {
+    let days = secs / 86400;
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    // Civil from days, after Howard Hinnant
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}T{hour:02}:{minute:02}:{second:02}Z")
+}
+
+/// The horizontal angle of view from an ONVIF lens description, whose projections give angles off
+/// the optical axis in degrees against radii in the normalized coordinates, 1 at the edge of the
+/// image. Taken from the projection furthest out as if there were no distortion.
+fn angle_of_view(description: &str) -> Option<Lens> {
+    let mut widest: Option<(f32, f32)> = None;
+    let mut rest = description;
+    while let Some(projection) = section(rest, "Projection") {
+        let value = |name| text(projection, name).and_then(|value| value.parse::<f32>().ok());
+        if let (Some(angle), Some(radius)) = (value("Angle"), value("Radius")) {
+            if radius > 0.0 && widest.is_none_or(|(_, widest)| radius > widest) {
+                widest = Some((angle, radius));
+            }
+        }
+        let end = projection.as_ptr() as usize - rest.as_ptr() as usize + projection.len();
+        rest = &rest[end..];
+    }
+
+    let (angle, radius) = widest?;
+    let half = (angle.to_radians().tan() / radius).atan();
+    Some(Lens { fov: (2.0 * half).to_degrees() })
+}
+
+/// Where the first element named `name` starts, whatever its namespace prefix, and where its
+/// start tag ends.
+fn find_tag(xml: &str, name: &str) -> Option<(usize, usize)> {
+    let mut from = 0;
+    while let Some(offset) = xml[from..].find(name) {
+        let at = from + offset;
+        from = at + name.len();
+
+        let before = xml[..at].chars().next_back();
+        let after = xml[from..].chars().next();
+        let prefixed = before == Some(':') && xml[..at].rfind('<') > xml[..at].rfind(['>', ' ']);
+        if (before == Some('<') || prefixed) && matches!(after, Some(' ' | '>' | '/')) {
+            let start = xml[..at].rfind('<')?;
+            let end = from + xml[from..].find('>')?;
+            return Some((start, end));
+        }
+    }
+    None
+}
+
+/// Everything in the first element named `name`.
+fn section<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let (start, end) = find_tag(xml, name)?;
+    if xml[..end].ends_with('/') {
+        return Some("");
+    }
+    let open = &xml[start + 1..end].split_whitespace().next()?;
+    let close = format!("</{}>", open.trim_end_matches('/'));
+    let content = &xml[end + 1..];
+    Some(&content[..content.find(&close)?])
+}
+
+/// The text of the first element named `name`.
+fn text<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let content = section(xml, name)?.trim();
+    (!content.contains('<')).then_some(content)
+}
+
+fn attribute<'a>(xml: &'a str, name: &str, attr: &str) -> Option<&'a str> {
+    let (start, end) = find_tag(xml, name)?;
+    let tag = &xml[start..end];
+    let value = &tag[tag.find(&format!(" {attr}=\""))? + attr.len() + 3..];
+    Some(&value[..value.find('"')?])
+}
@@ -0,0 +1,132 @@
+//! Frames from an Intel RealSense depth camera, with the depth aligned to the color so each
+//! detection has a depth right under it, see `depth`.
+//!
+//! Needs librealsense2 installed, and is only built with the `realsense` feature.
+
+use std::{collections::HashSet, sync::atomic::Ordering, time::Duration};
+
+use anyhow::Context as _;
+use eframe::epaint::TextureHandle;
+use realsense_rust::{
+    config::Config,
+    context::Context,
+    frame::{ColorFrame, DepthFrame, FrameEx, PixelKind},
+    kind::{Rs2Format, Rs2StreamKind},
+    pipeline::InactivePipeline,
+    processing_blocks::align::Align,
+};
+use tracing::info;
+
+use crate::{
+    depth,
+    pipeline::{self, IMAGE, IMAGE_WIDTH},
+    preview, stats,
+};
+
+/// Every RealSense camera with a color sensor supports this for both streams.
+const WIDTH: usize = 640;
+const HEIGHT: usize = 480;
+const FRAME_RATE: usize = 30;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads color frames into `IMAGE`, and into `texture` when running with a window, and depth
+/// frames into `depth::FRAME`, until the pipeline shuts down.
+pub fn decode(mut texture: Option<TextureHandle>) -> anyhow::Result<()> {
+    let context = Context::new()?;
+    anyhow::ensure!(
+        !context.query_devices(HashSet::new()).is_empty(),
+        "No RealSense camera connected"
+    );
+
+    let mut config = Config::new();
+    config
+        .enable_stream(Rs2StreamKind::Depth, None, WIDTH, HEIGHT, Rs2Format::Z16, FRAME_RATE)?
+        .enable_stream(Rs2StreamKind::Color, None, WIDTH, HEIGHT, Rs2Format::Rgb8, FRAME_RATE)?;
+    let mut stream = InactivePipeline::try_from(&context)?.start(Some(config))?;
+    let mut align = Align::new(Rs2StreamKind::Color, 1)?;
+    info!("Opened the RealSense camera at {WIDTH}x{HEIGHT}");
+
+    let source = pipeline::source();
+    let result = loop {
+        if pipeline::source_stopped(source) {
+            info!("Closing the RealSense camera");
+            break Ok(());
+        }
+
+        let frames = match stream.wait(Some(TIMEOUT)) {
+            Ok(frames) => frames,
+            Err(e) => break Err(e.into()),
+        };
+        if let Err(e) = align.queue(frames) {
+            break Err(e.into());
+        }
+        let aligned = match align.wait(TIMEOUT) {
+            Ok(aligned) => aligned,
+            Err(e) => break Err(e.into()),
+        };
+
+        let (Some(color), Some(depth)) = (
+            aligned.frames_of_type::<ColorFrame>().pop(),
+            aligned.frames_of_type::<DepthFrame>().pop(),
+        ) else {
+            continue;
+        };
+        stats::frame_decoded(None, FRAME_RATE as f64);
+
+        match read_depth(&color, &depth) {
+            Ok(frame) => *depth::FRAME.write().unwrap() = Some(frame),
+            Err(e) => break Err(e),
+        }
+
+        let (width, height) = (color.width(), color.height());
+        if IMAGE_WIDTH.load(Ordering::Relaxed) != width {
+            info!("Receiving {width}x{height} frames");
+        }
+
+        IMAGE.publish(|data| {
+            for y in 0..height {
+                for x in 0..width {
+                    if let PixelKind::Rgb8 { r, g, b } = color.get_unchecked(x, y) {
+                        data.extend_from_slice(&[*r, *g, *b]);
+                    }
+                }
+            }
+            if let Some(texture) = &mut texture {
+                preview::show(texture, [width, height], data);
+            }
+        });
+        IMAGE_WIDTH.store(width, Ordering::Relaxed);
+    };
+
+    *depth::FRAME.write().unwrap() = None;
+    result
+}
+
+/// `depth`, aligned to `color`, in meters.
+fn read_depth(color: &ColorFrame, depth: &DepthFrame) -> anyhow::Result<depth::Frame> {
+    let intrinsics = color
+        .stream_profile()
+        .intrinsics()
+        .context("No intrinsics for the color camera")?;
+    let units = depth.depth_units()?;
+
+    let (width, height) = (depth.width(), depth.height());
+    let mut meters = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            meters.push(match depth.get_unchecked(x, y) {
+                PixelKind::Z16 { depth } => *depth as f32 * units,
+                _ => 0.0,
+            });
+        }
+    }
+
+    Ok(depth::Frame {
+        width,
+        height,
+        meters,
+        focal_length: [intrinsics.fx(), intrinsics.fy()],
+        principal_point: [intrinsics.ppx(), intrinsics.ppy()],
+    })
+}
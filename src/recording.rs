@@ -0,0 +1,111 @@
+//! Recording a scan as a sequence of annotated frames, one per capture, with the detections boxed
+//! and the LEDs found so far labeled. Handy for documentation, and for going through the
+//! detections with someone who wasn't there.
+//!
+//! The frames are numbered PNGs, so they can be turned into a video with something like
+//! `ffmpeg -framerate 10 -i frame-%05d.png scan.mp4`. Next to each goes the `timelapse` so far,
+//! for a video of the installation building up, and a line in `STEPS` saying what it captured,
+//! for `playback`.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, RwLock,
+    },
+    time::Instant,
+};
+
+use eframe::epaint::Pos2;
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{scan, snapshot, timelapse};
+
+/// Where to write the frames of the next scans, `None` to not record them.
+pub static DIRECTORY: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// What each frame of a recording is of, one JSON `Step` per line.
+pub const STEPS: &str = "steps.jsonl";
+
+static NEXT_FRAME: AtomicUsize = AtomicUsize::new(0);
+static STARTED: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// A capture in a recording.
+#[derive(Serialize, Deserialize)]
+pub struct Step {
+    /// Number of its files, `frame-00000.png` and on.
+    pub frame: usize,
+    pub index: usize,
+    pub position: Option<[f32; 2]>,
+    /// Since the scan started.
+    pub millis: u64,
+}
+
+/// Starts numbering the frames from the beginning again, for a new scan.
+pub fn begin() {
+    let Some(directory) = DIRECTORY.read().unwrap().clone() else {
+        return;
+    };
+
+    NEXT_FRAME.store(0, Ordering::Relaxed);
+    *STARTED.lock().unwrap() = Some(Instant::now());
+    match fs::create_dir_all(&directory) {
+        Ok(()) => info!("Recording the scan to {}", directory.display()),
+        Err(e) => warn!("Can't create {}: {e}", directory.display()),
+    }
+    // The steps of an earlier recording would be mixed in otherwise
+    let _ = fs::remove_file(directory.join(STEPS));
+}
+
+/// Writes the latest frame for the capture of LED `index`, found at `position`, if recording.
+/// Failures are only logged, the scan is worth more than its recording.
+pub fn frame(index: usize, position: Option<Pos2>) {
+    let Some(directory) = DIRECTORY.read().unwrap().clone() else {
+        return;
+    };
+
+    let frame = NEXT_FRAME.fetch_add(1, Ordering::Relaxed);
+    let path = directory.join(format!("frame-{frame:05}.png"));
+    if let Err(e) = write(&path, index) {
+        warn!("Failed to record {}: {e}", path.display());
+    }
+
+    let step = Step {
+        frame,
+        index,
+        position: position.map(|position| [position.x, position.y]),
+        millis: STARTED
+            .lock()
+            .unwrap()
+            .map_or(0, |started| started.elapsed().as_millis() as u64),
+    };
+    let path = directory.join(STEPS);
+    if let Err(e) = append(&path, &step) {
+        warn!("Failed to record {}: {e}", path.display());
+    }
+
+    let path = directory.join(format!("timelapse-{frame:05}.png"));
+    if let Some(Err(e)) = timelapse::composite().map(|image| image.save(&path)) {
+        warn!("Failed to record {}: {e}", path.display());
+    }
+}
+
+fn append(path: &Path, step: &Step) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(step)?)?;
+    Ok(())
+}
+
+fn write(path: &Path, index: usize) -> anyhow::Result<()> {
+    let mut image = snapshot::latest()?;
+    snapshot::annotate(&mut image, &scan::leds());
+    // Which LED this frame is for, in the corner
+    snapshot::draw_number(&mut image, 8, 8, index);
+
+    image.save_with_format(path, ImageFormat::Png)?;
+    Ok(())
+}
@@ -0,0 +1,169 @@
+//! The coarse-to-fine scan: a quick sequential pass on frames at a fraction of the resolution for
+//! rough positions, then a second pass lighting each LED found again and placing it precisely
+//! from a full resolution crop around where the first pass saw it.
+//!
+//! The second pass weighs every pixel detection lets through by its brightness, so positions
+//! come out to a fraction of a pixel. That's what makes the difference for distant LEDs that are
+//! only a few pixels across, where the middle of the blob's bounding box is off by up to half of
+//! one.
+
+use std::sync::atomic::Ordering;
+
+use eframe::epaint::{Color32, Pos2, Rect};
+use led_detect::Settings;
+use tracing::{debug, info};
+
+use crate::{
+    pipeline::{self, DARK_FRAME, SETTINGS},
+    scan::{self, SharedController, CURRENT, MAP, STEPS},
+};
+
+/// How much smaller the frames of the first pass are on each side.
+const COARSE_FACTOR: usize = 2;
+/// How far from the first pass's position the second looks, in full resolution pixels. A coarse
+/// pixel either way, and then some for the LED's own size.
+const CROP_RADIUS: usize = 24;
+
+pub fn run(controller: &SharedController) -> anyhow::Result<()> {
+    let mut controller = controller.lock().unwrap();
+    let count = controller.len();
+    let step = scan::step_time(controller.latency_hint());
+    let stamped = pipeline::frames_stamped();
+    let light = scan::SETTINGS.read().unwrap().led_light();
+    let coarse = coarse_settings(&SETTINGS.read().unwrap());
+    let leds = scan::order(count)?;
+
+    info!("Coarse pass over {} LEDs at 1/{COARSE_FACTOR} resolution", leds.len());
+    STEPS.store(leds.len() * 2, Ordering::Relaxed);
+    for (current, &index) in leds.iter().enumerate() {
+        if scan::cancelled() {
+            break;
+        }
+        CURRENT.store(current, Ordering::Relaxed);
+
+        controller.set_all(Color32::BLACK);
+        light.set(&mut **controller, index);
+        controller.flush()?;
+        let (frame, width) = scan::frame_after_flush(stamped, step, controller.latency_hint())?;
+
+        let (small, small_width) = downscale(&without_dark(&frame, width), width);
+        let factor = COARSE_FACTOR as f32;
+        let points = led_detect::detect_rgb(&small, small_width, &coarse)?
+            .into_iter()
+            .map(|rect| Rect::from_min_max(rect.min * factor, rect.max * factor))
+            .collect::<Vec<_>>();
+        scan::store(index, scan::pick(&points, index));
+    }
+
+    let rough = MAP.read().unwrap().clone();
+    info!("Refining {} LEDs at full resolution", rough.iter().flatten().count());
+    for (current, &index) in leds.iter().enumerate() {
+        CURRENT.store(leds.len() + current, Ordering::Relaxed);
+        let Some(rough) = rough[index] else {
+            continue;
+        };
+        if scan::cancelled() {
+            break;
+        }
+
+        controller.set_all(Color32::BLACK);
+        light.set(&mut **controller, index);
+        controller.flush()?;
+        let (frame, width) = scan::frame_after_flush(stamped, step, controller.latency_hint())?;
+
+        // Staying with the rough position when the crop has nothing, it's better than none
+        if let Some(fine) = centroid(&without_dark(&frame, width), width, rough)? {
+            debug!("LED {index} moved {:.2} px refining", rough.distance(fine));
+            if let Some(slot) = MAP.write().unwrap().get_mut(index) {
+                *slot = Some(fine);
+            }
+        }
+    }
+
+    controller.set_all(Color32::BLACK);
+    controller.flush()
+}
+
+/// The brightness-weighted middle of what detection lets through within `CROP_RADIUS` of
+/// `around`.
+fn centroid(frame: &[u8], width: usize, around: Pos2) -> anyhow::Result<Option<Pos2>> {
+    let height = frame.len() / width / 3;
+    let left = (around.x as usize).saturating_sub(CROP_RADIUS);
+    let top = (around.y as usize).saturating_sub(CROP_RADIUS);
+    let right = (around.x as usize + CROP_RADIUS).min(width);
+    let bottom = (around.y as usize + CROP_RADIUS).min(height);
+    if right <= left || bottom <= top {
+        return Ok(None);
+    }
+
+    let crop_width = right - left;
+    let pixels = (top..bottom)
+        .flat_map(|row| &frame[(row * width + left) * 3..(row * width + right) * 3])
+        .copied()
+        .collect::<Vec<_>>();
+
+    let mask = led_detect::mask_rgb(&pixels, crop_width, &SETTINGS.read().unwrap())?;
+    let (mut total, mut x, mut y) = (0.0, 0.0, 0.0);
+    for (i, _) in mask
+        .data
+        .iter()
+        .enumerate()
+        .filter(|(_, &matched)| matched > 0)
+    {
+        let [r, g, b] = [0, 1, 2].map(|channel| f32::from(pixels[i * 3 + channel]));
+        let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+        total += luma;
+        x += luma * (i % crop_width) as f32;
+        y += luma * (i / crop_width) as f32;
+    }
+
+    // Pixel centers are half a pixel in
+    Ok((total > 0.0)
+        .then(|| Pos2::new(left as f32 + x / total + 0.5, top as f32 + y / total + 0.5)))
+}
+
+/// `frame` with the dark frame taken off, if there is one.
+fn without_dark(frame: &[u8], width: usize) -> Vec<u8> {
+    match DARK_FRAME.read().unwrap().as_ref() {
+        Some(dark) => led_detect::subtract_dark(frame.to_vec(), width, dark),
+        None => frame.to_vec(),
+    }
+}
+
+/// `frame` at 1/`COARSE_FACTOR` size, keeping the brightest of each block so LEDs only a pixel
+/// across don't average away.
+fn downscale(frame: &[u8], width: usize) -> (Vec<u8>, usize) {
+    let height = frame.len() / width / 3;
+    let (small_width, small_height) = (width / COARSE_FACTOR, height / COARSE_FACTOR);
+
+    let mut small = Vec::with_capacity(small_width * small_height * 3);
+    for y in 0..small_height {
+        for x in 0..small_width {
+            let brightest = (0..COARSE_FACTOR * COARSE_FACTOR)
+                .map(|i| {
+                    let (dx, dy) = (i % COARSE_FACTOR, i / COARSE_FACTOR);
+                    let offset = ((y * COARSE_FACTOR + dy) * width + x * COARSE_FACTOR + dx) * 3;
+                    &frame[offset..offset + 3]
+                })
+                .max_by_key(|pixel| pixel.iter().map(|&channel| channel as u16).sum::<u16>())
+                .unwrap();
+            small.extend_from_slice(brightest);
+        }
+    }
+    (small, small_width)
+}
+
+/// `settings` with everything measured in pixels shrunk to match the downscaled frames.
+fn coarse_settings(settings: &Settings) -> Settings {
+    let factor = COARSE_FACTOR as f32;
+    let odd = |size: i32| (size / COARSE_FACTOR as i32).max(1) | 1;
+    Settings {
+        merge_radius: settings.merge_radius / factor,
+        blob_area: settings.blob_area / (factor * factor),
+        min_area: settings.min_area / (factor * factor),
+        max_area: settings.max_area / (factor * factor),
+        adaptive_block: odd(settings.adaptive_block).max(3),
+        denoise_kernel: odd(settings.denoise_kernel),
+        ..settings.clone()
+    }
+}
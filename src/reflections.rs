@@ -0,0 +1,63 @@
+//! Telling an LED from its reflection in a window, a mirror or a glossy floor, which show up as a
+//! second blob lit at the same time. A sequential scan that finds more than one candidate blinks
+//! the LED a few times and keeps the blob whose light goes up and down with it the most: the
+//! reflection follows too, but loses light to the surface, and stray light doesn't follow at all.
+
+use std::sync::atomic::AtomicBool;
+
+use eframe::epaint::{Color32, Pos2, Rect};
+use tracing::debug;
+
+use crate::{
+    controller::LedController,
+    pipeline,
+    scan::{self, SETTINGS},
+};
+
+/// Whether sequential scans blink LEDs with several candidates, see `resolve`.
+pub static RESOLVE: AtomicBool = AtomicBool::new(false);
+
+/// Times the LED is turned off and on again.
+const BLINKS: usize = 3;
+
+/// Blinks LED `index`, which is lit, and picks whichever of `candidates` got the most brighter
+/// each time it came on. `None` if none of them followed it, leaving the LED lit as it was.
+pub fn resolve(
+    controller: &mut dyn LedController,
+    index: usize,
+    candidates: &[Rect],
+) -> anyhow::Result<Option<Pos2>> {
+    let stamped = pipeline::frames_stamped();
+    let latency = controller.latency_hint();
+    let step = scan::step_time(latency);
+    let on = SETTINGS.read().unwrap().led_light();
+
+    let mut swings = vec![0.0; candidates.len()];
+    for lit in [false, true].repeat(BLINKS) {
+        match lit {
+            true => on.set(controller, index),
+            false => controller.set_pixel(index, Color32::BLACK),
+        }
+        controller.flush()?;
+        let (frame, width) = scan::frame_after_flush(stamped, step, latency)?;
+
+        for (swing, rect) in swings.iter_mut().zip(candidates) {
+            let light = light(&frame, width, rect);
+            *swing += if lit { light } else { -light };
+        }
+    }
+    debug!("LED {index}: {} candidates swinging by {swings:.0?}", candidates.len());
+
+    Ok(swings
+        .iter()
+        .zip(candidates)
+        .filter(|(&swing, _)| swing > 0.0)
+        .max_by(|a, b| a.0.total_cmp(b.0))
+        .map(|(_, rect)| rect.center()))
+}
+
+/// All the light in `rect`, so a bigger blob counts for more than a small one as bright.
+fn light(frame: &[u8], width: usize, rect: &Rect) -> f32 {
+    let [r, g, b] = scan::mean_color(frame, width, rect);
+    (r + g + b) * rect.area()
+}
@@ -0,0 +1,136 @@
+//! Scanning an installation too big for one view a region at a time, by pointing a PTZ camera at
+//! each of `ptz::REGIONS` in turn and doing a sequential scan there. The camera moving between
+//! regions isn't drift, so `drift` takes a new reference in each.
+//!
+//! The maps of the regions are stitched into the first one's view. Each region is registered to
+//! the regions before it by the LEDs both found, with a similarity transform: scale for the
+//! zoom, rotation and translation. That ignores the perspective change of panning, which is
+//! fine as long as regions don't pan far from each other.
+
+use std::{sync::atomic::Ordering, thread};
+
+use eframe::epaint::{Pos2, Vec2};
+use tracing::{info, warn};
+
+use crate::{drift, ignored, ptz, scan, scan::SharedController};
+
+/// Fewest LEDs a region has to share with the ones before to be placed.
+const MIN_OVERLAP: usize = 3;
+
+/// Runs the scan, leaving the stitched map in the scan's map, and the camera back on the first
+/// region so the live view matches it.
+pub fn run(controller: &SharedController) -> anyhow::Result<()> {
+    let camera = ptz::CAMERA
+        .read()
+        .unwrap()
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("Connect a PTZ camera first"))?;
+    let regions = ptz::REGIONS.read().unwrap().clone();
+    anyhow::ensure!(!regions.is_empty(), "There are no regions to scan");
+
+    let mut stitched: Vec<Option<Pos2>> = Vec::new();
+    for (number, &region) in regions.iter().enumerate() {
+        if scan::cancelled() {
+            break;
+        }
+
+        info!("Scanning region {} of {} at {region}", number + 1, regions.len());
+        camera.go_to(region)?;
+        // What's stray light changes with the view
+        if ignored::LEARN_BEFORE_SCAN.load(Ordering::Relaxed) {
+            ignored::learn(controller)?;
+        }
+        // Each region is its own view to notice the camera moving from, once the stream has
+        // caught up with the move
+        thread::sleep(scan::step_time(controller.lock().unwrap().latency_hint()));
+        drift::begin(false);
+
+        scan::MAP.write().unwrap().fill(None);
+        let leds = scan::order(scan::MAP.read().unwrap().len())?;
+        scan::run_sequential(controller, &leds, 0)?;
+        let map = scan::MAP.read().unwrap().clone();
+
+        if stitched.is_empty() {
+            stitched = map;
+            continue;
+        }
+        match Similarity::register(&map, &stitched) {
+            Some((similarity, overlap)) => {
+                let mut added = 0;
+                for (stitched, found) in stitched.iter_mut().zip(&map) {
+                    if let (None, Some(found)) = (&stitched, found) {
+                        *stitched = Some(similarity.apply(*found));
+                        added += 1;
+                    }
+                }
+                info!(
+                    "Region {} overlaps by {overlap} LEDs, adding {added}, scaled {:.2}",
+                    number + 1,
+                    similarity.scale
+                );
+            }
+            None => warn!(
+                "Region {} shares fewer than {MIN_OVERLAP} LEDs with the ones before, leaving it \
+                 out",
+                number + 1
+            ),
+        }
+    }
+
+    *scan::MAP.write().unwrap() = stitched;
+    camera.go_to(regions[0])?;
+    Ok(())
+}
+
+/// `p` scaled by `scale`, rotated by `rotation` and moved by `translation`.
+struct Similarity {
+    scale: f32,
+    /// Cosine and sine of the angle.
+    rotation: Vec2,
+    translation: Vec2,
+}
+
+impl Similarity {
+    /// The least squares fit taking the LEDs in `from` to where they are in `to`, with how many
+    /// there were in both. `None` with fewer than `MIN_OVERLAP`.
+    fn register(from: &[Option<Pos2>], to: &[Option<Pos2>]) -> Option<(Self, usize)> {
+        let pairs = from
+            .iter()
+            .zip(to)
+            .filter_map(|(from, to)| Some((from.as_ref()?.to_vec2(), to.as_ref()?.to_vec2())))
+            .collect::<Vec<_>>();
+        if pairs.len() < MIN_OVERLAP {
+            return None;
+        }
+
+        let n = pairs.len() as f32;
+        let from_mean = pairs.iter().fold(Vec2::ZERO, |sum, (from, _)| sum + *from) / n;
+        let to_mean = pairs.iter().fold(Vec2::ZERO, |sum, (_, to)| sum + *to) / n;
+
+        // Closed form in 2D: the angle comes from the summed dot and cross products
+        let (mut dot, mut cross, mut spread) = (0.0, 0.0, 0.0);
+        for (from, to) in &pairs {
+            let (a, b) = (*from - from_mean, *to - to_mean);
+            dot += a.x * b.x + a.y * b.y;
+            cross += a.x * b.y - a.y * b.x;
+            spread += a.length_sq();
+        }
+        if spread <= f32::EPSILON {
+            return None;
+        }
+
+        let length = dot.hypot(cross);
+        let rotation = Vec2::new(dot, cross) / length.max(f32::EPSILON);
+        let scale = length / spread;
+        let translation = to_mean - rotate(from_mean, rotation) * scale;
+        Some((Self { scale, rotation, translation }, pairs.len()))
+    }
+
+    fn apply(&self, p: Pos2) -> Pos2 {
+        (rotate(p.to_vec2(), self.rotation) * self.scale + self.translation).to_pos2()
+    }
+}
+
+fn rotate(v: Vec2, rotation: Vec2) -> Vec2 {
+    Vec2::new(v.x * rotation.x - v.y * rotation.y, v.x * rotation.y + v.y * rotation.x)
+}
@@ -0,0 +1,297 @@
+//! The calibration report: one HTML file with a plot of the layout, every LED with how bright it
+//! came out, what went wrong, the settings the scan ran with and pictures of it, all inline so the
+//! file can be handed over or archived with the installation on its own.
+
+use std::{fmt::Write as _, io::Cursor, path::Path};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use eframe::epaint::Pos2;
+use image::{ImageOutputFormat, RgbImage};
+
+use crate::{
+    export::Metadata,
+    issues, ptz,
+    scan::{self, ScanMode, ScanOrder},
+    segments::{self, Layout},
+    snapshot, timelapse,
+};
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em auto; max-width: 60em; color: #222; }
+h1, h2 { font-weight: normal; }
+table { border-collapse: collapse; }
+th, td { padding: 0.2em 0.8em; text-align: right; border-bottom: 1px solid #ddd; }
+th:first-child, td:first-child { text-align: left; }
+.missing { color: #b00; }
+.dim { color: #b60; }
+img, svg { max-width: 100%; height: auto; }
+svg { background: #111; }
+";
+
+/// Above this many LEDs only every tenth gets its index written in the plot.
+const LABEL_ALL: usize = 200;
+
+pub fn save(path: &Path) -> anyhow::Result<()> {
+    std::fs::write(path, html()).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The report on the last scan.
+pub fn html() -> String {
+    let metadata = Metadata::current();
+    let map = scan::MAP.read().unwrap().clone();
+    let brightness = issues::brightness();
+    let report = issues::report();
+    // Both of these read the layout too
+    let issues = report.text();
+    let snapshot = snapshot_image();
+    let layout = segments::LAYOUT.read().unwrap();
+    let found = map.iter().flatten().count();
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+         <title>LED calibration report</title><style>{STYLE}</style></head><body>\n\
+         <h1>LED calibration report</h1>\n<p>{}, led-position-calibrator {}. Found {found} of \
+         {} LEDs.</p>\n",
+        ptz::timestamp(metadata.created),
+        metadata.app_version,
+        map.len()
+    );
+
+    let _ = writeln!(html, "<h2>Layout</h2>");
+    html.push_str(&plot(&map, &layout, metadata.resolution));
+
+    let pictures = [
+        ("Latest frame with the detections", snapshot),
+        ("Every frame of the scan blended into one", timelapse::composite()),
+    ];
+    if pictures.iter().any(|(_, image)| image.is_some()) {
+        let _ = writeln!(html, "<h2>Pictures</h2>");
+        for (caption, image) in pictures {
+            if let Some(data) = image.and_then(|image| png(&image)) {
+                let _ = writeln!(
+                    html,
+                    "<figure><img src=\"data:image/png;base64,{data}\" alt=\"{caption}\">\
+                     <figcaption>{caption}</figcaption></figure>"
+                );
+            }
+        }
+    }
+
+    let _ = writeln!(html, "<h2>Failures</h2>");
+    let missing = (0..map.len())
+        .filter(|&index| map[index].is_none())
+        .map(|index| name(&layout, index))
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        let _ = writeln!(html, "<p>Every LED was found.</p>");
+    } else {
+        let _ = writeln!(
+            html,
+            "<p class=\"missing\">Not found ({}): {}</p>",
+            missing.len(),
+            escape(&missing.join(", "))
+        );
+    }
+    let _ = writeln!(html, "<pre>{}</pre>", escape(&issues));
+
+    let _ = writeln!(html, "<h2>LEDs</h2>\n<table>");
+    let _ = write!(html, "<tr><th>LED</th>");
+    if layout.len() > 1 {
+        let _ = write!(html, "<th>Segment</th><th>On segment</th>");
+    }
+    let _ =
+        writeln!(html, "<th>x</th><th>y</th><th>Peak</th><th>Total</th><th>Of neighbors</th></tr>");
+    for (index, position) in map.iter().enumerate() {
+        let dim = report.dim.iter().any(|&(dim, _)| dim == index);
+        let class = match position {
+            None => " class=\"missing\"",
+            Some(_) if dim => " class=\"dim\"",
+            Some(_) => "",
+        };
+        let _ = write!(html, "<tr{class}><td>{index}</td>");
+        if layout.len() > 1 {
+            let (segment, local) = layout.split(index);
+            let _ = write!(html, "<td>{}</td><td>{local}</td>", escape(layout.name(segment)));
+        }
+        match position {
+            Some(position) => {
+                let _ = write!(html, "<td>{:.1}</td><td>{:.1}</td>", position.x, position.y);
+            }
+            None => {
+                let _ = write!(html, "<td colspan=\"2\">not found</td>");
+            }
+        }
+        match brightness.get(index).and_then(Option::as_ref) {
+            Some(brightness) => {
+                let relative = brightness
+                    .relative
+                    .map_or(String::new(), |relative| format!("{:.0}%", relative * 100.0));
+                let _ = writeln!(
+                    html,
+                    "<td>{:.0}</td><td>{:.1}</td><td>{relative}</td></tr>",
+                    brightness.peak, brightness.total
+                );
+            }
+            None => {
+                let _ = writeln!(html, "<td></td><td></td><td></td></tr>");
+            }
+        }
+    }
+    let _ = writeln!(html, "</table>");
+
+    let scan_settings = scan::SETTINGS.read().unwrap().clone();
+    let [r, g, b] = scan_settings.color;
+    let parameters = [
+        (
+            "Scan",
+            metadata
+                .scan_mode
+                .as_ref()
+                .map_or("None yet", mode_name)
+                .to_owned(),
+        ),
+        ("Detector", metadata.settings.detector.name().to_owned()),
+        ("Detection mode", metadata.settings.mode.name().to_owned()),
+        (
+            "Resolution",
+            metadata
+                .resolution
+                .map_or("No stream".to_owned(), |[width, height]| format!("{width}x{height}")),
+        ),
+        (
+            "Latency",
+            metadata
+                .latency_ms
+                .map_or("Not measured".to_owned(), |latency| format!("{latency} ms")),
+        ),
+        ("Settle time", format!("{} ms", scan_settings.settle.as_millis())),
+        ("On time", format!("{} ms", scan_settings.on_time.as_millis())),
+        ("LED color", format!("rgb({r}, {g}, {b}) at {:.0}%", scan_settings.brightness * 100.0)),
+        (
+            "Second capture",
+            scan_settings
+                .second_brightness
+                .map_or("None".to_owned(), |share| format!("at {:.0}%", share * 100.0)),
+        ),
+        ("Order", match &scan_settings.order {
+            ScanOrder::Custom(list) => format!("Custom: {list}"),
+            order => order.name().to_owned(),
+        }),
+    ];
+    let _ = writeln!(html, "<h2>Scan parameters</h2>\n<table>");
+    for (name, value) in parameters {
+        let _ = writeln!(html, "<tr><td>{name}</td><td>{}</td></tr>", escape(&value));
+    }
+    let _ = writeln!(html, "</table>");
+
+    // The template is a wall of numbers that says nothing read like this
+    let settings = crate::pipeline::Settings { template: None, ..metadata.settings };
+    if let Ok(json) = serde_json::to_string_pretty(&settings) {
+        let _ = writeln!(
+            html,
+            "<details><summary>All detection settings</summary><pre>{}</pre></details>",
+            escape(&json)
+        );
+    }
+
+    html.push_str("</body></html>\n");
+    html
+}
+
+/// An SVG of where the LEDs were found, each segment in its own color with a line running
+/// through it in order. Frame coordinates, so it lines up with the pictures.
+fn plot(map: &[Option<Pos2>], layout: &Layout, resolution: Option<[usize; 2]>) -> String {
+    let [width, height] = resolution.unwrap_or_else(|| {
+        let far = map
+            .iter()
+            .flatten()
+            .fold([1.0f32; 2], |[x, y], position| [x.max(position.x), y.max(position.y)]);
+        far.map(|far| far.ceil() as usize + 10)
+    });
+    let radius = (width.max(height) as f32 / 300.0).max(2.0);
+
+    let mut svg =
+        format!("<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {width} {height}\">\n");
+    for segment in 0..layout.len() {
+        let offset = layout.offset(segment);
+        let count = layout.count(segment).unwrap_or(map.len());
+        let color = segments::PALETTE[segment % segments::PALETTE.len()];
+        let color = format!("rgb({}, {}, {})", color.r(), color.g(), color.b());
+        let leds = (offset..(offset + count).min(map.len()))
+            .filter_map(|index| Some((index, map[index]?)))
+            .collect::<Vec<_>>();
+
+        let points = leds
+            .iter()
+            .map(|(_, position)| format!("{:.1},{:.1}", position.x, position.y))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let _ = writeln!(
+            svg,
+            "<polyline points=\"{points}\" fill=\"none\" stroke=\"{color}\" stroke-opacity=\"0.5\"/>"
+        );
+        for &(index, position) in &leds {
+            let _ = writeln!(
+                svg,
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{radius:.1}\" fill=\"{color}\"/>",
+                position.x, position.y
+            );
+            if map.len() <= LABEL_ALL || index % 10 == 0 {
+                let _ = writeln!(
+                    svg,
+                    "<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"{:.0}\" fill=\"#fff\">{index}</text>",
+                    position.x + radius * 1.5,
+                    position.y - radius * 1.5,
+                    radius * 4.0
+                );
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// The latest frame with the detections and indices drawn in, if there is one.
+fn snapshot_image() -> Option<RgbImage> {
+    let mut image = snapshot::latest().ok()?;
+    snapshot::annotate(&mut image, &scan::leds());
+    Some(image)
+}
+
+fn png(image: &RgbImage) -> Option<String> {
+    let mut data = Cursor::new(Vec::new());
+    image.write_to(&mut data, ImageOutputFormat::Png).ok()?;
+    Some(BASE64.encode(data.into_inner()))
+}
+
+/// LED `index` by its global index, with its segment when there are several.
+fn name(layout: &Layout, index: usize) -> String {
+    if layout.len() == 1 {
+        return index.to_string();
+    }
+    let (segment, local) = layout.split(index);
+    format!("{index} ({} LED {local})", layout.name(segment))
+}
+
+fn mode_name(mode: &ScanMode) -> &'static str {
+    match mode {
+        ScanMode::Sequential => "Sequential",
+        ScanMode::StrobeDiff => "Strobe difference",
+        ScanMode::Interleaved => "Interleaved segments",
+        ScanMode::ColorCoded => "Color coded",
+        ScanMode::Script(_) => "Script",
+        ScanMode::Regions => "PTZ regions",
+        ScanMode::CoarseToFine => "Coarse to fine",
+        ScanMode::Grouped => "Grouped",
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
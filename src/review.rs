@@ -0,0 +1,338 @@
+//! Going over the crop of every LED after a scan, to accept each position, reject it or click
+//! where the LED really is. Everything works from the keyboard, with the shortcuts from the Keys
+//! window: by default the arrows move between crops, A accepts and R rejects, moving on to the
+//! next, and Ctrl+Z takes the latest of those back.
+
+use eframe::{
+    egui::{self, Button, Sense, TextureOptions},
+    epaint::{Color32, ColorImage, Pos2, Rect, Stroke, TextureHandle, Vec2},
+};
+
+use crate::{
+    keys::{Action, Keymap},
+    outliers,
+    scan::{self, THUMBNAILS},
+};
+
+const COLUMNS: usize = 8;
+const CELL: f32 = 64.0;
+/// Size of the crop being reviewed, where clicks place the LED.
+const ZOOMED: f32 = 256.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Verdict {
+    Accepted,
+    Rejected,
+    /// Placed by hand.
+    Fixed,
+}
+
+impl Verdict {
+    fn color(self) -> Color32 {
+        match self {
+            Self::Accepted => Color32::GREEN,
+            Self::Rejected => Color32::RED,
+            Self::Fixed => Color32::YELLOW,
+        }
+    }
+}
+
+struct Entry {
+    index: usize,
+    /// Where the scan found it, which accepting a rejected LED goes back to.
+    found: Option<Pos2>,
+    origin: [usize; 2],
+    size: [usize; 2],
+    texture: TextureHandle,
+    verdict: Option<Verdict>,
+}
+
+impl Entry {
+    /// Where `pos` in frame pixels goes when the crop is drawn in `rect`.
+    fn to_screen(&self, rect: Rect, pos: Pos2) -> Pos2 {
+        let scale = rect.width() / self.size[0] as f32;
+        rect.min + (pos - Pos2::new(self.origin[0] as f32, self.origin[1] as f32)) * scale
+    }
+
+    fn to_frame(&self, rect: Rect, pos: Pos2) -> Pos2 {
+        let scale = self.size[0] as f32 / rect.width();
+        Pos2::new(self.origin[0] as f32, self.origin[1] as f32) + (pos - rect.min) * scale
+    }
+}
+
+/// What an LED had before an accept, reject or fix, for undoing it.
+struct Edit {
+    index: usize,
+    position: Option<Pos2>,
+    verdict: Option<Verdict>,
+}
+
+#[derive(Default)]
+pub struct Review {
+    /// The latest crop of each LED, by index.
+    entries: Vec<Entry>,
+    /// How many of `scan::THUMBNAILS` are in `entries`.
+    seen: usize,
+    selected: usize,
+    /// Keeps the selection in view after the keys moved it.
+    scroll: bool,
+    /// The edits to `scan::MAP` made here, latest last.
+    history: Vec<Edit>,
+}
+
+impl Review {
+    pub fn show(&mut self, ui: &mut egui::Ui, keymap: &mut Keymap) {
+        self.update(ui.ctx());
+        if self.entries.is_empty() {
+            ui.label("Nothing to review until a scan captured something.");
+            return;
+        }
+
+        let reviewed = self.entries.iter().filter(|e| e.verdict.is_some()).count();
+        ui.label(format!("{reviewed} of {} reviewed", self.entries.len()));
+
+        self.handle_keys(ui.ctx(), keymap);
+
+        let map = scan::MAP.read().unwrap().clone();
+        let position = |index: usize| map.get(index).copied().flatten();
+        let outliers = outliers::find();
+        let off = |index: usize| {
+            outliers
+                .iter()
+                .find(|outlier| outlier.index == index)
+                .map(|outlier| outlier.off)
+        };
+
+        let mut judged = None;
+        let mut fixed = None;
+        let mut undo = false;
+        let ctx = ui.ctx().clone();
+        let shortcut = |action| keymap.describe(&ctx, action);
+        ui.horizontal(|ui| {
+            let entry = &mut self.entries[self.selected];
+            let (response, painter) = ui.allocate_painter(Vec2::splat(ZOOMED), Sense::click());
+            let rect = response.rect;
+            painter.image(
+                entry.texture.id(),
+                rect,
+                Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+                Color32::WHITE,
+            );
+            if let Some(pos) = position(entry.index) {
+                cross(&painter, entry.to_screen(rect, pos), 8.0);
+            }
+            if let Some(click) = response
+                .interact_pointer_pos()
+                .filter(|_| response.clicked())
+            {
+                fixed = Some(entry.to_frame(rect, click));
+            }
+
+            ui.vertical(|ui| {
+                ui.heading(format!("LED {}", entry.index));
+                match position(entry.index) {
+                    Some(pos) => ui.label(format!("At {:.1}, {:.1}", pos.x, pos.y)),
+                    None => ui.label("Not found"),
+                };
+                if let Some(off) = off(entry.index) {
+                    ui.colored_label(
+                        outliers::COLOR,
+                        format!("{off:.0} LED spacings from where its neighbors put it"),
+                    );
+                }
+                ui.label("Click the crop where the LED is to move it there.");
+                ui.horizontal(|ui| {
+                    let accept = ui
+                        .button("Accept")
+                        .on_hover_text(shortcut(Action::AcceptLed));
+                    if accept.clicked() {
+                        judged = Some(Verdict::Accepted);
+                    }
+                    let reject = ui
+                        .button("Reject")
+                        .on_hover_text(shortcut(Action::RejectLed));
+                    if reject.clicked() {
+                        judged = Some(Verdict::Rejected);
+                    }
+                    undo = ui
+                        .add_enabled(!self.history.is_empty(), Button::new("Undo"))
+                        .on_hover_text(shortcut(Action::Undo))
+                        .clicked();
+                });
+            });
+        });
+        if let Some(pos) = fixed {
+            self.change(Verdict::Fixed, Some(pos));
+        }
+        if let Some(verdict) = judged {
+            self.judge(verdict);
+        }
+        if undo {
+            self.undo();
+        }
+
+        ui.separator();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("review grid")
+                .spacing(Vec2::splat(4.0))
+                .show(ui, |ui| {
+                    for (i, entry) in self.entries.iter().enumerate() {
+                        let (response, painter) =
+                            ui.allocate_painter(Vec2::splat(CELL), Sense::click());
+                        let rect = response.rect;
+                        painter.image(
+                            entry.texture.id(),
+                            rect,
+                            Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+                            Color32::WHITE,
+                        );
+                        if let Some(pos) = position(entry.index) {
+                            cross(&painter, entry.to_screen(rect, pos), 4.0);
+                        }
+                        let color = match entry.verdict {
+                            Some(verdict) => Some(verdict.color()),
+                            None => off(entry.index).map(|_| outliers::COLOR),
+                        };
+                        if let Some(color) = color {
+                            painter.rect_stroke(rect, 0.0, Stroke::new(2.0, color));
+                        }
+                        if i == self.selected {
+                            painter.rect_stroke(
+                                rect.expand(2.0),
+                                0.0,
+                                Stroke::new(2.0, Color32::WHITE),
+                            );
+                            if self.scroll {
+                                response.scroll_to_me(None);
+                            }
+                        }
+                        if response
+                            .on_hover_text(format!("LED {}", entry.index))
+                            .clicked()
+                        {
+                            self.selected = i;
+                        }
+
+                        if (i + 1) % COLUMNS == 0 {
+                            ui.end_row();
+                        }
+                    }
+                });
+        });
+        self.scroll = false;
+    }
+
+    /// Takes in the crops captured since the last frame, starting over for a new scan.
+    fn update(&mut self, ctx: &egui::Context) {
+        let thumbnails = THUMBNAILS.lock().unwrap();
+        if thumbnails.len() < self.seen {
+            *self = Self::default();
+        }
+
+        for thumbnail in &thumbnails[self.seen..] {
+            let texture = ctx.load_texture(
+                format!("review {}", thumbnail.index),
+                ColorImage::from_rgb(thumbnail.size, &thumbnail.rgb),
+                TextureOptions::NEAREST,
+            );
+            let entry = Entry {
+                index: thumbnail.index,
+                found: thumbnail.position,
+                origin: thumbnail.origin,
+                size: thumbnail.size,
+                texture,
+                verdict: None,
+            };
+
+            // A recapture of the same LED replaces the one before
+            match self.entries.iter_mut().find(|e| e.index == thumbnail.index) {
+                Some(old) => *old = entry,
+                None => self.entries.push(entry),
+            }
+        }
+        self.seen = thumbnails.len();
+        self.entries.sort_by_key(|entry| entry.index);
+        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+    }
+
+    fn handle_keys(&mut self, ctx: &egui::Context, keymap: &mut Keymap) {
+        let columns = COLUMNS as isize;
+        for action in keymap.pressed(ctx, true) {
+            let by = match action {
+                Action::NextLed => 1,
+                Action::PrevLed => -1,
+                Action::NextRow => columns,
+                Action::PrevRow => -columns,
+                Action::AcceptLed => {
+                    self.judge(Verdict::Accepted);
+                    continue;
+                }
+                Action::RejectLed => {
+                    self.judge(Verdict::Rejected);
+                    continue;
+                }
+                _ => continue,
+            };
+            self.selected = self
+                .selected
+                .saturating_add_signed(by)
+                .min(self.entries.len() - 1);
+            self.scroll = true;
+        }
+    }
+
+    /// Accepts or rejects the selected LED and moves on to the next. Accepting keeps a position
+    /// placed by hand, and brings back the scan's after a rejection.
+    fn judge(&mut self, verdict: Verdict) {
+        let entry = &self.entries[self.selected];
+        match (verdict, entry.verdict) {
+            (Verdict::Accepted, Some(Verdict::Fixed)) => {}
+            (Verdict::Accepted, Some(Verdict::Rejected)) => self.change(verdict, entry.found),
+            (Verdict::Accepted, _) => self.change(verdict, mapped(entry.index)),
+            _ => self.change(verdict, None),
+        }
+        self.selected = (self.selected + 1).min(self.entries.len() - 1);
+        self.scroll = true;
+    }
+
+    /// Gives the selected LED `verdict` and `position` in the map, keeping what it had for `undo`.
+    fn change(&mut self, verdict: Verdict, position: Option<Pos2>) {
+        let entry = &mut self.entries[self.selected];
+        self.history.push(Edit {
+            index: entry.index,
+            position: mapped(entry.index),
+            verdict: entry.verdict,
+        });
+        set(entry.index, position);
+        entry.verdict = Some(verdict);
+    }
+
+    /// Takes back the latest accept, reject or fix, selecting its LED again.
+    pub fn undo(&mut self) {
+        let Some(edit) = self.history.pop() else {
+            return;
+        };
+        set(edit.index, edit.position);
+        if let Some(i) = self.entries.iter().position(|e| e.index == edit.index) {
+            self.entries[i].verdict = edit.verdict;
+            self.selected = i;
+            self.scroll = true;
+        }
+    }
+}
+
+fn mapped(index: usize) -> Option<Pos2> {
+    scan::MAP.read().unwrap().get(index).copied().flatten()
+}
+
+fn set(index: usize, position: Option<Pos2>) {
+    if let Some(slot) = scan::MAP.write().unwrap().get_mut(index) {
+        *slot = position;
+    }
+}
+
+fn cross(painter: &egui::Painter, center: Pos2, size: f32) {
+    let stroke = Stroke::new(1.5, Color32::RED);
+    painter.line_segment([center - Vec2::X * size, center + Vec2::X * size], stroke);
+    painter.line_segment([center - Vec2::Y * size, center + Vec2::Y * size], stroke);
+}
@@ -0,0 +1,109 @@
+//! Frames from a Raspberry Pi camera module, through `rpicam-vid` writing raw YUV 4:2:0 to its
+//! stdout. That keeps libcamera out of the build, and the frames go straight into the YUV path
+//! without an encode and decode in between.
+
+use std::{
+    io::{ErrorKind, Read},
+    process::{Command, Stdio},
+    sync::{atomic::Ordering, Arc},
+};
+
+use anyhow::Context;
+use eframe::epaint::TextureHandle;
+use led_detect::Yuv420;
+use tracing::info;
+
+use crate::{
+    pipeline::{self, IMAGE, IMAGE_WIDTH, YUV_FRAME},
+    preview, stats,
+};
+
+const PROGRAM: &str = "rpicam-vid";
+
+#[derive(Clone, Debug)]
+pub struct Camera {
+    /// Which camera, for boards with more than one connected.
+    pub index: usize,
+    pub width: usize,
+    pub height: usize,
+    pub framerate: f64,
+}
+
+/// Runs `rpicam-vid` and reads its frames into `YUV_FRAME`, and into `IMAGE` and `texture` when
+/// running with a window, until it exits or the pipeline shuts down.
+pub fn decode(camera: &Camera, mut texture: Option<TextureHandle>) -> anyhow::Result<()> {
+    // rpicam-vid writes whole buffers, padding included, and those are only unpadded at this
+    // alignment
+    anyhow::ensure!(
+        camera.width.is_multiple_of(64) && camera.height.is_multiple_of(2),
+        "The camera width has to be a multiple of 64 and its height even, not {}x{}",
+        camera.width,
+        camera.height
+    );
+
+    let mut child = Command::new(PROGRAM)
+        .args(["--nopreview", "--timeout", "0", "--codec", "yuv420", "--output", "-"])
+        .args(["--camera", &camera.index.to_string()])
+        .args(["--width", &camera.width.to_string()])
+        .args(["--height", &camera.height.to_string()])
+        .args(["--framerate", &camera.framerate.to_string()])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {PROGRAM}, is rpicam-apps installed?"))?;
+    let mut stdout = child.stdout.take().context("No stdout from rpicam-vid")?;
+
+    info!("Opened Raspberry Pi camera {} at {}x{}", camera.index, camera.width, camera.height);
+
+    let (width, height) = (camera.width, camera.height);
+    let luma = width * height;
+    let chroma = luma / 4;
+    let mut buffer = vec![0; luma + 2 * chroma];
+
+    let source = pipeline::source();
+    let result = loop {
+        if pipeline::source_stopped(source) {
+            info!("Closing the Raspberry Pi camera");
+            break Ok(());
+        }
+
+        match stdout.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                break Err(anyhow::anyhow!("{PROGRAM} exited"))
+            }
+            Err(e) => break Err(e.into()),
+        }
+
+        let yuv = Yuv420 {
+            width,
+            height,
+            // The video color spaces libcamera picks are all limited range
+            full_range: false,
+            y: buffer[..luma].to_vec(),
+            u: buffer[luma..luma + chroma].to_vec(),
+            v: buffer[luma + chroma..].to_vec(),
+        };
+        stats::frame_decoded(None, camera.framerate);
+
+        if IMAGE_WIDTH.load(Ordering::Relaxed) != width {
+            info!("Receiving {width}x{height} frames");
+        }
+
+        // As with the stream's YUV path, only the preview needs RGB
+        if let Some(texture) = texture.as_mut().filter(|_| preview::due()) {
+            IMAGE.publish(|data| {
+                yuv.write_rgb(data);
+                preview::show(texture, [width, height], data);
+            });
+        }
+
+        *YUV_FRAME.write().unwrap() = Some(Arc::new(yuv));
+        IMAGE_WIDTH.store(width, Ordering::Relaxed);
+    };
+
+    // Whatever went wrong, don't leave the camera claimed
+    let _ = child.kill();
+    let _ = child.wait();
+    result
+}
@@ -0,0 +1,1377 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use eframe::epaint::{Color32, Pos2, Rect, Vec2};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    alerts, awb,
+    controller::LedController,
+    depth,
+    drift::{self, DriftAction},
+    grouped, ignored, issues, journal,
+    pipeline::{self, POINTS},
+    pixel_order, recording, refine, reflections, regions, script, segments, stereo, timelapse,
+    toasts::{self, Retry},
+    zones, Led,
+};
+
+/// The connected controller, shared between the scan thread and verify mode.
+pub type SharedController = Arc<Mutex<Box<dyn LedController>>>;
+
+/// Position of every LED by index, `None` where the scan didn't find it.
+pub static MAP: RwLock<Vec<Option<Pos2>>> = RwLock::new(Vec::new());
+
+/// `MAP` as it was before the current scan cleared it, which grouped scans go by without priors.
+static PREVIOUS: RwLock<Vec<Option<Pos2>>> = RwLock::new(Vec::new());
+
+/// Positions from an earlier calibration of the same installation. While set, a capture only
+/// accepts blobs near the LED's old position.
+pub static PRIORS: RwLock<Option<Priors>> = RwLock::new(None);
+
+pub struct Priors {
+    /// Old position by index, `None` for LEDs the earlier map didn't have.
+    pub positions: Vec<Option<Pos2>>,
+    /// How far an LED may have moved, in pixels.
+    pub radius: f32,
+}
+
+impl Priors {
+    pub fn new(leds: &[Led], radius: f32) -> Self {
+        let layout = segments::LAYOUT.read().unwrap();
+        let index = |led: &Led| layout.global(led.segment, led.index);
+
+        let count = leds.iter().map(|led| index(led) + 1).max().unwrap_or(0);
+        let mut positions = vec![None; count];
+        for led in leds {
+            positions[index(led)] = Some(Pos2::new(led.position[0], led.position[1]));
+        }
+
+        Self { positions, radius }
+    }
+
+    fn get(&self, index: usize) -> Option<Pos2> {
+        self.positions.get(index).copied().flatten()
+    }
+}
+
+/// Measured time from flushing a frame to the controller until the LED shows up in `POINTS`.
+/// Replaces the guessed settle time once set.
+pub static MEASURED_LATENCY: RwLock<Option<Duration>> = RwLock::new(None);
+
+/// A crop of the frame around each capture, in capture order, so bad detections stand out while
+/// the scan is still running.
+pub static THUMBNAILS: Mutex<Vec<Thumbnail>> = Mutex::new(Vec::new());
+
+pub struct Thumbnail {
+    pub index: usize,
+    /// Where the LED was found, in frame pixels.
+    pub position: Option<Pos2>,
+    /// Top left corner of the crop in the frame.
+    pub origin: [usize; 2],
+    pub size: [usize; 2],
+    pub rgb: Vec<u8>,
+}
+
+const THUMBNAIL_SIZE: usize = 48;
+
+/// When the current scan started and at which LED, for the ETA.
+static STARTED: Mutex<Option<(Instant, usize)>> = Mutex::new(None);
+
+/// Steps in the current scan, which is fewer than the LEDs when segments are interleaved.
+pub static STEPS: AtomicUsize = AtomicUsize::new(0);
+pub static RUNNING: AtomicBool = AtomicBool::new(false);
+pub static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static CANCEL: AtomicBool = AtomicBool::new(false);
+/// Set along with `CANCEL` when the scan's results should be thrown away rather than kept.
+static ABORT: AtomicBool = AtomicBool::new(false);
+
+/// Where the last scan is at, as reported to the GUI and the HTTP API. Only scans go through
+/// here, the other background jobs only hold `RUNNING`.
+pub static STATE: RwLock<State> = RwLock::new(State::Idle);
+
+/// The controller `command` starts scans on, set whenever one is connected.
+pub static CONTROLLER: RwLock<Option<SharedController>> = RwLock::new(None);
+
+/// The mode of the last scan started, for `Command::Rescan`.
+pub static LAST_MODE: RwLock<Option<ScanMode>> = RwLock::new(None);
+
+/// The thread of the last scan started through `command`, for joining on exit.
+static JOB: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+
+#[derive(Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase", tag = "state", content = "error")]
+pub enum State {
+    Idle,
+    Running,
+    /// Asked to stop, waiting for the current step to finish.
+    Stopping,
+    Finished,
+    /// Stopped early, with what it found so far kept and resumable.
+    Stopped,
+    /// Stopped early, with what it found thrown away.
+    Aborted,
+    Failed(String),
+}
+
+/// What the window, the web UI and the HTTP API can ask of the scan. See `command`.
+pub enum Command {
+    Start(ScanMode),
+    /// Continue the interrupted scan in the journal.
+    Resume,
+    /// Stop after the current LED, keeping what was found.
+    Stop,
+    /// Stop after the current LED and throw away what was found.
+    Abort,
+    /// Run the last scan again, with the same mode.
+    Rescan,
+}
+
+/// How the LEDs are lit during a scan, which wants tuning per camera: full white at full
+/// brightness blooms into neighbouring LEDs on most.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanSettings {
+    /// How long to wait after lighting an LED before reading the detections. This has to cover
+    /// the stream latency, on top of which `step_time` adds a full pass of the detection loop.
+    /// A measured latency replaces it.
+    pub settle: Duration,
+    /// How long each LED stays lit at least in a sequential scan, for cameras with long
+    /// exposures that catch the next LED coming on otherwise.
+    pub on_time: Duration,
+    /// What an LED is lit in when scanned on its own.
+    pub color: [u8; 3],
+    /// Light LEDs scanned on their own with only their white channel instead of `color`, for
+    /// RGBW and white-only strips.
+    pub white: bool,
+    /// Scales every color the scans light, from 0 to 1.
+    pub brightness: f32,
+    /// A sequential scan captures each LED a second time at this share of its color, see `fuse`.
+    pub second_brightness: Option<f32>,
+    /// Which LEDs the scans that go one LED at a time light, in which order.
+    pub order: ScanOrder,
+    /// How many LEDs a grouped scan lights per step at most.
+    pub group_size: usize,
+    /// How far apart the LEDs a grouped scan lights together are expected to be at least, in
+    /// pixels. Each one's blob has to be within half of it.
+    pub group_separation: f32,
+    /// What to do when the camera moves during a scan, see `drift`.
+    pub drift: DriftAction,
+}
+
+impl ScanSettings {
+    pub const DEFAULT: Self = Self {
+        settle: Duration::from_millis(400),
+        on_time: Duration::ZERO,
+        color: [255, 255, 255],
+        white: false,
+        brightness: 1.0,
+        second_brightness: None,
+        order: ScanOrder::Sequential,
+        group_size: 8,
+        group_separation: 100.0,
+        drift: DriftAction::Stop,
+    };
+
+    /// What to light a single LED in.
+    pub fn led_color(&self) -> Color32 {
+        let [r, g, b] = self.color;
+        self.dim(Color32::from_rgb(r, g, b))
+    }
+
+    /// How to light a single LED, `led_color` or its white channel.
+    pub fn led_light(&self) -> Light {
+        match self.white {
+            true => Light::White((self.brightness.clamp(0.0, 1.0) * 255.0).round() as u8),
+            false => Light::Color(self.led_color()),
+        }
+    }
+
+    /// How to light a single LED for the second capture, if there is one.
+    pub fn second_light(&self) -> Option<Light> {
+        self.second_brightness
+            .map(|share| self.led_light().scaled(share))
+    }
+
+    /// `color` at `brightness`.
+    pub fn dim(&self, color: Color32) -> Color32 {
+        scale(color, self.brightness)
+    }
+}
+
+/// How a single LED is lit, see `ScanSettings::led_light`.
+#[derive(Clone, Copy)]
+pub enum Light {
+    Color(Color32),
+    White(u8),
+}
+
+impl Light {
+    /// Lights LED `index` of `controller` like this, to show on the next flush.
+    pub fn set(self, controller: &mut dyn LedController, index: usize) {
+        match self {
+            Light::Color(color) => controller.set_pixel(index, color),
+            Light::White(white) => controller.set_white(index, white),
+        }
+    }
+
+    fn scaled(self, by: f32) -> Self {
+        match self {
+            Light::Color(color) => Light::Color(scale(color, by)),
+            Light::White(white) => Light::White((white as f32 * by.clamp(0.0, 1.0)).round() as u8),
+        }
+    }
+}
+
+fn scale(color: Color32, by: f32) -> Color32 {
+    let scale = |channel: u8| (channel as f32 * by.clamp(0.0, 1.0)).round() as u8;
+    Color32::from_rgb(scale(color.r()), scale(color.g()), scale(color.b()))
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ScanOrder {
+    #[default]
+    Sequential,
+    Reverse,
+    /// Each segment in turn, every other one backwards, so a strip laid out back and forth is
+    /// scanned along the way it runs.
+    Serpentine,
+    /// Shuffled, so drift in the camera or the lighting spreads over the whole map instead of
+    /// building up from one end to the other.
+    Random,
+    /// The LEDs of a list like `0-49, 100, 199-150`, as written, leaving out the rest.
+    Custom(String),
+}
+
+impl ScanOrder {
+    /// Every order but `Custom`, which needs a list.
+    pub const FIXED: [Self; 4] = [Self::Sequential, Self::Reverse, Self::Serpentine, Self::Random];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Sequential => "Sequential",
+            Self::Reverse => "Reverse",
+            Self::Serpentine => "Serpentine",
+            Self::Random => "Random",
+            Self::Custom(_) => "Custom list",
+        }
+    }
+
+    /// The indices to scan of `count` LEDs, in order.
+    pub fn leds(&self, count: usize) -> anyhow::Result<Vec<usize>> {
+        Ok(match self {
+            Self::Sequential => (0..count).collect(),
+            Self::Reverse => (0..count).rev().collect(),
+            Self::Serpentine => {
+                let layout = segments::LAYOUT.read().unwrap();
+                let mut leds = Vec::with_capacity(count);
+                for segment in 0..layout.len() {
+                    let offset = layout.offset(segment).min(count);
+                    let end = layout
+                        .count(segment)
+                        .map_or(count, |n| (offset + n).min(count));
+                    if segment % 2 == 0 {
+                        leds.extend(offset..end);
+                    } else {
+                        leds.extend((offset..end).rev());
+                    }
+                }
+                leds
+            }
+            Self::Random => {
+                let mut leds = (0..count).collect::<Vec<_>>();
+                shuffle(&mut leds);
+                leds
+            }
+            Self::Custom(list) => {
+                let leds = parse_list(list)?;
+                if let Some(index) = leds.iter().find(|&&index| index >= count) {
+                    anyhow::bail!("LED {index} of the scan order isn't there, there are {count}");
+                }
+                leds
+            }
+        })
+    }
+}
+
+/// The indices of a list of indices and inclusive ranges, separated by commas or spaces. Ranges
+/// can run backwards.
+pub fn parse_list(list: &str) -> anyhow::Result<Vec<usize>> {
+    let mut leds = Vec::new();
+    for item in list.split([',', ' ', '\n']).filter(|item| !item.is_empty()) {
+        let index = |text: &str| {
+            text.trim()
+                .parse::<usize>()
+                .with_context(|| format!("{item:?} isn't an LED index or a range like 10-20"))
+        };
+        match item.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (index(from)?, index(to)?);
+                if from <= to {
+                    leds.extend(from..=to);
+                } else {
+                    leds.extend((to..=from).rev());
+                }
+            }
+            None => leds.push(index(item)?),
+        }
+    }
+    anyhow::ensure!(!leds.is_empty(), "The scan order list is empty");
+    Ok(leds)
+}
+
+/// Fisher-Yates with a xorshift seeded from the clock, which is plenty for spreading out drift.
+fn shuffle(leds: &mut [usize]) {
+    let mut state = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64)
+        | 1;
+    for i in (1..leds.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        leds.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+}
+
+/// The LEDs to scan of `count`, in the order of `SETTINGS`.
+pub fn order(count: usize) -> anyhow::Result<Vec<usize>> {
+    let order = SETTINGS.read().unwrap().order.clone();
+    order.leds(count)
+}
+
+pub static SETTINGS: RwLock<ScanSettings> = RwLock::new(ScanSettings::DEFAULT);
+
+fn led_color() -> Color32 {
+    SETTINGS.read().unwrap().led_color()
+}
+
+fn led_light() -> Light {
+    SETTINGS.read().unwrap().led_light()
+}
+
+fn dim(color: Color32) -> Color32 {
+    SETTINGS.read().unwrap().dim(color)
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScanMode {
+    /// Lights every LED in turn and records where it shows up.
+    Sequential,
+    /// Like `Sequential`, but detects on the difference between a frame with the LED off and one
+    /// with it on, so status LEDs and reflections that stay lit are ignored.
+    StrobeDiff,
+    /// Lights the same index on every segment at once, each in its own color from
+    /// `segments::PALETTE`, and tells the blobs apart by color. Takes as long as the longest
+    /// segment rather than all of them together, but needs a detection mode that passes colored
+    /// light. With more segments than colors, they're scanned a palette's worth at a time.
+    Interleaved,
+    /// Lights every LED at once in a sequence of colors spelling out its index, and reads the
+    /// sequence off each blob. Takes a handful of steps however many LEDs there are, but needs a
+    /// still camera and a detection mode that passes colored light.
+    ColorCoded,
+    /// A Rhai script deciding what to light and when to capture, see `script`.
+    Script(String),
+    /// Sequential scans of each of `ptz::REGIONS` with a PTZ camera, stitched together, see
+    /// `regions`.
+    Regions,
+    /// A sequential scan at half resolution, then each LED found lit again and placed to a
+    /// fraction of a pixel from a full resolution crop around it, see `refine`.
+    CoarseToFine,
+    /// Several LEDs far enough apart lit per step, going by priors or the previous map, see
+    /// `grouped`.
+    Grouped,
+}
+
+/// Carries out `command`, or explains why it can't right now.
+pub fn command(command: Command) -> anyhow::Result<()> {
+    let running = RUNNING.load(Ordering::SeqCst);
+
+    let job = match command {
+        Command::Stop | Command::Abort => {
+            anyhow::ensure!(running, "No scan is running");
+            if matches!(command, Command::Abort) {
+                ABORT.store(true, Ordering::SeqCst);
+            }
+            stop();
+            return Ok(());
+        }
+        Command::Start(_) | Command::Resume | Command::Rescan if running => {
+            anyhow::bail!("A scan is already running")
+        }
+        Command::Start(mode) => start(controller()?, mode),
+        Command::Resume => start_resume(controller()?),
+        Command::Rescan => {
+            let mode = LAST_MODE.read().unwrap().clone();
+            start(controller()?, mode.context("There's no earlier scan to run again")?)
+        }
+    };
+
+    // Another job got in first
+    let job = job.context("A scan is already running")?;
+    if let Some(finished) = JOB.lock().unwrap().replace(job) {
+        let _ = finished.join();
+    }
+    Ok(())
+}
+
+fn controller() -> anyhow::Result<SharedController> {
+    CONTROLLER
+        .read()
+        .unwrap()
+        .clone()
+        .context("No controller connected")
+}
+
+/// Joins the last scan started through `command`, see `pipeline::join_until`.
+pub fn join_until(deadline: Instant) -> bool {
+    match JOB.lock().unwrap().take() {
+        Some(job) => pipeline::join_until(job, deadline),
+        None => true,
+    }
+}
+
+/// Runs a scan in the background unless one is already running.
+pub fn start(controller: SharedController, mode: ScanMode) -> Option<JoinHandle<()>> {
+    *LAST_MODE.write().unwrap() = Some(mode.clone());
+
+    start_scan_job(move || {
+        info!("Scan started");
+
+        let result = run(&controller, mode).and_then(|()| check_colors_after(&controller));
+        match &result {
+            Ok(()) => {
+                let map = MAP.read().unwrap();
+                let found = map.iter().filter(|pos| pos.is_some()).count();
+                info!("Scan finished, found {found} of {} LEDs", map.len());
+
+                let layout = segments::LAYOUT.read().unwrap();
+                if layout.len() > 1 {
+                    for segment in 0..layout.len() {
+                        let offset = layout.offset(segment);
+                        let count = layout.count(segment).unwrap_or(0);
+                        let found = map
+                            .iter()
+                            .skip(offset)
+                            .take(count)
+                            .filter(|pos| pos.is_some())
+                            .count();
+                        info!("Segment {}: found {found} of {count}", layout.name(segment));
+                    }
+                }
+            }
+            Err(e) if !cancelled() => {
+                error!("Scan failed: {e}");
+                toasts::error(format!("Scan failed: {e}"), Some(Retry::Scan));
+            }
+            Err(_) => {}
+        }
+        result
+    })
+}
+
+/// Continues the scan left behind in the journal in the background, see `resume`.
+pub fn start_resume(controller: SharedController) -> Option<JoinHandle<()>> {
+    start_scan_job(move || {
+        let result = resume(&controller).and_then(|()| check_colors_after(&controller));
+        match &result {
+            Ok(()) => info!("Resumed scan finished"),
+            Err(e) if !cancelled() => {
+                error!("Resuming the scan failed: {e}");
+                toasts::error(format!("Resuming the scan failed: {e}"), None);
+            }
+            Err(_) => {}
+        }
+        result
+    })
+}
+
+/// `start_job` for scans, keeping `STATE` up to date and carrying out aborts.
+fn start_scan_job(
+    job: impl FnOnce() -> anyhow::Result<()> + Send + 'static,
+) -> Option<JoinHandle<()>> {
+    start_job(move || {
+        ABORT.store(false, Ordering::SeqCst);
+        *STATE.write().unwrap() = State::Running;
+
+        let locked = if awb::LOCK.load(Ordering::Relaxed) {
+            awb::lock()
+        } else {
+            Vec::new()
+        };
+        let result = job();
+        awb::unlock(locked);
+
+        let state = if ABORT.load(Ordering::SeqCst) {
+            *MAP.write().unwrap() = Vec::new();
+            THUMBNAILS.lock().unwrap().clear();
+            journal::discard();
+            info!("Scan aborted");
+            State::Aborted
+        } else if cancelled() {
+            info!("Scan stopped");
+            State::Stopped
+        } else {
+            match result {
+                Ok(()) => State::Finished,
+                Err(e) => State::Failed(format!("{e:#}")),
+            }
+        };
+        alerts::scan_ended(&state);
+        *STATE.write().unwrap() = state;
+    })
+}
+
+/// Runs `ignored::learn` in the background, unless a scan is running.
+pub fn start_baseline(controller: SharedController) -> Option<JoinHandle<()>> {
+    start_job(move || {
+        if let Err(e) = ignored::learn(&controller) {
+            error!("Learning the ignored sources failed: {e}");
+            toasts::error(format!("Learning the ignored sources failed: {e}"), None);
+        }
+    })
+}
+
+/// Runs `capture_dark_frame` in the background, unless a scan is running.
+pub fn start_dark_frame(controller: SharedController) -> Option<JoinHandle<()>> {
+    start_job(move || {
+        if let Err(e) = capture_dark_frame(&controller) {
+            error!("Capturing the dark frame failed: {e}");
+            toasts::error(format!("Capturing the dark frame failed: {e}"), None);
+        }
+    })
+}
+
+/// Captures a dark frame, in the background with `CONTROLLER` turning the LEDs off first if
+/// there is one, right away otherwise.
+pub fn dark_frame() -> anyhow::Result<Option<JoinHandle<()>>> {
+    match CONTROLLER.read().unwrap().clone() {
+        Some(controller) => Ok(start_dark_frame(controller)),
+        None => pipeline::capture_dark_frame().map(|()| None),
+    }
+}
+
+/// Turns every LED off and records what the camera sees as `pipeline::DARK_FRAME`.
+pub fn capture_dark_frame(controller: &SharedController) -> anyhow::Result<()> {
+    let mut controller = controller.lock().unwrap();
+    controller.set_all(Color32::BLACK);
+    controller.flush()?;
+    thread::sleep(step_time(controller.latency_hint()));
+
+    pipeline::capture_dark_frame()
+}
+
+/// Runs `measure_latency` in the background, unless a scan is running.
+pub fn start_latency_measurement(
+    controller: SharedController,
+    index: usize,
+) -> Option<JoinHandle<()>> {
+    start_job(move || {
+        if let Err(e) = measure_latency(&controller, index) {
+            error!("Latency measurement failed: {e}");
+            toasts::error(format!("Latency measurement failed: {e}"), None);
+        }
+    })
+}
+
+/// Runs `pixel_order::detect` on the segments `controller` is connected to, leaving what it found
+/// in `pixel_order::DETECTED`.
+pub fn start_pixel_order_detection(
+    controller: SharedController,
+    segments: Vec<segments::Segment>,
+) -> Option<JoinHandle<()>> {
+    start_job(move || match pixel_order::detect(&controller, &segments) {
+        Ok(orders) => *pixel_order::DETECTED.lock().unwrap() = Some(orders),
+        Err(e) => {
+            error!("Pixel order detection failed: {e}");
+            toasts::error(format!("Pixel order detection failed: {e}"), None);
+        }
+    })
+}
+
+fn start_job(job: impl FnOnce() + Send + 'static) -> Option<JoinHandle<()>> {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return None;
+    }
+
+    CANCEL.store(false, Ordering::SeqCst);
+
+    Some(thread::spawn(move || {
+        job();
+        RUNNING.store(false, Ordering::SeqCst);
+    }))
+}
+
+pub fn stop() {
+    CANCEL.store(true, Ordering::SeqCst);
+
+    let mut state = STATE.write().unwrap();
+    if *state == State::Running {
+        *state = State::Stopping;
+    }
+}
+
+pub fn cancelled() -> bool {
+    CANCEL.load(Ordering::SeqCst)
+}
+
+/// Records the biggest blob currently detected as the position of LED `index`. Returns whether
+/// anything was detected at all.
+pub fn capture(index: usize) -> bool {
+    CURRENT.store(index, Ordering::Relaxed);
+
+    let position = candidate(index);
+    store(index, position);
+    position.is_some()
+}
+
+/// Clears the map for a scan of `count` LEDs.
+pub fn reset(count: usize) {
+    let previous = std::mem::replace(&mut *MAP.write().unwrap(), vec![None; count]);
+    *PREVIOUS.write().unwrap() = previous;
+    STEPS.store(count, Ordering::Relaxed);
+    THUMBNAILS.lock().unwrap().clear();
+    *STARTED.lock().unwrap() = Some((Instant::now(), 0));
+    stereo::reset(count);
+    depth::reset(count);
+    issues::reset(count);
+    timelapse::reset();
+    recording::begin();
+}
+
+pub fn store(index: usize, position: Option<Pos2>) {
+    if !drift::check() {
+        return;
+    }
+    let position = position.map(drift::compensate);
+
+    if let Some(slot) = MAP.write().unwrap().get_mut(index) {
+        *slot = position;
+    }
+    journal::record(index, position);
+    stereo::capture(index, position);
+    depth::capture(index, position);
+    timelapse::add();
+    recording::frame(index, position);
+
+    let Some((frame, width)) = pipeline::latest_rgb() else {
+        return;
+    };
+    issues::capture(index, position, &frame, width);
+    let height = frame.len() / width / 3;
+
+    // Centered on the detection, or the middle of the frame when there wasn't one
+    let center = position.unwrap_or(Pos2::new(width as f32 / 2.0, height as f32 / 2.0));
+    let half = THUMBNAIL_SIZE as f32 / 2.0;
+    let left = (center.x - half).clamp(0.0, width.saturating_sub(THUMBNAIL_SIZE) as f32) as usize;
+    let top = (center.y - half).clamp(0.0, height.saturating_sub(THUMBNAIL_SIZE) as f32) as usize;
+    let size = [THUMBNAIL_SIZE.min(width), THUMBNAIL_SIZE.min(height)];
+
+    let rgb = (top..top + size[1])
+        .flat_map(|row| &frame[(row * width + left) * 3..(row * width + left + size[0]) * 3])
+        .copied()
+        .collect();
+
+    THUMBNAILS.lock().unwrap().push(Thumbnail {
+        index,
+        position,
+        origin: [left, top],
+        size,
+        rgb,
+    });
+}
+
+/// Estimated time left in the current scan, going by how long the LEDs so far took.
+pub fn eta() -> Option<Duration> {
+    let (started, first) = (*STARTED.lock().unwrap())?;
+    let current = CURRENT.load(Ordering::Relaxed);
+    let done = current.saturating_sub(first) as u32;
+    let left = STEPS.load(Ordering::Relaxed).saturating_sub(current) as u32;
+
+    (done > 0).then(|| started.elapsed() / done * left)
+}
+
+/// Where LED `index` appears to be in the current detections.
+fn candidate(index: usize) -> Option<Pos2> {
+    pick(&POINTS.read().unwrap(), index)
+}
+
+/// Where LED `index` appears to be among `points`, leaving out the ignored sources.
+pub fn pick(points: &[Rect], index: usize) -> Option<Pos2> {
+    let candidates = candidates(points, index);
+
+    match prior(index) {
+        // Nearest blob to where the LED used to be
+        Some(prior) => candidates
+            .iter()
+            .map(|rect| rect.center())
+            .min_by(|a, b| a.distance(prior).total_cmp(&b.distance(prior))),
+
+        // Only one LED should be lit, so anything else in view is noise
+        None => candidates
+            .iter()
+            .max_by(|a, b| a.area().total_cmp(&b.area()))
+            .map(|rect| rect.center()),
+    }
+}
+
+fn prior(index: usize) -> Option<Pos2> {
+    PRIORS.read().unwrap().as_ref()?.get(index)
+}
+
+/// Where LED `index` probably is before it's scanned: its prior, or where the scan before found
+/// it.
+pub fn expected(index: usize) -> Option<Pos2> {
+    prior(index).or_else(|| PREVIOUS.read().unwrap().get(index).copied().flatten())
+}
+
+/// Which of `points` could be LED `index`: all but the ignored sources and those in the zones of
+/// other segments, and with a prior only those near it, ignoring stray light elsewhere.
+pub fn candidates(points: &[Rect], index: usize) -> Vec<Rect> {
+    let mut points = zones::filter(&ignored::filter(points), index);
+    if let Some(priors) = PRIORS.read().unwrap().as_ref() {
+        if let Some(prior) = priors.get(index) {
+            points.retain(|rect| rect.center().distance(prior) <= priors.radius);
+        }
+    }
+    points
+}
+
+/// Like `pick`, but with `reflections::RESOLVE` on and more than one candidate the LED is blinked
+/// to tell which of them it is, see `reflections::resolve`.
+fn pick_blinking(
+    controller: &mut dyn LedController,
+    points: &[Rect],
+    index: usize,
+) -> anyhow::Result<Option<Pos2>> {
+    let candidates = candidates(points, index);
+    if candidates.len() > 1 && reflections::RESOLVE.load(Ordering::Relaxed) {
+        if let Some(position) = reflections::resolve(controller, index, &candidates)? {
+            return Ok(Some(position));
+        }
+    }
+    Ok(pick(points, index))
+}
+
+/// Blinks LED `index` a few times and times how long it takes to appear in and disappear from
+/// the detections, then keeps the worst case in `MEASURED_LATENCY`. Anything else lit in view is
+/// fine as long as it stays put.
+pub fn measure_latency(controller: &SharedController, index: usize) -> anyhow::Result<Duration> {
+    const TRIALS: usize = 5;
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    let mut controller = controller.lock().unwrap();
+    anyhow::ensure!(index < controller.len(), "There's no LED {index}");
+
+    controller.set_all(Color32::BLACK);
+    controller.flush()?;
+    thread::sleep(Duration::from_secs(1));
+    let baseline = POINTS.read().unwrap().len();
+
+    let mut samples = Vec::with_capacity(TRIALS * 2);
+    for _ in 0..TRIALS {
+        anyhow::ensure!(!cancelled(), "Cancelled");
+
+        led_light().set(&mut **controller, index);
+        controller.flush()?;
+        let on = wait_for_count(|count| count > baseline, TIMEOUT)
+            .ok_or_else(|| anyhow::anyhow!("LED {index} never showed up, is it in view?"))?;
+
+        controller.set_pixel(index, Color32::BLACK);
+        controller.flush()?;
+        let off = wait_for_count(|count| count <= baseline, TIMEOUT)
+            .ok_or_else(|| anyhow::anyhow!("LED {index} never went dark again"))?;
+
+        samples.extend([on, off]);
+    }
+
+    let min = samples.iter().min().copied().unwrap_or_default();
+    let max = samples.iter().max().copied().unwrap_or_default();
+    info!("Measured latency between {min:?} and {max:?}");
+
+    *MEASURED_LATENCY.write().unwrap() = Some(max);
+    Ok(max)
+}
+
+fn wait_for_count(condition: impl Fn(usize) -> bool, timeout: Duration) -> Option<Duration> {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if condition(POINTS.read().unwrap().len()) {
+            return Some(start.elapsed());
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    None
+}
+
+/// Waits for LED `index` to show up after lighting it. With a prior this returns as soon as a
+/// blob appears near the old position, unless the previous LED's old position is nearer, in which
+/// case it's probably that LED still lit in a late frame.
+fn settle(index: usize, latency: Duration) {
+    let deadline = Instant::now() + step_time(latency);
+
+    if prior(index).is_some() {
+        thread::sleep(latency);
+
+        while Instant::now() < deadline {
+            if let Some(pos) = candidate(index) {
+                let ours = prior(index).map_or(f32::INFINITY, |p| p.distance(pos));
+                let previous = index
+                    .checked_sub(1)
+                    .and_then(prior)
+                    .map_or(f32::INFINITY, |p| p.distance(pos));
+                if ours < previous {
+                    return;
+                }
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    thread::sleep(deadline.saturating_duration_since(Instant::now()));
+}
+
+/// Time from flushing an LED until its blob is safely in `POINTS`, given the controller's own
+/// latency hint. A measured latency already covers the controller, and gets a quarter on top as
+/// margin.
+pub fn step_time(controller_latency: Duration) -> Duration {
+    let interval = *pipeline::INTERVAL.read().unwrap();
+
+    match *MEASURED_LATENCY.read().unwrap() {
+        Some(measured) => measured + measured / 4 + interval,
+        None => controller_latency + SETTINGS.read().unwrap().settle + interval,
+    }
+}
+
+/// Time from flushing an LED until a frame captured with it lit, which unlike `step_time` needs
+/// no detection pass or margin for the stream falling behind.
+fn frame_latency(controller_latency: Duration) -> Duration {
+    match *MEASURED_LATENCY.read().unwrap() {
+        Some(measured) => measured + measured / 4,
+        None => controller_latency + SETTINGS.read().unwrap().settle,
+    }
+}
+
+/// Waits for the first frame captured `frame_latency` after `flushed` and detects in it. `None`
+/// if no such frame came in time, for the caller to fall back on `settle` and `POINTS`.
+fn detect_after(
+    index: usize,
+    flushed: Instant,
+    controller_latency: Duration,
+) -> anyhow::Result<Option<Vec<Rect>>> {
+    let latency = frame_latency(controller_latency);
+    let Some((frame, width, after)) =
+        pipeline::frame_after(flushed + latency, step_time(controller_latency) * 2)
+    else {
+        return Ok(None);
+    };
+    debug!(
+        "LED {index}: frame captured {:.0} ms after the command, used {:.0} ms later",
+        (latency + after).as_secs_f64() * 1000.0,
+        flushed
+            .elapsed()
+            .saturating_sub(latency + after)
+            .as_secs_f64()
+            * 1000.0
+    );
+
+    Ok(Some(pipeline::detect_frame(&frame, width)?))
+}
+
+/// Combines the detections of a step lit at full brightness with those of the second capture at
+/// `second_brightness`. An LED near the camera blooms into a blob at full brightness that hides
+/// where in it the LED is, which the dimmer capture narrows down, while an LED far away may only
+/// show up at full. So blobs of the dim capture inside one of the bright capture stand in for it,
+/// and the bright capture counts on its own when the dim one found nothing in it.
+fn fuse(bright: &[Rect], dim: &[Rect]) -> Vec<Rect> {
+    let resolved = bright
+        .iter()
+        .flat_map(|rect| dim.iter().filter(|dim| rect.contains(dim.center())))
+        .copied()
+        .collect::<Vec<_>>();
+    if resolved.is_empty() {
+        bright.to_vec()
+    } else {
+        resolved
+    }
+}
+
+/// Runs a scan on the current thread, filling `MAP`.
+pub fn run(controller: &SharedController, mode: ScanMode) -> anyhow::Result<()> {
+    if pipeline::PAUSED.swap(false, Ordering::Relaxed) {
+        info!("Resuming detection for the scan");
+    }
+
+    if ignored::LEARN_BEFORE_SCAN.load(Ordering::Relaxed) {
+        ignored::learn(controller)?;
+    }
+
+    let count = controller.lock().unwrap().len();
+    reset(count);
+    drift::begin(false);
+
+    match mode {
+        // Scripts capture in whatever order they like, so there's no index to resume them from
+        ScanMode::Script(source) => return script::run(controller, &source),
+        // And interleaved scans capture several LEDs per step
+        ScanMode::Interleaved => return run_interleaved(controller),
+        ScanMode::ColorCoded => return run_color_coded(controller),
+        ScanMode::Regions => return regions::run(controller),
+        ScanMode::CoarseToFine => return refine::run(controller),
+        ScanMode::Sequential | ScanMode::StrobeDiff | ScanMode::Grouped => {}
+    }
+
+    let leds = order(count)?;
+    STEPS.store(leds.len(), Ordering::Relaxed);
+    if let Err(e) = journal::begin(count, &mode) {
+        warn!("Can't keep a scan journal, this scan won't be resumable: {e}");
+    }
+
+    let result = run_from(controller, &mode, &leds, 0);
+    journal::close(result.is_ok() && !cancelled());
+    result
+}
+
+/// Picks up the scan recorded in the journal with the LEDs it didn't get to, keeping the positions
+/// it already found.
+pub fn resume(controller: &SharedController) -> anyhow::Result<()> {
+    let interrupted = journal::load().context("There's no interrupted scan to resume")?;
+
+    let count = controller.lock().unwrap().len();
+    anyhow::ensure!(
+        interrupted.led_count == count,
+        "The interrupted scan was of {} LEDs, but the controller has {count}",
+        interrupted.led_count
+    );
+
+    if pipeline::PAUSED.swap(false, Ordering::Relaxed) {
+        info!("Resuming detection for the scan");
+    }
+
+    if ignored::LEARN_BEFORE_SCAN.load(Ordering::Relaxed) {
+        ignored::learn(controller)?;
+    }
+
+    reset(count);
+    drift::begin(true);
+    // A shuffled order comes out different again, which doesn't matter for the LEDs left
+    let leds = order(count)?;
+    let left = leds
+        .iter()
+        .copied()
+        .filter(|&index| !interrupted.captured[index])
+        .collect::<Vec<_>>();
+    let done = leds.len() - left.len();
+    *MAP.write().unwrap() = interrupted.map;
+    STEPS.store(leds.len(), Ordering::Relaxed);
+    *STARTED.lock().unwrap() = Some((Instant::now(), done));
+    journal::reopen()?;
+
+    info!("Resuming the scan with {} of {} LEDs left", left.len(), leds.len());
+
+    let result = run_from(controller, &interrupted.mode, &left, done);
+    journal::close(result.is_ok() && !cancelled());
+    result
+}
+
+/// Scans `leds` in order with `mode`, counting the steps from `first`.
+fn run_from(
+    controller: &SharedController,
+    mode: &ScanMode,
+    leds: &[usize],
+    first: usize,
+) -> anyhow::Result<()> {
+    match mode {
+        ScanMode::Sequential => run_sequential(controller, leds, first),
+        ScanMode::StrobeDiff => run_strobe_diff(controller, leds, first),
+        ScanMode::Grouped => grouped::run(controller, leds, first),
+        ScanMode::Interleaved => anyhow::bail!("Interleaved scans can't be resumed"),
+        ScanMode::ColorCoded => anyhow::bail!("Color-coded scans can't be resumed"),
+        ScanMode::Script(_) => anyhow::bail!("Script scans can't be resumed"),
+        ScanMode::Regions => anyhow::bail!("Region scans can't be resumed"),
+        ScanMode::CoarseToFine => anyhow::bail!("Coarse-to-fine scans can't be resumed"),
+    }
+}
+
+/// The LEDs the last scan found, by index.
+pub fn leds() -> Vec<Led> {
+    let layout = segments::LAYOUT.read().unwrap();
+
+    MAP.read()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .filter_map(|(index, pos)| {
+            let pos = (*pos)?;
+            let (segment, index) = layout.split(index);
+            Some(Led {
+                segment,
+                index,
+                position: [pos.x, pos.y, 0.0],
+            })
+        })
+        .collect()
+}
+
+/// Lights each of `leds` in turn, `first` being the step the first of them is of the scan.
+pub fn run_sequential(
+    controller: &SharedController,
+    leds: &[usize],
+    first: usize,
+) -> anyhow::Result<()> {
+    let mut controller = controller.lock().unwrap();
+    let stamped = pipeline::frames_stamped();
+    let (light, second, on_time) = {
+        let settings = SETTINGS.read().unwrap();
+        (settings.led_light(), settings.second_light(), settings.on_time)
+    };
+
+    for (step, &index) in (first..).zip(leds) {
+        if cancelled() {
+            break;
+        }
+
+        CURRENT.store(step, Ordering::Relaxed);
+
+        controller.set_all(Color32::BLACK);
+        light.set(&mut **controller, index);
+        controller.flush()?;
+        let flushed = Instant::now();
+
+        // With timestamps the frame is picked by when it was taken, which a stream running late
+        // can't fool like the sleep can
+        let latency = controller.latency_hint();
+        let detected = if stamped {
+            detect_after(index, flushed, latency)?
+        } else {
+            None
+        };
+        let mut points = detected.unwrap_or_else(|| {
+            settle(index, latency);
+            POINTS.read().unwrap().clone()
+        });
+
+        if let Some(second) = second {
+            second.set(&mut **controller, index);
+            controller.flush()?;
+            let (frame, width) = frame_after_flush(stamped, step_time(latency), latency)?;
+            points = fuse(&points, &pipeline::detect_frame(&frame, width)?);
+        }
+
+        store(index, pick_blinking(&mut **controller, &points, index)?);
+        thread::sleep(on_time.saturating_sub(flushed.elapsed()));
+    }
+
+    controller.set_all(Color32::BLACK);
+    controller.flush()
+}
+
+fn run_strobe_diff(
+    controller: &SharedController,
+    leds: &[usize],
+    first: usize,
+) -> anyhow::Result<()> {
+    let mut controller = controller.lock().unwrap();
+    let step = step_time(controller.latency_hint());
+    let stamped = pipeline::frames_stamped();
+
+    for (current, &index) in (first..).zip(leds) {
+        if cancelled() {
+            break;
+        }
+
+        CURRENT.store(current, Ordering::Relaxed);
+
+        controller.set_all(Color32::BLACK);
+        controller.flush()?;
+        let (off, width) = frame_after_flush(stamped, step, controller.latency_hint())?;
+
+        led_light().set(&mut **controller, index);
+        controller.flush()?;
+        let (on, on_width) = frame_after_flush(stamped, step, controller.latency_hint())?;
+
+        if on.len() != off.len() || on_width != width {
+            warn!("Frame size changed while capturing LED {index}, skipping it");
+            continue;
+        }
+
+        let diff = on
+            .iter()
+            .zip(off.iter())
+            .map(|(on, off)| on.saturating_sub(*off))
+            .collect::<Vec<_>>();
+        let points = pipeline::detect_rgb(&diff, width)?;
+
+        store(index, pick(&points, index));
+    }
+
+    controller.set_all(Color32::BLACK);
+    controller.flush()
+}
+
+/// The first frame captured with what was just flushed showing, or with `stamped` false the latest
+/// one after sleeping for `step`.
+pub fn frame_after_flush(
+    stamped: bool,
+    step: Duration,
+    controller_latency: Duration,
+) -> anyhow::Result<(Arc<Vec<u8>>, usize)> {
+    let flushed = Instant::now();
+    if stamped {
+        let after = flushed + frame_latency(controller_latency);
+        if let Some((frame, width, _)) = pipeline::frame_after(after, step * 2) {
+            return Ok((frame, width));
+        }
+    }
+    thread::sleep(step.saturating_sub(flushed.elapsed()));
+    pipeline::latest_rgb().context("No frames from the stream")
+}
+
+fn run_interleaved(controller: &SharedController) -> anyhow::Result<()> {
+    let layout = segments::LAYOUT.read().unwrap().clone();
+    let mut controller = controller.lock().unwrap();
+    let counts = (0..layout.len())
+        .map(|segment| layout.count(segment).unwrap_or(controller.len()))
+        .collect::<Vec<_>>();
+    let step = step_time(controller.latency_hint());
+    let stamped = pipeline::frames_stamped();
+
+    // More segments than colors go in rounds, longest first so each round's segments are about
+    // as long as each other and none waits long on the others
+    let mut by_length = (0..counts.len()).collect::<Vec<_>>();
+    by_length.sort_by_key(|&segment| std::cmp::Reverse(counts[segment]));
+    let rounds = by_length
+        .chunks(segments::PALETTE.len())
+        .map(<[usize]>::to_vec)
+        .collect::<Vec<_>>();
+    let round_length = |round: &[usize]| round.iter().map(|&segment| counts[segment]).max();
+    STEPS.store(rounds.iter().filter_map(|round| round_length(round)).sum(), Ordering::Relaxed);
+    if rounds.len() > 1 {
+        info!("Interleaving {} segments in {} rounds", counts.len(), rounds.len());
+    }
+
+    let mut current = 0;
+    for round in &rounds {
+        for index in 0..round_length(round).unwrap_or(0) {
+            if cancelled() {
+                controller.set_all(Color32::BLACK);
+                return controller.flush();
+            }
+
+            CURRENT.store(current, Ordering::Relaxed);
+            current += 1;
+
+            controller.set_all(Color32::BLACK);
+            for (color, &segment) in round.iter().enumerate() {
+                if index < counts[segment] {
+                    let global = layout.global(segment, index);
+                    controller.set_pixel(global, dim(segments::PALETTE[color]));
+                }
+            }
+            controller.flush()?;
+
+            let (frame, width) = frame_after_flush(stamped, step, controller.latency_hint())?;
+            let mut points = vec![Vec::new(); round.len()];
+            for rect in pipeline::detect_frame(&frame, width)? {
+                let color = segments::classify(mean_color(&frame, width, &rect), round.len());
+                points[color].push(rect);
+            }
+
+            for (color, &segment) in round.iter().enumerate() {
+                if index < counts[segment] {
+                    let global = layout.global(segment, index);
+                    store(global, pick(&points[color], global));
+                }
+            }
+        }
+    }
+
+    controller.set_all(Color32::BLACK);
+    controller.flush()
+}
+
+/// Runs `check_colors` if it's enabled and the scan wasn't stopped.
+fn check_colors_after(controller: &SharedController) -> anyhow::Result<()> {
+    if !issues::CHECK_COLORS.load(Ordering::Relaxed) || cancelled() {
+        return Ok(());
+    }
+    check_colors(controller)
+}
+
+/// Size of the square around an LED averaged for its color, in pixels.
+const COLOR_SAMPLE_SIZE: f32 = 5.0;
+
+/// Lights every LED red, green and blue in turn and records the color around each one the scan
+/// found, for `issues` to flag swapped channels and color faults. All at once, as neighbors
+/// bleeding into each other show the same color anyway.
+pub fn check_colors(controller: &SharedController) -> anyhow::Result<()> {
+    let mut controller = controller.lock().unwrap();
+    let step = step_time(controller.latency_hint());
+    let map = MAP.read().unwrap().clone();
+    info!("Checking the colors of {} LEDs", map.iter().flatten().count());
+
+    let mut colors = vec![[[0.0; 3]; 3]; map.len()];
+    for (channel, &color) in CODE_COLORS.iter().enumerate() {
+        if cancelled() {
+            break;
+        }
+
+        controller.set_all(dim(color));
+        controller.flush()?;
+        thread::sleep(step);
+
+        let (frame, width) = pipeline::latest_rgb().context("No frames from the stream")?;
+        for (colors, position) in colors.iter_mut().zip(&map) {
+            if let Some(position) = position {
+                let rect = Rect::from_center_size(*position, Vec2::splat(COLOR_SAMPLE_SIZE));
+                colors[channel] = mean_color(&frame, width, &rect);
+            }
+        }
+    }
+
+    controller.set_all(Color32::BLACK);
+    controller.flush()?;
+
+    if !cancelled() {
+        issues::set_colors(
+            colors
+                .into_iter()
+                .zip(&map)
+                .map(|(colors, position)| position.map(|_| colors))
+                .collect(),
+        );
+    }
+    Ok(())
+}
+
+/// The digits of a color-coded scan, told apart by which channel is strongest.
+const CODE_COLORS: [Color32; 3] = [Color32::RED, Color32::GREEN, Color32::BLUE];
+
+fn run_color_coded(controller: &SharedController) -> anyhow::Result<()> {
+    let mut controller = controller.lock().unwrap();
+    let count = controller.len();
+    let step = step_time(controller.latency_hint());
+
+    let mut digits = 1;
+    while CODE_COLORS.len().pow(digits) < count {
+        digits += 1;
+    }
+    STEPS.store(digits as usize + 1, Ordering::Relaxed);
+
+    // Everything lit first, to find the blobs to read the codes off
+    CURRENT.store(0, Ordering::Relaxed);
+    controller.set_all(led_color());
+    controller.flush()?;
+    thread::sleep(step);
+    let blobs = ignored::filter(&POINTS.read().unwrap());
+    info!("Reading codes off {} blobs for {count} LEDs", blobs.len());
+
+    // `None` for blobs that weren't clearly one of the colors at some step, like other lights
+    let mut codes = vec![Some(0); blobs.len()];
+    let mut place = 1;
+    for digit in 0..digits as usize {
+        if cancelled() {
+            controller.set_all(Color32::BLACK);
+            return controller.flush();
+        }
+
+        CURRENT.store(digit + 1, Ordering::Relaxed);
+
+        for index in 0..count {
+            controller.set_pixel(index, dim(CODE_COLORS[index / place % CODE_COLORS.len()]));
+        }
+        controller.flush()?;
+        thread::sleep(step);
+
+        let (frame, width) = pipeline::latest_rgb().context("No frames from the stream")?;
+        for (code, rect) in codes.iter_mut().zip(&blobs) {
+            let digit = code_digit(mean_color(&frame, width, rect));
+            *code = code.zip(digit).map(|(code, digit)| code + digit * place);
+        }
+        place *= CODE_COLORS.len();
+    }
+
+    let mut found = vec![Vec::new(); count];
+    for (code, rect) in codes.iter().zip(&blobs) {
+        if let Some(points) = code.and_then(|code| found.get_mut(code)) {
+            points.push(*rect);
+        }
+    }
+    for (index, points) in found.iter().enumerate() {
+        store(index, pick(points, index));
+    }
+
+    controller.set_all(Color32::BLACK);
+    controller.flush()
+}
+
+/// Which of `CODE_COLORS` `rgb` is, when one channel is well above the other two.
+fn code_digit(rgb: [f32; 3]) -> Option<usize> {
+    let (strongest, max) = rgb
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))?;
+    let others = rgb.iter().sum::<f32>() - max;
+
+    (max > others).then_some(strongest)
+}
+
+/// Average color of the pixels of `frame` inside `rect`.
+pub fn mean_color(frame: &[u8], width: usize, rect: &Rect) -> [f32; 3] {
+    let height = frame.len() / width / 3;
+    let (left, right) = (rect.min.x.max(0.0) as usize, (rect.max.x as usize).min(width - 1));
+    let (top, bottom) = (rect.min.y.max(0.0) as usize, (rect.max.y as usize).min(height - 1));
+
+    // The frame may have changed size since the detection
+    if left > right || top > bottom {
+        return [0.0; 3];
+    }
+
+    let mut sum = [0.0; 3];
+    let mut count = 0.0;
+    for row in top..=bottom {
+        for pixel in frame[(row * width + left) * 3..(row * width + right + 1) * 3].chunks(3) {
+            for (sum, &c) in sum.iter_mut().zip(pixel) {
+                *sum += c as f32;
+            }
+            count += 1.0;
+        }
+    }
+
+    sum.map(|sum| sum / f32::max(count, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lists() {
+        assert_eq!(parse_list("3").unwrap(), [3]);
+        assert_eq!(parse_list("1, 2,5").unwrap(), [1, 2, 5]);
+        assert_eq!(parse_list("0-3 7").unwrap(), [0, 1, 2, 3, 7]);
+        assert_eq!(parse_list("5-2\n9").unwrap(), [5, 4, 3, 2, 9]);
+    }
+
+    #[test]
+    fn rejects_bad_lists() {
+        for list in ["", " , ", "a", "1-b", "1-2-3", "-1", "4 - 6"] {
+            assert!(parse_list(list).is_err(), "{list:?}");
+        }
+    }
+}
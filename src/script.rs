@@ -0,0 +1,126 @@
+//! Custom scan sequences written in Rhai.
+//!
+//! Scripts get these functions on top of the Rhai standard library:
+//!
+//! - `led_count()`: number of LEDs on the controller
+//! - `clear()`: set every LED to black
+//! - `light(index, r, g, b)`: set one LED, colors are 0..=255
+//! - `light_white(index, w)`: set only the white channel of one LED, for RGBW and white-only
+//!   strips
+//! - `show()`: send the colors set so far to the LEDs
+//! - `wait(ms)`: sleep, returning early when the scan is stopped
+//! - `settle()`: wait as long as the sequential scan does after each step, which is the measured
+//!   latency if there is one
+//! - `detection_count()`: number of blobs currently detected
+//! - `expect(n)`: throw unless there are exactly `n` detections, `try`/`catch` it to carry on
+//! - `capture(index)`: store the biggest detection as the position of `index`, returns whether
+//!   there was one
+
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use eframe::epaint::Color32;
+use rhai::{Dynamic, Engine, EvalAltResult, INT};
+
+use crate::{
+    pipeline::POINTS,
+    scan::{self, SharedController},
+};
+
+pub const EXAMPLE: &str = "\
+// Same as the built-in sequential scan
+for i in 0..led_count() {
+    clear();
+    light(i, 255, 255, 255);
+    show();
+    settle();
+    capture(i);
+}
+";
+
+pub fn run(controller: &SharedController, source: &str) -> anyhow::Result<()> {
+    let (led_count, step_time) = {
+        let controller = controller.lock().unwrap();
+        (controller.len(), scan::step_time(controller.latency_hint()))
+    };
+
+    let mut engine = Engine::new();
+
+    engine.on_progress(|_| scan::cancelled().then_some(Dynamic::UNIT));
+
+    engine.register_fn("led_count", move || led_count as INT);
+
+    engine.register_fn("clear", {
+        let controller = controller.clone();
+        move || controller.lock().unwrap().set_all(Color32::BLACK)
+    });
+
+    engine.register_fn("light", {
+        let controller = controller.clone();
+        move |index: INT, r: INT, g: INT, b: INT| {
+            let [r, g, b] = [r, g, b].map(|c| c.clamp(0, 255) as u8);
+            controller
+                .lock()
+                .unwrap()
+                .set_pixel(index as usize, Color32::from_rgb(r, g, b));
+        }
+    });
+
+    engine.register_fn("light_white", {
+        let controller = controller.clone();
+        move |index: INT, white: INT| {
+            let white = white.clamp(0, 255) as u8;
+            controller.lock().unwrap().set_white(index as usize, white);
+        }
+    });
+
+    engine.register_fn("show", {
+        let controller = controller.clone();
+        move || -> Result<(), Box<EvalAltResult>> {
+            controller
+                .lock()
+                .unwrap()
+                .flush()
+                .map_err(|e| e.to_string().into())
+        }
+    });
+
+    engine.register_fn("wait", |ms: INT| wait(Duration::from_millis(ms.max(0) as u64)));
+    engine.register_fn("settle", move || wait(step_time));
+
+    engine.register_fn("detection_count", || POINTS.read().unwrap().len() as INT);
+
+    engine.register_fn("expect", |n: INT| -> Result<(), Box<EvalAltResult>> {
+        let count = POINTS.read().unwrap().len() as INT;
+        if count == n {
+            Ok(())
+        } else {
+            Err(format!("Expected {n} detections, got {count}").into())
+        }
+    });
+
+    engine.register_fn("capture", |index: INT| scan::capture(index as usize));
+
+    let result = engine.run(source);
+
+    let mut controller = controller.lock().unwrap();
+    controller.set_all(Color32::BLACK);
+    controller.flush()?;
+
+    match result {
+        Err(e) if matches!(*e, EvalAltResult::ErrorTerminated(..)) => Ok(()),
+        result => result.map_err(|e| anyhow!("{e}")),
+    }
+}
+
+fn wait(duration: Duration) {
+    let until = Instant::now() + duration;
+    while Instant::now() < until && !scan::cancelled() {
+        thread::sleep(
+            Duration::from_millis(10).min(until.saturating_duration_since(Instant::now())),
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Projects made of several strips, each on its own controller or output.
+//!
+//! The segments are chained into one index space, so the scan and verify mode see a single long
+//! strip. `LAYOUT` maps those indices back to a segment and an index within it for the exports.
+
+use std::{
+    sync::{atomic::Ordering, RwLock},
+    time::Duration,
+};
+
+use anyhow::Context;
+use eframe::epaint::Color32;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controller::{ControllerConfig, LedController},
+    stats,
+    zones::{self, Zone},
+};
+
+/// Segments of the connected controller, in chaining order.
+pub static LAYOUT: RwLock<Layout> = RwLock::new(Layout { segments: Vec::new() });
+
+/// Colors an interleaved scan lights each segment in, picked to be far apart in hue so a blob's
+/// color tells which segment it belongs to.
+pub const PALETTE: [Color32; 6] = [
+    Color32::from_rgb(255, 0, 0),
+    Color32::from_rgb(0, 0, 255),
+    Color32::from_rgb(0, 255, 0),
+    Color32::from_rgb(255, 0, 255),
+    Color32::from_rgb(255, 255, 0),
+    Color32::from_rgb(0, 255, 255),
+];
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Segment {
+    pub name: String,
+    #[serde(flatten)]
+    pub config: ControllerConfig,
+    /// Where in view its LEDs are, see `zones`.
+    #[serde(default)]
+    pub zone: Option<Zone>,
+}
+
+#[derive(Clone, Default)]
+pub struct Layout {
+    /// Name and LED count of each segment. Empty when nothing was connected, which acts like a
+    /// single segment of any length.
+    segments: Vec<(String, usize)>,
+}
+
+impl Layout {
+    /// Segments of these names and LED counts, in chaining order.
+    pub fn new(segments: Vec<(String, usize)>) -> Self {
+        Self { segments }
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len().max(1)
+    }
+
+    pub fn name(&self, segment: usize) -> &str {
+        self.segments.get(segment).map_or("", |(name, _)| name)
+    }
+
+    /// Number of LEDs in `segment`, or `None` if it's unbounded.
+    pub fn count(&self, segment: usize) -> Option<usize> {
+        self.segments.get(segment).map(|&(_, count)| count)
+    }
+
+    /// Index of the first LED of `segment` in the chained index space.
+    pub fn offset(&self, segment: usize) -> usize {
+        self.segments[..segment.min(self.segments.len())]
+            .iter()
+            .map(|&(_, count)| count)
+            .sum()
+    }
+
+    /// Splits a chained index into a segment and the index within it.
+    pub fn split(&self, mut index: usize) -> (usize, usize) {
+        for (segment, &(_, count)) in self.segments.iter().enumerate() {
+            if index < count {
+                return (segment, index);
+            }
+            index -= count;
+        }
+
+        // Past the end, which only happens without segments or for indices that don't exist
+        (self.segments.len().saturating_sub(1), index)
+    }
+
+    /// The chained index of LED `index` of `segment`.
+    pub fn global(&self, segment: usize, index: usize) -> usize {
+        self.offset(segment) + index
+    }
+}
+
+/// Connects every segment and chains them into one controller, updating `LAYOUT` and
+/// `zones::ZONES` to match.
+pub fn connect(segments: &[Segment]) -> anyhow::Result<Box<dyn LedController>> {
+    anyhow::ensure!(!segments.is_empty(), "There are no segments to connect");
+
+    let controllers = segments
+        .iter()
+        .map(|segment| {
+            segment
+                .config
+                .connect()
+                .with_context(|| format!("Failed to connect segment {:?}", segment.name))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    *LAYOUT.write().unwrap() = Layout::new(
+        segments
+            .iter()
+            .map(|segment| (segment.name.clone(), segment.config.led_count))
+            .collect(),
+    );
+    zones::set(segments);
+
+    Ok(Box::new(Chain { controllers }))
+}
+
+/// Several controllers acting as one, with the LEDs of each following those of the one before.
+struct Chain {
+    controllers: Vec<Box<dyn LedController>>,
+}
+
+impl LedController for Chain {
+    fn len(&self) -> usize {
+        self.controllers
+            .iter()
+            .map(|controller| controller.len())
+            .sum()
+    }
+
+    fn set_pixel(&mut self, mut index: usize, color: Color32) {
+        for controller in &mut self.controllers {
+            if index < controller.len() {
+                controller.set_pixel(index, color);
+                return;
+            }
+            index -= controller.len();
+        }
+    }
+
+    fn set_all(&mut self, color: Color32) {
+        for controller in &mut self.controllers {
+            controller.set_all(color);
+        }
+    }
+
+    fn set_white(&mut self, mut index: usize, white: u8) {
+        for controller in &mut self.controllers {
+            if index < controller.len() {
+                controller.set_white(index, white);
+                return;
+            }
+            index -= controller.len();
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        for controller in &mut self.controllers {
+            if let Err(e) = controller.flush() {
+                stats::CONTROLLER_ERRORS.fetch_add(1, Ordering::Relaxed);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// The slowest segment, since every step has to wait for all of them.
+    fn latency_hint(&self) -> Duration {
+        self.controllers
+            .iter()
+            .map(|controller| controller.latency_hint())
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// Which segment a blob of average color `rgb` was lit by in an interleaved scan, going by the
+/// nearest hue among the first `segments` palette colors.
+pub fn classify(rgb: [f32; 3], segments: usize) -> usize {
+    let hue = hue(rgb);
+
+    (0..segments.min(PALETTE.len()))
+        .min_by(|&a, &b| {
+            let distance = |segment: usize| {
+                let [r, g, b, _] = PALETTE[segment].to_array();
+                let d = (hue - self::hue([r, g, b].map(f32::from))).abs();
+                d.min(360.0 - d)
+            };
+            distance(a).total_cmp(&distance(b))
+        })
+        .unwrap_or(0)
+}
+
+/// Hue of `rgb` in degrees, red at 0.
+pub fn hue([r, g, b]: [f32; 3]) -> f32 {
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * (g - b) / delta
+    } else if max == g {
+        120.0 + 60.0 * (b - r) / delta
+    } else {
+        240.0 + 60.0 * (r - g) / delta
+    };
+
+    hue.rem_euclid(360.0)
+}
@@ -0,0 +1,66 @@
+//! A made up camera looking at the simulated controller's LEDs, so the whole workflow can be
+//! tried and demoed without any hardware. Pick the simulated controller and start with
+//! `--simulate` instead of a stream.
+//!
+//! The LEDs sit at random but fixed spots in a `led_detect::synthetic` scene, and light up in
+//! whatever color they were last flushed with.
+
+use std::{
+    sync::atomic::Ordering,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use eframe::epaint::TextureHandle;
+use led_detect::synthetic::Scene;
+use tracing::info;
+
+use crate::{
+    controller::simulated,
+    pipeline::{self, IMAGE, IMAGE_WIDTH},
+    preview, stats,
+};
+
+pub const WIDTH: usize = 960;
+pub const HEIGHT: usize = 540;
+const FRAME_RATE: f64 = 15.0;
+
+/// Which installation gets simulated. Fixed, so a map from one run matches the next.
+const SEED: u64 = 1;
+
+/// Renders frames into `IMAGE`, and into `texture` when running with a window, until the
+/// pipeline shuts down.
+pub fn spawn_camera(mut texture: Option<TextureHandle>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        info!("Simulating a {WIDTH}x{HEIGHT} camera");
+        let interval = Duration::from_secs_f64(1.0 / FRAME_RATE);
+        let mut scene = Scene::random(WIDTH, HEIGHT, 0, SEED);
+
+        let source = pipeline::source();
+        for frame in 0.. {
+            if pipeline::source_stopped(source) {
+                break;
+            }
+            let started = Instant::now();
+
+            let colors = simulated::shown();
+            // Placed again whenever a strip is connected or dropped
+            if colors.len() != scene.leds.len() {
+                scene = Scene::random(WIDTH, HEIGHT, colors.len(), SEED);
+            }
+
+            let data = scene.render_lit(&colors, frame);
+            stats::frame_decoded(Some(frame as f64 / FRAME_RATE), FRAME_RATE);
+
+            IMAGE.publish(|buffer| {
+                buffer.extend_from_slice(&data);
+                if let Some(texture) = &mut texture {
+                    preview::show(texture, [WIDTH, HEIGHT], buffer);
+                }
+            });
+            IMAGE_WIDTH.store(WIDTH, Ordering::Relaxed);
+
+            thread::sleep(interval.saturating_sub(started.elapsed()));
+        }
+    })
+}
@@ -0,0 +1,114 @@
+//! Saving the current frame as a PNG, optionally with the detections burned in.
+
+use std::{
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+};
+
+use image::{ImageFormat, Rgb, RgbImage};
+
+use crate::{
+    pipeline::{IMAGE, IMAGE_WIDTH, POINTS},
+    Led,
+};
+
+const BOX_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+const LABEL_COLOR: Rgb<u8> = Rgb([255, 255, 0]);
+const LABEL_SCALE: u32 = 2;
+
+/// 3x5 glyphs for 0-9, one bit per pixel, top left first. Indices are all the overlay ever has to
+/// write, so this saves pulling in a font rasterizer.
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+/// Writes the latest frame to `path`. With `overlay`, the detection boxes are drawn in and each
+/// LED gets its index written next to it.
+pub fn save(path: &Path, overlay: Option<&[Led]>) -> anyhow::Result<()> {
+    let mut image = latest()?;
+    if let Some(leds) = overlay {
+        annotate(&mut image, leds);
+    }
+
+    image.save_with_format(path, ImageFormat::Png)?;
+    Ok(())
+}
+
+/// The latest frame.
+pub fn latest() -> anyhow::Result<RgbImage> {
+    let width = IMAGE_WIDTH.load(Ordering::Relaxed);
+    let data = IMAGE.latest().map(Arc::unwrap_or_clone).unwrap_or_default();
+    if width == 0 || data.is_empty() {
+        anyhow::bail!("No frame has been received yet");
+    }
+
+    let height = data.len() / width / 3;
+    RgbImage::from_raw(width as u32, height as u32, data)
+        .ok_or_else(|| anyhow::anyhow!("Frame doesn't match its size"))
+}
+
+/// Draws the detection boxes in and writes each LED's index next to it.
+pub fn annotate(image: &mut RgbImage, leds: &[Led]) {
+    draw_detections(image);
+
+    for led in leds {
+        let [x, y, _] = led.position;
+        draw_number(image, x as i64 + 6, y as i64 - 6, led.index);
+    }
+}
+
+/// Draws a box around every detection in `POINTS`.
+pub fn draw_detections(image: &mut RgbImage) {
+    for rect in POINTS.read().unwrap().iter() {
+        draw_box(image, rect.min.x as i64, rect.min.y as i64, rect.max.x as i64, rect.max.y as i64);
+    }
+}
+
+fn put(image: &mut RgbImage, x: i64, y: i64, color: Rgb<u8>) {
+    if x >= 0 && y >= 0 && x < image.width() as i64 && y < image.height() as i64 {
+        image.put_pixel(x as u32, y as u32, color);
+    }
+}
+
+fn draw_box(image: &mut RgbImage, x0: i64, y0: i64, x1: i64, y1: i64) {
+    for x in x0..=x1 {
+        put(image, x, y0, BOX_COLOR);
+        put(image, x, y1, BOX_COLOR);
+    }
+    for y in y0..=y1 {
+        put(image, x0, y, BOX_COLOR);
+        put(image, x1, y, BOX_COLOR);
+    }
+}
+
+pub fn draw_number(image: &mut RgbImage, x: i64, y: i64, number: usize) {
+    let scale = LABEL_SCALE as i64;
+
+    for (i, digit) in number.to_string().bytes().enumerate() {
+        let glyph = DIGITS[(digit - b'0') as usize];
+        let left = x + i as i64 * 4 * scale;
+
+        for row in 0..5 {
+            for col in 0..3 {
+                if (glyph >> (14 - (row * 3 + col))) & 1 == 0 {
+                    continue;
+                }
+
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        put(image, left + col * scale + dx, y + row * scale + dy, LABEL_COLOR);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Pipeline counters for the stats overlay, to tell a slow camera from a slow network or a slow
+//! detection thread.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use eframe::egui::{self, Align2, Area, Frame, Order};
+use video_rs::ffmpeg::Rational;
+
+use crate::freeze;
+
+pub static DECODED: AtomicU64 = AtomicU64::new(0);
+pub static DETECTED: AtomicU64 = AtomicU64::new(0);
+/// Frames missing from the stream, going by gaps in the timestamps.
+pub static DROPPED: AtomicU64 = AtomicU64::new(0);
+/// Flushes to the controller that failed.
+pub static CONTROLLER_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+struct Timing {
+    start: Option<Instant>,
+    /// When the frame currently in `IMAGE` arrived.
+    frame_time: Option<Instant>,
+    last_pts: Option<f64>,
+    /// Smallest difference between arrival time and timestamp seen so far, as the baseline for
+    /// `lag`.
+    min_offset: f64,
+    /// How far behind its best the stream is running, in seconds.
+    lag: f64,
+    /// When the latest frame would have arrived without the lag, the closest there is to when
+    /// it was captured.
+    captured: Option<Instant>,
+    /// Time from a frame arriving to its detections being published.
+    detection_latency: Duration,
+}
+
+static TIMING: Mutex<Timing> = Mutex::new(Timing {
+    start: None,
+    frame_time: None,
+    last_pts: None,
+    min_offset: f64::INFINITY,
+    lag: 0.0,
+    captured: None,
+    detection_latency: Duration::ZERO,
+});
+
+pub fn seconds(rational: Rational) -> f64 {
+    rational.numerator() as f64 / rational.denominator().max(1) as f64
+}
+
+/// Called by the decoder for every frame, with its timestamp in seconds if it has one.
+pub fn frame_decoded(pts: Option<f64>, frame_rate: f64) {
+    DECODED.fetch_add(1, Ordering::Relaxed);
+
+    let now = Instant::now();
+    let mut timing = TIMING.lock().unwrap();
+    timing.frame_time = Some(now);
+    timing.captured = Some(now);
+
+    let Some(pts) = pts else {
+        return;
+    };
+    freeze::timestamp(pts);
+
+    match timing.last_pts {
+        // The stream restarted or wrapped, the old baseline means nothing now
+        Some(last) if pts < last => timing.min_offset = f64::INFINITY,
+        Some(last) if frame_rate > 0.0 => {
+            let frames = ((pts - last) * frame_rate).round() as u64;
+            DROPPED.fetch_add(frames.saturating_sub(1), Ordering::Relaxed);
+        }
+        _ => {}
+    }
+    timing.last_pts = Some(pts);
+
+    let start = *timing.start.get_or_insert(now);
+    let offset = (now - start).as_secs_f64() - pts;
+    timing.min_offset = timing.min_offset.min(offset);
+    timing.lag = offset - timing.min_offset;
+    timing.captured = Duration::try_from_secs_f64(timing.lag)
+        .ok()
+        .and_then(|lag| now.checked_sub(lag));
+}
+
+/// When the latest frame was captured, going by its timestamp. Off from the truth by however long
+/// the least delayed frame took to arrive, but unlike the arrival time not by network or decoder
+/// hiccups. The arrival time for sources without timestamps.
+pub fn frame_captured() -> Option<Instant> {
+    TIMING.lock().unwrap().captured
+}
+
+/// When the latest frame arrived, for the detection thread to pass back to `detection_done`.
+pub fn frame_time() -> Option<Instant> {
+    TIMING.lock().unwrap().frame_time
+}
+
+pub fn detection_done(frame_time: Option<Instant>) {
+    DETECTED.fetch_add(1, Ordering::Relaxed);
+
+    if let Some(frame_time) = frame_time {
+        TIMING.lock().unwrap().detection_latency = frame_time.elapsed();
+    }
+}
+
+/// How far behind the stream is running, and how long detection takes per frame.
+pub fn timing() -> (f64, Duration) {
+    let timing = TIMING.lock().unwrap();
+    (timing.lag, timing.detection_latency)
+}
+
+/// Turns the counters into rates, sampled about once a second.
+#[derive(Default)]
+pub struct Rates {
+    sample: Option<(Instant, u64, u64)>,
+    pub decode_fps: f64,
+    pub detection_fps: f64,
+}
+
+impl Rates {
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let decoded = DECODED.load(Ordering::Relaxed);
+        let detected = DETECTED.load(Ordering::Relaxed);
+
+        match self.sample {
+            Some((time, last_decoded, last_detected)) => {
+                let elapsed = (now - time).as_secs_f64();
+                if elapsed >= 1.0 {
+                    self.decode_fps = (decoded - last_decoded) as f64 / elapsed;
+                    self.detection_fps = (detected - last_detected) as f64 / elapsed;
+                    self.sample = Some((now, decoded, detected));
+                }
+            }
+            None => self.sample = Some((now, decoded, detected)),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Overlay {
+    rates: Rates,
+}
+
+impl Overlay {
+    pub fn show(&mut self, ctx: &egui::Context) {
+        self.rates.update();
+        let (lag, latency) = timing();
+
+        Area::new("stats")
+            .anchor(Align2::LEFT_TOP, [10.0, 10.0])
+            .order(Order::Foreground)
+            .interactable(false)
+            .show(ctx, |ui| {
+                Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.monospace(format!("Decode     {:5.1} fps", self.rates.decode_fps));
+                    ui.monospace(format!("Detection  {:5.1} fps", self.rates.detection_fps));
+                    ui.monospace(format!("Latency    {:5} ms", latency.as_millis()));
+                    ui.monospace(format!("Stream lag {:5} ms", (lag * 1000.0) as u64));
+                    ui.monospace(format!("Dropped    {:5}", DROPPED.load(Ordering::Relaxed)));
+                    let repeated = freeze::REPEATED.load(Ordering::Relaxed);
+                    ui.monospace(format!("Repeated   {repeated:5}"));
+                });
+            });
+
+        // Keep the rates moving even when nothing else repaints
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,195 @@
+//! A second camera next to the first, so each LED gets a depth from the disparity between the
+//! two views as it's scanned, instead of combining separate sessions.
+//!
+//! The pair has to be rectified, with the second camera to the right of the first and rows lined
+//! up, which is what stereo rigs and OpenCV's `stereoRectify` give. The calibration is JSON:
+//!
+//! ```json
+//! { "focal_length": 1050.0, "principal_point": [640.0, 360.0], "baseline": 0.12 }
+//! ```
+//!
+//! with the focal length and principal point in pixels of the first camera, and the baseline,
+//! the distance between the cameras, in whatever unit the 3D positions should come out in. For a
+//! rough setup the focal length and principal point can be left out, they're then worked out
+//! from the angle of view in `lens`.
+
+use std::{
+    path::Path,
+    sync::RwLock,
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use anyhow::Context;
+use eframe::epaint::{Pos2, Rect};
+use serde::Deserialize;
+use tracing::{error, info};
+use video_rs::{Decoder, Url};
+
+use crate::{
+    lens,
+    pipeline::{self, Transport},
+    segments, Led,
+};
+
+/// How far off the same row a detection in the second camera may be to match, in pixels.
+const ROW_TOLERANCE: f32 = 4.0;
+
+#[derive(Clone, Copy, Deserialize)]
+pub struct Calibration {
+    pub focal_length: Option<f32>,
+    pub principal_point: Option<[f32; 2]>,
+    pub baseline: f32,
+}
+
+impl Calibration {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// The focal length and principal point, the angle of view's for what the calibration leaves
+    /// out. `None` when that's needed but not known.
+    fn intrinsics(&self) -> Option<(f32, [f32; 2])> {
+        if let (Some(focal_length), Some(principal_point)) =
+            (self.focal_length, self.principal_point)
+        {
+            return Some((focal_length, principal_point));
+        }
+        let (focal_length, principal_point) = lens::intrinsics()?;
+        Some((
+            self.focal_length.unwrap_or(focal_length),
+            self.principal_point.unwrap_or(principal_point),
+        ))
+    }
+
+    /// Position relative to the first camera of a point seen at `left` and `right`, with the
+    /// focal length `f` and principal point `[cx, cy]`. `None` when the disparity puts it at or
+    /// behind infinity.
+    fn triangulate(
+        &self,
+        (f, [cx, cy]): (f32, [f32; 2]),
+        left: Pos2,
+        right: Pos2,
+    ) -> Option<[f32; 3]> {
+        let disparity = left.x - right.x;
+        if disparity <= 0.0 {
+            return None;
+        }
+
+        let z = f * self.baseline / disparity;
+        Some([(left.x - cx) * z / f, (left.y - cy) * z / f, z])
+    }
+}
+
+/// Set while a second camera is running.
+pub static CALIBRATION: RwLock<Option<Calibration>> = RwLock::new(None);
+
+/// Detections in the second camera, like `pipeline::POINTS`.
+pub static POINTS: RwLock<Vec<Rect>> = RwLock::new(Vec::new());
+
+/// Where each LED was in the second camera by index, like `scan::MAP`.
+pub static MAP: RwLock<Vec<Option<Pos2>>> = RwLock::new(Vec::new());
+
+/// Starts decoding and detecting on the second camera.
+pub fn spawn(url: Url, transport: Transport, calibration: Calibration) -> JoinHandle<()> {
+    *CALIBRATION.write().unwrap() = Some(calibration);
+
+    thread::spawn(move || {
+        if let Err(e) = run(&url, transport) {
+            error!("Second camera stopped: {e:#}");
+        }
+        *CALIBRATION.write().unwrap() = None;
+    })
+}
+
+fn run(url: &Url, transport: Transport) -> anyhow::Result<()> {
+    let mut decoder =
+        Decoder::new_with_options(&pipeline::locator(url), &pipeline::options(transport))
+            .map_err(|e| anyhow::anyhow!("Failed to open the second camera: {e}"))?;
+    info!("Opened the second camera");
+
+    let mut rgb = Vec::new();
+    let mut last_detection: Option<Instant> = None;
+    for frame in decoder.decode_raw_iter() {
+        if pipeline::shutting_down() {
+            return Ok(());
+        }
+        let frame = frame.map_err(|e| anyhow::anyhow!("{e}"))?;
+
+        // Every frame still has to be decoded to keep up with the stream, but detecting on
+        // each would be wasted work, as for the first camera
+        let interval = *pipeline::INTERVAL.read().unwrap();
+        if last_detection.is_some_and(|last| last.elapsed() < interval) {
+            continue;
+        }
+        last_detection = Some(Instant::now());
+
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+        rgb.clear();
+        pipeline::packed_rgb(frame.data(0), frame.stride(0), width, height, &mut rgb);
+
+        let settings = pipeline::SETTINGS.read().unwrap().clone();
+        *POINTS.write().unwrap() = led_detect::detect_rgb(&rgb, width, &settings)?;
+    }
+
+    anyhow::bail!("End of stream")
+}
+
+/// Clears the map for a scan of `count` LEDs.
+pub fn reset(count: usize) {
+    *MAP.write().unwrap() = vec![None; count];
+}
+
+/// Records where LED `index`, found at `position` in the first camera, is in the second: the
+/// detection closest to the same row, to the left of it.
+pub fn capture(index: usize, position: Option<Pos2>) {
+    if CALIBRATION.read().unwrap().is_none() {
+        return;
+    }
+
+    let matched = position.and_then(|left| {
+        POINTS
+            .read()
+            .unwrap()
+            .iter()
+            .map(Rect::center)
+            .filter(|right| right.x < left.x && (right.y - left.y).abs() <= ROW_TOLERANCE)
+            .min_by(|a, b| (a.y - left.y).abs().total_cmp(&(b.y - left.y).abs()))
+    });
+
+    if let Some(slot) = MAP.write().unwrap().get_mut(index) {
+        *slot = matched;
+    }
+}
+
+/// `leds` placed in 3D relative to the first camera, for those the second camera saw too. The
+/// rest keep their position in the first camera's image, so they stand out when viewed in 3D.
+/// All of them do while the calibration is missing intrinsics and there's no angle of view.
+pub fn to_3d(leds: &[Led]) -> Vec<Led> {
+    let Some(calibration) = *CALIBRATION.read().unwrap() else {
+        return leds.to_vec();
+    };
+    let Some(intrinsics) = calibration.intrinsics() else {
+        return leds.to_vec();
+    };
+
+    let layout = segments::LAYOUT.read().unwrap();
+    let map = MAP.read().unwrap();
+
+    leds.iter()
+        .map(|led| {
+            let left = Pos2::new(led.position[0], led.position[1]);
+            let right = map
+                .get(layout.global(led.segment, led.index))
+                .copied()
+                .flatten();
+
+            match right.and_then(|right| calibration.triangulate(intrinsics, left, right)) {
+                Some(position) => Led { position, ..*led },
+                None => *led,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,44 @@
+//! A "long exposure" of the scan: every captured frame blended into one picture by keeping the
+//! brightest value of each pixel, so the whole installation shows up lit at once.
+
+use std::{path::Path, sync::Mutex};
+
+use image::{ImageFormat, RgbImage};
+
+use crate::snapshot;
+
+static COMPOSITE: Mutex<Option<RgbImage>> = Mutex::new(None);
+
+/// Starts over with an empty picture, for a new scan.
+pub fn reset() {
+    *COMPOSITE.lock().unwrap() = None;
+}
+
+/// Blends in the latest frame. Does nothing before the first frame arrived.
+pub fn add() {
+    let Ok(frame) = snapshot::latest() else {
+        return;
+    };
+
+    let mut composite = COMPOSITE.lock().unwrap();
+    match composite.as_mut() {
+        // The resolution only changes when the stream does, and then there's no use mixing them
+        Some(image) if image.dimensions() == frame.dimensions() => {
+            for (pixel, new) in image.iter_mut().zip(frame.iter()) {
+                *pixel = (*pixel).max(*new);
+            }
+        }
+        _ => *composite = Some(frame),
+    }
+}
+
+/// The picture so far, `None` until a scan captured something.
+pub fn composite() -> Option<RgbImage> {
+    COMPOSITE.lock().unwrap().clone()
+}
+
+pub fn save(path: &Path) -> anyhow::Result<()> {
+    let image = composite().ok_or_else(|| anyhow::anyhow!("Nothing was scanned yet"))?;
+    image.save_with_format(path, ImageFormat::Png)?;
+    Ok(())
+}
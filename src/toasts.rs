@@ -0,0 +1,31 @@
+//! Error notifications for failures on the background threads, which would otherwise only show
+//! up in the log.
+
+use std::sync::Mutex;
+
+/// What the toast's retry button does, if it has one.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// Reopen the video stream.
+    Stream,
+    /// Run the last scan again.
+    Scan,
+}
+
+pub struct Toast {
+    pub message: String,
+    pub retry: Option<Retry>,
+}
+
+/// Toasts waiting to be dismissed, oldest first.
+pub static TOASTS: Mutex<Vec<Toast>> = Mutex::new(Vec::new());
+
+pub fn error(message: impl Into<String>, retry: Option<Retry>) {
+    let message = message.into();
+    let mut toasts = TOASTS.lock().unwrap();
+
+    // A failure that keeps happening shouldn't pile up
+    if !toasts.iter().any(|toast| toast.message == message) {
+        toasts.push(Toast { message, retry });
+    }
+}
@@ -0,0 +1,157 @@
+//! Flipping, rotating and scaling the map on its way out, so it lands in the exported file the way
+//! the consuming software wants it instead of in camera pixels.
+
+use clap::Args;
+use tracing::warn;
+
+use crate::{lens, Led};
+
+#[derive(Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Rotation {
+    #[default]
+    #[value(name = "0")]
+    None,
+    #[value(name = "90")]
+    Quarter,
+    #[value(name = "180")]
+    Half,
+    #[value(name = "270")]
+    ThreeQuarters,
+}
+
+impl Rotation {
+    pub const ALL: [Self; 4] = [Self::None, Self::Quarter, Self::Half, Self::ThreeQuarters];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "0°",
+            Self::Quarter => "90°",
+            Self::Half => "180°",
+            Self::ThreeQuarters => "270°",
+        }
+    }
+}
+
+/// Applied in order: placing at a distance, flips and rotation about the middle of the map, so it
+/// stays roughly where it was, then fitting, then translation. Rotation is clockwise as seen in
+/// the video.
+#[derive(Clone, Default, Args)]
+pub struct Transform {
+    /// Put the LEDs without a depth on a plane facing the camera this many meters away, going by
+    /// --fov or --focal-length, so a flat installation comes out in meters
+    #[arg(long, value_name = "METERS")]
+    pub distance: Option<f32>,
+    /// Mirror the map left to right before exporting
+    #[arg(long)]
+    pub flip_x: bool,
+    /// Mirror the map top to bottom before exporting
+    #[arg(long)]
+    pub flip_y: bool,
+    /// Rotate the map clockwise by this many degrees before exporting
+    #[arg(long, value_enum, default_value_t)]
+    pub rotate: Rotation,
+    /// Scale the map to fit a WIDTH,HEIGHT box with its top left corner at the origin, keeping
+    /// the aspect ratio
+    #[arg(long, value_delimiter = ',', num_args = 2, value_names = ["WIDTH", "HEIGHT"])]
+    pub fit: Option<Vec<f32>>,
+    /// Offset to add after everything else, as X,Y
+    #[arg(long, value_delimiter = ',', num_args = 2, value_names = ["X", "Y"])]
+    pub translate: Option<Vec<f32>>,
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        self.distance.is_none()
+            && !self.flip_x
+            && !self.flip_y
+            && self.rotate == Rotation::None
+            && self.fit.is_none()
+            && self.translate.is_none()
+    }
+
+    pub fn apply(&self, leds: &[Led]) -> Vec<Led> {
+        if self.is_identity() || leds.is_empty() {
+            return leds.to_vec();
+        }
+
+        let mut leds = leds.to_vec();
+        if let Some(distance) = self.distance {
+            place(&mut leds, distance);
+        }
+
+        let (min, max) = bounds(leds.iter().map(|led| [led.position[0], led.position[1]]));
+        let center = [(min[0] + max[0]) / 2.0, (min[1] + max[1]) / 2.0];
+
+        let mut leds = leds
+            .iter()
+            .map(|led| {
+                let [x, y, z] = led.position;
+                let (mut x, mut y) = (x - center[0], y - center[1]);
+                if self.flip_x {
+                    x = -x;
+                }
+                if self.flip_y {
+                    y = -y;
+                }
+
+                // y points down, so (x, y) -> (-y, x) turns clockwise on screen
+                let (x, y) = match self.rotate {
+                    Rotation::None => (x, y),
+                    Rotation::Quarter => (-y, x),
+                    Rotation::Half => (-x, -y),
+                    Rotation::ThreeQuarters => (y, -x),
+                };
+
+                Led {
+                    position: [x + center[0], y + center[1], z],
+                    ..*led
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(&[width, height]) = self.fit.as_deref() {
+            let (min, max) = bounds(leds.iter().map(|led| [led.position[0], led.position[1]]));
+            let size = [max[0] - min[0], max[1] - min[1]];
+
+            // A map that's a single point or line only has one dimension to fit
+            let scale = [width / size[0], height / size[1]]
+                .into_iter()
+                .filter(|scale| scale.is_finite())
+                .min_by(f32::total_cmp)
+                .unwrap_or(1.0);
+
+            for led in &mut leds {
+                let [x, y, z] = led.position;
+                led.position = [(x - min[0]) * scale, (y - min[1]) * scale, z * scale];
+            }
+        }
+
+        if let Some(&[dx, dy]) = self.translate.as_deref() {
+            for led in &mut leds {
+                led.position[0] += dx;
+                led.position[1] += dy;
+            }
+        }
+
+        leds
+    }
+}
+
+/// Moves the LEDs still in camera pixels, those without a depth, to `distance` in front of the
+/// camera, where the lens puts them.
+fn place(leds: &mut [Led], distance: f32) {
+    let Some((f, [cx, cy])) = lens::intrinsics() else {
+        warn!("Can't place the map at a distance without the camera's angle of view and a frame");
+        return;
+    };
+    for led in leds.iter_mut().filter(|led| led.position[2] == 0.0) {
+        let [x, y, _] = led.position;
+        led.position = [(x - cx) * distance / f, (y - cy) * distance / f, distance];
+    }
+}
+
+fn bounds(points: impl Iterator<Item = [f32; 2]>) -> ([f32; 2], [f32; 2]) {
+    points.fold(([f32::INFINITY; 2], [f32::NEG_INFINITY; 2]), |(min, max), [x, y]| {
+        ([min[0].min(x), min[1].min(y)], [max[0].max(x), max[1].max(y)])
+    })
+}
@@ -0,0 +1,215 @@
+//! Local capture devices through Video4Linux, like USB webcams and HDMI capture sticks. They're
+//! found through sysfs, and read through `ffmpeg` writing raw RGB to its stdout, like `rpicam`.
+
+use std::{
+    fs,
+    io::{ErrorKind, Read},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{atomic::Ordering, RwLock},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Context;
+use eframe::epaint::TextureHandle;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    pipeline::{self, IMAGE, IMAGE_WIDTH},
+    preview, stats,
+};
+
+const PROGRAM: &str = "ffmpeg";
+const SYSFS: &str = "/sys/class/video4linux";
+
+/// How often to look for devices being plugged in or out.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// For devices whose sizes can't be listed.
+const DEFAULT_SIZE: [usize; 2] = [640, 480];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Device {
+    pub path: PathBuf,
+    pub name: String,
+    /// Sizes it can capture at, largest first. Empty until listed.
+    pub sizes: Vec<[usize; 2]>,
+}
+
+/// The devices plugged in, kept up to date by `spawn_watcher`.
+pub static DEVICES: RwLock<Vec<Device>> = RwLock::new(Vec::new());
+
+/// The device `decode` is reading, if any.
+pub static OPENED: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// The capture devices in sysfs, without their sizes.
+pub fn scan() -> Vec<Device> {
+    let Ok(entries) = fs::read_dir(SYSFS) else {
+        return Vec::new();
+    };
+
+    let mut devices = entries
+        .flatten()
+        .filter(|entry| {
+            // A camera also has a node for its metadata, which is always the one after
+            fs::read_to_string(entry.path().join("index")).is_ok_and(|index| index.trim() == "0")
+        })
+        .map(|entry| Device {
+            path: Path::new("/dev").join(entry.file_name()),
+            name: fs::read_to_string(entry.path().join("name"))
+                .map(|name| name.trim().to_owned())
+                .unwrap_or_else(|_| entry.file_name().to_string_lossy().into_owned()),
+            sizes: Vec::new(),
+        })
+        .collect::<Vec<_>>();
+    devices.sort_by(|a, b| a.path.cmp(&b.path));
+    devices
+}
+
+/// What `path` can capture at, from ffmpeg's list of its formats.
+pub fn sizes(path: &Path) -> Vec<[usize; 2]> {
+    let Ok(output) = Command::new(PROGRAM)
+        .args(["-hide_banner", "-f", "v4l2", "-list_formats", "all", "-i"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+    else {
+        return Vec::new();
+    };
+
+    // Lines like `[video4linux2,v4l2 @ 0x...] Raw : yuyv422 : YUYV 4:2:2 : 640x480 1280x720`
+    let mut sizes = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter_map(|line| line.rsplit_once(" : "))
+        .flat_map(|(_, sizes)| sizes.split_whitespace())
+        .filter_map(|size| {
+            let (width, height) = size.split_once('x')?;
+            Some([width.parse().ok()?, height.parse().ok()?])
+        })
+        .collect::<Vec<[usize; 2]>>();
+    sizes.sort_by_key(|&[width, height]| std::cmp::Reverse(width * height));
+    sizes.dedup();
+    sizes
+}
+
+/// Keeps `DEVICES` up to date as devices are plugged in and out, until the pipeline shuts down.
+pub fn spawn_watcher() -> JoinHandle<()> {
+    thread::spawn(|| {
+        while !pipeline::shutting_down() {
+            let mut found = scan();
+            let known = DEVICES.read().unwrap().clone();
+
+            for device in &mut found {
+                match known.iter().find(|known| known.path == device.path) {
+                    Some(known) if known.name == device.name => device.sizes = known.sizes.clone(),
+                    _ => {
+                        device.sizes = sizes(&device.path);
+                        info!("Found {} at {}", device.name, device.path.display());
+                    }
+                }
+            }
+            for device in &known {
+                if !found.iter().any(|found| found.path == device.path) {
+                    info!("{} at {} was unplugged", device.name, device.path.display());
+                }
+            }
+
+            if found != known {
+                *DEVICES.write().unwrap() = found;
+            }
+
+            // In steps, so shutting down doesn't wait on it
+            for _ in 0..POLL_INTERVAL.as_millis() / 100 {
+                if pipeline::shutting_down() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+    })
+}
+
+/// Runs `ffmpeg` on the device at `path` and reads its frames into `IMAGE`, and into `texture`
+/// when running with a window, until it exits or the source is switched. Without a `size` it
+/// captures at the largest the device can.
+pub fn decode(
+    path: &Path,
+    size: Option<[usize; 2]>,
+    mut texture: Option<TextureHandle>,
+) -> anyhow::Result<()> {
+    let [width, height] = size
+        .or_else(|| sizes(path).first().copied())
+        .unwrap_or(DEFAULT_SIZE);
+
+    let mut child = Command::new(PROGRAM)
+        .args(["-hide_banner", "-loglevel", "error", "-f", "v4l2"])
+        .args(["-video_size", &format!("{width}x{height}"), "-i"])
+        .arg(path)
+        .args(["-f", "rawvideo", "-pix_fmt", "rgb24", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run {PROGRAM}, is it installed?"))?;
+    let mut stdout = child.stdout.take().context("No stdout from ffmpeg")?;
+
+    info!("Opened {} at {width}x{height}", path.display());
+    *OPENED.write().unwrap() = Some(path.to_owned());
+
+    let mut buffer = vec![0; width * height * 3];
+    let source = pipeline::source();
+    let result = loop {
+        if pipeline::source_stopped(source) {
+            info!("Closing {}", path.display());
+            break Ok(());
+        }
+
+        match stdout.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                break Err(anyhow::anyhow!("{} stopped sending frames", path.display()))
+            }
+            Err(e) => break Err(e.into()),
+        }
+        stats::frame_decoded(None, 0.0);
+
+        if IMAGE_WIDTH.load(Ordering::Relaxed) != width {
+            info!("Receiving {width}x{height} frames");
+        }
+
+        IMAGE.publish(|data| {
+            data.extend_from_slice(&buffer);
+            if let Some(texture) = &mut texture {
+                preview::show(texture, [width, height], data);
+            }
+        });
+        IMAGE_WIDTH.store(width, Ordering::Relaxed);
+    };
+
+    // Free the device for whatever is opened next
+    let _ = child.kill();
+    let _ = child.wait();
+    *OPENED.write().unwrap() = None;
+    result
+}
+
+/// The device last used in a directory, remembered across runs.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Remembered {
+    pub name: String,
+    pub path: PathBuf,
+    pub size: Option<[usize; 2]>,
+}
+
+impl Remembered {
+    /// The remembered device if it's plugged in, by name first as the paths change whenever
+    /// devices are plugged in a different order.
+    pub fn find(&self) -> Option<Device> {
+        let devices = scan();
+        let by_name = devices.iter().position(|device| device.name == self.name);
+        let by_path = devices.iter().position(|device| device.path == self.path);
+        by_name.or(by_path).map(|index| devices[index].clone())
+    }
+}
@@ -0,0 +1,132 @@
+use std::f32::consts::FRAC_PI_2;
+
+use eframe::{
+    egui::{Sense, Ui},
+    epaint::{Color32, Hsva, Stroke, Vec2},
+};
+
+use crate::Led;
+
+/// Orbit camera around a point cloud, drawn with the egui painter.
+///
+/// Positions are in camera space like the rest of the app: x right, y down, z away from the
+/// camera. The cloud is centered and scaled to fit, so any unit works.
+pub struct Viewport3d {
+    yaw: f32,
+    pitch: f32,
+    zoom: f32,
+}
+
+impl Default for Viewport3d {
+    fn default() -> Self {
+        Self { yaw: 0.0, pitch: 0.0, zoom: 1.0 }
+    }
+}
+
+impl Viewport3d {
+    pub fn show(&mut self, ui: &mut Ui, leds: &[Led]) {
+        let (rect, response) = ui.allocate_exact_size(ui.available_size(), Sense::drag());
+
+        if response.dragged() {
+            let delta = response.drag_delta();
+            self.yaw += delta.x * 0.01;
+            self.pitch = (self.pitch + delta.y * 0.01).clamp(-FRAC_PI_2, FRAC_PI_2);
+        }
+
+        if response.hovered() {
+            let scroll = ui.input(|i| i.scroll_delta.y);
+            self.zoom = (self.zoom * (scroll * 0.002).exp()).clamp(0.1, 20.0);
+        }
+
+        if response.double_clicked() {
+            *self = Self::default();
+        }
+
+        let painter = ui.painter_at(rect);
+        painter.rect_filled(rect, 0.0, Color32::from_gray(16));
+
+        // Flip into a y up, z towards the viewer frame so the default view matches the video
+        let points = leds
+            .iter()
+            .map(|led| {
+                let [x, y, z] = led.position;
+                [x, -y, -z]
+            })
+            .collect::<Vec<_>>();
+        let count = leds.iter().map(|led| led.index + 1).max().unwrap_or(0);
+
+        let (center, extent) = bounds(&points);
+        let scale = rect.size().min_elem() * 0.45 * self.zoom / extent;
+
+        let project = |p: [f32; 3]| {
+            let [x, y, z] = self.rotate([p[0] - center[0], p[1] - center[1], p[2] - center[2]]);
+            (rect.center() + Vec2::new(x, -y) * scale, z)
+        };
+
+        // Axis gizmo in the bottom left corner
+        let origin = rect.left_bottom() + Vec2::new(30.0, -30.0);
+        for (axis, color) in [
+            ([1.0, 0.0, 0.0], Color32::RED),
+            ([0.0, 1.0, 0.0], Color32::GREEN),
+            ([0.0, 0.0, 1.0], Color32::LIGHT_BLUE),
+        ] {
+            let [x, y, _] = self.rotate(axis);
+            painter
+                .line_segment([origin, origin + Vec2::new(x, -y) * 20.0], Stroke::new(2.0, color));
+        }
+
+        // Painter's algorithm: draw back to front so near points cover far ones
+        let mut projected = leds
+            .iter()
+            .zip(points.iter())
+            .map(|(led, point)| {
+                let (pos, depth) = project(*point);
+                (led.index, pos, depth)
+            })
+            .collect::<Vec<_>>();
+        projected.sort_by(|a, b| a.2.total_cmp(&b.2));
+
+        for (index, pos, _) in projected {
+            painter.circle_filled(pos, 3.0, index_color(index, count));
+        }
+    }
+
+    fn rotate(&self, [x, y, z]: [f32; 3]) -> [f32; 3] {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+
+        let (x, z) = (x * cos_yaw + z * sin_yaw, z * cos_yaw - x * sin_yaw);
+        let (y, z) = (y * cos_pitch - z * sin_pitch, z * cos_pitch + y * sin_pitch);
+
+        [x, y, z]
+    }
+}
+
+/// Spreads indices over the hue wheel, from blue for the first LED to red for the last.
+pub fn index_color(index: usize, count: usize) -> Color32 {
+    let t = index as f32 / count.saturating_sub(1).max(1) as f32;
+    Hsva::new(0.66 * (1.0 - t), 1.0, 1.0, 1.0).into()
+}
+
+fn bounds(points: &[[f32; 3]]) -> ([f32; 3], f32) {
+    if points.is_empty() {
+        return ([0.0; 3], 1.0);
+    }
+
+    let mut min = [f32::INFINITY; 3];
+    let mut max = [f32::NEG_INFINITY; 3];
+    for point in points {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(point[axis]);
+            max[axis] = max[axis].max(point[axis]);
+        }
+    }
+
+    let center = [0, 1, 2].map(|axis| (min[axis] + max[axis]) / 2.0);
+    let extent = [0, 1, 2]
+        .map(|axis| (max[axis] - min[axis]) / 2.0)
+        .into_iter()
+        .fold(f32::EPSILON, f32::max);
+
+    (center, extent)
+}
@@ -0,0 +1,331 @@
+//! A small web server with a live preview and the main controls, for running the calibration from
+//! another device than the one the camera and controller are plugged into.
+//!
+//! The preview is MJPEG, which every browser shows in a plain `<img>`, and everything else is a
+//! handful of JSON endpoints the page polls. Scripts can use the same endpoints to run scans as
+//! part of a bigger setup:
+//!
+//! - `GET /api/state`: detection settings and where the scan is at
+//! - `GET /metrics`: the same for Prometheus, see `metrics`
+//! - `GET /api/map`: the LEDs found so far, as in the JSON export
+//! - `POST /api/scan/start`: start a scan, optionally with `{"mode": "sequential" | "strobe" |
+//!   "interleaved" | "colorcoded" | "regions" | "coarsetofine" | "grouped" | "script", "script":
+//!   "..."}`, the last mode used by default
+//! - `POST /api/scan/stop`, `/api/scan/abort`, `/api/scan/resume`, `/api/scan/rescan`: see
+//!   `scan::Command`
+//!
+//! Commands that can't be carried out right now, like starting a scan while one is running, get a
+//! 409 with the reason.
+
+use std::{
+    io::{Cursor, Write},
+    sync::{atomic::Ordering, Arc},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use image::{codecs::jpeg::JpegEncoder, ColorType, RgbImage};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info, warn};
+
+use crate::{
+    metrics,
+    pipeline::{self, DetectionMode, PAUSED, POINTS, SETTINGS},
+    scan::{self, ScanMode},
+    snapshot,
+};
+
+const INDEX: &str = include_str!("web.html");
+
+/// Time between preview frames. The page is for framing the camera and keeping an eye on the
+/// scan, so a few frames a second is plenty and keeps it usable over wifi.
+const FRAME_INTERVAL: Duration = Duration::from_millis(200);
+
+const JPEG_QUALITY: u8 = 70;
+
+/// How often the server checks whether the app is shutting down while no requests come in.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Body of `/api/scan/start`.
+#[derive(Deserialize)]
+struct StartRequest {
+    mode: Option<String>,
+    /// Source for `"mode": "script"`.
+    script: Option<String>,
+}
+
+/// Detection settings the page can change. Anything left out stays as it is.
+#[derive(Deserialize)]
+struct SettingsPatch {
+    mode: Option<String>,
+    lower_h: Option<f64>,
+    lower_s: Option<f64>,
+    lower_v: Option<f64>,
+    upper_h: Option<f64>,
+    upper_s: Option<f64>,
+    upper_v: Option<f64>,
+    brightness: Option<f64>,
+    lab_tolerance: Option<f64>,
+    merge_radius: Option<f32>,
+    blob_area: Option<f32>,
+    paused: Option<bool>,
+}
+
+/// Starts serving on `address`, like `0.0.0.0:8080`. The server stops along with the pipeline.
+pub fn spawn(address: &str) -> anyhow::Result<JoinHandle<()>> {
+    let server =
+        Server::http(address).map_err(|e| anyhow::anyhow!("Can't listen on {address}: {e}"))?;
+    info!("Serving the web UI on http://{address}");
+
+    Ok(thread::spawn(move || {
+        while !pipeline::shutting_down() {
+            match server.recv_timeout(POLL_INTERVAL) {
+                Ok(Some(request)) => handle(request),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Web UI stopped: {e}");
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+fn handle(mut request: Request) {
+    let url = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_owned();
+
+    let response = match (request.method(), url.as_str()) {
+        (Method::Get, "/") => Response::from_string(INDEX).with_header(content_type("text/html")),
+        (Method::Get, "/stream.mjpg") => {
+            // Runs until the browser goes away, so it can't hold up the other requests
+            thread::spawn(move || stream(request));
+            return;
+        }
+        (Method::Get, "/api/state") => json_response(state()),
+        (Method::Get, "/metrics") => Response::from_string(metrics::render())
+            .with_header(content_type("text/plain; version=0.0.4")),
+        (Method::Get, "/api/map") => match serde_json::to_value(scan::leds()) {
+            Ok(leds) => json_response(leds),
+            Err(e) => Response::from_string(e.to_string()).with_status_code(500),
+        },
+        (Method::Post, "/api/settings") => match body::<SettingsPatch>(&mut request) {
+            Ok(patch) => {
+                apply(patch);
+                json_response(state())
+            }
+            Err(e) => Response::from_string(format!("{e:#}")).with_status_code(400),
+        },
+        (Method::Post, "/api/scan/start") => {
+            match body::<Option<StartRequest>>(&mut request).and_then(start_command) {
+                Ok(command) => send(command),
+                Err(e) => Response::from_string(format!("{e:#}")).with_status_code(400),
+            }
+        }
+        (Method::Post, "/api/scan/resume") => send(scan::Command::Resume),
+        (Method::Post, "/api/scan/rescan") => send(scan::Command::Rescan),
+        (Method::Post, "/api/scan/stop") => send(scan::Command::Stop),
+        (Method::Post, "/api/scan/abort") => send(scan::Command::Abort),
+        (Method::Post, "/api/dark-frame") => match scan::dark_frame() {
+            Ok(_) => json_response(state()),
+            Err(e) => Response::from_string(format!("{e:#}")).with_status_code(409),
+        },
+        (Method::Post, "/api/dark-frame/clear") => {
+            *pipeline::DARK_FRAME.write().unwrap() = None;
+            json_response(state())
+        }
+        _ => Response::from_string("Not found").with_status_code(404),
+    };
+
+    if let Err(e) = request.respond(response) {
+        warn!("Failed to answer a web UI request: {e}");
+    }
+}
+
+/// Parses the request body as JSON, with an empty body as `null`.
+fn body<T: DeserializeOwned>(request: &mut Request) -> anyhow::Result<T> {
+    let mut body = String::new();
+    request.as_reader().read_to_string(&mut body)?;
+    if body.trim().is_empty() {
+        body = "null".to_owned();
+    }
+    Ok(serde_json::from_str(&body)?)
+}
+
+fn start_command(request: Option<StartRequest>) -> anyhow::Result<scan::Command> {
+    let StartRequest { mode, script } =
+        request.unwrap_or(StartRequest { mode: None, script: None });
+
+    let mode = match mode.as_deref() {
+        None => scan::LAST_MODE
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap_or(ScanMode::Sequential),
+        Some("sequential") => ScanMode::Sequential,
+        Some("strobe") => ScanMode::StrobeDiff,
+        Some("interleaved") => ScanMode::Interleaved,
+        Some("colorcoded") => ScanMode::ColorCoded,
+        Some("regions") => ScanMode::Regions,
+        Some("coarsetofine") => ScanMode::CoarseToFine,
+        Some("grouped") => ScanMode::Grouped,
+        Some("script") => ScanMode::Script(script.context("A script scan needs a script")?),
+        Some(mode) => anyhow::bail!("Unknown scan mode {mode:?}"),
+    };
+    Ok(scan::Command::Start(mode))
+}
+
+fn send(command: scan::Command) -> Response<Cursor<Vec<u8>>> {
+    match scan::command(command) {
+        Ok(()) => json_response(state()),
+        Err(e) => Response::from_string(format!("{e:#}")).with_status_code(409),
+    }
+}
+
+fn state() -> serde_json::Value {
+    let settings = SETTINGS.read().unwrap().clone();
+
+    json!({
+        "detections": POINTS.read().unwrap().len(),
+        "paused": PAUSED.load(Ordering::Relaxed),
+        "dark_frame": pipeline::DARK_FRAME.read().unwrap().is_some(),
+        "can_scan": scan::CONTROLLER.read().unwrap().is_some(),
+        "scan": scan_state(),
+        "settings": {
+            "mode": mode_name(settings.mode),
+            "lower_h": settings.lower_h,
+            "lower_s": settings.lower_s,
+            "lower_v": settings.lower_v,
+            "upper_h": settings.upper_h,
+            "upper_s": settings.upper_s,
+            "upper_v": settings.upper_v,
+            "brightness": settings.brightness,
+            "lab_tolerance": settings.lab_tolerance,
+            "merge_radius": settings.merge_radius,
+            "blob_area": settings.blob_area,
+        },
+    })
+}
+
+/// `scan::STATE`, with the progress of the scan.
+fn scan_state() -> serde_json::Value {
+    let mut state = json!(*scan::STATE.read().unwrap());
+    state["running"] = json!(scan::RUNNING.load(Ordering::Relaxed));
+    state["current"] = json!(scan::CURRENT.load(Ordering::Relaxed));
+    state["steps"] = json!(scan::STEPS.load(Ordering::Relaxed));
+    state["found"] = json!(scan::MAP.read().unwrap().iter().flatten().count());
+    state["eta"] = json!(scan::eta().map(|eta| eta.as_secs()));
+    state
+}
+
+fn apply(patch: SettingsPatch) {
+    let mut guard = SETTINGS.write().unwrap();
+    let settings = &mut *guard;
+
+    if let Some(mode) = patch.mode {
+        match DetectionMode::ALL
+            .into_iter()
+            .find(|m| mode_name(*m) == mode)
+        {
+            Some(mode) => settings.mode = mode,
+            None => warn!("Web UI asked for unknown detection mode {mode:?}"),
+        }
+    }
+
+    let fields = [
+        (patch.lower_h, &mut settings.lower_h),
+        (patch.lower_s, &mut settings.lower_s),
+        (patch.lower_v, &mut settings.lower_v),
+        (patch.upper_h, &mut settings.upper_h),
+        (patch.upper_s, &mut settings.upper_s),
+        (patch.upper_v, &mut settings.upper_v),
+        (patch.brightness, &mut settings.brightness),
+        (patch.lab_tolerance, &mut settings.lab_tolerance),
+    ];
+    for (value, field) in fields {
+        if let Some(value) = value {
+            *field = value;
+        }
+    }
+
+    if let Some(radius) = patch.merge_radius {
+        settings.merge_radius = radius.max(0.0);
+    }
+    if let Some(area) = patch.blob_area {
+        settings.blob_area = area.max(0.0);
+    }
+    if let Some(paused) = patch.paused {
+        PAUSED.store(paused, Ordering::Relaxed);
+    }
+}
+
+fn mode_name(mode: DetectionMode) -> &'static str {
+    match mode {
+        DetectionMode::Hsv => "hsv",
+        DetectionMode::Brightness => "brightness",
+        DetectionMode::Lab => "lab",
+    }
+}
+
+/// Writes the preview as `multipart/x-mixed-replace` until the connection drops.
+fn stream(request: Request) {
+    let mut writer = request.into_writer();
+    let header = "HTTP/1.1 200 OK\r\n\
+                  Content-Type: multipart/x-mixed-replace; boundary=frame\r\n\
+                  Cache-Control: no-cache\r\n\
+                  Connection: close\r\n\r\n";
+    if writer.write_all(header.as_bytes()).is_err() {
+        return;
+    }
+
+    while !pipeline::shutting_down() {
+        let started = Instant::now();
+
+        if let Some(jpeg) = preview_frame() {
+            let part = format!(
+                "--frame\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                jpeg.len()
+            );
+            let sent = writer
+                .write_all(part.as_bytes())
+                .and_then(|()| writer.write_all(&jpeg))
+                .and_then(|()| writer.write_all(b"\r\n"))
+                .and_then(|()| writer.flush());
+            if sent.is_err() {
+                return;
+            }
+        }
+
+        thread::sleep(FRAME_INTERVAL.saturating_sub(started.elapsed()));
+    }
+}
+
+/// The latest frame with the detections boxed, as JPEG.
+fn preview_frame() -> Option<Vec<u8>> {
+    let (rgb, width) = pipeline::latest_rgb()?;
+    let height = rgb.len() / width / 3;
+    let mut image = RgbImage::from_raw(width as u32, height as u32, Arc::unwrap_or_clone(rgb))?;
+    snapshot::draw_detections(&mut image);
+
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY)
+        .encode(&image, image.width(), image.height(), ColorType::Rgb8)
+        .map_err(|e| warn!("Failed to encode a preview frame: {e}"))
+        .ok()?;
+    Some(jpeg)
+}
+
+fn json_response(value: serde_json::Value) -> Response<Cursor<Vec<u8>>> {
+    Response::from_string(value.to_string()).with_header(content_type("application/json"))
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).unwrap()
+}
@@ -0,0 +1,35 @@
+//! Getting decoded frames into the detection's YUV type without converting them to RGB.
+
+use led_detect::Yuv420;
+use video_rs::ffmpeg::{format::Pixel, frame::Video};
+
+/// Copies out a decoded frame, or returns `None` if it isn't planar 4:2:0.
+pub fn from_frame(frame: &Video) -> Option<Yuv420> {
+    let full_range = match frame.format() {
+        Pixel::YUV420P => false,
+        Pixel::YUVJ420P => true,
+        _ => return None,
+    };
+
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+    let plane = |index: usize, width: usize, height: usize| {
+        frame
+            .data(index)
+            .chunks(frame.stride(index))
+            .take(height)
+            .flat_map(|line| &line[..width])
+            .copied()
+            .collect()
+    };
+
+    Some(Yuv420 {
+        width,
+        height,
+        full_range,
+        y: plane(0, width, height),
+        u: plane(1, chroma_width, chroma_height),
+        v: plane(2, chroma_width, chroma_height),
+    })
+}
@@ -0,0 +1,59 @@
+//! Parts of the view that belong to one segment, for props that overlap in the camera view like
+//! a bush in front of a roofline. Scanning an LED leaves out the blobs in the zones of other
+//! segments, and with a zone of its own everything outside it, so one prop's LEDs don't get
+//! mapped onto the other. Where zones overlap, the one in front gets the blobs.
+
+use std::sync::RwLock;
+
+use eframe::epaint::{Pos2, Rect};
+use serde::{Deserialize, Serialize};
+
+use crate::segments::{self, Segment};
+
+/// The zone of each connected segment, in chaining order.
+pub static ZONES: RwLock<Vec<Option<Zone>>> = RwLock::new(Vec::new());
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Zone {
+    /// In frame pixels, like the detections.
+    pub rect: Rect,
+    /// Zones with a lower depth are in front of those with a higher one, and between equal depths
+    /// the segment that comes first is.
+    #[serde(default)]
+    pub depth: i32,
+}
+
+/// Takes the zones of `segments`, as connected.
+pub fn set(segments: &[Segment]) {
+    *ZONES.write().unwrap() = segments.iter().map(|segment| segment.zone).collect();
+}
+
+/// `points` without the ones LED `index` can't be, going by the zones of the segments.
+pub fn filter(points: &[Rect], index: usize) -> Vec<Rect> {
+    let zones = ZONES.read().unwrap();
+    if zones.iter().all(Option::is_none) {
+        return points.to_vec();
+    }
+
+    let (segment, _) = segments::LAYOUT.read().unwrap().split(index);
+    let own = zones.get(segment).copied().flatten().is_some();
+    points
+        .iter()
+        .filter(|rect| match owner(&zones, rect.center()) {
+            Some(owner) => owner == segment,
+            None => !own,
+        })
+        .copied()
+        .collect()
+}
+
+/// The segment whose zone in front has `point` in it, if any.
+fn owner(zones: &[Option<Zone>], point: Pos2) -> Option<usize> {
+    zones
+        .iter()
+        .enumerate()
+        .filter_map(|(segment, zone)| Some((segment, (*zone)?)))
+        .filter(|(_, zone)| zone.rect.contains(point))
+        .min_by_key(|&(segment, zone)| (zone.depth, segment))
+        .map(|(segment, _)| segment)
+}